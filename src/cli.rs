@@ -0,0 +1,46 @@
+use std::str::FromStr;
+use nalgebra::{Point3, Vector3};
+
+// Accepts both `--name value` and `--name=value`
+pub fn arg_value<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+  let prefix = format!("{}=", name);
+  for (index, arg) in args.iter().enumerate() {
+    if let Some(value) = arg.strip_prefix(&prefix) {
+      return Some(value);
+    } else if arg == name {
+      return args.get(index + 1).map(String::as_str);
+    }
+  }
+  None
+}
+
+pub fn has_flag(args: &[String], name: &str) -> bool {
+  args.iter().any(|arg| arg == name)
+}
+
+pub fn parse_arg<T: FromStr>(args: &[String], name: &str, default: T) -> Result<T, String> {
+  match arg_value(args, name) {
+    Some(value) => value.parse().map_err(|_| format!("Invalid value for {}: '{}'", name, value)),
+    None => Ok(default),
+  }
+}
+
+// Vectors are written as `x,y,z`
+pub fn parse_vector3(args: &[String], name: &str, default: Vector3<f32>) -> Result<Vector3<f32>, String> {
+  let value = match arg_value(args, name) {
+    Some(value) => value,
+    None => return Ok(default),
+  };
+  let components: Vec<f32> = value.split(',')
+    .map(|component| component.trim().parse::<f32>())
+    .collect::<Result<_, _>>()
+    .map_err(|_| format!("Invalid vector for {}: '{}'", name, value))?;
+  match components.as_slice() {
+    [x, y, z] => Ok(Vector3::new(*x, *y, *z)),
+    _ => Err(format!("Expected 3 components for {}: '{}'", name, value)),
+  }
+}
+
+pub fn parse_point3(args: &[String], name: &str, default: Point3<f32>) -> Result<Point3<f32>, String> {
+  parse_vector3(args, name, default.coords).map(Point3::from)
+}
@@ -0,0 +1,181 @@
+use std::path::PathBuf;
+use nalgebra::{Isometry3, Point3, UnitQuaternion, Vector3};
+
+use crate::drivers::{
+  gl::utils::load_textured_obj,
+  renderer::{
+    as_bytes, Application, BufferUsage, PipelineDesc, Renderer, RendererError, ShaderSource, VertexLayout
+  },
+};
+use crate::scene::{Light, MaterialRef, MeshRef, NodeId, Scene, SceneCamera, Transform};
+
+pub const VERTEX_SOURCE: &str = r#"
+  #version 330 core
+  layout (location = 0) in vec3 aPos;
+  layout (location = 1) in vec2 aTexCoord;
+  layout (location = 2) in vec3 aNormal;
+
+  out vec2 TexCoord;
+  out vec3 Shade;
+
+  uniform mat4 model;
+  uniform mat4 view;
+  uniform mat4 projection;
+
+  // See `Lighting` in drivers/renderer.rs
+  uniform int lightCount;
+  uniform vec4 lightPositions[4];
+  uniform vec4 lightColors[4];
+
+  vec3 shade(vec3 position, vec3 normal) {
+    if (lightCount == 0 || length(normal) == 0.0) {
+      return vec3(1.0);
+    }
+    normal = normalize(normal);
+    vec3 result = vec3(0.1);
+    for (int i = 0; i < min(lightCount, 4); i++) {
+      vec3 toLight = -lightPositions[i].xyz;
+      float attenuation = 1.0;
+      if (lightPositions[i].w != 0.0) {
+        toLight = lightPositions[i].xyz - position;
+        float lightDistance = length(toLight);
+        float window = lightColors[i].w > 0.0 ? clamp(1.0 - lightDistance / lightColors[i].w, 0.0, 1.0) : 1.0;
+        attenuation = window / (1.0 + lightDistance * lightDistance);
+      }
+      float diffuse = length(toLight) > 0.0 ? max(dot(normal, normalize(toLight)), 0.0) : 0.0;
+      result += lightColors[i].rgb * diffuse * attenuation;
+    }
+    return result;
+  }
+
+  void main() {
+      vec4 worldPos = model * vec4(aPos, 1.0);
+      gl_Position = projection * view * worldPos;
+      TexCoord = aTexCoord;
+      Shade = shade(worldPos.xyz, mat3(transpose(inverse(model))) * aNormal);
+  }
+"#;
+
+pub const FRAGMENT_SOURCE: &str = r#"
+  #version 330 core
+  out vec4 FragColor;
+
+  in vec2 TexCoord;
+  in vec3 Shade;
+
+  uniform sampler2D texture1;
+
+  void main() {
+    vec4 color = texture(texture1, TexCoord);
+    FragColor = vec4(color.rgb * Shade, color.a);
+  }
+"#;
+
+// Assets live at the repository root, next to Cargo.toml
+pub fn root_dir() -> PathBuf {
+  PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+}
+
+pub struct CubeDemo {
+  scene : Scene,
+  cube  : Option<NodeId>,
+  angle : f32,
+}
+
+impl CubeDemo {
+  pub fn new() -> Self {
+    CubeDemo {
+      scene : Scene::new(),
+      cube  : None,
+      angle : 0.0,
+    }
+  }
+}
+
+impl Default for CubeDemo {
+  fn default() -> Self {
+    CubeDemo::new()
+  }
+}
+
+impl Application for CubeDemo {
+
+  fn init(&mut self, renderer: &mut dyn Renderer) -> Result<(), RendererError> {
+    let root_dir = root_dir();
+    let shaders_dir = root_dir.join("src").join("shaders");
+
+    let parts = load_textured_obj(root_dir.join("assets/cube.obj"))
+      .map_err(|err| RendererError::Backend(format!("Error loading .obj file: {}", err)))?;
+
+    let shader = renderer.create_shader(&[
+      ShaderSource::Glsl {
+        vertex   : VERTEX_SOURCE.to_string(),
+        fragment : FRAGMENT_SOURCE.to_string(),
+      },
+      ShaderSource::Files {
+        vertex_path   : shaders_dir.join("mesh.vert").to_string_lossy().into_owned(),
+        fragment_path : shaders_dir.join("mesh.frag").to_string_lossy().into_owned(),
+        defines       : Vec::new(),
+      },
+    ])?;
+
+    let pipeline = renderer.create_pipeline(&PipelineDesc {
+      shader,
+      vertex_layout : VertexLayout::position_tex_normal(),
+      depth_test    : true,
+      cull_back     : false,
+    })?;
+
+    // One child per material, so each part is drawn with its own texture
+    let cube = self.scene.add_node("cube", Transform::identity(), None);
+    for (part_index, (vertices, indices, texture)) in parts.into_iter().enumerate() {
+      let texture = match texture {
+        Some(image) => match renderer.create_texture(&image) {
+          Ok(texture) => Some(texture),
+          Err(err) => {
+            eprintln!("Drawing cube part {} untextured: {}", part_index, err);
+            None
+          }
+        },
+        None => None,
+      };
+
+      let part = self.scene.add_node(&format!("cube part {}", part_index), Transform::identity(), Some(cube));
+      let node = self.scene.node_mut(part);
+      node.mesh = Some(MeshRef {
+        vertex_buffer : renderer.create_buffer(BufferUsage::Vertex, as_bytes(&vertices))?,
+        index_buffer  : Some(renderer.create_buffer(BufferUsage::Index, as_bytes(&indices))?),
+        element_count : indices.len() as u32,
+      });
+      node.material = Some(MaterialRef { pipeline, texture });
+    }
+    self.cube = Some(cube);
+
+    // Cameras look down their node's -Z, the inverse of a view matrix places them
+    let eye = Isometry3::look_at_rh(&Point3::new(0.0, 0.0, 3.0), &Point3::origin(), &Vector3::y()).inverse();
+    let camera = self.scene.add_node("camera", Transform { isometry: eye, scale: Vector3::new(1.0, 1.0, 1.0) }, None);
+    self.scene.node_mut(camera).camera = Some(SceneCamera::default());
+    self.scene.active_camera = Some(camera);
+
+    let sun = Isometry3::look_at_rh(&Point3::new(1.0, 2.0, 3.0), &Point3::origin(), &Vector3::y()).inverse();
+    let sun = self.scene.add_node("sun", Transform { isometry: sun, scale: Vector3::new(1.0, 1.0, 1.0) }, None);
+    self.scene.node_mut(sun).light = Some(Light::Directional { color: [1.0, 1.0, 1.0], intensity: 1.0 });
+    Ok(())
+  }
+
+  fn frame(&mut self, renderer: &mut dyn Renderer, delta_time: f32) -> Result<(), RendererError> {
+    let cube = self.cube.ok_or(RendererError::Backend("CubeDemo used before init".to_string()))?;
+
+    self.angle += delta_time * 0.5;
+    let angle = self.angle;
+    self.scene.modify_transform(cube, |transform| {
+      transform.isometry.rotation = UnitQuaternion::from_axis_angle(&Vector3::y_axis(), angle);
+    });
+    self.scene.update();
+    let (view, projection) = self.scene.camera_matrices(renderer.aspect_ratio())
+      .ok_or(RendererError::Backend("CubeDemo has no camera".to_string()))?;
+    renderer.begin_frame([0.2, 0.2, 0.2, 1.0])?;
+    self.scene.draw(renderer, &view, &projection)?;
+    renderer.end_frame()
+  }
+}
@@ -0,0 +1,256 @@
+use std::ptr;
+use gl::types::{GLenum, GLint, GLsizeiptr, GLuint, GLvoid};
+use image::RgbaImage;
+use nalgebra::Vector4;
+
+use crate::drivers::renderer::{
+  BufferId, BufferUsage, Device, DrawCall, PipelineDesc, PipelineId, Renderer, RendererError, ShaderId, ShaderSource, TextureId, MAX_LIGHTS
+};
+use super::shader::{Shader, ShaderError};
+use super::uniforms::UniformError;
+
+struct GlBuffer {
+  id     : GLuint,
+  target : GLenum,
+}
+
+pub struct GlDevice {
+  buffers   : Vec<GlBuffer>,
+  textures  : Vec<GLuint>,
+  shaders   : Vec<Shader>,
+  pipelines : Vec<PipelineDesc>,
+  vao       : GLuint,
+  width     : u32,
+  height    : u32,
+}
+
+impl GlDevice {
+
+  // Requires a current GL context with loaded function pointers
+  pub fn new(width: u32, height: u32) -> Self {
+    let mut vao = 0;
+    unsafe {
+      gl::GenVertexArrays(1, &mut vao);
+    }
+
+    let mut device = GlDevice {
+      buffers   : Vec::new(),
+      textures  : Vec::new(),
+      shaders   : Vec::new(),
+      pipelines : Vec::new(),
+      vao,
+      width,
+      height,
+    };
+    device.set_extent(width, height);
+    device
+  }
+
+  pub fn set_extent(&mut self, width: u32, height: u32) {
+    self.width = width;
+    self.height = height;
+    unsafe {
+      gl::Viewport(0, 0, width as GLint, height as GLint);
+    }
+  }
+
+  // Polls every shader loaded from files, see `Shader::poll_reload`. Returns the shaders that reloaded or failed to
+  pub fn poll_shader_reloads(&mut self) -> Vec<(ShaderId, Result<(), ShaderError>)> {
+    self.shaders.iter_mut()
+      .enumerate()
+      .filter_map(|(index, shader)| match shader.poll_reload() {
+        Ok(false) => None,
+        result => Some((ShaderId(index), result.map(|_| ()))),
+      })
+      .collect()
+  }
+}
+
+impl Device for GlDevice {
+
+  fn create_buffer(&mut self, usage: BufferUsage, data: &[u8]) -> Result<BufferId, RendererError> {
+    let target = match usage {
+      BufferUsage::Vertex => gl::ARRAY_BUFFER,
+      BufferUsage::Index  => gl::ELEMENT_ARRAY_BUFFER,
+    };
+
+    let mut id = 0;
+    unsafe {
+      // Index buffers bind to the VAO, keep it bound so uploads don't clobber another object's state
+      gl::BindVertexArray(self.vao);
+      gl::GenBuffers(1, &mut id);
+      gl::BindBuffer(target, id);
+      gl::BufferData(target, data.len() as GLsizeiptr, data.as_ptr() as *const GLvoid, gl::STATIC_DRAW);
+      gl::BindVertexArray(0);
+    }
+
+    self.buffers.push(GlBuffer { id, target });
+    Ok(BufferId(self.buffers.len() - 1))
+  }
+
+  fn create_texture(&mut self, image: &RgbaImage) -> Result<TextureId, RendererError> {
+    let mut texture_id = 0;
+    unsafe {
+      gl::GenTextures(1, &mut texture_id);
+      gl::BindTexture(gl::TEXTURE_2D, texture_id);
+
+      gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::REPEAT as GLint);
+      gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::REPEAT as GLint);
+      gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR_MIPMAP_LINEAR as GLint);
+      gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+
+      gl::TexImage2D(
+        gl::TEXTURE_2D,
+        0,
+        gl::RGBA as GLint,
+        image.width() as GLint,
+        image.height() as GLint,
+        0,
+        gl::RGBA,
+        gl::UNSIGNED_BYTE,
+        image.as_raw().as_ptr() as *const GLvoid,
+      );
+      gl::GenerateMipmap(gl::TEXTURE_2D);
+      gl::BindTexture(gl::TEXTURE_2D, 0);
+    }
+
+    self.textures.push(texture_id);
+    Ok(TextureId(self.textures.len() - 1))
+  }
+
+  fn create_shader(&mut self, sources: &[ShaderSource]) -> Result<ShaderId, RendererError> {
+    // Shaders from files reload when the files change, see `begin_frame`
+    let shader = sources.iter()
+      .find_map(|source| match source {
+        ShaderSource::Glsl { vertex, fragment } => Some(Shader::from_source(vertex, fragment)),
+        ShaderSource::Files { vertex_path, fragment_path, defines } => Some(Shader::from_files(vertex_path, fragment_path, defines)),
+        ShaderSource::SpirvFiles { .. } => None,
+      })
+      .ok_or(RendererError::Unsupported("OpenGL requires a GLSL shader source"))?
+      .map_err(|err| RendererError::Backend(err.to_string()))?;
+
+    self.shaders.push(shader);
+    Ok(ShaderId(self.shaders.len() - 1))
+  }
+
+  fn create_pipeline(&mut self, desc: &PipelineDesc) -> Result<PipelineId, RendererError> {
+    if desc.shader.0 >= self.shaders.len() {
+      return Err(RendererError::InvalidHandle("shader"));
+    }
+    self.pipelines.push(desc.clone());
+    Ok(PipelineId(self.pipelines.len() - 1))
+  }
+}
+
+impl Renderer for GlDevice {
+
+  fn begin_frame(&mut self, clear_color: [f32; 4]) -> Result<(), RendererError> {
+    unsafe {
+      gl::ClearColor(clear_color[0], clear_color[1], clear_color[2], clear_color[3]);
+      gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+    }
+    Ok(())
+  }
+
+  fn draw(&mut self, draw: &DrawCall) -> Result<(), RendererError> {
+    let pipeline = self.pipelines.get(draw.pipeline.0).ok_or(RendererError::InvalidHandle("pipeline"))?;
+    let shader = &self.shaders[pipeline.shader.0];
+    let vertex_buffer = self.buffers.get(draw.vertex_buffer.0).ok_or(RendererError::InvalidHandle("buffer"))?;
+    let index_buffer = match draw.index_buffer {
+      Some(id) => Some(self.buffers.get(id.0).ok_or(RendererError::InvalidHandle("buffer"))?),
+      None => None,
+    };
+    let texture = match draw.texture {
+      Some(id) => *self.textures.get(id.0).ok_or(RendererError::InvalidHandle("texture"))?,
+      None => 0,
+    };
+
+    unsafe {
+      if pipeline.depth_test { gl::Enable(gl::DEPTH_TEST) } else { gl::Disable(gl::DEPTH_TEST) }
+      if pipeline.cull_back {
+        gl::Enable(gl::CULL_FACE);
+        gl::CullFace(gl::BACK);
+      } else {
+        gl::Disable(gl::CULL_FACE);
+      }
+
+      shader.use_program();
+      for (name, matrix) in [("model", draw.model), ("view", draw.view), ("projection", draw.projection)] {
+        optional_uniform(shader.set_mat4(name, matrix))?;
+      }
+      let lighting = draw.lighting;
+      let count = (lighting.count as usize).min(MAX_LIGHTS);
+      optional_uniform(shader.set_int("lightCount", count as i32))?;
+      if count > 0 {
+        let positions: Vec<Vector4<f32>> = lighting.positions[..count].iter().map(|&position| Vector4::from(position)).collect();
+        let colors: Vec<Vector4<f32>> = lighting.colors[..count].iter().map(|&color| Vector4::from(color)).collect();
+        optional_uniform(shader.set_vec4_array("lightPositions", &positions))?;
+        optional_uniform(shader.set_vec4_array("lightColors", &colors))?;
+      }
+
+      gl::BindVertexArray(self.vao);
+      gl::BindBuffer(vertex_buffer.target, vertex_buffer.id);
+      for attribute in &pipeline.vertex_layout.attributes {
+        gl::VertexAttribPointer(
+          attribute.location,
+          attribute.components as GLint,
+          gl::FLOAT,
+          gl::FALSE,
+          pipeline.vertex_layout.stride as GLint,
+          attribute.offset as usize as *const _
+        );
+        gl::EnableVertexAttribArray(attribute.location);
+      }
+
+      gl::ActiveTexture(gl::TEXTURE0);
+      gl::BindTexture(gl::TEXTURE_2D, texture);
+
+      match index_buffer {
+        Some(index_buffer) => {
+          gl::BindBuffer(index_buffer.target, index_buffer.id);
+          gl::DrawElements(gl::TRIANGLES, draw.element_count as GLint, gl::UNSIGNED_INT, ptr::null());
+        },
+        None => gl::DrawArrays(gl::TRIANGLES, 0, draw.element_count as GLint),
+      }
+
+      for attribute in &pipeline.vertex_layout.attributes {
+        gl::DisableVertexAttribArray(attribute.location);
+      }
+      gl::BindVertexArray(0);
+    }
+    Ok(())
+  }
+
+  fn end_frame(&mut self) -> Result<(), RendererError> {
+    unsafe {
+      gl::Flush();
+    }
+    Ok(())
+  }
+
+  fn extent(&self) -> (u32, u32) {
+    (self.width, self.height)
+  }
+}
+
+// Shaders that leave a uniform out simply don't get it
+fn optional_uniform(result: Result<(), UniformError>) -> Result<(), RendererError> {
+  match result {
+    Ok(()) | Err(UniformError::NotFound(_)) => Ok(()),
+    Err(err) => Err(RendererError::Backend(err.to_string())),
+  }
+}
+
+impl Drop for GlDevice {
+  fn drop(&mut self) {
+    unsafe {
+      for buffer in &self.buffers {
+        gl::DeleteBuffers(1, &buffer.id);
+      }
+      if !self.textures.is_empty() {
+        gl::DeleteTextures(self.textures.len() as i32, self.textures.as_ptr());
+      }
+      gl::DeleteVertexArrays(1, &self.vao);
+    }
+  }
+}
@@ -0,0 +1,191 @@
+use std::{ffi::c_void, path::Path};
+use gl::types::{GLint, GLsizei, GLuint, GLvoid};
+use image::{DynamicImage, RgbaImage};
+use khronos_egl as egl;
+
+use crate::drivers::renderer::{Application, RendererError};
+use super::device::GlDevice;
+
+const PLATFORM_SURFACELESS_MESA: egl::Enum = 0x31DD;
+
+// EGL context that needs no window system, e.g. Mesa llvmpipe on a CI machine without a display
+pub struct HeadlessContext {
+  egl     : egl::DynamicInstance<egl::EGL1_5>,
+  display : egl::Display,
+  context : egl::Context,
+  surface : Option<egl::Surface>,
+}
+
+impl HeadlessContext {
+
+  pub fn new() -> Result<Self, RendererError> {
+    let egl = unsafe { egl::DynamicInstance::<egl::EGL1_5>::load_required() }
+      .map_err(|err| RendererError::Backend(format!("Failed to load libEGL: {}", err)))?;
+
+    let display = unsafe {
+      egl.get_platform_display(PLATFORM_SURFACELESS_MESA, egl::DEFAULT_DISPLAY, &[egl::ATTRIB_NONE])
+        .ok()
+        .or_else(|| egl.get_display(egl::DEFAULT_DISPLAY))
+    }.ok_or(RendererError::Backend("No EGL display available".to_string()))?;
+
+    egl.initialize(display).map_err(Self::egl_error)?;
+    egl.bind_api(egl::OPENGL_API).map_err(Self::egl_error)?;
+
+    let color_attributes = [
+      egl::RED_SIZE, 8,
+      egl::GREEN_SIZE, 8,
+      egl::BLUE_SIZE, 8,
+      egl::ALPHA_SIZE, 8,
+      egl::RENDERABLE_TYPE, egl::OPENGL_BIT,
+    ];
+    let with_pbuffer: Vec<egl::Int> = color_attributes.iter().copied()
+      .chain([egl::SURFACE_TYPE, egl::PBUFFER_BIT, egl::NONE])
+      .collect();
+    let without_surface: Vec<egl::Int> = color_attributes.iter().copied()
+      .chain([egl::NONE])
+      .collect();
+
+    // The surfaceless platform may expose configs without any surface type
+    let config = match egl.choose_first_config(display, &with_pbuffer).map_err(Self::egl_error)? {
+      Some(config) => Some(config),
+      None => egl.choose_first_config(display, &without_surface).map_err(Self::egl_error)?,
+    }.ok_or(RendererError::Backend("No EGL config supports desktop OpenGL".to_string()))?;
+
+    let context_attributes = [
+      egl::CONTEXT_MAJOR_VERSION, 3,
+      egl::CONTEXT_MINOR_VERSION, 3,
+      egl::CONTEXT_OPENGL_PROFILE_MASK, egl::CONTEXT_OPENGL_CORE_PROFILE_BIT,
+      egl::NONE,
+    ];
+    let context = egl.create_context(display, config, None, &context_attributes).map_err(Self::egl_error)?;
+
+    // Prefer EGL_KHR_surfaceless_context, fall back to a dummy pbuffer; we only ever render into FBOs
+    let surface = match egl.make_current(display, None, None, Some(context)) {
+      Ok(()) => None,
+      Err(_) => {
+        let surface = egl.create_pbuffer_surface(display, config, &[egl::WIDTH, 1, egl::HEIGHT, 1, egl::NONE])
+          .map_err(Self::egl_error)?;
+        egl.make_current(display, Some(surface), Some(surface), Some(context)).map_err(Self::egl_error)?;
+        Some(surface)
+      }
+    };
+
+    gl::load_with(|name| match egl.get_proc_address(name) {
+      Some(function) => function as *const c_void,
+      None => std::ptr::null(),
+    });
+
+    Ok(HeadlessContext { egl, display, context, surface })
+  }
+
+  fn egl_error(err: egl::Error) -> RendererError {
+    RendererError::Backend(format!("EGL error: {}", err))
+  }
+}
+
+impl Drop for HeadlessContext {
+  fn drop(&mut self) {
+    let _ = self.egl.make_current(self.display, None, None, None);
+    if let Some(surface) = self.surface {
+      let _ = self.egl.destroy_surface(self.display, surface);
+    }
+    let _ = self.egl.destroy_context(self.display, self.context);
+    let _ = self.egl.terminate(self.display);
+  }
+}
+
+pub struct OffscreenTarget {
+  framebuffer : GLuint,
+  color       : GLuint,
+  depth       : GLuint,
+  width       : u32,
+  height      : u32,
+}
+
+impl OffscreenTarget {
+
+  // Creates the FBO and leaves it bound, so the default framebuffer is never touched
+  pub fn new(width: u32, height: u32) -> Result<Self, RendererError> {
+    let (mut framebuffer, mut color, mut depth) = (0, 0, 0);
+    let status = unsafe {
+      gl::GenFramebuffers(1, &mut framebuffer);
+      gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+
+      gl::GenRenderbuffers(1, &mut color);
+      gl::BindRenderbuffer(gl::RENDERBUFFER, color);
+      gl::RenderbufferStorage(gl::RENDERBUFFER, gl::RGBA8, width as GLsizei, height as GLsizei);
+      gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::RENDERBUFFER, color);
+
+      gl::GenRenderbuffers(1, &mut depth);
+      gl::BindRenderbuffer(gl::RENDERBUFFER, depth);
+      gl::RenderbufferStorage(gl::RENDERBUFFER, gl::DEPTH24_STENCIL8, width as GLsizei, height as GLsizei);
+      gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, gl::DEPTH_STENCIL_ATTACHMENT, gl::RENDERBUFFER, depth);
+
+      gl::BindRenderbuffer(gl::RENDERBUFFER, 0);
+      gl::CheckFramebufferStatus(gl::FRAMEBUFFER)
+    };
+
+    let target = OffscreenTarget { framebuffer, color, depth, width, height };
+    if status != gl::FRAMEBUFFER_COMPLETE {
+      return Err(RendererError::Backend(format!("Offscreen framebuffer incomplete: 0x{:X}", status)));
+    }
+    Ok(target)
+  }
+
+  pub fn read_pixels(&self) -> RgbaImage {
+    let mut pixels = vec![0u8; (self.width * self.height * 4) as usize];
+    unsafe {
+      gl::BindFramebuffer(gl::FRAMEBUFFER, self.framebuffer);
+      gl::Finish();
+      gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+      gl::ReadPixels(
+        0,
+        0,
+        self.width as GLint,
+        self.height as GLint,
+        gl::RGBA,
+        gl::UNSIGNED_BYTE,
+        pixels.as_mut_ptr() as *mut GLvoid
+      );
+    }
+
+    // GL rows start at the bottom
+    let image = RgbaImage::from_raw(self.width, self.height, pixels).expect("Pixel buffer size mismatch");
+    image::imageops::flip_vertical(&image)
+  }
+}
+
+impl Drop for OffscreenTarget {
+  fn drop(&mut self) {
+    unsafe {
+      gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+      gl::DeleteRenderbuffers(1, &self.depth);
+      gl::DeleteRenderbuffers(1, &self.color);
+      gl::DeleteFramebuffers(1, &self.framebuffer);
+    }
+  }
+}
+
+// Runs one init + frame of `app` into an FBO and returns the rendered pixels
+pub fn render_offscreen(app: &mut dyn Application, width: u32, height: u32) -> Result<RgbaImage, RendererError> {
+  let _context = HeadlessContext::new()?;
+  let target = OffscreenTarget::new(width, height)?;
+  let mut device = GlDevice::new(width, height);
+
+  app.init(&mut device)?;
+  app.frame(&mut device, 0.0)?;
+
+  Ok(target.read_pixels())
+}
+
+// The file extension picks the encoder, .exr is written as 32-bit float
+pub fn save_image<P: AsRef<Path>>(image: &RgbaImage, path: P) -> Result<(), RendererError> {
+  let path = path.as_ref();
+  let is_exr = path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("exr"));
+  let result = if is_exr {
+    DynamicImage::ImageRgba8(image.clone()).to_rgba32f().save(path)
+  } else {
+    image.save(path)
+  };
+  result.map_err(|err| RendererError::Backend(format!("Failed to write {}: {}", path.display(), err)))
+}
@@ -1,4 +1,8 @@
 pub mod window;
 pub mod viewport;
 pub mod render_object;
-pub mod utils;
\ No newline at end of file
+pub mod shader;
+pub mod uniforms;
+pub mod utils;
+pub mod device;
+pub mod headless;
\ No newline at end of file
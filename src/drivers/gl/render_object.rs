@@ -1,102 +1,54 @@
-use std::{ffi::CString, mem::size_of, ptr};
-use gl::{types::{GLenum, GLint, GLsizei, GLsizeiptr, GLuint, GLvoid}, ActiveTexture, AttachShader, BindBuffer, BindTexture, BindVertexArray, BufferData, CompileShader, CreateProgram, CreateShader, DeleteBuffers, DeleteProgram, DeleteShader, DeleteVertexArrays, DrawElements, EnableVertexAttribArray, GenBuffers, GenTextures, GenVertexArrays, GetUniformLocation, LinkProgram, ShaderSource, TexImage2D, TexParameteri, Uniform1i, UniformMatrix4fv, UseProgram, VertexAttribPointer, ARRAY_BUFFER, ELEMENT_ARRAY_BUFFER, FRAGMENT_SHADER, LINEAR, REPEAT, RGBA, STATIC_DRAW, TEXTURE0, TEXTURE_2D, TEXTURE_MAG_FILTER, TEXTURE_MIN_FILTER, TEXTURE_WRAP_R, TEXTURE_WRAP_S, TEXTURE_WRAP_T, TRIANGLES, UNSIGNED_INT, VERTEX_SHADER };
+use std::{mem::size_of, ptr};
+use gl::{types::{GLint, GLsizei, GLsizeiptr, GLuint, GLvoid}, ActiveTexture, BindBuffer, BindTexture, BindVertexArray, BufferData, DeleteBuffers, DeleteTextures, DeleteVertexArrays, DrawElements, EnableVertexAttribArray, GenBuffers, GenTextures, GenVertexArrays, GenerateMipmap, TexImage2D, TexParameteri, VertexAttribPointer, ARRAY_BUFFER, ELEMENT_ARRAY_BUFFER, LINEAR, REPEAT, RGBA, STATIC_DRAW, TEXTURE0, TEXTURE_2D, TEXTURE_MAG_FILTER, TEXTURE_MIN_FILTER, TEXTURE_WRAP_S, TEXTURE_WRAP_T, TRIANGLES, UNSIGNED_BYTE, UNSIGNED_INT };
 use image::RgbaImage;
 use nalgebra::Matrix4;
 
+use crate::loaders::obj::OBJ_VERTEX_STRIDE;
+use super::shader::{Shader, ShaderError};
+use super::uniforms::UniformError;
+
+// An indexed mesh in the OBJ vertex layout with its own shader and optional texture, drawn outside of GlDevice
 pub struct RenderObject {
   render_context : RenderContext,
 }
 
 impl RenderObject {
-  pub fn new(vertices: Vec<f32>, indices: Vec<u32>, shader: Shader, texture: Option<RgbaImage>) -> Self {
-    let index_count = vertices.len() as i32;
-    RenderObject { 
-      render_context : RenderContext::new(vertices, indices, shader, index_count, texture),
-    }
+  // Requires a current GL context. Shaders without a `texture1` sampler are fine
+  pub fn new(vertices: Vec<f32>, indices: Vec<u32>, shader: Shader, texture: Option<RgbaImage>) -> Result<Self, UniformError> {
+    let index_count = indices.len() as i32;
+    Ok(RenderObject {
+      render_context : RenderContext::new(vertices, indices, shader, index_count, texture)?,
+    })
   }
 
-  pub fn draw(
-    &mut self, model: &Matrix4<f32>, view: &Matrix4<f32>, projection  : &Matrix4<f32>) { 
-      self.render_context.draw(model, view, projection) 
-    }
-}
-
-pub struct Shader{
-  id: u32
-}
-
-impl Shader {
-  
-  pub fn from_source(vertex_source: &str, fragment_source: &str, ) -> Shader {
-
-    let compile_shader = |src: &str, ty: GLenum| -> u32 {
-      let shader;
-      unsafe {
-        shader = CreateShader(ty);
-        ShaderSource(shader, 1, &CString::new(src).unwrap().as_ptr(), std::ptr::null());
-        CompileShader(shader);
-      }
-      shader
-    };
-
-    let vertex_shader = compile_shader(vertex_source, VERTEX_SHADER);
-    let fragment_shader = compile_shader(fragment_source, FRAGMENT_SHADER);
-
-    let program;
-    unsafe {
-      program = CreateProgram();
-      AttachShader(program, vertex_shader);
-      AttachShader(program, fragment_shader);
-      LinkProgram(program);
-      DeleteShader(vertex_shader);
-      DeleteShader(fragment_shader);
-    }
-
-    Shader { id: program }
+  // Uniforms the shader does not declare are skipped
+  pub fn draw(&mut self, model: &Matrix4<f32>, view: &Matrix4<f32>, projection: &Matrix4<f32>) -> Result<(), UniformError> {
+    self.render_context.draw(model, view, projection)
   }
 
-  fn get_uniform_location(&self, name: &str) -> i32 {
-    let c_str_name = CString::new(name).expect("Failed to convert string to CString");
-    unsafe {
-        gl::GetUniformLocation(self.id, c_str_name.as_ptr())
-    }
+  // See `Shader::poll_reload`, for render loops that draw the object every frame
+  pub fn poll_reload(&mut self) -> Result<bool, ShaderError> {
+    self.render_context.shader.poll_reload()
   }
 
-  fn set_mat4(&self, name: &str, mat: &Matrix4<f32>) {
-    unsafe {
-      let loc = GetUniformLocation(self.id, CString::new(name).unwrap().as_ptr());
-      UniformMatrix4fv(loc, 1, gl::FALSE, mat.as_ptr())
-    }
-  }
-
-  fn use_program(&self) {
-    unsafe {
-      UseProgram(self.id);
-    }
-  }
-}
-
-impl Drop for Shader {
-  fn drop(&mut self) {
-    unsafe {
-      DeleteProgram(self.id);
-    }
+  pub fn shader(&self) -> &Shader {
+    &self.render_context.shader
   }
 }
 
 struct RenderContext {
   shader      : Shader,
-  texture_id  : u32, 
+  texture_id  : GLuint,
   index_count : i32,
-  vao         : u32,
-  vbo         : u32,
-  ebo         : u32,
+  vao         : GLuint,
+  vbo         : GLuint,
+  ebo         : GLuint,
 }
 
 impl RenderContext {
 
-  fn new(vertices: Vec<f32>, indices: Vec<u32>, shader: Shader, index_count: i32, texture: Option<RgbaImage>) -> Self {
-    
+  fn new(vertices: Vec<f32>, indices: Vec<u32>, shader: Shader, index_count: i32, texture: Option<RgbaImage>) -> Result<Self, UniformError> {
+
     let (mut vao, mut vbo, mut ebo) = (0, 0, 0);
     let mut texture_id: GLuint = 0;
 
@@ -107,10 +59,11 @@ impl RenderContext {
       GenBuffers(1, &mut ebo);
 
       BindVertexArray(vao);
-      
+
       // VBO
       BindBuffer(ARRAY_BUFFER, vbo);
-      BufferData(ARRAY_BUFFER, 
+      BufferData(
+        ARRAY_BUFFER,
         (vertices.len() * size_of::<f32>()) as GLsizeiptr,
         vertices.as_ptr() as *const GLvoid,
         STATIC_DRAW
@@ -125,14 +78,17 @@ impl RenderContext {
         STATIC_DRAW
       );
 
-      // Attribute Stride
-      let stride = (5 * std::mem::size_of::<f32>()) as gl::types::GLsizei;
-
-      // Vertex Attribtues
-      //VertexAttribPointer(0, 3, gl::FLOAT, gl::FALSE, 3 * size_of::<f32>() as GLsizei, ptr::null());
-      //EnableVertexAttribArray(0);
-      VertexAttribPointer(0, 3, gl::FLOAT, gl::FALSE, stride, 0 as *const _);
+      // Position, texture coordinate and normal, see OBJ_VERTEX_STRIDE
+      let stride = (OBJ_VERTEX_STRIDE * size_of::<f32>()) as GLsizei;
+      VertexAttribPointer(0, 3, gl::FLOAT, gl::FALSE, stride, ptr::null());
       EnableVertexAttribArray(0);
+      VertexAttribPointer(1, 2, gl::FLOAT, gl::FALSE, stride, (3 * size_of::<f32>()) as *const _);
+      EnableVertexAttribArray(1);
+      VertexAttribPointer(2, 3, gl::FLOAT, gl::FALSE, stride, (5 * size_of::<f32>()) as *const _);
+      EnableVertexAttribArray(2);
+
+      BindBuffer(ARRAY_BUFFER, 0);
+      BindVertexArray(0);
 
       if let Some(texture) = texture {
 
@@ -144,82 +100,49 @@ impl RenderContext {
         TexParameteri(TEXTURE_2D, TEXTURE_MIN_FILTER, LINEAR as GLint);
         TexParameteri(TEXTURE_2D, TEXTURE_MAG_FILTER, LINEAR as GLint);
 
-        Uniform1i(shader.get_uniform_location("texture1"), 0);
-        
         let (width, height) = (texture.width() as i32, texture.height() as i32);
-        let raw_data = texture.into_raw();
-        let data = raw_data.as_slice();
-    
-        unsafe {
-          gl::TexImage2D(
-            gl::TEXTURE_2D,
-            0,
-            gl::RGBA as GLint,
-            width,
-            height,
-            0,
-            gl::RGBA,
-            gl::UNSIGNED_BYTE,
-            data.as_ptr() as *const GLvoid,
-          );
-
-          gl::GenerateMipmap(gl::TEXTURE_2D);      
-        }
-
-        BindBuffer(ARRAY_BUFFER, 0);
-        BindVertexArray(0);
+        TexImage2D(TEXTURE_2D, 0, RGBA as GLint, width, height, 0, RGBA, UNSIGNED_BYTE, texture.as_raw().as_ptr() as *const GLvoid);
+        GenerateMipmap(TEXTURE_2D);
       }
-
     }
 
-    RenderContext { vao, vbo, ebo, shader, index_count, texture_id }
+    let context = RenderContext { vao, vbo, ebo, shader, index_count, texture_id };
+    context.shader.use_program();
+    match context.shader.set_sampler("texture1", 0) {
+      Ok(()) | Err(UniformError::NotFound(_)) => Ok(context),
+      Err(err) => Err(err),
+    }
   }
 
-  fn draw(&mut self, model: &Matrix4<f32>, view: &Matrix4<f32>, projection: &Matrix4<f32>) {
-    unsafe {
-
-      self.shader.use_program();
-      self.shader.set_mat4("model", model);
-      self.shader.set_mat4("view", view);
-      self.shader.set_mat4("projection", projection);
-
-      unsafe {
-
-        BindVertexArray(self.vao);
-  
-        // Position attribute
-        //let stride = (6 * size_of::<f32>()) as GLsizei;
-        let stride = (5 * size_of::<f32>()) as GLsizei;
-        VertexAttribPointer(0, 3, gl::FLOAT, gl::FALSE, stride, 0 as *const _);
-        EnableVertexAttribArray(0);
-  
-        // Texture Coordinate Attribute
-        VertexAttribPointer(1, 2, gl::FLOAT, gl::FALSE, stride, (3 * size_of::<f32>()) as *const _);
-        EnableVertexAttribArray(1);
-
-        ActiveTexture(TEXTURE0);
-        BindTexture(TEXTURE_2D, self.texture_id);
-  
-        DrawElements(
-          TRIANGLES,
-          self.index_count,
-          UNSIGNED_INT,
-          ptr::null()
-        );
-  
-        BindVertexArray(0);
+  fn draw(&mut self, model: &Matrix4<f32>, view: &Matrix4<f32>, projection: &Matrix4<f32>) -> Result<(), UniformError> {
+    self.shader.use_program();
+    for (name, matrix) in [("model", model), ("view", view), ("projection", projection)] {
+      match self.shader.set_mat4(name, matrix) {
+        Ok(()) | Err(UniformError::NotFound(_)) => {},
+        Err(err) => return Err(err),
       }
+    }
 
+    unsafe {
+      BindVertexArray(self.vao);
+      ActiveTexture(TEXTURE0);
+      BindTexture(TEXTURE_2D, self.texture_id);
+      DrawElements(TRIANGLES, self.index_count, UNSIGNED_INT, ptr::null());
+      BindVertexArray(0);
     }
+    Ok(())
   }
 }
 
 impl Drop for RenderContext {
   fn drop(&mut self) {
     unsafe {
-      DeleteBuffers(1, &mut self.ebo);
-      DeleteBuffers(1, &mut self.vbo);
-      DeleteVertexArrays(1, &mut self.vao);
+      if self.texture_id != 0 {
+        DeleteTextures(1, &self.texture_id);
+      }
+      DeleteBuffers(1, &self.ebo);
+      DeleteBuffers(1, &self.vbo);
+      DeleteVertexArrays(1, &self.vao);
     }
   }
-}
\ No newline at end of file
+}
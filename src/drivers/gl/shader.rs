@@ -0,0 +1,481 @@
+use std::{
+  error::Error,
+  ffi::CString,
+  fmt, fs, io,
+  path::{Path, PathBuf},
+  time::{Duration, Instant, SystemTime},
+};
+use gl::{types::{GLchar, GLenum, GLint, GLsizei, GLuint}, AttachShader, CompileShader, CreateProgram, CreateShader, DeleteProgram, DeleteShader, GetProgramInfoLog, GetProgramiv, GetShaderInfoLog, GetShaderiv, LinkProgram, ShaderSource, Uniform1f, Uniform1fv, Uniform1i, Uniform1iv, Uniform2fv, Uniform3fv, Uniform4fv, UniformBlockBinding, UniformMatrix3fv, UniformMatrix4fv, UseProgram, FRAGMENT_SHADER, VERTEX_SHADER, COMPILE_STATUS, INFO_LOG_LENGTH, LINK_STATUS };
+use nalgebra::{Matrix3, Matrix4, Vector2, Vector3, Vector4};
+
+use super::uniforms::{AttributeInfo, ProgramInterface, UniformBlockInfo, UniformError, UniformType, UniformValue, SAMPLER_TYPES};
+
+// Where a line of the info log points in the source, numbered like the file the source came from
+#[derive(Debug)]
+pub struct ShaderLogLine {
+  pub line        : Option<u32>,
+  pub message     : String,
+  pub source_line : Option<String>,
+}
+
+impl fmt::Display for ShaderLogLine {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match (self.line, &self.source_line) {
+      (Some(line), Some(source_line)) => write!(f, "line {}: {}\n    {:>4} | {}", line, self.message, line, source_line.trim_end()),
+      (Some(line), None) => write!(f, "line {}: {}", line, self.message),
+      (None, _) => write!(f, "{}", self.message),
+    }
+  }
+}
+
+#[derive(Debug)]
+pub enum ShaderError {
+  Io { path: PathBuf, error: io::Error },
+  InvalidSource(String),
+  Compile { stage: &'static str, origin: String, log: Vec<ShaderLogLine> },
+  Link(String),
+}
+
+impl fmt::Display for ShaderError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ShaderError::Io { path, error } => write!(f, "Failed to read shader {}: {}", path.display(), error),
+      ShaderError::InvalidSource(origin) => write!(f, "Shader {} contains a NUL byte", origin),
+      ShaderError::Compile { stage, origin, log } => {
+        write!(f, "Failed to compile {} shader {}", stage, origin)?;
+        for line in log {
+          write!(f, "\n  {}", line)?;
+        }
+        Ok(())
+      },
+      ShaderError::Link(log) => write!(f, "Failed to link shader program:\n{}", log.trim_end()),
+    }
+  }
+}
+
+impl Error for ShaderError {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    match self {
+      ShaderError::Io { error, .. } => Some(error),
+      _ => None,
+    }
+  }
+}
+
+// Source handed to the driver: the original text with any defines inserted after #version
+struct StageSource {
+  origin : String,
+  text   : String,
+  lines  : Vec<Option<u32>>, // Line of the original per line of text, None for inserted defines
+}
+
+impl StageSource {
+  fn new(origin: String, original: &str, defines: &[(String, String)]) -> Self {
+    let version_line = original.lines().position(|line| line.trim_start().starts_with("#version"));
+    let insert_at = version_line.map_or(0, |line| line + 1);
+
+    let mut text = String::with_capacity(original.len());
+    let mut lines = Vec::new();
+    for (index, line) in original.lines().enumerate() {
+      if index == insert_at {
+        for (name, value) in defines {
+          text.push_str(&format!("#define {} {}\n", name, value));
+          lines.push(None);
+        }
+      }
+      text.push_str(line);
+      text.push('\n');
+      lines.push(Some(index as u32 + 1));
+    }
+    StageSource { origin, text, lines }
+  }
+
+  // Splits the info log into lines and points each back at the original source
+  fn annotate(&self, log: &str) -> Vec<ShaderLogLine> {
+    log.lines()
+      .filter(|line| !line.trim().is_empty())
+      .map(|line| {
+        let (log_line, message) = parse_log_line(line);
+        let line = log_line.and_then(|line| self.lines.get((line as usize).wrapping_sub(1)).copied().flatten());
+        let source_line = log_line.and_then(|line| self.text.lines().nth((line as usize).wrapping_sub(1))).map(str::to_string);
+        ShaderLogLine { line, message, source_line }
+      })
+      .collect()
+  }
+}
+
+// Drivers disagree on the format: Mesa "0:12(5): error: ...", NVIDIA "0(12) : error C0000: ...",
+// AMD and Apple "ERROR: 0:12: ...". Returns the line number, if any, and the rest of the message
+fn parse_log_line(text: &str) -> (Option<u32>, String) {
+  let text = text.trim();
+  let (severity, rest) = if let Some(rest) = text.strip_prefix("ERROR: ") {
+    ("error: ", rest)
+  } else if let Some(rest) = text.strip_prefix("WARNING: ") {
+    ("warning: ", rest)
+  } else {
+    ("", text)
+  };
+  let number = |text: &str| -> (Option<u32>, usize) {
+    let end = text.find(|ch: char| !ch.is_ascii_digit()).unwrap_or(text.len());
+    (text[..end].parse().ok(), end)
+  };
+  let message = |rest: &str| format!("{}{}", severity, rest.trim_start_matches(|ch: char| ch == ':' || ch.is_whitespace()));
+
+  let (source_string, end) = number(rest);
+  if source_string.is_some() {
+    let after_source = &rest[end..];
+    if let Some(after) = after_source.strip_prefix(':') {
+      let (line, end) = number(after);
+      if let Some(line) = line {
+        // Mesa follows the line with the column in parentheses
+        let after_line = &after[end..];
+        let after_line = match after_line.strip_prefix('(') {
+          Some(column) => column.split_once(')').map_or(after_line, |(_, rest)| rest),
+          None => after_line,
+        };
+        return (Some(line), message(after_line));
+      }
+    } else if let Some(after) = after_source.strip_prefix('(') {
+      let (line, end) = number(after);
+      if let (Some(line), Some(rest)) = (line, after[end..].strip_prefix(')')) {
+        return (Some(line), message(rest));
+      }
+    }
+  }
+  (None, text.to_string())
+}
+
+unsafe fn shader_info_log(shader: GLuint) -> String {
+  let mut length = 0;
+  GetShaderiv(shader, INFO_LOG_LENGTH, &mut length);
+  let mut log = vec![0u8; length.max(1) as usize];
+  let mut written = 0;
+  GetShaderInfoLog(shader, log.len() as GLsizei, &mut written, log.as_mut_ptr() as *mut GLchar);
+  log.truncate(written as usize);
+  String::from_utf8_lossy(&log).into_owned()
+}
+
+unsafe fn program_info_log(program: GLuint) -> String {
+  let mut length = 0;
+  GetProgramiv(program, INFO_LOG_LENGTH, &mut length);
+  let mut log = vec![0u8; length.max(1) as usize];
+  let mut written = 0;
+  GetProgramInfoLog(program, log.len() as GLsizei, &mut written, log.as_mut_ptr() as *mut GLchar);
+  log.truncate(written as usize);
+  String::from_utf8_lossy(&log).into_owned()
+}
+
+// How often file backed shaders look at their modification times
+const RELOAD_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+struct ShaderFiles {
+  vertex_path   : PathBuf,
+  fragment_path : PathBuf,
+  defines       : Vec<(String, String)>,
+  modified      : (Option<SystemTime>, Option<SystemTime>),
+  last_check    : Instant,
+}
+
+impl ShaderFiles {
+  fn modified_times(&self) -> (Option<SystemTime>, Option<SystemTime>) {
+    let modified = |path: &Path| fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+    (modified(&self.vertex_path), modified(&self.fragment_path))
+  }
+
+  fn read(&self) -> Result<(StageSource, StageSource), ShaderError> {
+    let read = |path: &Path| -> Result<StageSource, ShaderError> {
+      let text = fs::read_to_string(path).map_err(|error| ShaderError::Io { path: path.to_path_buf(), error })?;
+      Ok(StageSource::new(path.display().to_string(), &text, &self.defines))
+    };
+    Ok((read(&self.vertex_path)?, read(&self.fragment_path)?))
+  }
+}
+
+pub struct Shader{
+  id        : u32,
+  files     : Option<ShaderFiles>, // Set for shaders loaded with `from_files`, see `poll_reload`
+  interface : ProgramInterface,
+}
+
+impl Shader {
+  
+  pub fn from_source(vertex_source: &str, fragment_source: &str) -> Result<Shader, ShaderError> {
+    let id = Shader::link(
+      &StageSource::new("vertex source".to_string(), vertex_source, &[]),
+      &StageSource::new("fragment source".to_string(), fragment_source, &[])
+    )?;
+    Ok(Shader { id, files: None, interface: unsafe { ProgramInterface::introspect(id) } })
+  }
+
+  // Each (name, value) in `defines` becomes a #define right after the #version line
+  pub fn from_files<P: AsRef<Path>>(vertex_path: P, fragment_path: P, defines: &[(String, String)]) -> Result<Shader, ShaderError> {
+    let mut files = ShaderFiles {
+      vertex_path   : vertex_path.as_ref().to_path_buf(),
+      fragment_path : fragment_path.as_ref().to_path_buf(),
+      defines       : defines.to_vec(),
+      modified      : (None, None),
+      last_check    : Instant::now(),
+    };
+    files.modified = files.modified_times();
+    let (vertex, fragment) = files.read()?;
+    let id = Shader::link(&vertex, &fragment)?;
+    Ok(Shader { id, files: Some(files), interface: unsafe { ProgramInterface::introspect(id) } })
+  }
+
+  fn link(vertex: &StageSource, fragment: &StageSource) -> Result<u32, ShaderError> {
+
+    let compile_shader = |source: &StageSource, ty: GLenum, stage: &'static str| -> Result<u32, ShaderError> {
+      let c_source = CString::new(source.text.as_str()).map_err(|_| ShaderError::InvalidSource(source.origin.clone()))?;
+      unsafe {
+        let shader = CreateShader(ty);
+        ShaderSource(shader, 1, &c_source.as_ptr(), std::ptr::null());
+        CompileShader(shader);
+
+        let mut status = 0;
+        GetShaderiv(shader, COMPILE_STATUS, &mut status);
+        if status != gl::TRUE as GLint {
+          let log = shader_info_log(shader);
+          DeleteShader(shader);
+          return Err(ShaderError::Compile { stage, origin: source.origin.clone(), log: source.annotate(&log) });
+        }
+        Ok(shader)
+      }
+    };
+
+    let vertex_shader = compile_shader(vertex, VERTEX_SHADER, "vertex")?;
+    let fragment_shader = match compile_shader(fragment, FRAGMENT_SHADER, "fragment") {
+      Ok(shader) => shader,
+      Err(err) => {
+        unsafe { DeleteShader(vertex_shader) };
+        return Err(err);
+      }
+    };
+
+    unsafe {
+      let program = CreateProgram();
+      AttachShader(program, vertex_shader);
+      AttachShader(program, fragment_shader);
+      LinkProgram(program);
+      DeleteShader(vertex_shader);
+      DeleteShader(fragment_shader);
+
+      let mut status = 0;
+      GetProgramiv(program, LINK_STATUS, &mut status);
+      if status != gl::TRUE as GLint {
+        let log = program_info_log(program);
+        DeleteProgram(program);
+        return Err(ShaderError::Link(log));
+      }
+      Ok(program)
+    }
+  }
+
+  // Relinks from the files when either changed on disk, checked at most every RELOAD_POLL_INTERVAL so it can run
+  // every frame. On failure the previous program stays in use and the same files are not retried until they change
+  // again. Draws look the program up through their pipeline's ShaderId, so a reloaded program is used by every
+  // pipeline built from the shader from then on. Requires the shader's GL context to be current
+  pub fn poll_reload(&mut self) -> Result<bool, ShaderError> {
+    let files = match self.files.as_mut() {
+      Some(files) if files.last_check.elapsed() >= RELOAD_POLL_INTERVAL => files,
+      _ => return Ok(false),
+    };
+    files.last_check = Instant::now();
+    let modified = files.modified_times();
+    if modified == files.modified {
+      return Ok(false);
+    }
+    files.modified = modified;
+
+    let (vertex, fragment) = files.read()?;
+    let id = Shader::link(&vertex, &fragment)?;
+    let mut interface = unsafe { ProgramInterface::introspect(id) };
+    // Block bindings are program state, carry them over to the new program
+    for (name, binding) in self.interface.blocks().map(|block| (block.name.clone(), block.binding)).collect::<Vec<_>>() {
+      if interface.block(&name).is_some() {
+        unsafe { UniformBlockBinding(id, interface.block(&name).unwrap().index, binding) };
+        interface.set_block_binding(&name, binding);
+      }
+    }
+    unsafe { DeleteProgram(self.id) };
+    self.id = id;
+    self.interface = interface;
+    Ok(true)
+  }
+
+  // Active uniforms, attributes and uniform blocks, introspected after every link
+  pub fn interface(&self) -> &ProgramInterface {
+    &self.interface
+  }
+
+  pub fn attributes(&self) -> &[AttributeInfo] {
+    self.interface.attributes()
+  }
+
+  pub fn uniform_block(&self, name: &str) -> Option<&UniformBlockInfo> {
+    self.interface.block(name)
+  }
+
+  // Points the block at a binding point a UniformBuffer is bound to
+  pub fn bind_uniform_block(&mut self, name: &str, binding: u32) -> Result<(), UniformError> {
+    let index = self.interface.block(name).ok_or_else(|| UniformError::BlockNotFound(name.to_string()))?.index;
+    unsafe { UniformBlockBinding(self.id, index, binding) };
+    self.interface.set_block_binding(name, binding);
+    Ok(())
+  }
+
+  // The setters below write to the program in use, call `use_program` first
+
+  pub fn set_uniform(&self, name: &str, value: &UniformValue) -> Result<(), UniformError> {
+    match value {
+      UniformValue::Float(value)      => self.set_float(name, *value),
+      UniformValue::Vec2(value)       => self.set_vec2(name, value),
+      UniformValue::Vec3(value)       => self.set_vec3(name, value),
+      UniformValue::Vec4(value)       => self.set_vec4(name, value),
+      UniformValue::Int(value)        => self.set_int(name, *value),
+      UniformValue::Mat3(value)       => self.set_mat3(name, value),
+      UniformValue::Mat4(value)       => self.set_mat4(name, value),
+      UniformValue::Sampler(unit)     => self.set_sampler(name, *unit),
+      UniformValue::FloatArray(values) => self.set_float_array(name, values),
+      UniformValue::IntArray(values)  => self.set_int_array(name, values),
+      UniformValue::Vec2Array(values) => self.set_vec2_array(name, values),
+      UniformValue::Vec3Array(values) => self.set_vec3_array(name, values),
+      UniformValue::Vec4Array(values) => self.set_vec4_array(name, values),
+      UniformValue::Mat4Array(values) => self.set_mat4_array(name, values),
+    }
+  }
+
+  pub fn set_float(&self, name: &str, value: f32) -> Result<(), UniformError> {
+    let location = self.interface.locate(name, &[UniformType::Float], 1)?;
+    unsafe { Uniform1f(location, value) };
+    Ok(())
+  }
+
+  pub fn set_vec2(&self, name: &str, value: &Vector2<f32>) -> Result<(), UniformError> {
+    self.set_vec2_array(name, std::slice::from_ref(value))
+  }
+
+  pub fn set_vec3(&self, name: &str, value: &Vector3<f32>) -> Result<(), UniformError> {
+    self.set_vec3_array(name, std::slice::from_ref(value))
+  }
+
+  pub fn set_vec4(&self, name: &str, value: &Vector4<f32>) -> Result<(), UniformError> {
+    self.set_vec4_array(name, std::slice::from_ref(value))
+  }
+
+  // Bools are set as ints, as in GL
+  pub fn set_int(&self, name: &str, value: i32) -> Result<(), UniformError> {
+    let location = self.interface.locate(name, &[UniformType::Int, UniformType::Bool], 1)?;
+    unsafe { Uniform1i(location, value) };
+    Ok(())
+  }
+
+  pub fn set_mat3(&self, name: &str, value: &Matrix3<f32>) -> Result<(), UniformError> {
+    let location = self.interface.locate(name, &[UniformType::Mat3], 1)?;
+    unsafe { UniformMatrix3fv(location, 1, gl::FALSE, value.as_ptr()) };
+    Ok(())
+  }
+
+  pub fn set_mat4(&self, name: &str, value: &Matrix4<f32>) -> Result<(), UniformError> {
+    self.set_mat4_array(name, std::slice::from_ref(value))
+  }
+
+  // Texture unit the sampler reads from
+  pub fn set_sampler(&self, name: &str, unit: i32) -> Result<(), UniformError> {
+    let location = self.interface.locate(name, &SAMPLER_TYPES, 1)?;
+    unsafe { Uniform1i(location, unit) };
+    Ok(())
+  }
+
+  // Array setters start at element 0 and may set fewer elements than the array holds
+
+  pub fn set_float_array(&self, name: &str, values: &[f32]) -> Result<(), UniformError> {
+    let location = self.interface.locate(name, &[UniformType::Float], values.len())?;
+    unsafe { Uniform1fv(location, values.len() as GLsizei, values.as_ptr()) };
+    Ok(())
+  }
+
+  pub fn set_int_array(&self, name: &str, values: &[i32]) -> Result<(), UniformError> {
+    let location = self.interface.locate(name, &[UniformType::Int, UniformType::Bool], values.len())?;
+    unsafe { Uniform1iv(location, values.len() as GLsizei, values.as_ptr()) };
+    Ok(())
+  }
+
+  pub fn set_vec2_array(&self, name: &str, values: &[Vector2<f32>]) -> Result<(), UniformError> {
+    let location = self.interface.locate(name, &[UniformType::Vec2], values.len())?;
+    unsafe { Uniform2fv(location, values.len() as GLsizei, values.as_ptr() as *const f32) };
+    Ok(())
+  }
+
+  pub fn set_vec3_array(&self, name: &str, values: &[Vector3<f32>]) -> Result<(), UniformError> {
+    let location = self.interface.locate(name, &[UniformType::Vec3], values.len())?;
+    unsafe { Uniform3fv(location, values.len() as GLsizei, values.as_ptr() as *const f32) };
+    Ok(())
+  }
+
+  pub fn set_vec4_array(&self, name: &str, values: &[Vector4<f32>]) -> Result<(), UniformError> {
+    let location = self.interface.locate(name, &[UniformType::Vec4], values.len())?;
+    unsafe { Uniform4fv(location, values.len() as GLsizei, values.as_ptr() as *const f32) };
+    Ok(())
+  }
+
+  pub fn set_mat4_array(&self, name: &str, values: &[Matrix4<f32>]) -> Result<(), UniformError> {
+    let location = self.interface.locate(name, &[UniformType::Mat4], values.len())?;
+    unsafe { UniformMatrix4fv(location, values.len() as GLsizei, gl::FALSE, values.as_ptr() as *const f32) };
+    Ok(())
+  }
+
+  pub fn use_program(&self) {
+    unsafe {
+      UseProgram(self.id);
+    }
+  }
+}
+
+impl Drop for Shader {
+  fn drop(&mut self) {
+    unsafe {
+      DeleteProgram(self.id);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parses_mesa_logs() {
+    assert_eq!(parse_log_line("0:12(5): error: `foo' undeclared"), (Some(12), "error: `foo' undeclared".to_string()));
+  }
+
+  #[test]
+  fn parses_nvidia_logs() {
+    assert_eq!(parse_log_line("0(7) : error C0000: syntax error"), (Some(7), "error C0000: syntax error".to_string()));
+  }
+
+  #[test]
+  fn parses_amd_and_apple_logs() {
+    assert_eq!(parse_log_line("ERROR: 0:3: 'x' : undeclared identifier"), (Some(3), "error: 'x' : undeclared identifier".to_string()));
+    assert_eq!(parse_log_line("WARNING: 0:9: unused variable"), (Some(9), "warning: unused variable".to_string()));
+  }
+
+  #[test]
+  fn keeps_lines_without_a_location() {
+    assert_eq!(parse_log_line("  Link failed  "), (None, "Link failed".to_string()));
+    assert_eq!(parse_log_line("0:x: error"), (None, "0:x: error".to_string()));
+  }
+
+  #[test]
+  fn annotations_skip_inserted_defines() {
+    let original = "#version 330 core\nvoid main() {\n  gl_Position = vec4(SCALE);\n}\n";
+    let source = StageSource::new("test.vert".to_string(), original, &[("SCALE".to_string(), "2.0".to_string())]);
+    assert_eq!(source.text.lines().nth(1), Some("#define SCALE 2.0"));
+
+    let log = source.annotate("0:4(3): error: bad\n\n0:2(1): error: in a define\n");
+    assert_eq!(log.len(), 2);
+    assert_eq!(log[0].line, Some(3));
+    assert_eq!(log[0].source_line.as_deref(), Some("  gl_Position = vec4(SCALE);"));
+    assert_eq!(log[1].line, None);
+    assert_eq!(log[1].to_string(), "error: in a define");
+  }
+}
@@ -0,0 +1,429 @@
+use std::{collections::HashMap, error::Error, ffi::CString, fmt, ptr};
+use gl::types::{GLchar, GLenum, GLint, GLsizei, GLsizeiptr, GLuint, GLvoid};
+use nalgebra::{Matrix3, Matrix4, Vector2, Vector3, Vector4};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UniformType {
+  Float,
+  Vec2,
+  Vec3,
+  Vec4,
+  Int,
+  IVec2,
+  IVec3,
+  IVec4,
+  UInt,
+  Bool,
+  Mat2,
+  Mat3,
+  Mat4,
+  Sampler2D,
+  Sampler3D,
+  SamplerCube,
+  Sampler2DShadow,
+  Sampler2DArray,
+  Other(GLenum),
+}
+
+pub const SAMPLER_TYPES: [UniformType; 5] = [
+  UniformType::Sampler2D, UniformType::Sampler3D, UniformType::SamplerCube, UniformType::Sampler2DShadow, UniformType::Sampler2DArray
+];
+
+impl UniformType {
+  pub fn from_gl(gl_type: GLenum) -> Self {
+    match gl_type {
+      gl::FLOAT             => UniformType::Float,
+      gl::FLOAT_VEC2        => UniformType::Vec2,
+      gl::FLOAT_VEC3        => UniformType::Vec3,
+      gl::FLOAT_VEC4        => UniformType::Vec4,
+      gl::INT               => UniformType::Int,
+      gl::INT_VEC2          => UniformType::IVec2,
+      gl::INT_VEC3          => UniformType::IVec3,
+      gl::INT_VEC4          => UniformType::IVec4,
+      gl::UNSIGNED_INT      => UniformType::UInt,
+      gl::BOOL              => UniformType::Bool,
+      gl::FLOAT_MAT2        => UniformType::Mat2,
+      gl::FLOAT_MAT3        => UniformType::Mat3,
+      gl::FLOAT_MAT4        => UniformType::Mat4,
+      gl::SAMPLER_2D        => UniformType::Sampler2D,
+      gl::SAMPLER_3D        => UniformType::Sampler3D,
+      gl::SAMPLER_CUBE      => UniformType::SamplerCube,
+      gl::SAMPLER_2D_SHADOW => UniformType::Sampler2DShadow,
+      gl::SAMPLER_2D_ARRAY  => UniformType::Sampler2DArray,
+      other                 => UniformType::Other(other),
+    }
+  }
+}
+
+impl fmt::Display for UniformType {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let name = match self {
+      UniformType::Float           => "float",
+      UniformType::Vec2            => "vec2",
+      UniformType::Vec3            => "vec3",
+      UniformType::Vec4            => "vec4",
+      UniformType::Int             => "int",
+      UniformType::IVec2           => "ivec2",
+      UniformType::IVec3           => "ivec3",
+      UniformType::IVec4           => "ivec4",
+      UniformType::UInt            => "uint",
+      UniformType::Bool            => "bool",
+      UniformType::Mat2            => "mat2",
+      UniformType::Mat3            => "mat3",
+      UniformType::Mat4            => "mat4",
+      UniformType::Sampler2D       => "sampler2D",
+      UniformType::Sampler3D       => "sampler3D",
+      UniformType::SamplerCube     => "samplerCube",
+      UniformType::Sampler2DShadow => "sampler2DShadow",
+      UniformType::Sampler2DArray  => "sampler2DArray",
+      UniformType::Other(gl_type)  => return write!(f, "GL type 0x{:04X}", gl_type),
+    };
+    write!(f, "{}", name)
+  }
+}
+
+// Owned so materials can keep parameters around and apply them by name, see `Shader::set_uniform`
+#[derive(Clone, Debug)]
+pub enum UniformValue {
+  Float(f32),
+  Vec2(Vector2<f32>),
+  Vec3(Vector3<f32>),
+  Vec4(Vector4<f32>),
+  Int(i32),
+  Mat3(Matrix3<f32>),
+  Mat4(Matrix4<f32>),
+  Sampler(i32), // Texture unit
+  FloatArray(Vec<f32>),
+  IntArray(Vec<i32>),
+  Vec2Array(Vec<Vector2<f32>>),
+  Vec3Array(Vec<Vector3<f32>>),
+  Vec4Array(Vec<Vector4<f32>>),
+  Mat4Array(Vec<Matrix4<f32>>),
+}
+
+#[derive(Debug)]
+pub enum UniformError {
+  NotFound(String), // Misspelled, or unused and optimized out by the driver
+  TypeMismatch { name: String, expected: UniformType, actual: UniformType },
+  TooManyElements { name: String, size: i32, provided: usize },
+  BlockNotFound(String),
+}
+
+impl fmt::Display for UniformError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      UniformError::NotFound(name) => write!(f, "No active uniform named '{}'", name),
+      UniformError::TypeMismatch { name, expected, actual } => write!(f, "Uniform '{}' is a {}, not a {}", name, actual, expected),
+      UniformError::TooManyElements { name, size, provided } => write!(f, "Uniform '{}' holds {} elements, {} provided", name, size, provided),
+      UniformError::BlockNotFound(name) => write!(f, "No active uniform block named '{}'", name),
+    }
+  }
+}
+
+impl Error for UniformError {}
+
+#[derive(Clone, Debug)]
+pub struct UniformInfo {
+  pub name         : String,
+  pub location     : GLint,
+  pub uniform_type : UniformType,
+  pub size         : i32, // Array length, 1 for plain uniforms
+}
+
+#[derive(Clone, Debug)]
+pub struct AttributeInfo {
+  pub name           : String,
+  pub location       : GLint,
+  pub attribute_type : UniformType,
+  pub size           : i32,
+}
+
+// Byte layout of a block member, what a UniformBuffer is written with
+#[derive(Clone, Debug)]
+pub struct UniformBlockMember {
+  pub name          : String,
+  pub uniform_type  : UniformType,
+  pub size          : i32,
+  pub offset        : i32,
+  pub array_stride  : i32,
+  pub matrix_stride : i32,
+}
+
+#[derive(Clone, Debug)]
+pub struct UniformBlockInfo {
+  pub name      : String,
+  pub index     : GLuint,
+  pub data_size : i32,
+  pub binding   : GLuint,
+  pub members   : Vec<UniformBlockMember>,
+}
+
+impl UniformBlockInfo {
+  // Members of blocks with an instance name are reported as `Block.member`, either form is accepted
+  pub fn member(&self, name: &str) -> Option<&UniformBlockMember> {
+    self.members.iter().find(|member| {
+      member.name == name || member.name.strip_prefix(self.name.as_str()).and_then(|rest| rest.strip_prefix('.')) == Some(name)
+    })
+  }
+}
+
+// Everything a linked program reports as active, looked up by name instead of asking GL every draw.
+// Arrays are keyed without their `[0]` suffix
+#[derive(Debug, Default)]
+pub struct ProgramInterface {
+  uniforms   : HashMap<String, UniformInfo>,
+  attributes : Vec<AttributeInfo>,
+  blocks     : HashMap<String, UniformBlockInfo>,
+}
+
+impl ProgramInterface {
+
+  /// # Safety
+  /// Requires the program's GL context to be current
+  pub unsafe fn introspect(program: GLuint) -> Self {
+    let mut interface = ProgramInterface::default();
+
+    let read_name = |buffer: &[u8], length: GLsizei| String::from_utf8_lossy(&buffer[..length.max(0) as usize]).into_owned();
+    let program_parameter = |parameter: GLenum| {
+      let mut value = 0;
+      gl::GetProgramiv(program, parameter, &mut value);
+      value
+    };
+
+    let mut name_buffer = vec![0u8; program_parameter(gl::ACTIVE_UNIFORM_MAX_LENGTH).max(1) as usize];
+    let mut block_members: Vec<(GLint, UniformBlockMember)> = Vec::new();
+    for index in 0..program_parameter(gl::ACTIVE_UNIFORMS) as GLuint {
+      let (mut length, mut size, mut gl_type) = (0, 0, 0);
+      gl::GetActiveUniform(program, index, name_buffer.len() as GLsizei, &mut length, &mut size, &mut gl_type, name_buffer.as_mut_ptr() as *mut GLchar);
+      let name = read_name(&name_buffer, length);
+
+      let uniform_parameter = |parameter: GLenum| {
+        let mut value = 0;
+        gl::GetActiveUniformsiv(program, 1, &index, parameter, &mut value);
+        value
+      };
+      let block_index = uniform_parameter(gl::UNIFORM_BLOCK_INDEX);
+      if block_index >= 0 {
+        block_members.push((block_index, UniformBlockMember {
+          name,
+          uniform_type  : UniformType::from_gl(gl_type),
+          size,
+          offset        : uniform_parameter(gl::UNIFORM_OFFSET),
+          array_stride  : uniform_parameter(gl::UNIFORM_ARRAY_STRIDE),
+          matrix_stride : uniform_parameter(gl::UNIFORM_MATRIX_STRIDE),
+        }));
+        continue;
+      }
+
+      let c_name = CString::new(name.as_str()).unwrap();
+      let location = gl::GetUniformLocation(program, c_name.as_ptr());
+      let name = name.strip_suffix("[0]").map(str::to_string).unwrap_or(name);
+      interface.uniforms.insert(name.clone(), UniformInfo { name, location, uniform_type: UniformType::from_gl(gl_type), size });
+    }
+
+    let mut name_buffer = vec![0u8; program_parameter(gl::ACTIVE_UNIFORM_BLOCK_MAX_NAME_LENGTH).max(1) as usize];
+    for index in 0..program_parameter(gl::ACTIVE_UNIFORM_BLOCKS) as GLuint {
+      let mut length = 0;
+      gl::GetActiveUniformBlockName(program, index, name_buffer.len() as GLsizei, &mut length, name_buffer.as_mut_ptr() as *mut GLchar);
+      let block_parameter = |parameter: GLenum| {
+        let mut value = 0;
+        gl::GetActiveUniformBlockiv(program, index, parameter, &mut value);
+        value
+      };
+      let name = read_name(&name_buffer, length);
+      let mut members: Vec<UniformBlockMember> = block_members.iter()
+        .filter(|(block_index, _)| *block_index == index as GLint)
+        .map(|(_, member)| member.clone())
+        .collect();
+      members.sort_by_key(|member| member.offset);
+      interface.blocks.insert(name.clone(), UniformBlockInfo {
+        name,
+        index,
+        data_size : block_parameter(gl::UNIFORM_BLOCK_DATA_SIZE),
+        binding   : block_parameter(gl::UNIFORM_BLOCK_BINDING) as GLuint,
+        members,
+      });
+    }
+
+    let mut name_buffer = vec![0u8; program_parameter(gl::ACTIVE_ATTRIBUTE_MAX_LENGTH).max(1) as usize];
+    for index in 0..program_parameter(gl::ACTIVE_ATTRIBUTES) as GLuint {
+      let (mut length, mut size, mut gl_type) = (0, 0, 0);
+      gl::GetActiveAttrib(program, index, name_buffer.len() as GLsizei, &mut length, &mut size, &mut gl_type, name_buffer.as_mut_ptr() as *mut GLchar);
+      let name = read_name(&name_buffer, length);
+      let c_name = CString::new(name.as_str()).unwrap();
+      let location = gl::GetAttribLocation(program, c_name.as_ptr());
+      interface.attributes.push(AttributeInfo { name, location, attribute_type: UniformType::from_gl(gl_type), size });
+    }
+    interface.attributes.sort_by_key(|attribute| attribute.location);
+
+    interface
+  }
+
+  pub fn uniform(&self, name: &str) -> Option<&UniformInfo> {
+    self.uniforms.get(name)
+  }
+
+  pub fn uniforms(&self) -> impl Iterator<Item = &UniformInfo> {
+    self.uniforms.values()
+  }
+
+  pub fn attributes(&self) -> &[AttributeInfo] {
+    &self.attributes
+  }
+
+  pub fn block(&self, name: &str) -> Option<&UniformBlockInfo> {
+    self.blocks.get(name)
+  }
+
+  pub fn blocks(&self) -> impl Iterator<Item = &UniformBlockInfo> {
+    self.blocks.values()
+  }
+
+  pub(crate) fn set_block_binding(&mut self, name: &str, binding: GLuint) {
+    if let Some(block) = self.blocks.get_mut(name) {
+      block.binding = binding;
+    }
+  }
+
+  // Checks `name` is one of `accepted` with room for `count` elements, returns its location
+  pub(crate) fn locate(&self, name: &str, accepted: &[UniformType], count: usize) -> Result<GLint, UniformError> {
+    let info = self.uniform(name).ok_or_else(|| UniformError::NotFound(name.to_string()))?;
+    if !accepted.contains(&info.uniform_type) {
+      return Err(UniformError::TypeMismatch { name: name.to_string(), expected: accepted[0], actual: info.uniform_type });
+    }
+    if count as i32 > info.size {
+      return Err(UniformError::TooManyElements { name: name.to_string(), size: info.size, provided: count });
+    }
+    Ok(info.location)
+  }
+}
+
+// Buffer backing a uniform block, shared by every program whose block is bound to the same binding point
+pub struct UniformBuffer {
+  id   : GLuint,
+  size : usize,
+}
+
+impl UniformBuffer {
+  pub fn new(size: usize) -> Self {
+    let mut id = 0;
+    unsafe {
+      gl::GenBuffers(1, &mut id);
+      gl::BindBuffer(gl::UNIFORM_BUFFER, id);
+      gl::BufferData(gl::UNIFORM_BUFFER, size as GLsizeiptr, ptr::null(), gl::DYNAMIC_DRAW);
+      gl::BindBuffer(gl::UNIFORM_BUFFER, 0);
+    }
+    UniformBuffer { id, size }
+  }
+
+  // Sized for `block`, write its members at the offsets the block reports
+  pub fn for_block(block: &UniformBlockInfo) -> Self {
+    UniformBuffer::new(block.data_size as usize)
+  }
+
+  pub fn write(&self, offset: usize, data: &[u8]) {
+    assert!(offset + data.len() <= self.size, "Write of {} bytes at {} overflows a {} byte uniform buffer", data.len(), offset, self.size);
+    unsafe {
+      gl::BindBuffer(gl::UNIFORM_BUFFER, self.id);
+      gl::BufferSubData(gl::UNIFORM_BUFFER, offset as isize, data.len() as GLsizeiptr, data.as_ptr() as *const GLvoid);
+      gl::BindBuffer(gl::UNIFORM_BUFFER, 0);
+    }
+  }
+
+  pub fn bind(&self, binding: GLuint) {
+    unsafe {
+      gl::BindBufferBase(gl::UNIFORM_BUFFER, binding, self.id);
+    }
+  }
+
+  pub fn size(&self) -> usize {
+    self.size
+  }
+}
+
+impl Drop for UniformBuffer {
+  fn drop(&mut self) {
+    unsafe {
+      gl::DeleteBuffers(1, &self.id);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn uniform(name: &str, location: GLint, uniform_type: UniformType, size: i32) -> (String, UniformInfo) {
+    (name.to_string(), UniformInfo { name: name.to_string(), location, uniform_type, size })
+  }
+
+  fn member(name: &str, uniform_type: UniformType, offset: i32) -> UniformBlockMember {
+    UniformBlockMember { name: name.to_string(), uniform_type, size: 1, offset, array_stride: 0, matrix_stride: 16 }
+  }
+
+  fn interface() -> ProgramInterface {
+    let uniforms = HashMap::from([
+      uniform("model", 0, UniformType::Mat4, 1),
+      uniform("lightPositions", 4, UniformType::Vec4, 8),
+      uniform("useTexture", 12, UniformType::Bool, 1),
+      uniform("shadowMap", 13, UniformType::Sampler2DShadow, 1),
+    ]);
+    let block = UniformBlockInfo {
+      name      : "Camera".to_string(),
+      index     : 0,
+      data_size : 144,
+      binding   : 0,
+      members   : vec![member("Camera.view", UniformType::Mat4, 0), member("Camera.projection", UniformType::Mat4, 64), member("exposure", UniformType::Float, 128)],
+    };
+    ProgramInterface { uniforms, attributes: Vec::new(), blocks: HashMap::from([(block.name.clone(), block)]) }
+  }
+
+  #[test]
+  fn locates_uniforms_of_an_accepted_type() {
+    let interface = interface();
+    assert_eq!(interface.locate("model", &[UniformType::Mat4], 1).unwrap(), 0);
+    assert_eq!(interface.locate("lightPositions", &[UniformType::Vec4], 3).unwrap(), 4);
+    assert_eq!(interface.locate("useTexture", &[UniformType::Int, UniformType::Bool], 1).unwrap(), 12);
+    assert_eq!(interface.locate("shadowMap", &SAMPLER_TYPES, 1).unwrap(), 13);
+  }
+
+  #[test]
+  fn rejects_unknown_names_types_and_overlong_arrays() {
+    let interface = interface();
+    assert!(matches!(interface.locate("modl", &[UniformType::Mat4], 1), Err(UniformError::NotFound(_))));
+    assert!(matches!(
+      interface.locate("model", &[UniformType::Mat3], 1),
+      Err(UniformError::TypeMismatch { expected: UniformType::Mat3, actual: UniformType::Mat4, .. })
+    ));
+    assert!(matches!(
+      interface.locate("lightPositions", &[UniformType::Vec4], 9),
+      Err(UniformError::TooManyElements { size: 8, provided: 9, .. })
+    ));
+  }
+
+  #[test]
+  fn finds_block_members_with_or_without_the_block_name() {
+    let interface = interface();
+    let block = interface.block("Camera").unwrap();
+    assert_eq!(block.member("projection").unwrap().offset, 64);
+    assert_eq!(block.member("Camera.view").unwrap().offset, 0);
+    assert_eq!(block.member("exposure").unwrap().uniform_type, UniformType::Float);
+    assert!(block.member("Camera.exposure").is_none());
+    assert!(interface.block("Lights").is_none());
+  }
+
+  #[test]
+  fn updates_block_bindings() {
+    let mut interface = interface();
+    interface.set_block_binding("Camera", 3);
+    interface.set_block_binding("Lights", 4);
+    assert_eq!(interface.blocks().map(|block| (block.name.as_str(), block.binding)).collect::<Vec<_>>(), vec![("Camera", 3)]);
+  }
+
+  #[test]
+  fn maps_gl_types() {
+    assert_eq!(UniformType::from_gl(gl::FLOAT_MAT4), UniformType::Mat4);
+    assert_eq!(UniformType::from_gl(gl::SAMPLER_2D_SHADOW), UniformType::Sampler2DShadow);
+    assert_eq!(UniformType::from_gl(gl::DOUBLE).to_string(), format!("GL type 0x{:04X}", gl::DOUBLE));
+  }
+}
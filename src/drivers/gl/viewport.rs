@@ -1,5 +1,6 @@
 use nalgebra::{Matrix4, Point3, Vector3};
 
+#[derive(Clone, Debug)]
 pub struct Viewport {
   pub position : Point3<f32>,
   pub target   : Point3<f32>,
@@ -1,2 +1,4 @@
 pub mod vulkan;
-pub mod gl;
\ No newline at end of file
+pub mod gl;
+pub mod renderer;
+pub mod software;
\ No newline at end of file
@@ -0,0 +1,281 @@
+use std::{env, error::Error, fmt, str::FromStr};
+use image::RgbaImage;
+use nalgebra::{Matrix4, Point3, Vector3};
+
+use crate::cli;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+  OpenGl,
+  Vulkan,
+  Software,
+}
+
+pub const BACKEND_ENV_VAR: &str = "RENDERER_BACKEND";
+
+impl Backend {
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      Backend::OpenGl => "gl",
+      Backend::Vulkan => "vulkan",
+      Backend::Software => "software",
+    }
+  }
+
+  // `--backend <name>` / `--backend=<name>` takes precedence over the RENDERER_BACKEND env var
+  pub fn from_args_and_env() -> Result<Self, RendererError> {
+    let args: Vec<String> = env::args().collect();
+    let from_args = cli::arg_value(&args, "--backend").map(str::to_string);
+
+    match from_args.or_else(|| env::var(BACKEND_ENV_VAR).ok()) {
+      Some(name) => name.parse(),
+      None => Ok(Backend::OpenGl),
+    }
+  }
+}
+
+impl FromStr for Backend {
+  type Err = RendererError;
+
+  fn from_str(name: &str) -> Result<Self, Self::Err> {
+    match name.to_ascii_lowercase().as_str() {
+      "gl" | "opengl" => Ok(Backend::OpenGl),
+      "vk" | "vulkan" => Ok(Backend::Vulkan),
+      "sw" | "software" | "cpu" => Ok(Backend::Software),
+      _ => Err(RendererError::Backend(format!("Unknown renderer backend '{}'", name))),
+    }
+  }
+}
+
+#[derive(Debug)]
+pub enum RendererError {
+  Backend(String),
+  Unsupported(&'static str),
+  InvalidHandle(&'static str),
+}
+
+impl fmt::Display for RendererError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      RendererError::Backend(message)  => write!(f, "{}", message),
+      RendererError::Unsupported(what) => write!(f, "Not supported by this backend: {}", what),
+      RendererError::InvalidHandle(kind) => write!(f, "Invalid {} handle", kind),
+    }
+  }
+}
+
+impl Error for RendererError {}
+
+impl From<ash::vk::Result> for RendererError {
+  fn from(result: ash::vk::Result) -> Self {
+    RendererError::Backend(format!("Vulkan error: {:?}", result))
+  }
+}
+
+// Handles are indices into backend owned resource tables
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BufferId(pub usize);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TextureId(pub usize);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ShaderId(pub usize);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PipelineId(pub usize);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BufferUsage {
+  Vertex,
+  Index,
+}
+
+// All attributes are tightly packed f32 components
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct VertexAttribute {
+  pub location   : u32,
+  pub components : u32,
+  pub offset     : u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct VertexLayout {
+  pub stride     : u32,
+  pub attributes : Vec<VertexAttribute>,
+}
+
+impl VertexLayout {
+  // Layout emitted by the OBJ and glTF loaders: position (0), tex coord (1), normal (2)
+  pub fn position_tex_normal() -> Self {
+    let float = std::mem::size_of::<f32>() as u32;
+    VertexLayout {
+      stride     : 8 * float,
+      attributes : vec![
+        VertexAttribute { location: 0, components: 3, offset: 0 },
+        VertexAttribute { location: 1, components: 2, offset: 3 * float },
+        VertexAttribute { location: 2, components: 3, offset: 5 * float },
+      ],
+    }
+  }
+}
+
+#[derive(Clone, Debug)]
+pub enum ShaderSource {
+  Glsl { vertex: String, fragment: String },
+  SpirvFiles { vertex_path: String, fragment_path: String },
+  // GLSL or WGSL source files compiled by the backend, `defines` are set for both stages
+  Files { vertex_path: String, fragment_path: String, defines: Vec<(String, String)> },
+}
+
+#[derive(Clone, Debug)]
+pub struct PipelineDesc {
+  pub shader        : ShaderId,
+  pub vertex_layout : VertexLayout,
+  pub depth_test    : bool,
+  pub cull_back     : bool,
+}
+
+pub const MAX_LIGHTS: usize = 4;
+
+// Lights shaded per vertex with a diffuse term by every backend. Draws without lights are unlit
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Lighting {
+  pub count     : u32,
+  pub positions : [[f32; 4]; MAX_LIGHTS], // World space. w = 0: the direction light travels in, w = 1: a position
+  pub colors    : [[f32; 4]; MAX_LIGHTS], // Color times intensity, w = range or 0 when unbounded
+}
+
+pub const AMBIENT_LIGHT: f32 = 0.1;
+
+impl Lighting {
+
+  // CPU version of `shade` in the GL vertex shader and shaders/include/lighting.glsl, keep them in sync
+  pub fn shade(&self, position: &Point3<f32>, normal: &Vector3<f32>) -> Vector3<f32> {
+    let normal = normal.try_normalize(f32::EPSILON);
+    let (normal, count) = match normal {
+      Some(normal) if self.count > 0 => (normal, (self.count as usize).min(MAX_LIGHTS)),
+      _ => return Vector3::new(1.0, 1.0, 1.0),
+    };
+
+    let mut shade = Vector3::repeat(AMBIENT_LIGHT);
+    for (position_or_direction, color) in self.positions.iter().zip(&self.colors).take(count) {
+      let [x, y, z, w] = *position_or_direction;
+      let (to_light, attenuation) = match w == 0.0 {
+        true  => (-Vector3::new(x, y, z), 1.0),
+        false => {
+          let to_light = Point3::new(x, y, z) - position;
+          let distance = to_light.norm();
+          let window = match color[3] > 0.0 {
+            true  => (1.0 - distance / color[3]).clamp(0.0, 1.0),
+            false => 1.0,
+          };
+          (to_light, window / (1.0 + distance * distance))
+        }
+      };
+      let diffuse = to_light.try_normalize(f32::EPSILON).map_or(0.0, |to_light| normal.dot(&to_light).max(0.0));
+      shade += Vector3::new(color[0], color[1], color[2]) * diffuse * attenuation;
+    }
+    shade
+  }
+}
+
+pub struct DrawCall<'a> {
+  pub pipeline      : PipelineId,
+  pub vertex_buffer : BufferId,
+  pub index_buffer  : Option<BufferId>,
+  pub element_count : u32,
+  pub texture       : Option<TextureId>,
+  pub model         : &'a Matrix4<f32>,
+  pub view          : &'a Matrix4<f32>,
+  pub projection    : &'a Matrix4<f32>,
+  pub lighting      : &'a Lighting,
+}
+
+pub trait Device {
+  fn create_buffer(&mut self, usage: BufferUsage, data: &[u8]) -> Result<BufferId, RendererError>;
+
+  fn create_texture(&mut self, image: &RgbaImage) -> Result<TextureId, RendererError>;
+
+  // Backends pick the first source they can consume
+  fn create_shader(&mut self, sources: &[ShaderSource]) -> Result<ShaderId, RendererError>;
+
+  fn create_pipeline(&mut self, desc: &PipelineDesc) -> Result<PipelineId, RendererError>;
+}
+
+pub trait Renderer: Device {
+  fn begin_frame(&mut self, clear_color: [f32; 4]) -> Result<(), RendererError>;
+
+  fn draw(&mut self, draw: &DrawCall) -> Result<(), RendererError>;
+
+  fn end_frame(&mut self) -> Result<(), RendererError>;
+
+  fn extent(&self) -> (u32, u32);
+
+  fn aspect_ratio(&self) -> f32 {
+    let (width, height) = self.extent();
+    width as f32 / height.max(1) as f32
+  }
+}
+
+pub trait Application {
+  fn init(&mut self, renderer: &mut dyn Renderer) -> Result<(), RendererError>;
+
+  fn frame(&mut self, renderer: &mut dyn Renderer, delta_time: f32) -> Result<(), RendererError>;
+}
+
+/// Plain data that can be viewed as raw bytes for buffer uploads.
+///
+/// # Safety
+/// Implementors must have no padding bytes, so every byte of a value is initialized
+pub unsafe trait Pod: Copy {}
+
+unsafe impl Pod for u8 {}
+unsafe impl Pod for u16 {}
+unsafe impl Pod for u32 {}
+unsafe impl Pod for f32 {}
+unsafe impl<T: Pod, const N: usize> Pod for [T; N] {}
+
+pub fn as_bytes<T: Pod>(data: &[T]) -> &[u8] {
+  unsafe { std::slice::from_raw_parts(data.as_ptr() as *const u8, std::mem::size_of_val(data)) }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn unlit_without_lights() {
+    let shade = Lighting::default().shade(&Point3::origin(), &Vector3::z());
+    assert_eq!(shade, Vector3::new(1.0, 1.0, 1.0));
+  }
+
+  #[test]
+  fn directional_light_follows_the_cosine_law() {
+    let mut lighting = Lighting { count: 1, ..Lighting::default() };
+    lighting.positions[0] = [0.0, -1.0, 0.0, 0.0];
+    lighting.colors[0] = [1.0, 1.0, 1.0, 0.0];
+
+    let facing = lighting.shade(&Point3::origin(), &Vector3::y());
+    assert!((facing.x - (1.0 + AMBIENT_LIGHT)).abs() < 1e-6);
+    let tilted = lighting.shade(&Point3::origin(), &Vector3::new(1.0, 1.0, 0.0));
+    assert!((tilted.x - (std::f32::consts::FRAC_1_SQRT_2 + AMBIENT_LIGHT)).abs() < 1e-6);
+    let away = lighting.shade(&Point3::origin(), &-Vector3::y());
+    assert!((away.x - AMBIENT_LIGHT).abs() < 1e-6);
+  }
+
+  #[test]
+  fn point_lights_fade_with_distance_and_range() {
+    let mut lighting = Lighting { count: 1, ..Lighting::default() };
+    lighting.positions[0] = [0.0, 2.0, 0.0, 1.0];
+    lighting.colors[0] = [5.0, 5.0, 5.0, 0.0];
+    // 1 / (1 + 2^2) of the color reaches a surface facing the light
+    let shade = lighting.shade(&Point3::origin(), &Vector3::y());
+    assert!((shade.x - (1.0 + AMBIENT_LIGHT)).abs() < 1e-6);
+
+    lighting.colors[0][3] = 2.0;
+    let shade = lighting.shade(&Point3::origin(), &Vector3::y());
+    assert!((shade.x - AMBIENT_LIGHT).abs() < 1e-6);
+  }
+}
@@ -0,0 +1,164 @@
+use image::RgbaImage;
+use nalgebra::{Matrix3, Point3, Vector2, Vector3, Vector4};
+
+use crate::drivers::renderer::{
+  Application, BufferId, BufferUsage, Device, DrawCall, PipelineDesc, PipelineId, Renderer, RendererError, ShaderId, ShaderSource, TextureId, VertexLayout
+};
+use super::rasterizer::{draw_triangle, ClipVertex, FrameBuffer, RasterState};
+use super::texture::Sampler;
+
+struct SoftwareBuffer {
+  usage : BufferUsage,
+  data  : Vec<u8>,
+}
+
+// Pure CPU implementation of the renderer traits. Shading is fixed: every fragment takes the
+// bound texture's color at the interpolated tex coord times the per-vertex `Lighting::shade`,
+// which is what the bundled GLSL shaders do
+pub struct SoftwareDevice {
+  buffers      : Vec<SoftwareBuffer>,
+  textures     : Vec<RgbaImage>,
+  shader_count : usize,
+  pipelines    : Vec<PipelineDesc>,
+  framebuffer  : FrameBuffer,
+}
+
+impl SoftwareDevice {
+
+  pub fn new(width: u32, height: u32) -> Self {
+    SoftwareDevice {
+      buffers      : Vec::new(),
+      textures     : Vec::new(),
+      shader_count : 0,
+      pipelines    : Vec::new(),
+      framebuffer  : FrameBuffer::new(width, height),
+    }
+  }
+
+  pub fn set_extent(&mut self, width: u32, height: u32) {
+    self.framebuffer = FrameBuffer::new(width, height);
+  }
+
+  pub fn image(&self) -> &RgbaImage {
+    &self.framebuffer.color
+  }
+
+  fn read_f32(data: &[u8], offset: usize) -> Option<f32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(f32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+  }
+
+  fn read_u32(data: &[u8], index: usize) -> Option<u32> {
+    let bytes = data.get(index * 4..index * 4 + 4)?;
+    Some(u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+  }
+
+  // Missing attributes read as zero, like a disabled GL vertex attribute
+  fn fetch_attribute<const N: usize>(data: &[u8], layout: &VertexLayout, location: u32, vertex: usize) -> Option<[f32; N]> {
+    let mut value = [0.0; N];
+    if let Some(attribute) = layout.attributes.iter().find(|attribute| attribute.location == location) {
+      let base = vertex * layout.stride as usize + attribute.offset as usize;
+      for (component, slot) in value.iter_mut().enumerate().take(attribute.components as usize) {
+        *slot = SoftwareDevice::read_f32(data, base + component * 4)?;
+      }
+    }
+    Some(value)
+  }
+}
+
+impl Device for SoftwareDevice {
+
+  fn create_buffer(&mut self, usage: BufferUsage, data: &[u8]) -> Result<BufferId, RendererError> {
+    self.buffers.push(SoftwareBuffer { usage, data: data.to_vec() });
+    Ok(BufferId(self.buffers.len() - 1))
+  }
+
+  fn create_texture(&mut self, image: &RgbaImage) -> Result<TextureId, RendererError> {
+    self.textures.push(image.clone());
+    Ok(TextureId(self.textures.len() - 1))
+  }
+
+  fn create_shader(&mut self, _sources: &[ShaderSource]) -> Result<ShaderId, RendererError> {
+    self.shader_count += 1;
+    Ok(ShaderId(self.shader_count - 1))
+  }
+
+  fn create_pipeline(&mut self, desc: &PipelineDesc) -> Result<PipelineId, RendererError> {
+    if desc.shader.0 >= self.shader_count {
+      return Err(RendererError::InvalidHandle("shader"));
+    }
+    self.pipelines.push(desc.clone());
+    Ok(PipelineId(self.pipelines.len() - 1))
+  }
+}
+
+impl Renderer for SoftwareDevice {
+
+  fn begin_frame(&mut self, clear_color: [f32; 4]) -> Result<(), RendererError> {
+    self.framebuffer.clear(clear_color);
+    Ok(())
+  }
+
+  fn draw(&mut self, draw: &DrawCall) -> Result<(), RendererError> {
+    let pipeline = self.pipelines.get(draw.pipeline.0).ok_or(RendererError::InvalidHandle("pipeline"))?;
+    let vertex_buffer = self.buffers.get(draw.vertex_buffer.0)
+      .filter(|buffer| buffer.usage == BufferUsage::Vertex)
+      .ok_or(RendererError::InvalidHandle("buffer"))?;
+    let index_buffer = match draw.index_buffer {
+      Some(id) => Some(self.buffers.get(id.0)
+        .filter(|buffer| buffer.usage == BufferUsage::Index)
+        .ok_or(RendererError::InvalidHandle("buffer"))?),
+      None => None,
+    };
+    let texture = match draw.texture {
+      Some(id) => Some(self.textures.get(id.0).ok_or(RendererError::InvalidHandle("texture"))?),
+      None => None,
+    };
+
+    let model_view_projection = draw.projection * draw.view * draw.model;
+    // Normals go through the inverse transpose so non-uniform scales keep them perpendicular
+    let normal_matrix = draw.model.fixed_slice::<3, 3>(0, 0).into_owned().try_inverse().map_or(Matrix3::identity(), |inverse| inverse.transpose());
+    let layout = &pipeline.vertex_layout;
+    let out_of_range = || RendererError::Backend("Draw reads past the end of a buffer".to_string());
+
+    let vertex = |element: u32| -> Result<ClipVertex, RendererError> {
+      let index = match index_buffer {
+        Some(buffer) => SoftwareDevice::read_u32(&buffer.data, element as usize).ok_or_else(out_of_range)? as usize,
+        None => element as usize,
+      };
+      let [x, y, z] = SoftwareDevice::fetch_attribute::<3>(&vertex_buffer.data, layout, 0, index).ok_or_else(out_of_range)?;
+      let [u, v] = SoftwareDevice::fetch_attribute::<2>(&vertex_buffer.data, layout, 1, index).ok_or_else(out_of_range)?;
+      let normal = SoftwareDevice::fetch_attribute::<3>(&vertex_buffer.data, layout, 2, index).ok_or_else(out_of_range)?;
+      let world_position = draw.model.transform_point(&Point3::new(x, y, z));
+      Ok(ClipVertex {
+        position  : model_view_projection * Vector4::new(x, y, z, 1.0),
+        tex_coord : Vector2::new(u, v),
+        shade     : draw.lighting.shade(&world_position, &(normal_matrix * Vector3::from(normal))),
+      })
+    };
+
+    let sampler = Sampler::new(texture);
+    let state = RasterState { depth_test: pipeline.depth_test, cull_back: pipeline.cull_back };
+    for first in (0..draw.element_count - draw.element_count % 3).step_by(3) {
+      let triangle = [vertex(first)?, vertex(first + 1)?, vertex(first + 2)?];
+      draw_triangle(&mut self.framebuffer, triangle, &sampler, state);
+    }
+    Ok(())
+  }
+
+  fn end_frame(&mut self) -> Result<(), RendererError> {
+    Ok(())
+  }
+
+  fn extent(&self) -> (u32, u32) {
+    (self.framebuffer.width(), self.framebuffer.height())
+  }
+}
+
+// Runs one init + frame of `app` on the CPU and returns the rendered pixels
+pub fn render_offscreen(app: &mut dyn Application, width: u32, height: u32) -> Result<RgbaImage, RendererError> {
+  let mut device = SoftwareDevice::new(width, height);
+  app.init(&mut device)?;
+  app.frame(&mut device, 0.0)?;
+  Ok(device.image().clone())
+}
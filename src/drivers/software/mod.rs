@@ -0,0 +1,3 @@
+pub mod device;
+pub mod rasterizer;
+pub mod texture;
@@ -0,0 +1,189 @@
+use image::{Rgba, RgbaImage};
+use nalgebra::{Vector2, Vector3, Vector4};
+
+use super::texture::Sampler;
+
+#[derive(Clone, Copy, Debug)]
+pub struct ClipVertex {
+  pub position  : Vector4<f32>, // Clip space, before the perspective divide
+  pub tex_coord : Vector2<f32>,
+  pub shade     : Vector3<f32>, // Light reaching the vertex, multiplies the texture color
+}
+
+impl ClipVertex {
+  fn lerp(&self, other: &ClipVertex, t: f32) -> ClipVertex {
+    ClipVertex {
+      position  : self.position.lerp(&other.position, t),
+      tex_coord : self.tex_coord.lerp(&other.tex_coord, t),
+      shade     : self.shade.lerp(&other.shade, t),
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RasterState {
+  pub depth_test : bool,
+  pub cull_back  : bool,
+}
+
+// Color and depth planes, row 0 is the top of the image
+pub struct FrameBuffer {
+  pub color : RgbaImage,
+  depth     : Vec<f32>,
+}
+
+impl FrameBuffer {
+
+  pub fn new(width: u32, height: u32) -> Self {
+    FrameBuffer {
+      color : RgbaImage::new(width, height),
+      depth : vec![1.0; (width * height) as usize],
+    }
+  }
+
+  pub fn clear(&mut self, color: [f32; 4]) {
+    let pixel = Rgba(color.map(|channel| (channel.clamp(0.0, 1.0) * 255.0).round() as u8));
+    self.color.pixels_mut().for_each(|target| *target = pixel);
+    self.depth.fill(1.0);
+  }
+
+  pub fn width(&self) -> u32 {
+    self.color.width()
+  }
+
+  pub fn height(&self) -> u32 {
+    self.color.height()
+  }
+}
+
+// Vertex after the perspective divide and viewport transform
+#[derive(Clone, Copy)]
+struct ScreenVertex {
+  x            : f32,
+  y            : f32,
+  depth        : f32,
+  inverse_w    : f32,
+  tex_over_w   : Vector2<f32>,
+  shade_over_w : Vector3<f32>,
+}
+
+pub fn draw_triangle(target: &mut FrameBuffer, triangle: [ClipVertex; 3], sampler: &Sampler, state: RasterState) {
+  let clipped = clip_near(&triangle);
+  if clipped.len() < 3 {
+    return;
+  }
+
+  let screen: Vec<ScreenVertex> = clipped.iter().map(|vertex| to_screen(vertex, target.width(), target.height())).collect();
+  for index in 1..screen.len() - 1 {
+    rasterize(target, [screen[0], screen[index], screen[index + 1]], sampler, state);
+  }
+}
+
+// Sutherland-Hodgman against the near plane (z >= -w in GL clip space), the other planes are handled by the screen bounds
+fn clip_near(triangle: &[ClipVertex; 3]) -> Vec<ClipVertex> {
+  let distance = |vertex: &ClipVertex| vertex.position.z + vertex.position.w;
+  let mut output = Vec::with_capacity(4);
+
+  for index in 0..3 {
+    let current = &triangle[index];
+    let next = &triangle[(index + 1) % 3];
+    let (current_distance, next_distance) = (distance(current), distance(next));
+
+    if current_distance >= 0.0 {
+      output.push(*current);
+    }
+    if (current_distance >= 0.0) != (next_distance >= 0.0) {
+      let t = current_distance / (current_distance - next_distance);
+      output.push(current.lerp(next, t));
+    }
+  }
+  output
+}
+
+fn to_screen(vertex: &ClipVertex, width: u32, height: u32) -> ScreenVertex {
+  let inverse_w = 1.0 / vertex.position.w;
+  let ndc = vertex.position.xyz() * inverse_w;
+  ScreenVertex {
+    x            : (ndc.x + 1.0) * 0.5 * width as f32,
+    y            : (1.0 - ndc.y) * 0.5 * height as f32,
+    depth        : ndc.z * 0.5 + 0.5,
+    inverse_w,
+    tex_over_w   : vertex.tex_coord * inverse_w,
+    shade_over_w : vertex.shade * inverse_w,
+  }
+}
+
+fn edge(a: &ScreenVertex, b: &ScreenVertex, x: f32, y: f32) -> f32 {
+  (b.x - a.x) * (y - a.y) - (b.y - a.y) * (x - a.x)
+}
+
+// Top-left fill rule for a clockwise (in screen space) triangle, so shared edges are drawn exactly once
+fn is_top_left(a: &ScreenVertex, b: &ScreenVertex) -> bool {
+  let (dx, dy) = (b.x - a.x, b.y - a.y);
+  (dy == 0.0 && dx > 0.0) || dy < 0.0
+}
+
+fn rasterize(target: &mut FrameBuffer, triangle: [ScreenVertex; 3], sampler: &Sampler, state: RasterState) {
+  let [v0, mut v1, mut v2] = triangle;
+  let area = edge(&v0, &v1, v2.x, v2.y);
+  if area == 0.0 || !area.is_finite() {
+    return;
+  }
+
+  // Counter-clockwise in NDC is front facing, the y flip makes it negative area on screen
+  let front_facing = area < 0.0;
+  if state.cull_back && !front_facing {
+    return;
+  }
+  if front_facing {
+    std::mem::swap(&mut v1, &mut v2);
+  }
+  let area = area.abs();
+
+  let min_x = v0.x.min(v1.x).min(v2.x).floor().max(0.0) as u32;
+  let min_y = v0.y.min(v1.y).min(v2.y).floor().max(0.0) as u32;
+  let max_x = (v0.x.max(v1.x).max(v2.x).ceil().max(0.0) as u32).min(target.width());
+  let max_y = (v0.y.max(v1.y).max(v2.y).ceil().max(0.0) as u32).min(target.height());
+
+  let edges = [(v1, v2), (v2, v0), (v0, v1)];
+  let top_left = edges.map(|(a, b)| is_top_left(&a, &b));
+
+  for y in min_y..max_y {
+    for x in min_x..max_x {
+      let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+
+      let weights = [
+        edge(&v1, &v2, px, py),
+        edge(&v2, &v0, px, py),
+        edge(&v0, &v1, px, py),
+      ];
+      let inside = weights.iter().zip(top_left.iter())
+        .all(|(&weight, &top_left)| weight > 0.0 || (weight == 0.0 && top_left));
+      if !inside {
+        continue;
+      }
+
+      let [b0, b1, b2] = weights.map(|weight| weight / area);
+      let depth = b0 * v0.depth + b1 * v1.depth + b2 * v2.depth;
+      if !(0.0..=1.0).contains(&depth) {
+        continue;
+      }
+
+      let index = (y * target.width() + x) as usize;
+      if state.depth_test {
+        if depth >= target.depth[index] {
+          continue;
+        }
+        target.depth[index] = depth;
+      }
+
+      // Attributes are interpolated as attribute / w, then divided by the interpolated 1 / w
+      let inverse_w = b0 * v0.inverse_w + b1 * v1.inverse_w + b2 * v2.inverse_w;
+      let tex_coord = (v0.tex_over_w * b0 + v1.tex_over_w * b1 + v2.tex_over_w * b2) / inverse_w;
+      let shade = (v0.shade_over_w * b0 + v1.shade_over_w * b1 + v2.shade_over_w * b2) / inverse_w;
+      let Rgba([r, g, b, a]) = sampler.sample(tex_coord);
+      let lit = |channel: u8, light: f32| (channel as f32 * light).round().clamp(0.0, 255.0) as u8;
+      target.color.put_pixel(x, y, Rgba([lit(r, shade.x), lit(g, shade.y), lit(b, shade.z), a]));
+    }
+  }
+}
@@ -0,0 +1,49 @@
+use image::{Rgba, RgbaImage};
+use nalgebra::Vector2;
+
+// What an unbound GL sampler returns, keeps untextured draws identical across backends
+const UNBOUND_COLOR: Rgba<u8> = Rgba([0, 0, 0, 255]);
+
+// Bilinear filtering with GL_REPEAT wrapping, (0, 0) addresses the first row of the image like an unflipped GL upload
+pub struct Sampler<'a> {
+  image : Option<&'a RgbaImage>,
+}
+
+impl<'a> Sampler<'a> {
+
+  pub fn new(image: Option<&'a RgbaImage>) -> Self {
+    Sampler { image }
+  }
+
+  pub fn sample(&self, tex_coord: Vector2<f32>) -> Rgba<u8> {
+    let image = match self.image {
+      Some(image) if image.width() > 0 && image.height() > 0 => image,
+      _ => return UNBOUND_COLOR,
+    };
+
+    // Texel centers sit at half-integer coordinates
+    let x = tex_coord.x * image.width() as f32 - 0.5;
+    let y = tex_coord.y * image.height() as f32 - 0.5;
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+
+    let texel = |x: f32, y: f32| -> [f32; 4] {
+      let x = (x as i64).rem_euclid(image.width() as i64) as u32;
+      let y = (y as i64).rem_euclid(image.height() as i64) as u32;
+      image.get_pixel(x, y).0.map(|channel| channel as f32)
+    };
+
+    let top_left = texel(x0, y0);
+    let top_right = texel(x0 + 1.0, y0);
+    let bottom_left = texel(x0, y0 + 1.0);
+    let bottom_right = texel(x0 + 1.0, y0 + 1.0);
+
+    let mut color = [0u8; 4];
+    for channel in 0..4 {
+      let top = top_left[channel] + (top_right[channel] - top_left[channel]) * fx;
+      let bottom = bottom_left[channel] + (bottom_right[channel] - bottom_left[channel]) * fx;
+      color[channel] = (top + (bottom - top) * fy).round().clamp(0.0, 255.0) as u8;
+    }
+    Rgba(color)
+  }
+}
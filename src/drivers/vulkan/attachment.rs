@@ -0,0 +1,115 @@
+use ash::{
+  vk::{
+    self, Extent2D, Extent3D, Format, FormatFeatureFlags, Image, ImageAspectFlags, ImageCreateInfo, ImageLayout, ImageSubresourceRange, ImageTiling, ImageType, ImageUsageFlags, ImageView, ImageViewCreateInfo, ImageViewType, MemoryPropertyFlags, PhysicalDevice, SampleCountFlags, SharingMode
+  },
+  Device, Instance
+};
+
+use super::memory::{Allocation, AllocationKind, AllocationStrategy, GpuAllocator};
+
+// Preferred first. The stencil candidates all carry an 8 bit stencil, the others fall back to them
+const DEPTH_FORMATS         : [Format; 3] = [Format::D32_SFLOAT, Format::D32_SFLOAT_S8_UINT, Format::D24_UNORM_S8_UINT];
+const DEPTH_STENCIL_FORMATS : [Format; 3] = [Format::D32_SFLOAT_S8_UINT, Format::D24_UNORM_S8_UINT, Format::D16_UNORM_S8_UINT];
+
+// First candidate the device can use as an optimally tiled depth attachment
+pub fn find_depth_format(instance: &Instance, physical_device: PhysicalDevice, stencil: bool) -> Result<Format, vk::Result> {
+  let candidates = if stencil { &DEPTH_STENCIL_FORMATS } else { &DEPTH_FORMATS };
+  candidates.iter()
+    .copied()
+    .find(|&format| {
+      let properties = unsafe { instance.get_physical_device_format_properties(physical_device, format) };
+      properties.optimal_tiling_features.contains(FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT)
+    })
+    .ok_or(vk::Result::ERROR_FORMAT_NOT_SUPPORTED)
+}
+
+pub fn has_stencil(format: Format) -> bool {
+  matches!(format, Format::D32_SFLOAT_S8_UINT | Format::D24_UNORM_S8_UINT | Format::D16_UNORM_S8_UINT | Format::S8_UINT)
+}
+
+pub fn depth_aspect(format: Format) -> ImageAspectFlags {
+  if has_stencil(format) {
+    ImageAspectFlags::DEPTH | ImageAspectFlags::STENCIL
+  } else {
+    ImageAspectFlags::DEPTH
+  }
+}
+
+pub struct DepthBuffer {
+  pub view   : ImageView,
+  image      : Image,
+  allocation : Allocation,
+}
+
+impl DepthBuffer {
+
+  pub fn new(allocator: &mut GpuAllocator, device: &Device, extent: Extent2D, format: Format) -> Result<Self, vk::Result> {
+    let (image, allocation) = create_image(allocator, device, extent, 1, format, ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT)?;
+    let view = create_view(device, image, format, depth_aspect(format), 1)?;
+    Ok(DepthBuffer { view, image, allocation })
+  }
+
+  pub fn destroy(self, device: &Device, allocator: &mut GpuAllocator) {
+    unsafe {
+      device.destroy_image_view(self.view, None);
+      device.destroy_image(self.image, None);
+    }
+    allocator.free(device, self.allocation);
+  }
+}
+
+// Single sampled, optimally tiled 2D image in device local memory
+pub(super) fn create_image(
+  allocator  : &mut GpuAllocator,
+  device     : &Device,
+  extent     : Extent2D,
+  mip_levels : u32,
+  format     : Format,
+  usage      : ImageUsageFlags
+) -> Result<(Image, Allocation), vk::Result> {
+
+  let image = create_image_handle(device, extent, mip_levels, format, usage)?;
+  let requirements = unsafe { device.get_image_memory_requirements(image) };
+  let allocation = match allocator.allocate(device, requirements, MemoryPropertyFlags::DEVICE_LOCAL, AllocationKind::Optimal, AllocationStrategy::FreeList) {
+    Ok(allocation) => allocation,
+    Err(err) => {
+      unsafe { device.destroy_image(image, None) };
+      return Err(err);
+    }
+  };
+  unsafe { device.bind_image_memory(image, allocation.memory, allocation.offset)? };
+  Ok((image, allocation))
+}
+
+// The image `create_image` binds, without memory. Defragmentation binds it to an allocation it already reserved
+pub(super) fn create_image_handle(device: &Device, extent: Extent2D, mip_levels: u32, format: Format, usage: ImageUsageFlags) -> Result<Image, vk::Result> {
+  let image_info = ImageCreateInfo::builder()
+    .image_type(ImageType::TYPE_2D)
+    .format(format)
+    .extent(Extent3D { width: extent.width, height: extent.height, depth: 1 })
+    .mip_levels(mip_levels)
+    .array_layers(1)
+    .samples(SampleCountFlags::TYPE_1)
+    .tiling(ImageTiling::OPTIMAL)
+    .usage(usage)
+    .sharing_mode(SharingMode::EXCLUSIVE)
+    .initial_layout(ImageLayout::UNDEFINED)
+    .build();
+  unsafe { device.create_image(&image_info, None) }
+}
+
+pub(super) fn create_view(device: &Device, image: Image, format: Format, aspect_mask: ImageAspectFlags, mip_levels: u32) -> Result<ImageView, vk::Result> {
+  let view_info = ImageViewCreateInfo::builder()
+    .image(image)
+    .view_type(ImageViewType::TYPE_2D)
+    .format(format)
+    .subresource_range(ImageSubresourceRange {
+      aspect_mask,
+      base_mip_level   : 0,
+      level_count      : mip_levels,
+      base_array_layer : 0,
+      layer_count      : 1,
+    })
+    .build();
+  unsafe { device.create_image_view(&view_info, None) }
+}
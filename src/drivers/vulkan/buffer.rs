@@ -0,0 +1,234 @@
+use ash::{
+  vk::{
+    self, AccessFlags, Buffer, BufferCopy, BufferCreateInfo, BufferMemoryBarrier, BufferUsageFlags, CommandBuffer, CommandBufferAllocateInfo, CommandBufferBeginInfo, CommandBufferLevel, CommandBufferUsageFlags, CommandPool, DependencyFlags, DeviceSize, MemoryPropertyFlags, PipelineStageFlags, Queue, SharingMode, SubmitInfo
+  },
+  Device
+};
+
+use super::memory::{Allocation, AllocationKind, AllocationStrategy, GpuAllocator};
+
+// A queue with a command pool of its family, for one-time submits
+#[derive(Clone, Copy, Debug)]
+pub struct QueueContext {
+  pub family_index : u32,
+  pub queue        : Queue,
+  pub command_pool : CommandPool,
+}
+
+// Staging copies run on `transfer`, what they write is used on `graphics`. Both are the graphics queue when
+// the device has no separate transfer family
+#[derive(Clone, Copy, Debug)]
+pub struct UploadQueues {
+  pub transfer : QueueContext,
+  pub graphics : QueueContext,
+}
+
+impl UploadQueues {
+  // (source, destination) families when resources have to be released by the transfer family and acquired
+  // by the graphics family, None when they share a family
+  pub fn ownership_transfer(&self) -> Option<(u32, u32)> {
+    let families = (self.transfer.family_index, self.graphics.family_index);
+    (families.0 != families.1).then_some(families)
+  }
+}
+
+pub struct GpuBuffer {
+  pub buffer     : Buffer,
+  pub size       : DeviceSize,
+  pub usage      : BufferUsageFlags,
+  pub allocation : Allocation,
+}
+
+impl GpuBuffer {
+
+  pub fn new(
+    allocator  : &mut GpuAllocator,
+    device     : &Device,
+    size       : DeviceSize,
+    usage      : BufferUsageFlags,
+    properties : MemoryPropertyFlags,
+    strategy   : AllocationStrategy
+  ) -> Result<Self, vk::Result> {
+
+    let buffer = GpuBuffer::create_handle(device, size, usage)?;
+    let requirements = unsafe { device.get_buffer_memory_requirements(buffer) };
+    let allocation = match allocator.allocate(device, requirements, properties, AllocationKind::Linear, strategy) {
+      Ok(allocation) => allocation,
+      Err(err) => {
+        unsafe { device.destroy_buffer(buffer, None) };
+        return Err(err);
+      }
+    };
+
+    if let Err(err) = unsafe { device.bind_buffer_memory(buffer, allocation.memory, allocation.offset) } {
+      unsafe { device.destroy_buffer(buffer, None) };
+      allocator.free(device, allocation);
+      return Err(err);
+    }
+
+    Ok(GpuBuffer { buffer, size, usage, allocation })
+  }
+
+  // Binds a fresh handle with the same size and usage to `allocation`, used to move a buffer during defragmentation
+  pub fn rebind(&self, device: &Device, allocation: Allocation) -> Result<GpuBuffer, vk::Result> {
+    let buffer = GpuBuffer::create_handle(device, self.size, self.usage)?;
+    if let Err(err) = unsafe { device.bind_buffer_memory(buffer, allocation.memory, allocation.offset) } {
+      unsafe { device.destroy_buffer(buffer, None) };
+      return Err(err);
+    }
+    Ok(GpuBuffer { buffer, size: self.size, usage: self.usage, allocation })
+  }
+
+  fn create_handle(device: &Device, size: DeviceSize, usage: BufferUsageFlags) -> Result<Buffer, vk::Result> {
+    let buffer_info = BufferCreateInfo {
+      size,
+      usage,
+      sharing_mode : SharingMode::EXCLUSIVE,
+      ..Default::default()
+    };
+    unsafe { device.create_buffer(&buffer_info, None) }
+  }
+
+  // Only valid for buffers created with HOST_VISIBLE | HOST_COHERENT memory
+  pub fn write(&self, data: &[u8]) {
+    assert!(data.len() as DeviceSize <= self.size, "Write of {} bytes overflows a {} byte buffer", data.len(), self.size);
+    let data_ptr = self.allocation.mapped_ptr().expect("Buffer memory is not host visible");
+    unsafe { data_ptr.copy_from_nonoverlapping(data.as_ptr(), data.len()) };
+  }
+
+  pub fn read(&self, data: &mut [u8]) {
+    assert!(data.len() as DeviceSize <= self.size, "Read of {} bytes overflows a {} byte buffer", data.len(), self.size);
+    let data_ptr = self.allocation.mapped_ptr().expect("Buffer memory is not host visible");
+    unsafe { data_ptr.copy_to_nonoverlapping(data.as_mut_ptr(), data.len()) };
+  }
+
+  // Copies `data` through a host visible staging buffer into a new DEVICE_LOCAL buffer owned by the graphics family.
+  // Blocks until the transfer has finished, so the staging buffer can be released right away
+  pub fn upload_device_local(
+    allocator : &mut GpuAllocator,
+    device    : &Device,
+    queues    : &UploadQueues,
+    data      : &[u8],
+    usage     : BufferUsageFlags
+  ) -> Result<Self, vk::Result> {
+
+    let size = data.len() as DeviceSize;
+    let staging = GpuBuffer::new(
+      allocator, device, size,
+      BufferUsageFlags::TRANSFER_SRC,
+      MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT,
+      AllocationStrategy::Linear
+    )?;
+    staging.write(data);
+
+    let result = GpuBuffer::new(
+      allocator, device, size,
+      usage | BufferUsageFlags::TRANSFER_DST,
+      MemoryPropertyFlags::DEVICE_LOCAL,
+      AllocationStrategy::FreeList
+    );
+    let result = match result {
+      Ok(buffer) => match GpuBuffer::upload_copy(device, queues, staging.buffer, buffer.buffer, size) {
+        Ok(()) => Ok(buffer),
+        Err(err) => {
+          buffer.destroy(device, allocator);
+          Err(err)
+        }
+      },
+      Err(err) => Err(err),
+    };
+
+    staging.destroy(device, allocator);
+    result
+  }
+
+  // The copy runs on the transfer queue. With a separate transfer family the destination is released there and
+  // acquired by the graphics family, `submit_one_time` waits in between so the release completes first
+  fn upload_copy(device: &Device, queues: &UploadQueues, source: Buffer, destination: Buffer, size: DeviceSize) -> Result<(), vk::Result> {
+    let ownership_barrier = |access: (AccessFlags, AccessFlags)| queues.ownership_transfer().map(|(source_family, destination_family)| {
+      BufferMemoryBarrier::builder()
+        .src_access_mask(access.0)
+        .dst_access_mask(access.1)
+        .src_queue_family_index(source_family)
+        .dst_queue_family_index(destination_family)
+        .buffer(destination)
+        .offset(0)
+        .size(vk::WHOLE_SIZE)
+        .build()
+    });
+
+    submit_one_time(device, queues.transfer.command_pool, queues.transfer.queue, |command_buffer| unsafe {
+      device.cmd_copy_buffer(command_buffer, source, destination, &[BufferCopy { src_offset: 0, dst_offset: 0, size }]);
+      if let Some(release) = ownership_barrier((AccessFlags::TRANSFER_WRITE, AccessFlags::empty())) {
+        device.cmd_pipeline_barrier(command_buffer, PipelineStageFlags::TRANSFER, PipelineStageFlags::BOTTOM_OF_PIPE, DependencyFlags::empty(), &[], &[release], &[]);
+      }
+    })?;
+    match ownership_barrier((AccessFlags::empty(), AccessFlags::MEMORY_READ)) {
+      Some(acquire) => submit_one_time(device, queues.graphics.command_pool, queues.graphics.queue, |command_buffer| unsafe {
+        device.cmd_pipeline_barrier(command_buffer, PipelineStageFlags::TOP_OF_PIPE, PipelineStageFlags::ALL_GRAPHICS, DependencyFlags::empty(), &[], &[acquire], &[]);
+      }),
+      None => Ok(()),
+    }
+  }
+
+  // Records every (source, destination, size) copy into one command buffer and waits for it
+  pub fn copy(device: &Device, command_pool: CommandPool, queue: Queue, copies: &[(Buffer, Buffer, DeviceSize)]) -> Result<(), vk::Result> {
+    submit_one_time(device, command_pool, queue, |command_buffer| {
+      for &(source, destination, size) in copies {
+        let region = BufferCopy { src_offset: 0, dst_offset: 0, size };
+        unsafe { device.cmd_copy_buffer(command_buffer, source, destination, &[region]) };
+      }
+    })
+  }
+
+  pub fn destroy(self, device: &Device, allocator: &mut GpuAllocator) {
+    unsafe { device.destroy_buffer(self.buffer, None) };
+    allocator.free(device, self.allocation);
+  }
+}
+
+// Records through `record` into a throwaway command buffer, submits it and waits for the queue to drain
+pub fn submit_one_time<F: FnOnce(CommandBuffer)>(device: &Device, command_pool: CommandPool, queue: Queue, record: F) -> Result<(), vk::Result> {
+  let allocate_info = CommandBufferAllocateInfo::builder()
+    .command_pool(command_pool)
+    .level(CommandBufferLevel::PRIMARY)
+    .command_buffer_count(1)
+    .build();
+  let command_buffers = unsafe { device.allocate_command_buffers(&allocate_info)? };
+
+  let begin_info = CommandBufferBeginInfo::builder()
+    .flags(CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+    .build();
+  let submit_info = SubmitInfo::builder()
+    .command_buffers(&command_buffers)
+    .build();
+
+  let result = unsafe {
+    device.begin_command_buffer(command_buffers[0], &begin_info)
+      .and_then(|_| {
+        record(command_buffers[0]);
+        device.end_command_buffer(command_buffers[0])
+      })
+      .and_then(|_| device.queue_submit(queue, &[submit_info], vk::Fence::null()))
+      .and_then(|_| device.queue_wait_idle(queue))
+  };
+
+  unsafe { device.free_command_buffers(command_pool, &command_buffers) };
+  result
+}
+
+#[cfg(test)]
+mod tests {
+  use ash::vk::Handle;
+  use super::*;
+
+  fn queue(family_index: u32) -> QueueContext {
+    QueueContext { family_index, queue: Queue::from_raw(family_index as u64 + 1), command_pool: CommandPool::null() }
+  }
+
+  #[test]
+  fn ownership_moves_only_between_distinct_families() {
+    assert_eq!(UploadQueues { transfer: queue(0), graphics: queue(0) }.ownership_transfer(), None);
+    assert_eq!(UploadQueues { transfer: queue(2), graphics: queue(0) }.ownership_transfer(), Some((2, 0)));
+  }
+}
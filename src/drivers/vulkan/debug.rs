@@ -0,0 +1,151 @@
+use std::{env, ffi::{c_void, CStr, CString}};
+use ash::{
+  extensions::ext,
+  vk::{self, Bool32, DebugUtilsMessageSeverityFlagsEXT, DebugUtilsMessageTypeFlagsEXT, DebugUtilsMessengerCallbackDataEXT, Handle},
+  Entry, Instance
+};
+
+pub const DEBUG_ENV_VAR: &str = "RENDERER_VULKAN_DEBUG";
+
+pub const VALIDATION_LAYER: &CStr = c"VK_LAYER_KHRONOS_validation";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DebugMode {
+  Off,
+  // Validation layer and debug messenger, reporting warnings and errors
+  Validation,
+  // Same, plus the layers' and driver's info and verbose messages
+  Verbose,
+}
+
+impl DebugMode {
+  // RENDERER_VULKAN_DEBUG=1 turns validation on, RENDERER_VULKAN_DEBUG=verbose also reports info messages
+  pub fn from_env() -> Self {
+    match env::var(DEBUG_ENV_VAR).unwrap_or_default().to_ascii_lowercase().as_str() {
+      "" | "0" | "off" | "false" => DebugMode::Off,
+      "verbose"                  => DebugMode::Verbose,
+      _                          => DebugMode::Validation,
+    }
+  }
+
+  pub fn enabled(&self) -> bool {
+    *self != DebugMode::Off
+  }
+
+  fn severities(&self) -> DebugUtilsMessageSeverityFlagsEXT {
+    let reported = DebugUtilsMessageSeverityFlagsEXT::WARNING | DebugUtilsMessageSeverityFlagsEXT::ERROR;
+    match self {
+      DebugMode::Verbose => reported | DebugUtilsMessageSeverityFlagsEXT::INFO | DebugUtilsMessageSeverityFlagsEXT::VERBOSE,
+      _                  => reported,
+    }
+  }
+}
+
+pub fn validation_layer_available(entry: &Entry) -> bool {
+  entry.enumerate_instance_layer_properties()
+    .map(|layers| layers.iter().any(|layer| unsafe { CStr::from_ptr(layer.layer_name.as_ptr()) } == VALIDATION_LAYER))
+    .unwrap_or(false)
+}
+
+pub fn debug_utils_available(entry: &Entry) -> bool {
+  entry.enumerate_instance_extension_properties(None)
+    .map(|extensions| extensions.iter().any(|extension| unsafe { CStr::from_ptr(extension.extension_name.as_ptr()) } == ext::DebugUtils::name()))
+    .unwrap_or(false)
+}
+
+// Also chained into InstanceCreateInfo, so instance creation and destruction are reported too
+pub fn messenger_create_info(mode: DebugMode) -> vk::DebugUtilsMessengerCreateInfoEXT {
+  vk::DebugUtilsMessengerCreateInfoEXT::builder()
+    .message_severity(mode.severities())
+    .message_type(DebugUtilsMessageTypeFlagsEXT::GENERAL | DebugUtilsMessageTypeFlagsEXT::VALIDATION | DebugUtilsMessageTypeFlagsEXT::PERFORMANCE)
+    .pfn_user_callback(Some(debug_callback))
+    .build()
+}
+
+// Warnings and errors go to stderr, everything else to stdout
+unsafe extern "system" fn debug_callback(
+  severity      : DebugUtilsMessageSeverityFlagsEXT,
+  message_types : DebugUtilsMessageTypeFlagsEXT,
+  callback_data : *const DebugUtilsMessengerCallbackDataEXT,
+  _user_data    : *mut c_void
+) -> Bool32 {
+  let callback_data = &*callback_data;
+  let text = |pointer: *const std::os::raw::c_char| match pointer.is_null() {
+    true  => String::new(),
+    false => CStr::from_ptr(pointer).to_string_lossy().into_owned(),
+  };
+  let kind = match message_types {
+    types if types.contains(DebugUtilsMessageTypeFlagsEXT::VALIDATION)  => "validation",
+    types if types.contains(DebugUtilsMessageTypeFlagsEXT::PERFORMANCE) => "performance",
+    _                                                                   => "general",
+  };
+  let (message_id, message) = (text(callback_data.p_message_id_name), text(callback_data.p_message));
+
+  match severity {
+    DebugUtilsMessageSeverityFlagsEXT::ERROR   => eprintln!("[vulkan {} error] {} {}", kind, message_id, message),
+    DebugUtilsMessageSeverityFlagsEXT::WARNING => eprintln!("[vulkan {} warning] {} {}", kind, message_id, message),
+    DebugUtilsMessageSeverityFlagsEXT::INFO    => println!("[vulkan {} info] {}", kind, message),
+    _                                          => println!("[vulkan {} verbose] {}", kind, message),
+  }
+  // The call that triggered the message must not be aborted
+  vk::FALSE
+}
+
+pub struct DebugMessenger {
+  loader    : ext::DebugUtils,
+  messenger : vk::DebugUtilsMessengerEXT
+}
+
+impl DebugMessenger {
+  pub fn new(entry: &Entry, instance: &Instance, mode: DebugMode) -> Result<Self, vk::Result> {
+    let loader = ext::DebugUtils::new(entry, instance);
+    let messenger = unsafe { loader.create_debug_utils_messenger(&messenger_create_info(mode), None)? };
+    Ok(DebugMessenger { loader, messenger })
+  }
+
+  pub fn object_namer(&self, device: &ash::Device) -> ObjectNamer {
+    ObjectNamer { loader: self.loader.clone(), device: device.handle() }
+  }
+
+  pub fn destroy(&self) {
+    unsafe { self.loader.destroy_debug_utils_messenger(self.messenger, None) };
+  }
+}
+
+// Labels Vulkan objects and command buffer regions, so validation messages and capture tools show our IDs
+#[derive(Clone)]
+pub struct ObjectNamer {
+  loader : ext::DebugUtils,
+  device : vk::Device
+}
+
+impl ObjectNamer {
+  pub fn name<H: Handle>(&self, handle: H, name: &str) {
+    let name = c_string(name);
+    let name_info = vk::DebugUtilsObjectNameInfoEXT::builder()
+      .object_type(H::TYPE)
+      .object_handle(handle.as_raw())
+      .object_name(&name)
+      .build();
+    if let Err(err) = unsafe { self.loader.set_debug_utils_object_name(self.device, &name_info) } {
+      eprintln!("Failed to name {:?} '{}': {}", H::TYPE, name.to_string_lossy(), err);
+    }
+  }
+
+  pub fn begin_label(&self, command_buffer: vk::CommandBuffer, name: &str, color: [f32; 4]) {
+    let name = c_string(name);
+    let label = vk::DebugUtilsLabelEXT::builder()
+      .label_name(&name)
+      .color(color)
+      .build();
+    unsafe { self.loader.cmd_begin_debug_utils_label(command_buffer, &label) };
+  }
+
+  pub fn end_label(&self, command_buffer: vk::CommandBuffer) {
+    unsafe { self.loader.cmd_end_debug_utils_label(command_buffer) };
+  }
+}
+
+fn c_string(name: &str) -> CString {
+  CString::new(name.replace('\0', "")).unwrap()
+}
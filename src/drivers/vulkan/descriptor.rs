@@ -0,0 +1,98 @@
+use ash::{
+  vk::{ self, DescriptorBufferInfo, DescriptorImageInfo, DescriptorSet, DescriptorType, DeviceSize, ImageLayout, ImageView, Sampler, WriteDescriptorSet },
+  Device
+};
+
+// Where a texture goes in a set: one combined image sampler, or an image and a sampler binding of their own
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextureBinding {
+  Combined(u32),
+  Separate { image: u32, sampler: u32 },
+}
+
+// Collects buffer and image writes for one descriptor set and applies them in a single update
+pub struct DescriptorWriter {
+  buffers : Vec<(u32, DescriptorType, DescriptorBufferInfo)>,
+  images  : Vec<(u32, DescriptorType, DescriptorImageInfo)>,
+}
+
+impl Default for DescriptorWriter {
+  fn default() -> Self {
+    DescriptorWriter::new()
+  }
+}
+
+impl DescriptorWriter {
+
+  pub fn new() -> Self {
+    DescriptorWriter {
+      buffers : Vec::new(),
+      images  : Vec::new(),
+    }
+  }
+
+  pub fn buffer(&mut self, binding: u32, descriptor_type: DescriptorType, buffer: vk::Buffer, offset: DeviceSize, range: DeviceSize) -> &mut Self {
+    self.buffers.push((binding, descriptor_type, DescriptorBufferInfo { buffer, offset, range }));
+    self
+  }
+
+  // Combined image sampler, the image is expected in SHADER_READ_ONLY_OPTIMAL
+  pub fn image(&mut self, binding: u32, image_view: ImageView, sampler: Sampler) -> &mut Self {
+    self.images.push((binding, DescriptorType::COMBINED_IMAGE_SAMPLER, DescriptorImageInfo {
+      sampler,
+      image_view,
+      image_layout : ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+    }));
+    self
+  }
+
+  pub fn sampled_image(&mut self, binding: u32, image_view: ImageView) -> &mut Self {
+    self.images.push((binding, DescriptorType::SAMPLED_IMAGE, DescriptorImageInfo {
+      sampler      : Sampler::null(),
+      image_view,
+      image_layout : ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+    }));
+    self
+  }
+
+  pub fn sampler(&mut self, binding: u32, sampler: Sampler) -> &mut Self {
+    self.images.push((binding, DescriptorType::SAMPLER, DescriptorImageInfo {
+      sampler,
+      image_view   : ImageView::null(),
+      image_layout : ImageLayout::UNDEFINED,
+    }));
+    self
+  }
+
+  pub fn texture(&mut self, binding: TextureBinding, image_view: ImageView, sampler: Sampler) -> &mut Self {
+    match binding {
+      TextureBinding::Combined(binding) => self.image(binding, image_view, sampler),
+      TextureBinding::Separate { image, sampler: sampler_binding } => self.sampled_image(image, image_view).sampler(sampler_binding, sampler),
+    }
+  }
+
+  pub fn write(&self, device: &Device, descriptor_set: DescriptorSet) {
+    let buffer_writes = self.buffers.iter().map(|(binding, descriptor_type, info)| {
+      WriteDescriptorSet::builder()
+        .dst_set(descriptor_set)
+        .dst_binding(*binding)
+        .dst_array_element(0)
+        .descriptor_type(*descriptor_type)
+        .buffer_info(std::slice::from_ref(info))
+        .build()
+    });
+    let image_writes = self.images.iter().map(|(binding, descriptor_type, info)| {
+      WriteDescriptorSet::builder()
+        .dst_set(descriptor_set)
+        .dst_binding(*binding)
+        .dst_array_element(0)
+        .descriptor_type(*descriptor_type)
+        .image_info(std::slice::from_ref(info))
+        .build()
+    });
+    let writes: Vec<WriteDescriptorSet> = buffer_writes.chain(image_writes).collect();
+    if !writes.is_empty() {
+      unsafe { device.update_descriptor_sets(&writes, &[]) };
+    }
+  }
+}
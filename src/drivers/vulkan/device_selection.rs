@@ -0,0 +1,423 @@
+use std::{env, error::Error, ffi::CStr, fmt};
+use ash::{
+  extensions::khr::{Surface, Swapchain},
+  vk::{self, DeviceSize, Format, FormatFeatureFlags, MemoryHeapFlags, PhysicalDevice, PhysicalDeviceType, QueueFlags, SurfaceKHR},
+  Instance
+};
+
+use super::attachment;
+use super::offscreen::OFFSCREEN_COLOR_FORMAT;
+use super::texture::TEXTURE_FORMAT;
+
+pub const DEVICE_ENV_VAR: &str = "RENDERER_VULKAN_DEVICE";
+
+// Forces a device instead of the best scoring one: its index in the report, or part of its name
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DevicePreference {
+  Index(usize),
+  Name(String),
+}
+
+impl DevicePreference {
+  // `RENDERER_VULKAN_DEVICE=1` or `RENDERER_VULKAN_DEVICE=llvmpipe`
+  pub fn from_env() -> Option<Self> {
+    env::var(DEVICE_ENV_VAR).ok()
+      .filter(|value| !value.trim().is_empty())
+      .map(|value| DevicePreference::parse(&value))
+  }
+
+  pub fn parse(value: &str) -> Self {
+    match value.trim().parse() {
+      Ok(index) => DevicePreference::Index(index),
+      Err(_)    => DevicePreference::Name(value.trim().to_string()),
+    }
+  }
+
+  fn matches(&self, candidate: &DeviceCandidate) -> bool {
+    match self {
+      DevicePreference::Index(index) => candidate.index == *index,
+      DevicePreference::Name(name)   => candidate.name.to_lowercase().contains(&name.to_lowercase()),
+    }
+  }
+}
+
+impl fmt::Display for DevicePreference {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      DevicePreference::Index(index) => write!(f, "device {}", index),
+      DevicePreference::Name(name)   => write!(f, "a device named '{}'", name),
+    }
+  }
+}
+
+// What the renderer needs from a device. With a surface it must also present to it
+pub struct DeviceRequirements<'a> {
+  pub surface       : Option<(&'a Surface, SurfaceKHR)>,
+  pub depth_stencil : bool,
+}
+
+impl<'a> DeviceRequirements<'a> {
+  fn extensions(&self) -> Vec<&'static CStr> {
+    match self.surface {
+      Some(_) => vec![Swapchain::name()],
+      None    => Vec::new(),
+    }
+  }
+}
+
+// Queue families the device is created with. `transfer` and `compute` are only set for families without
+// graphics support, whose queues can run uploads and compute work alongside the graphics queue
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct QueueFamilies {
+  pub graphics : Option<u32>,
+  pub present  : Option<u32>,
+  pub transfer : Option<u32>,
+  pub compute  : Option<u32>,
+}
+
+impl QueueFamilies {
+  // Each family once, graphics first, for one DeviceQueueCreateInfo per family
+  pub fn unique(&self) -> Vec<u32> {
+    let mut families = Vec::new();
+    for family in [self.graphics, self.present, self.transfer, self.compute].into_iter().flatten() {
+      if !families.contains(&family) {
+        families.push(family);
+      }
+    }
+    families
+  }
+}
+
+impl fmt::Display for QueueFamilies {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let family = |index: Option<u32>| index.map_or("none".to_string(), |index| index.to_string());
+    write!(
+      f, "Graphics: {}, Presentation: {}, Transfer: {}, Compute: {}",
+      family(self.graphics), family(self.present), family(self.transfer), family(self.compute)
+    )
+  }
+}
+
+#[derive(Clone, Debug)]
+pub struct DeviceCandidate {
+  pub index           : usize,
+  pub physical_device : PhysicalDevice,
+  pub name            : String,
+  pub device_type     : PhysicalDeviceType,
+  pub api_version     : u32,
+  pub local_memory    : DeviceSize,
+  pub queue_families  : QueueFamilies,
+  // Unmet requirements, the device is usable when this is empty
+  pub missing         : Vec<String>,
+  pub score           : u64,
+}
+
+impl DeviceCandidate {
+  pub fn usable(&self) -> bool {
+    self.missing.is_empty()
+  }
+
+  // CPU implementations like lavapipe or SwiftShader, only picked when nothing else works
+  pub fn is_software(&self) -> bool {
+    self.device_type == PhysicalDeviceType::CPU
+  }
+}
+
+#[derive(Debug)]
+pub enum DeviceSelectionError {
+  Vulkan(vk::Result),
+  NoDevices,
+  NoneUsable,
+  NotFound(DevicePreference),
+  Unusable { name: String, missing: Vec<String> },
+}
+
+impl fmt::Display for DeviceSelectionError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      DeviceSelectionError::Vulkan(err)                => write!(f, "Failed to query physical devices: {}", err),
+      DeviceSelectionError::NoDevices                  => write!(f, "No Vulkan devices found"),
+      DeviceSelectionError::NoneUsable                 => write!(f, "No Vulkan device meets the renderer's requirements"),
+      DeviceSelectionError::NotFound(preference)       => write!(f, "Requested {} but there is no such Vulkan device", preference),
+      DeviceSelectionError::Unusable { name, missing } => write!(f, "Requested device '{}' cannot be used: {}", name, missing.join(", ")),
+    }
+  }
+}
+
+impl Error for DeviceSelectionError {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    match self {
+      DeviceSelectionError::Vulkan(err) => Some(err),
+      _ => None,
+    }
+  }
+}
+
+impl From<vk::Result> for DeviceSelectionError {
+  fn from(err: vk::Result) -> Self {
+    DeviceSelectionError::Vulkan(err)
+  }
+}
+
+// Every device with its score, usable devices first and the best of them at the front
+pub fn rank_devices(instance: &Instance, requirements: &DeviceRequirements) -> Result<Vec<DeviceCandidate>, DeviceSelectionError> {
+  let physical_devices = unsafe { instance.enumerate_physical_devices()? };
+  if physical_devices.is_empty() {
+    return Err(DeviceSelectionError::NoDevices);
+  }
+  let mut candidates = physical_devices.iter()
+    .enumerate()
+    .map(|(index, &physical_device)| evaluate_device(instance, index, physical_device, requirements))
+    .collect::<Result<Vec<_>, _>>()?;
+  candidates.sort_by(|a, b| b.usable().cmp(&a.usable()).then(b.score.cmp(&a.score)).then(a.index.cmp(&b.index)));
+  Ok(candidates)
+}
+
+// The preferred device when one is given, the best usable one otherwise
+pub fn select_device<'a>(candidates: &'a [DeviceCandidate], preference: Option<&DevicePreference>) -> Result<&'a DeviceCandidate, DeviceSelectionError> {
+  match preference {
+    Some(preference) => {
+      let candidate = candidates.iter()
+        .find(|candidate| preference.matches(candidate))
+        .ok_or_else(|| DeviceSelectionError::NotFound(preference.clone()))?;
+      match candidate.usable() {
+        true  => Ok(candidate),
+        false => Err(DeviceSelectionError::Unusable { name: candidate.name.clone(), missing: candidate.missing.clone() }),
+      }
+    },
+    None => candidates.first().filter(|candidate| candidate.usable()).ok_or(DeviceSelectionError::NoneUsable),
+  }
+}
+
+pub fn format_report(candidates: &[DeviceCandidate], selected: Option<&DeviceCandidate>) -> String {
+  let mut report = String::from("Vulkan devices, best first:\n");
+  for candidate in candidates {
+    let marker = match selected.map(|selected| selected.index) == Some(candidate.index) {
+      true  => "*",
+      false => " ",
+    };
+    let status = match candidate.usable() {
+      true  => format!("score {}", candidate.score),
+      false => format!("unusable: {}", candidate.missing.join(", ")),
+    };
+    report.push_str(&format!(
+      "{} [{}] {} ({:?}, Vulkan {}.{}, {} MiB) - {}\n",
+      marker,
+      candidate.index,
+      candidate.name,
+      candidate.device_type,
+      vk::api_version_major(candidate.api_version),
+      vk::api_version_minor(candidate.api_version),
+      candidate.local_memory / (1024 * 1024),
+      status
+    ));
+  }
+  report
+}
+
+fn evaluate_device(
+  instance        : &Instance,
+  index           : usize,
+  physical_device : PhysicalDevice,
+  requirements    : &DeviceRequirements
+) -> Result<DeviceCandidate, DeviceSelectionError> {
+  let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+  let memory_properties = unsafe { instance.get_physical_device_memory_properties(physical_device) };
+  let name = unsafe { CStr::from_ptr(properties.device_name.as_ptr()) }.to_string_lossy().into_owned();
+  let mut missing = Vec::new();
+
+  let available_extensions = unsafe { instance.enumerate_device_extension_properties(physical_device)? };
+  for extension in requirements.extensions() {
+    let available = available_extensions.iter()
+      .any(|available| unsafe { CStr::from_ptr(available.extension_name.as_ptr()) } == extension);
+    if !available {
+      missing.push(format!("extension {}", extension.to_string_lossy()));
+    }
+  }
+
+  let queue_families = find_queue_families(instance, physical_device, requirements.surface)?;
+  if queue_families.graphics.is_none() {
+    missing.push("graphics queue".to_string());
+  }
+  if let Some((surface_loader, surface)) = requirements.surface {
+    if queue_families.present.is_none() {
+      missing.push("presentation to the surface".to_string());
+    } else {
+      let formats = unsafe { surface_loader.get_physical_device_surface_formats(physical_device, surface)? };
+      let present_modes = unsafe { surface_loader.get_physical_device_surface_present_modes(physical_device, surface)? };
+      if formats.is_empty() || present_modes.is_empty() {
+        missing.push("surface formats or present modes".to_string());
+      }
+    }
+  }
+
+  if attachment::find_depth_format(instance, physical_device, requirements.depth_stencil).is_err() {
+    missing.push("depth attachment format".to_string());
+  }
+  let mut required_formats = vec![(TEXTURE_FORMAT, FormatFeatureFlags::SAMPLED_IMAGE | FormatFeatureFlags::TRANSFER_DST)];
+  if requirements.surface.is_none() {
+    required_formats.push((OFFSCREEN_COLOR_FORMAT, FormatFeatureFlags::COLOR_ATTACHMENT | FormatFeatureFlags::TRANSFER_SRC));
+  }
+  for (format, features) in required_formats {
+    if !supports_format(instance, physical_device, format, features) {
+      missing.push(format!("{:?} with {:?}", format, features));
+    }
+  }
+
+  let local_memory = memory_properties.memory_heaps[..memory_properties.memory_heap_count as usize]
+    .iter()
+    .filter(|heap| heap.flags.contains(MemoryHeapFlags::DEVICE_LOCAL))
+    .map(|heap| heap.size)
+    .sum();
+
+  Ok(DeviceCandidate {
+    index,
+    physical_device,
+    name,
+    device_type : properties.device_type,
+    api_version : properties.api_version,
+    local_memory,
+    queue_families,
+    score       : score(properties.device_type, local_memory, queue_families.graphics == queue_families.present),
+    missing,
+  })
+}
+
+// The device type dominates, then device local memory in MiB. Presenting from the graphics family breaks ties
+fn score(device_type: PhysicalDeviceType, local_memory: DeviceSize, shared_present_family: bool) -> u64 {
+  let type_rank = match device_type {
+    PhysicalDeviceType::DISCRETE_GPU   => 4,
+    PhysicalDeviceType::INTEGRATED_GPU => 3,
+    PhysicalDeviceType::VIRTUAL_GPU    => 2,
+    PhysicalDeviceType::CPU            => 0,
+    _                                  => 1,
+  };
+  let memory_mib = (local_memory / (1024 * 1024)).min(999_999);
+  type_rank * 10_000_000 + memory_mib * 10 + shared_present_family as u64
+}
+
+// Graphics and presentation from one family when a family can do both. Transfers prefer a family that does
+// nothing else, usually backed by a DMA engine, then any non-graphics family
+fn find_queue_families(
+  instance        : &Instance,
+  physical_device : PhysicalDevice,
+  surface         : Option<(&Surface, SurfaceKHR)>
+) -> Result<QueueFamilies, vk::Result> {
+  let queue_families = unsafe { instance.get_physical_device_queue_family_properties(physical_device) };
+  let families_with = |required: QueueFlags, excluded: QueueFlags| -> Vec<u32> {
+    queue_families.iter()
+      .enumerate()
+      .filter(|(_, family)| family.queue_count > 0 && family.queue_flags.contains(required) && !family.queue_flags.intersects(excluded))
+      .map(|(index, _)| index as u32)
+      .collect()
+  };
+  let graphics_families = families_with(QueueFlags::GRAPHICS, QueueFlags::empty());
+  // Compute queues support transfers even when they do not advertise it
+  let transfer = families_with(QueueFlags::TRANSFER, QueueFlags::GRAPHICS | QueueFlags::COMPUTE).first().copied()
+    .or_else(|| families_with(QueueFlags::TRANSFER, QueueFlags::GRAPHICS).first().copied())
+    .or_else(|| families_with(QueueFlags::COMPUTE, QueueFlags::GRAPHICS).first().copied());
+  let compute = families_with(QueueFlags::COMPUTE, QueueFlags::GRAPHICS).first().copied();
+
+  let (surface_loader, surface) = match surface {
+    Some(surface) => surface,
+    None => return Ok(QueueFamilies { graphics: graphics_families.first().copied(), present: None, transfer, compute }),
+  };
+  let mut present_families = Vec::new();
+  for index in 0..queue_families.len() as u32 {
+    if unsafe { surface_loader.get_physical_device_surface_support(physical_device, index, surface)? } {
+      present_families.push(index);
+    }
+  }
+  let (graphics, present) = match graphics_families.iter().find(|family| present_families.contains(family)) {
+    Some(&family) => (Some(family), Some(family)),
+    None          => (graphics_families.first().copied(), present_families.first().copied()),
+  };
+  Ok(QueueFamilies { graphics, present, transfer, compute })
+}
+
+fn supports_format(instance: &Instance, physical_device: PhysicalDevice, format: Format, features: FormatFeatureFlags) -> bool {
+  let properties = unsafe { instance.get_physical_device_format_properties(physical_device, format) };
+  properties.optimal_tiling_features.contains(features)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const GIB: DeviceSize = 1024 * 1024 * 1024;
+
+  fn candidate(index: usize, name: &str, device_type: PhysicalDeviceType, missing: &[&str]) -> DeviceCandidate {
+    DeviceCandidate {
+      index,
+      physical_device : PhysicalDevice::null(),
+      name            : name.to_string(),
+      device_type,
+      api_version     : vk::API_VERSION_1_2,
+      local_memory    : GIB,
+      queue_families  : QueueFamilies { graphics: Some(0), present: Some(0), ..QueueFamilies::default() },
+      missing         : missing.iter().map(|requirement| requirement.to_string()).collect(),
+      score           : score(device_type, GIB, true),
+    }
+  }
+
+  // Ranked like `rank_devices` leaves them: usable devices first
+  fn candidates() -> Vec<DeviceCandidate> {
+    vec![
+      candidate(1, "NVIDIA GeForce RTX 3070", PhysicalDeviceType::DISCRETE_GPU, &[]),
+      candidate(2, "llvmpipe (LLVM 15.0.7, 256 bits)", PhysicalDeviceType::CPU, &[]),
+      candidate(0, "Intel(R) UHD Graphics 620", PhysicalDeviceType::INTEGRATED_GPU, &["presentation to the surface"]),
+    ]
+  }
+
+  #[test]
+  fn device_type_outranks_memory() {
+    let integrated = score(PhysicalDeviceType::INTEGRATED_GPU, 64 * GIB, true);
+    assert!(score(PhysicalDeviceType::DISCRETE_GPU, GIB, false) > integrated);
+    assert!(integrated > score(PhysicalDeviceType::VIRTUAL_GPU, 64 * GIB, true));
+    assert!(score(PhysicalDeviceType::OTHER, 0, false) > score(PhysicalDeviceType::CPU, 64 * GIB, true));
+  }
+
+  #[test]
+  fn memory_then_shared_present_family_break_ties() {
+    let discrete = |memory, shared| score(PhysicalDeviceType::DISCRETE_GPU, memory, shared);
+    assert!(discrete(8 * GIB, false) > discrete(4 * GIB, true));
+    assert!(discrete(4 * GIB, true) > discrete(4 * GIB, false));
+    // Huge heaps are clamped so they cannot reach the next device type
+    assert!(score(PhysicalDeviceType::INTEGRATED_GPU, DeviceSize::MAX, true) < discrete(0, false));
+  }
+
+  #[test]
+  fn parses_indices_and_names() {
+    assert_eq!(DevicePreference::parse("1"), DevicePreference::Index(1));
+    assert_eq!(DevicePreference::parse(" 2 "), DevicePreference::Index(2));
+    assert_eq!(DevicePreference::parse(" llvmpipe "), DevicePreference::Name("llvmpipe".to_string()));
+    assert_eq!(DevicePreference::parse("-1"), DevicePreference::Name("-1".to_string()));
+  }
+
+  #[test]
+  fn selects_the_best_usable_device_by_default() {
+    let candidates = candidates();
+    assert_eq!(select_device(&candidates, None).unwrap().index, 1);
+  }
+
+  #[test]
+  fn selects_forced_devices_by_index_or_name() {
+    let candidates = candidates();
+    assert_eq!(select_device(&candidates, Some(&DevicePreference::Index(2))).unwrap().name, candidates[1].name);
+    assert_eq!(select_device(&candidates, Some(&DevicePreference::parse("LLVMPIPE"))).unwrap().index, 2);
+  }
+
+  #[test]
+  fn reports_missing_and_unusable_devices() {
+    let candidates = candidates();
+    assert!(matches!(select_device(&candidates, Some(&DevicePreference::Index(7))), Err(DeviceSelectionError::NotFound(DevicePreference::Index(7)))));
+    match select_device(&candidates, Some(&DevicePreference::parse("intel"))) {
+      Err(DeviceSelectionError::Unusable { name, missing }) => {
+        assert_eq!(name, candidates[2].name);
+        assert_eq!(missing, ["presentation to the surface"]);
+      },
+      _ => panic!("the integrated GPU cannot present and must be rejected"),
+    }
+    assert!(matches!(select_device(&candidates[2..], None), Err(DeviceSelectionError::NoneUsable)));
+  }
+}
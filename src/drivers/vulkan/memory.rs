@@ -0,0 +1,507 @@
+use std::collections::HashMap;
+use std::fmt;
+use ash::{
+  vk::{
+    self, DeviceMemory, DeviceSize, MemoryAllocateInfo, MemoryMapFlags, MemoryPropertyFlags, MemoryRequirements, PhysicalDevice, PhysicalDeviceMemoryProperties
+  },
+  Device, Instance
+};
+
+const DEFAULT_BLOCK_SIZE : DeviceSize = 64 * 1024 * 1024;
+const SMALL_HEAP_SIZE    : DeviceSize = 1024 * 1024 * 1024;
+// A free-list pool is compacted once its emptiest shared block is at most this full
+// and the pool's other blocks have room for what is left in it
+const DEFRAGMENTATION_THRESHOLD : f64 = 0.25;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AllocationStrategy {
+  Linear,   // Bump allocation at the end of a block, for short lived uploads. Space is reused once the block empties
+  FreeList, // Best fit into the gaps between live allocations
+}
+
+// bufferImageGranularity only separates linear resources (buffers, linear images) from optimally tiled images
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AllocationKind {
+  Linear,
+  Optimal,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Allocation {
+  pub memory      : DeviceMemory,
+  pub offset      : DeviceSize,
+  pub size        : DeviceSize,
+  mapped          : Option<*mut u8>,
+  memory_type     : u32,
+  strategy        : AllocationStrategy,
+  block_id        : u64,
+}
+
+impl Allocation {
+  // Host visible blocks stay mapped for their whole lifetime
+  pub fn mapped_ptr(&self) -> Option<*mut u8> {
+    self.mapped
+  }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HeapStats {
+  pub heap_index       : u32,
+  pub heap_size        : DeviceSize,
+  pub used_bytes       : DeviceSize,
+  pub reserved_bytes   : DeviceSize,
+  pub allocation_count : usize,
+  pub block_count      : usize,
+}
+
+impl fmt::Display for HeapStats {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    const MIB: f64 = 1024.0 * 1024.0;
+    write!(
+      f, "Heap {}: {:.2} / {:.2} MiB used in {} blocks ({} allocations, heap size {:.0} MiB)",
+      self.heap_index,
+      self.used_bytes as f64 / MIB,
+      self.reserved_bytes as f64 / MIB,
+      self.block_count,
+      self.allocation_count,
+      self.heap_size as f64 / MIB
+    )
+  }
+}
+
+// Destination is already reserved. The owner copies the resource over, rebinds it and frees `source`
+pub struct DefragmentationMove {
+  pub source      : Allocation,
+  pub destination : Allocation,
+}
+
+#[derive(Clone, Copy)]
+struct Suballocation {
+  offset    : DeviceSize,
+  size      : DeviceSize,
+  alignment : DeviceSize,
+  kind      : AllocationKind,
+}
+
+struct MemoryBlock {
+  id             : u64,
+  memory         : DeviceMemory,
+  size           : DeviceSize,
+  mapped         : Option<*mut u8>,
+  dedicated      : bool,
+  suballocations : Vec<Suballocation>, // Sorted by offset
+}
+
+impl MemoryBlock {
+
+  fn used(&self) -> DeviceSize {
+    self.suballocations.iter().map(|suballocation| suballocation.size).sum()
+  }
+
+  // Offset and insertion index for a new suballocation, or None if it does not fit
+  fn find_space(&self, size: DeviceSize, alignment: DeviceSize, kind: AllocationKind, strategy: AllocationStrategy, granularity: DeviceSize) -> Option<(DeviceSize, usize)> {
+    let gaps = self.suballocations.len() + 1;
+    let candidates: Box<dyn Iterator<Item = usize>> = match strategy {
+      AllocationStrategy::Linear   => Box::new(std::iter::once(gaps - 1)),
+      AllocationStrategy::FreeList => Box::new(0..gaps),
+    };
+
+    let mut best: Option<(DeviceSize, usize, DeviceSize)> = None;
+    for index in candidates {
+      let previous = index.checked_sub(1).map(|previous| &self.suballocations[previous]);
+      let next = self.suballocations.get(index);
+      let gap_start = previous.map_or(0, |previous| previous.offset + previous.size);
+      let gap_end = next.map_or(self.size, |next| next.offset);
+
+      let mut offset = align_up(gap_start, alignment);
+      if let Some(previous) = previous {
+        if previous.kind != kind && on_same_page(previous.offset + previous.size - 1, offset, granularity) {
+          offset = align_up(offset, granularity);
+        }
+      }
+      let end = offset + size;
+      if end > gap_end {
+        continue;
+      }
+      if let Some(next) = next {
+        if next.kind != kind && on_same_page(end - 1, next.offset, granularity) {
+          continue;
+        }
+      }
+
+      let waste = gap_end - end;
+      if best.is_none_or(|(_, _, best_waste)| waste < best_waste) {
+        best = Some((offset, index, waste));
+      }
+    }
+    best.map(|(offset, index, _)| (offset, index))
+  }
+}
+
+struct MemoryPool {
+  heap_index : u32,
+  blocks     : Vec<MemoryBlock>,
+}
+
+impl MemoryPool {
+
+  fn is_fragmented(&self) -> bool {
+    let shared: Vec<&MemoryBlock> = self.blocks.iter().filter(|block| !block.dedicated).collect();
+    let emptiest = match shared.iter().min_by_key(|block| block.used()) {
+      Some(emptiest) if shared.len() > 1 => emptiest,
+      _ => return false,
+    };
+    let free_elsewhere: DeviceSize = shared.iter()
+      .filter(|block| block.id != emptiest.id)
+      .map(|block| block.size - block.used())
+      .sum();
+    emptiest.used() as f64 <= emptiest.size as f64 * DEFRAGMENTATION_THRESHOLD && emptiest.used() <= free_elsewhere
+  }
+}
+
+// Hands out ranges of large VkDeviceMemory blocks instead of one allocation per resource.
+// Pools are kept per memory type and strategy, requests larger than half a block get a dedicated block
+pub struct GpuAllocator {
+  memory_properties : PhysicalDeviceMemoryProperties,
+  granularity       : DeviceSize,
+  pools             : HashMap<(u32, AllocationStrategy), MemoryPool>,
+  next_block_id     : u64,
+  freed             : bool, // Since the last plan, allocations that cannot move must not trigger a plan every frame
+}
+
+impl GpuAllocator {
+
+  pub fn new(instance: &Instance, physical_device: PhysicalDevice) -> Self {
+    let memory_properties = unsafe { instance.get_physical_device_memory_properties(physical_device) };
+    let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+    GpuAllocator {
+      memory_properties,
+      granularity   : properties.limits.buffer_image_granularity.max(1),
+      pools         : HashMap::new(),
+      next_block_id : 0,
+      freed         : false,
+    }
+  }
+
+  pub fn allocate(
+    &mut self,
+    device       : &Device,
+    requirements : MemoryRequirements,
+    properties   : MemoryPropertyFlags,
+    kind         : AllocationKind,
+    strategy     : AllocationStrategy
+  ) -> Result<Allocation, vk::Result> {
+
+    let memory_type = self.find_memory_type(requirements.memory_type_bits, properties)
+      .ok_or(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY)?;
+    let heap_index = self.memory_properties.memory_types[memory_type as usize].heap_index;
+    let block_size = self.block_size(heap_index);
+    let alignment = requirements.alignment.max(1);
+    let granularity = self.granularity;
+
+    let pool = self.pools.entry((memory_type, strategy)).or_insert_with(|| MemoryPool { heap_index, blocks: Vec::new() });
+    let existing = pool.blocks.iter().enumerate()
+      .filter(|(_, block)| !block.dedicated)
+      .find_map(|(index, block)| block.find_space(requirements.size, alignment, kind, strategy, granularity).map(|space| (index, space)));
+
+    let (block_index, offset, insert_at) = match existing {
+      Some((block_index, (offset, insert_at))) => (block_index, offset, insert_at),
+      None => {
+        let dedicated = requirements.size > block_size / 2;
+        let size = if dedicated { requirements.size } else { block_size };
+        let block = GpuAllocator::create_block(device, &self.memory_properties, memory_type, size, dedicated, self.next_block_id)?;
+        self.next_block_id += 1;
+        let pool = self.pools.get_mut(&(memory_type, strategy)).unwrap();
+        pool.blocks.push(block);
+        (pool.blocks.len() - 1, 0, 0)
+      }
+    };
+
+    let block = &mut self.pools.get_mut(&(memory_type, strategy)).unwrap().blocks[block_index];
+    block.suballocations.insert(insert_at, Suballocation { offset, size: requirements.size, alignment, kind });
+    Ok(Allocation {
+      memory   : block.memory,
+      offset,
+      size     : requirements.size,
+      mapped   : block.mapped.map(|ptr| unsafe { ptr.add(offset as usize) }),
+      memory_type,
+      strategy,
+      block_id : block.id,
+    })
+  }
+
+  // Empty blocks are released, except for the last shared block of a pool which is kept for reuse
+  pub fn free(&mut self, device: &Device, allocation: Allocation) {
+    let pool = match self.pools.get_mut(&(allocation.memory_type, allocation.strategy)) {
+      Some(pool) => pool,
+      None => return eprintln!("Freeing an allocation from an unknown memory pool"),
+    };
+    let block_index = match pool.blocks.iter().position(|block| block.id == allocation.block_id) {
+      Some(index) => index,
+      None => return eprintln!("Freeing an allocation from an unknown memory block"),
+    };
+
+    let block = &mut pool.blocks[block_index];
+    match block.suballocations.iter().position(|suballocation| suballocation.offset == allocation.offset) {
+      Some(index) => { block.suballocations.remove(index); },
+      None => return eprintln!("Double free of GPU allocation at offset {}", allocation.offset),
+    }
+    self.freed = true;
+
+    let shared_blocks = pool.blocks.iter().filter(|block| !block.dedicated).count();
+    let block = &pool.blocks[block_index];
+    if block.suballocations.is_empty() && (block.dedicated || shared_blocks > 1) {
+      let block = pool.blocks.remove(block_index);
+      unsafe { device.free_memory(block.memory, None) };
+    }
+  }
+
+  pub fn stats(&self) -> Vec<HeapStats> {
+    let mut stats: Vec<HeapStats> = (0..self.memory_properties.memory_heap_count).map(|heap_index| HeapStats {
+      heap_index,
+      heap_size : self.memory_properties.memory_heaps[heap_index as usize].size,
+      ..Default::default()
+    }).collect();
+
+    for pool in self.pools.values() {
+      let heap = &mut stats[pool.heap_index as usize];
+      for block in &pool.blocks {
+        heap.used_bytes += block.used();
+        heap.reserved_bytes += block.size;
+        heap.allocation_count += block.suballocations.len();
+        heap.block_count += 1;
+      }
+    }
+    stats
+  }
+
+  // Cheap enough to ask every frame, see `DEFRAGMENTATION_THRESHOLD`
+  pub fn needs_defragmentation(&self) -> bool {
+    self.freed && self.pools.iter().any(|(&(_, strategy), pool)| strategy == AllocationStrategy::FreeList && pool.is_fragmented())
+  }
+
+  // Plans moves out of the emptiest free-list blocks into gaps of fuller ones, so whole blocks can be released.
+  // Only allocations accepted by `movable` are considered, the caller owns the resources bound to them
+  pub fn plan_defragmentation<F: Fn(&Allocation) -> bool>(&mut self, movable: F) -> Vec<DefragmentationMove> {
+    let granularity = self.granularity;
+    let mut moves = Vec::new();
+    self.freed = false;
+
+    for (&(memory_type, strategy), pool) in self.pools.iter_mut() {
+      if strategy != AllocationStrategy::FreeList {
+        continue;
+      }
+
+      let mut order: Vec<usize> = (0..pool.blocks.len()).filter(|&index| !pool.blocks[index].dedicated).collect();
+      if order.len() < 2 {
+        continue;
+      }
+      order.sort_by_key(|&index| pool.blocks[index].used());
+
+      for (position, &source_index) in order.iter().enumerate() {
+        let sources: Vec<Suballocation> = pool.blocks[source_index].suballocations.clone();
+        for suballocation in sources {
+          let source = GpuAllocator::allocation_in(&pool.blocks[source_index], &suballocation, memory_type, strategy);
+          if !movable(&source) {
+            continue;
+          }
+
+          for &target_index in &order[position + 1..] {
+            let target = &mut pool.blocks[target_index];
+            if let Some((offset, insert_at)) = target.find_space(suballocation.size, suballocation.alignment, suballocation.kind, strategy, granularity) {
+              let placed = Suballocation { offset, ..suballocation };
+              target.suballocations.insert(insert_at, placed);
+              let destination = GpuAllocator::allocation_in(target, &placed, memory_type, strategy);
+              moves.push(DefragmentationMove { source, destination });
+              break;
+            }
+          }
+        }
+      }
+    }
+    moves
+  }
+
+  // Every allocation must have been freed, the device must be idle
+  pub fn destroy(&mut self, device: &Device) {
+    for (_, pool) in self.pools.drain() {
+      for block in pool.blocks {
+        if !block.suballocations.is_empty() {
+          eprintln!("Destroying a memory block with {} live allocations", block.suballocations.len());
+        }
+        unsafe { device.free_memory(block.memory, None) };
+      }
+    }
+  }
+
+  fn allocation_in(block: &MemoryBlock, suballocation: &Suballocation, memory_type: u32, strategy: AllocationStrategy) -> Allocation {
+    Allocation {
+      memory   : block.memory,
+      offset   : suballocation.offset,
+      size     : suballocation.size,
+      mapped   : block.mapped.map(|ptr| unsafe { ptr.add(suballocation.offset as usize) }),
+      memory_type,
+      strategy,
+      block_id : block.id,
+    }
+  }
+
+  fn create_block(
+    device            : &Device,
+    memory_properties : &PhysicalDeviceMemoryProperties,
+    memory_type       : u32,
+    size              : DeviceSize,
+    dedicated         : bool,
+    id                : u64
+  ) -> Result<MemoryBlock, vk::Result> {
+    let alloc_info = MemoryAllocateInfo {
+      allocation_size   : size,
+      memory_type_index : memory_type,
+      ..Default::default()
+    };
+    let memory = unsafe { device.allocate_memory(&alloc_info, None)? };
+
+    let host_visible = memory_properties.memory_types[memory_type as usize].property_flags.contains(MemoryPropertyFlags::HOST_VISIBLE);
+    let mapped = match host_visible {
+      true => match unsafe { device.map_memory(memory, 0, vk::WHOLE_SIZE, MemoryMapFlags::empty()) } {
+        Ok(ptr) => Some(ptr as *mut u8),
+        Err(err) => {
+          unsafe { device.free_memory(memory, None) };
+          return Err(err);
+        }
+      },
+      false => None,
+    };
+
+    Ok(MemoryBlock { id, memory, size, mapped, dedicated, suballocations: Vec::new() })
+  }
+
+  fn find_memory_type(&self, type_filter: u32, properties: MemoryPropertyFlags) -> Option<u32> {
+    (0..self.memory_properties.memory_type_count).find(|&index| {
+      (type_filter & (1 << index)) != 0 && self.memory_properties.memory_types[index as usize].property_flags.contains(properties)
+    })
+  }
+
+  // Small heaps (integrated GPUs, the host visible BAR) get proportionally smaller blocks
+  fn block_size(&self, heap_index: u32) -> DeviceSize {
+    let heap_size = self.memory_properties.memory_heaps[heap_index as usize].size;
+    match heap_size <= SMALL_HEAP_SIZE {
+      true  => (heap_size / 8).min(DEFAULT_BLOCK_SIZE),
+      false => DEFAULT_BLOCK_SIZE,
+    }
+  }
+}
+
+fn align_up(value: DeviceSize, alignment: DeviceSize) -> DeviceSize {
+  value.div_ceil(alignment) * alignment
+}
+
+// Whether the last byte of one resource and the first byte of the next share a granularity page
+fn on_same_page(end_of_previous: DeviceSize, start_of_next: DeviceSize, granularity: DeviceSize) -> bool {
+  end_of_previous / granularity == start_of_next / granularity
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const BLOCK_SIZE: DeviceSize = 1024;
+
+  fn suballocation(offset: DeviceSize, size: DeviceSize) -> Suballocation {
+    Suballocation { offset, size, alignment: 16, kind: AllocationKind::Linear }
+  }
+
+  // Blocks without memory behind them, enough for planning which never touches the device
+  fn synthetic_allocator(strategy: AllocationStrategy, blocks: Vec<Vec<Suballocation>>) -> GpuAllocator {
+    let blocks: Vec<MemoryBlock> = blocks.into_iter().enumerate().map(|(id, suballocations)| MemoryBlock {
+      id        : id as u64,
+      memory    : DeviceMemory::null(),
+      size      : BLOCK_SIZE,
+      mapped    : None,
+      dedicated : false,
+      suballocations,
+    }).collect();
+    let next_block_id = blocks.len() as u64;
+    GpuAllocator {
+      memory_properties : PhysicalDeviceMemoryProperties::default(),
+      granularity       : 1,
+      pools             : HashMap::from([((0, strategy), MemoryPool { heap_index: 0, blocks })]),
+      next_block_id,
+      freed             : true,
+    }
+  }
+
+  fn block_offsets(allocator: &GpuAllocator, strategy: AllocationStrategy, block_id: u64) -> Vec<DeviceSize> {
+    allocator.pools[&(0, strategy)].blocks.iter()
+      .find(|block| block.id == block_id)
+      .map(|block| block.suballocations.iter().map(|suballocation| suballocation.offset).collect())
+      .unwrap()
+  }
+
+  #[test]
+  fn sparse_block_moves_into_fuller_block() {
+    let mut allocator = synthetic_allocator(AllocationStrategy::FreeList, vec![
+      vec![suballocation(0, 512), suballocation(768, 128)],
+      vec![suballocation(256, 64)],
+    ]);
+    assert!(allocator.needs_defragmentation());
+
+    let moves = allocator.plan_defragmentation(|_| true);
+    assert_eq!(moves.len(), 1);
+    assert_eq!((moves[0].source.block_id, moves[0].source.offset, moves[0].source.size), (1, 256, 64));
+    // Best fit picks the smaller gap at the end over the one between the two allocations
+    assert_eq!((moves[0].destination.block_id, moves[0].destination.offset), (0, 896));
+    assert_eq!(block_offsets(&allocator, AllocationStrategy::FreeList, 0), vec![0, 768, 896]);
+    // Until the caller frees the sources nothing changed that warrants another plan
+    assert!(!allocator.needs_defragmentation());
+  }
+
+  #[test]
+  fn alignment_is_kept_when_moving() {
+    let mut allocator = synthetic_allocator(AllocationStrategy::FreeList, vec![
+      vec![suballocation(0, 500)],
+      vec![suballocation(0, 100)],
+    ]);
+    let moves = allocator.plan_defragmentation(|_| true);
+    assert_eq!(moves.len(), 1);
+    assert_eq!(moves[0].destination.offset, 512);
+  }
+
+  #[test]
+  fn only_movable_allocations_are_planned() {
+    let mut allocator = synthetic_allocator(AllocationStrategy::FreeList, vec![
+      vec![suballocation(0, 512)],
+      vec![suballocation(0, 64), suballocation(128, 64)],
+    ]);
+    let moves = allocator.plan_defragmentation(|allocation| allocation.offset == 128);
+    assert_eq!(moves.len(), 1);
+    assert_eq!((moves[0].source.block_id, moves[0].source.offset), (1, 128));
+  }
+
+  #[test]
+  fn full_blocks_are_not_fragmented() {
+    let allocator = synthetic_allocator(AllocationStrategy::FreeList, vec![
+      vec![suballocation(0, 1000)],
+      vec![suballocation(0, 200)],
+    ]);
+    // The emptiest block is under the threshold but nothing else has room for it
+    assert!(!allocator.needs_defragmentation());
+
+    let allocator = synthetic_allocator(AllocationStrategy::FreeList, vec![
+      vec![suballocation(0, 512)],
+      vec![suballocation(0, 384)],
+    ]);
+    // Room for it, but the emptiest block is too full to be worth the copies
+    assert!(!allocator.needs_defragmentation());
+  }
+
+  #[test]
+  fn linear_pools_are_left_alone() {
+    let mut allocator = synthetic_allocator(AllocationStrategy::Linear, vec![
+      vec![suballocation(0, 128)],
+      vec![suballocation(0, 64)],
+    ]);
+    assert!(!allocator.needs_defragmentation());
+    assert!(allocator.plan_defragmentation(|_| true).is_empty());
+  }
+}
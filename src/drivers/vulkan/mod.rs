@@ -1,4 +1,16 @@
 pub mod window;
 pub mod vulkan_instance;
 pub mod vulkan_resources;
-pub mod pipeline;
\ No newline at end of file
+pub mod pipeline;
+pub mod pipeline_cache;
+pub mod offscreen;
+pub mod attachment;
+pub mod buffer;
+pub mod memory;
+pub mod texture;
+pub mod descriptor;
+pub mod uniform;
+pub mod reflect;
+pub mod shader_compiler;
+pub mod debug;
+pub mod device_selection;
@@ -0,0 +1,160 @@
+use ash::{
+  vk::{
+    self, BufferImageCopy, BufferUsageFlags, CommandBuffer, DeviceSize, Extent2D, Extent3D, Format, Framebuffer, Image, ImageAspectFlags, ImageLayout, ImageSubresourceLayers, ImageUsageFlags, ImageView, MemoryPropertyFlags, RenderPass
+  },
+  Device
+};
+use image::RgbaImage;
+
+use crate::drivers::renderer::{Application, RendererError};
+use super::attachment::{self, DepthBuffer};
+use super::buffer::GpuBuffer;
+use super::memory::{Allocation, AllocationStrategy, GpuAllocator};
+use super::vulkan_instance::VulkanInstance;
+
+pub const OFFSCREEN_COLOR_FORMAT: Format = Format::R8G8B8A8_UNORM;
+
+// Color + depth images rendered without a surface, plus a host visible buffer the color image is copied into
+pub struct OffscreenTarget {
+  pub extent       : Extent2D,
+  pub framebuffer  : Framebuffer,
+  color_image      : Image,
+  color_allocation : Allocation,
+  color_view       : ImageView,
+  depth            : DepthBuffer,
+  readback         : GpuBuffer,
+}
+
+impl OffscreenTarget {
+
+  pub fn new(
+    allocator    : &mut GpuAllocator,
+    device       : &Device,
+    render_pass  : RenderPass,
+    extent       : Extent2D,
+    depth_format : Format
+  ) -> Result<Self, vk::Result> {
+
+    let (color_image, color_allocation) = attachment::create_image(
+      allocator, device, extent, 1, OFFSCREEN_COLOR_FORMAT,
+      ImageUsageFlags::COLOR_ATTACHMENT | ImageUsageFlags::TRANSFER_SRC
+    )?;
+    let color_view = attachment::create_view(device, color_image, OFFSCREEN_COLOR_FORMAT, ImageAspectFlags::COLOR, 1)?;
+    let depth = DepthBuffer::new(allocator, device, extent, depth_format)?;
+
+    let attachments = [color_view, depth.view];
+    let framebuffer_info = vk::FramebufferCreateInfo::builder()
+      .render_pass(render_pass)
+      .attachments(&attachments)
+      .width(extent.width)
+      .height(extent.height)
+      .layers(1)
+      .build();
+    let framebuffer = unsafe { device.create_framebuffer(&framebuffer_info, None)? };
+
+    let readback = GpuBuffer::new(
+      allocator, device,
+      (extent.width * extent.height * 4) as DeviceSize,
+      BufferUsageFlags::TRANSFER_DST,
+      MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT,
+      AllocationStrategy::FreeList
+    )?;
+
+    Ok(OffscreenTarget {
+      extent,
+      framebuffer,
+      color_image,
+      color_allocation,
+      color_view,
+      depth,
+      readback,
+    })
+  }
+
+  // Must be recorded after the render pass, which leaves the color image in TRANSFER_SRC_OPTIMAL and orders
+  // its color writes before transfer reads through the pass's outgoing dependency
+  pub fn record_readback(&self, device: &Device, command_buffer: CommandBuffer) {
+    let region = BufferImageCopy::builder()
+      .buffer_offset(0)
+      .buffer_row_length(0)
+      .buffer_image_height(0)
+      .image_subresource(ImageSubresourceLayers {
+        aspect_mask      : ImageAspectFlags::COLOR,
+        mip_level        : 0,
+        base_array_layer : 0,
+        layer_count      : 1,
+      })
+      .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+      .image_extent(Extent3D { width: self.extent.width, height: self.extent.height, depth: 1 })
+      .build();
+
+    let host_barrier = vk::BufferMemoryBarrier::builder()
+      .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+      .dst_access_mask(vk::AccessFlags::HOST_READ)
+      .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+      .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+      .buffer(self.readback.buffer)
+      .offset(0)
+      .size(vk::WHOLE_SIZE)
+      .build();
+
+    unsafe {
+      device.cmd_copy_image_to_buffer(
+        command_buffer,
+        self.color_image,
+        ImageLayout::TRANSFER_SRC_OPTIMAL,
+        self.readback.buffer,
+        &[region]
+      );
+      device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::TRANSFER,
+        vk::PipelineStageFlags::HOST,
+        vk::DependencyFlags::empty(),
+        &[],
+        &[host_barrier],
+        &[]
+      );
+    }
+  }
+
+  // Only valid once the command buffer holding `record_readback` has finished executing
+  pub fn read_pixels(&self) -> RgbaImage {
+    let mut pixels = vec![0u8; (self.extent.width * self.extent.height * 4) as usize];
+    self.readback.read(&mut pixels);
+    RgbaImage::from_raw(self.extent.width, self.extent.height, pixels).expect("Readback buffer size mismatch")
+  }
+
+  pub fn destroy(self, device: &Device, allocator: &mut GpuAllocator) {
+    unsafe {
+      device.destroy_framebuffer(self.framebuffer, None);
+      device.destroy_image_view(self.color_view, None);
+      device.destroy_image(self.color_image, None);
+    }
+    allocator.free(device, self.color_allocation);
+    self.depth.destroy(device, allocator);
+    self.readback.destroy(device, allocator);
+  }
+}
+
+// Runs one init + frame of `app` without a window and returns the rendered pixels
+pub fn render_offscreen(app: &mut dyn Application, width: u32, height: u32) -> Result<RgbaImage, RendererError> {
+  let mut vulkan_instance = VulkanInstance::new_offscreen("Vulkan Offscreen", "Vulkan Renderer")?;
+  vulkan_instance
+    .configure_hardware().map_err(|err| RendererError::Backend(err.to_string()))?
+    .create_logical_device()?
+    .configure_offscreen_extent(width, height)
+    .create_render_pass()?
+    .create_offscreen_target()?
+    .allocate_resources()
+    .create_command_pool()
+    .allocate_command_buffers()
+    .create_synchronization_objects();
+
+  app.init(&mut vulkan_instance)?;
+  app.frame(&mut vulkan_instance, 0.0)?;
+
+  let image = vulkan_instance.read_offscreen_image();
+  vulkan_instance.destroy_offscreen_target();
+  image
+}
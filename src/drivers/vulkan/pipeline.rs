@@ -1,21 +1,453 @@
-use std::{ffi::CString, io::Read,};
+use std::ffi::CString;
+use std::hash::{Hash, Hasher};
 use ash::{
   vk::{
-    self, ColorComponentFlags, CullModeFlags, Extent2D, FrontFace, GraphicsPipelineCreateInfo, Offset2D, Pipeline, PipelineCache, PipelineColorBlendAttachmentState, PipelineColorBlendAttachmentStateBuilder, PipelineColorBlendStateCreateInfo, PipelineInputAssemblyStateCreateInfo, PipelineLayout, PipelineMultisampleStateCreateInfo, PipelineRasterizationStateCreateInfo, PipelineShaderStageCreateInfo, PipelineVertexInputStateCreateInfo, PipelineViewportStateCreateInfo, PolygonMode, PrimitiveTopology, Rect2D, SampleCountFlags, ShaderModule, ShaderStageFlags, VertexInputAttributeDescription, VertexInputBindingDescription, VertexInputRate, Viewport
+    self, BlendFactor, BlendOp, ColorComponentFlags, CompareOp, CullModeFlags, DynamicState, Format, FrontFace, GraphicsPipelineCreateInfo, PhysicalDeviceFeatures, Pipeline, PipelineCache, PipelineColorBlendAttachmentState, PipelineColorBlendStateCreateInfo, PipelineDepthStencilStateCreateInfo, PipelineDynamicStateCreateInfo, PipelineInputAssemblyStateCreateInfo, PipelineLayout, PipelineMultisampleStateCreateInfo, PipelineRasterizationStateCreateInfo, PipelineShaderStageCreateInfo, PipelineVertexInputStateCreateInfo, PipelineViewportStateCreateInfo, PolygonMode, PrimitiveTopology, SampleCountFlags, ShaderModule, ShaderStageFlags, StencilOp, StencilOpState, VertexInputAttributeDescription, VertexInputBindingDescription, VertexInputRate
   },
   Device
 };
 
+use crate::drivers::renderer::{RendererError, VertexLayout};
+use super::reflect::{ShaderReflection, SpirvError};
+use super::shader_compiler::{CompiledShader, ShaderCompileError, ShaderCompiler};
 use super::vulkan_resources::Vertex;
 
+// `shader_path` is GLSL, WGSL or precompiled SPIR-V, see `ShaderCompiler`
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub struct ShaderStageConfig {
   pub stage       : ShaderStageFlags,
   pub shader_path : String,
-  pub entry_point : String
+  pub entry_point : String,
+  pub defines     : Vec<(String, String)>
 }
 
+impl ShaderStageConfig {
+  pub fn compile(&self, compiler: &ShaderCompiler) -> Result<CompiledShader, ShaderCompileError> {
+    compiler.compile(self.shader_path.as_ref(), self.stage, &self.entry_point, &self.defines)
+  }
+
+  // Parses the stage's compiled SPIR-V and checks it declares the configured entry point
+  pub fn reflect(&self, spirv: &[u32]) -> Result<ShaderReflection, SpirvError> {
+    let reflection = ShaderReflection::from_words(spirv)?;
+    if !reflection.has_entry_point(&self.entry_point, self.stage) {
+      return Err(SpirvError::MissingEntryPoint { name: self.entry_point.clone(), stage: self.stage });
+    }
+    Ok(reflection)
+  }
+}
+
+// Depth offset applied while rasterizing, the usual fix for shadow acne. A `clamp` of 0.0 means unclamped
+#[derive(Clone, Copy, Debug)]
+pub struct DepthBias {
+  pub constant_factor : f32,
+  pub clamp           : f32,
+  pub slope_factor    : f32
+}
+
+impl DepthBias {
+  pub fn new(constant_factor: f32, slope_factor: f32) -> Self {
+    DepthBias { constant_factor, clamp: 0.0, slope_factor }
+  }
+
+  fn bits(&self) -> [u32; 3] {
+    [self.constant_factor.to_bits(), self.clamp.to_bits(), self.slope_factor.to_bits()]
+  }
+}
+
+// Compared bit for bit, so configs can be used as hash keys
+impl PartialEq for DepthBias {
+  fn eq(&self, other: &Self) -> bool {
+    self.bits() == other.bits()
+  }
+}
+
+impl Eq for DepthBias {}
+
+impl Hash for DepthBias {
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    self.bits().hash(state);
+  }
+}
+
+// Blend equation of one color attachment
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BlendState {
+  pub enable     : bool,
+  pub src_color  : BlendFactor,
+  pub dst_color  : BlendFactor,
+  pub color_op   : BlendOp,
+  pub src_alpha  : BlendFactor,
+  pub dst_alpha  : BlendFactor,
+  pub alpha_op   : BlendOp,
+  pub write_mask : ColorComponentFlags
+}
+
+impl BlendState {
+  pub fn opaque() -> Self {
+    BlendState {
+      enable     : false,
+      src_color  : BlendFactor::ONE,
+      dst_color  : BlendFactor::ZERO,
+      color_op   : BlendOp::ADD,
+      src_alpha  : BlendFactor::ONE,
+      dst_alpha  : BlendFactor::ZERO,
+      alpha_op   : BlendOp::ADD,
+      write_mask : ColorComponentFlags::R | ColorComponentFlags::G | ColorComponentFlags::B | ColorComponentFlags::A
+    }
+  }
+
+  // Straight (non-premultiplied) alpha: src * a + dst * (1 - a)
+  pub fn alpha() -> Self {
+    BlendState {
+      enable    : true,
+      src_color : BlendFactor::SRC_ALPHA,
+      dst_color : BlendFactor::ONE_MINUS_SRC_ALPHA,
+      src_alpha : BlendFactor::ONE,
+      dst_alpha : BlendFactor::ONE_MINUS_SRC_ALPHA,
+      ..BlendState::opaque()
+    }
+  }
+
+  pub fn premultiplied_alpha() -> Self {
+    BlendState {
+      enable    : true,
+      src_color : BlendFactor::ONE,
+      dst_color : BlendFactor::ONE_MINUS_SRC_ALPHA,
+      src_alpha : BlendFactor::ONE,
+      dst_alpha : BlendFactor::ONE_MINUS_SRC_ALPHA,
+      ..BlendState::opaque()
+    }
+  }
+
+  pub fn additive() -> Self {
+    BlendState {
+      enable    : true,
+      src_color : BlendFactor::SRC_ALPHA,
+      dst_color : BlendFactor::ONE,
+      src_alpha : BlendFactor::ONE,
+      dst_alpha : BlendFactor::ONE,
+      ..BlendState::opaque()
+    }
+  }
+
+  fn to_vk(self) -> PipelineColorBlendAttachmentState {
+    PipelineColorBlendAttachmentState::builder()
+      .blend_enable(self.enable)
+      .src_color_blend_factor(self.src_color)
+      .dst_color_blend_factor(self.dst_color)
+      .color_blend_op(self.color_op)
+      .src_alpha_blend_factor(self.src_alpha)
+      .dst_alpha_blend_factor(self.dst_alpha)
+      .alpha_blend_op(self.alpha_op)
+      .color_write_mask(self.write_mask)
+      .build()
+  }
+}
+
+// Stencil test and update for one face
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct StencilFace {
+  pub fail_op       : StencilOp,
+  pub pass_op       : StencilOp,
+  pub depth_fail_op : StencilOp,
+  pub compare_op    : CompareOp,
+  pub compare_mask  : u32,
+  pub write_mask    : u32,
+  pub reference     : u32
+}
+
+impl StencilFace {
+  pub fn keep() -> Self {
+    StencilFace {
+      fail_op       : StencilOp::KEEP,
+      pass_op       : StencilOp::KEEP,
+      depth_fail_op : StencilOp::KEEP,
+      compare_op    : CompareOp::ALWAYS,
+      compare_mask  : 0xff,
+      write_mask    : 0xff,
+      reference     : 0
+    }
+  }
+
+  fn to_vk(self) -> StencilOpState {
+    StencilOpState {
+      fail_op       : self.fail_op,
+      pass_op       : self.pass_op,
+      depth_fail_op : self.depth_fail_op,
+      compare_op    : self.compare_op,
+      compare_mask  : self.compare_mask,
+      write_mask    : self.write_mask,
+      reference     : self.reference
+    }
+  }
+}
+
+// Everything a graphics pipeline bakes in besides its layout and render pass. The defaults are a solid,
+// back-face culled, depth tested and unblended pipeline with a dynamic viewport and scissor.
+// `sample_count` must match the render pass and `color_blend` needs one entry per color attachment,
+// with an empty `write_mask` for attachments the pipeline leaves alone
+#[derive(Clone)]
 pub struct PipelineConfig {
-  pub shader_stages: Vec<ShaderStageConfig>
+  pub shader_stages     : Vec<ShaderStageConfig>,
+  pub vertex_bindings   : Vec<VertexInputBindingDescription>,
+  pub vertex_attributes : Vec<VertexInputAttributeDescription>,
+  pub topology          : PrimitiveTopology,
+  pub primitive_restart : bool,
+  pub polygon_mode      : PolygonMode,
+  pub line_width        : f32,
+  pub cull_mode         : CullModeFlags,
+  pub front_face        : FrontFace,
+  pub depth_clamp       : bool,
+  pub depth_bias        : Option<DepthBias>,
+  pub depth_test        : bool,
+  pub depth_write       : bool,
+  pub depth_compare_op  : CompareOp,
+  pub stencil_test      : bool,
+  pub stencil_front     : StencilFace,
+  pub stencil_back      : StencilFace,
+  pub sample_count      : SampleCountFlags,
+  pub alpha_to_coverage : bool,
+  pub color_blend       : Vec<BlendState>,
+  pub blend_constants   : [f32; 4],
+  pub dynamic_states    : Vec<DynamicState>
+}
+
+// (binding, stride, input rate) and (location, binding, format, offset) of the vertex input
+type VertexInputKey = (Vec<(u32, u32, i32)>, Vec<(u32, u32, i32, u32)>);
+
+impl PipelineConfig {
+  pub fn new(shader_stages: Vec<ShaderStageConfig>) -> Self {
+    PipelineConfig {
+      shader_stages,
+      vertex_bindings   : vec![Vertex::binding_description()],
+      vertex_attributes : Vertex::attribute_descriptions().to_vec(),
+      topology          : PrimitiveTopology::TRIANGLE_LIST,
+      primitive_restart : false,
+      polygon_mode      : PolygonMode::FILL,
+      line_width        : 1.0,
+      cull_mode         : CullModeFlags::BACK,
+      front_face        : FrontFace::CLOCKWISE,
+      depth_clamp       : false,
+      depth_bias        : None,
+      depth_test        : true,
+      depth_write       : true,
+      depth_compare_op  : CompareOp::LESS,
+      stencil_test      : false,
+      stencil_front     : StencilFace::keep(),
+      stencil_back      : StencilFace::keep(),
+      sample_count      : SampleCountFlags::TYPE_1,
+      alpha_to_coverage : false,
+      color_blend       : vec![BlendState::opaque()],
+      blend_constants   : [0.0; 4],
+      // Viewport and scissor are set per frame, so pipelines survive swapchain recreation
+      dynamic_states    : vec![DynamicState::VIEWPORT, DynamicState::SCISSOR]
+    }
+  }
+
+  pub fn with_depth(mut self, test: bool, write: bool, compare_op: CompareOp) -> Self {
+    self.depth_test       = test;
+    self.depth_write      = write;
+    self.depth_compare_op = compare_op;
+    self
+  }
+
+  pub fn with_topology(mut self, topology: PrimitiveTopology, primitive_restart: bool) -> Self {
+    self.topology          = topology;
+    self.primitive_restart = primitive_restart;
+    self
+  }
+
+  // LINE and POINT need the fillModeNonSolid feature, widths other than 1.0 need wideLines
+  pub fn with_polygon_mode(mut self, polygon_mode: PolygonMode, line_width: f32) -> Self {
+    self.polygon_mode = polygon_mode;
+    self.line_width   = line_width;
+    self
+  }
+
+  pub fn with_culling(mut self, cull_mode: CullModeFlags, front_face: FrontFace) -> Self {
+    self.cull_mode  = cull_mode;
+    self.front_face = front_face;
+    self
+  }
+
+  pub fn with_depth_bias(mut self, depth_bias: Option<DepthBias>) -> Self {
+    self.depth_bias = depth_bias;
+    self
+  }
+
+  pub fn with_stencil(mut self, front: StencilFace, back: StencilFace) -> Self {
+    self.stencil_test  = true;
+    self.stencil_front = front;
+    self.stencil_back  = back;
+    self
+  }
+
+  pub fn with_samples(mut self, sample_count: SampleCountFlags, alpha_to_coverage: bool) -> Self {
+    self.sample_count      = sample_count;
+    self.alpha_to_coverage = alpha_to_coverage;
+    self
+  }
+
+  // Same blend equation on every color attachment
+  pub fn with_blend(mut self, blend: BlendState) -> Self {
+    self.color_blend = vec![blend; self.color_blend.len().max(1)];
+    self
+  }
+
+  pub fn with_attachment_blends(mut self, color_blend: Vec<BlendState>, blend_constants: [f32; 4]) -> Self {
+    self.color_blend     = color_blend;
+    self.blend_constants = blend_constants;
+    self
+  }
+
+  pub fn with_dynamic_state(mut self, dynamic_state: DynamicState) -> Self {
+    if !self.dynamic_states.contains(&dynamic_state) {
+      self.dynamic_states.push(dynamic_state);
+    }
+    self
+  }
+
+  // Edges only and nothing culled, for debug views
+  pub fn wireframe(self) -> Self {
+    self
+      .with_polygon_mode(PolygonMode::LINE, 1.0)
+      .with_culling(CullModeFlags::NONE, FrontFace::CLOCKWISE)
+  }
+
+  // Alpha blended over what is already drawn, still depth tested but not hiding what is drawn after it
+  pub fn transparent(self) -> Self {
+    let compare_op = self.depth_compare_op;
+    self
+      .with_blend(BlendState::alpha())
+      .with_depth(true, false, compare_op)
+  }
+
+  // Depth only, for render passes without color attachments. Front faces are culled and the depth biased
+  // to keep lit surfaces from shadowing themselves
+  pub fn shadow(self) -> Self {
+    let front_face = self.front_face;
+    self
+      .with_attachment_blends(Vec::new(), [0.0; 4])
+      .with_culling(CullModeFlags::FRONT, front_face)
+      .with_depth(true, true, CompareOp::LESS_OR_EQUAL)
+      .with_depth_bias(Some(DepthBias::new(1.25, 1.75)))
+  }
+
+  // Device features the config relies on that `features` lacks
+  pub fn missing_features(&self, features: &PhysicalDeviceFeatures) -> Vec<&'static str> {
+    let mut missing = Vec::new();
+    if self.polygon_mode != PolygonMode::FILL && features.fill_mode_non_solid == 0 {
+      missing.push("fillModeNonSolid");
+    }
+    if self.line_width != 1.0 && features.wide_lines == 0 {
+      missing.push("wideLines");
+    }
+    if self.depth_clamp && features.depth_clamp == 0 {
+      missing.push("depthClamp");
+    }
+    if self.depth_bias.is_some_and(|bias| bias.clamp != 0.0) && features.depth_bias_clamp == 0 {
+      missing.push("depthBiasClamp");
+    }
+    if self.color_blend.windows(2).any(|pair| pair[0] != pair[1]) && features.independent_blend == 0 {
+      missing.push("independentBlend");
+    }
+    missing
+  }
+
+  // Attributes are 1 to 4 f32 components, like glVertexAttribPointer takes
+  pub fn with_vertex_layout(mut self, layout: &VertexLayout) -> Result<Self, RendererError> {
+    self.vertex_bindings = vec![
+      VertexInputBindingDescription::builder()
+        .binding(0)
+        .stride(layout.stride)
+        .input_rate(VertexInputRate::VERTEX)
+        .build()
+    ];
+    self.vertex_attributes = layout.attributes.iter().map(|attribute| {
+      let format = match attribute.components {
+        1 => Format::R32_SFLOAT,
+        2 => Format::R32G32_SFLOAT,
+        3 => Format::R32G32B32_SFLOAT,
+        4 => Format::R32G32B32A32_SFLOAT,
+        components => return Err(RendererError::Backend(format!(
+          "Vertex attribute at location {} has {} components, 1 to 4 are supported", attribute.location, components
+        ))),
+      };
+      Ok(VertexInputAttributeDescription::builder()
+        .binding(0)
+        .location(attribute.location)
+        .format(format)
+        .offset(attribute.offset)
+        .build())
+    }).collect::<Result<_, _>>()?;
+    Ok(self)
+  }
+
+  // The vk vertex descriptions and the float fields implement neither Eq nor Hash, these stand in for them
+  fn vertex_input_key(&self) -> VertexInputKey {
+    (
+      self.vertex_bindings.iter().map(|binding| (binding.binding, binding.stride, binding.input_rate.as_raw())).collect(),
+      self.vertex_attributes.iter().map(|attribute| (attribute.location, attribute.binding, attribute.format.as_raw(), attribute.offset)).collect()
+    )
+  }
+
+  fn float_bits(&self) -> [u32; 5] {
+    let [r, g, b, a] = self.blend_constants.map(f32::to_bits);
+    [self.line_width.to_bits(), r, g, b, a]
+  }
+}
+
+// Two equal configs build the same pipeline for a given layout and render pass, so configs can key pipeline caches
+impl PartialEq for PipelineConfig {
+  fn eq(&self, other: &Self) -> bool {
+    self.shader_stages           == other.shader_stages
+      && self.vertex_input_key() == other.vertex_input_key()
+      && self.topology           == other.topology
+      && self.primitive_restart  == other.primitive_restart
+      && self.polygon_mode       == other.polygon_mode
+      && self.cull_mode          == other.cull_mode
+      && self.front_face         == other.front_face
+      && self.depth_clamp        == other.depth_clamp
+      && self.depth_bias         == other.depth_bias
+      && self.depth_test         == other.depth_test
+      && self.depth_write        == other.depth_write
+      && self.depth_compare_op   == other.depth_compare_op
+      && self.stencil_test       == other.stencil_test
+      && self.stencil_front      == other.stencil_front
+      && self.stencil_back       == other.stencil_back
+      && self.sample_count       == other.sample_count
+      && self.alpha_to_coverage  == other.alpha_to_coverage
+      && self.color_blend        == other.color_blend
+      && self.dynamic_states     == other.dynamic_states
+      && self.float_bits()       == other.float_bits()
+  }
+}
+
+impl Eq for PipelineConfig {}
+
+impl Hash for PipelineConfig {
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    self.shader_stages.hash(state);
+    self.vertex_input_key().hash(state);
+    self.topology.hash(state);
+    self.primitive_restart.hash(state);
+    self.polygon_mode.hash(state);
+    self.cull_mode.hash(state);
+    self.front_face.hash(state);
+    self.depth_clamp.hash(state);
+    self.depth_bias.hash(state);
+    self.depth_test.hash(state);
+    self.depth_write.hash(state);
+    self.depth_compare_op.hash(state);
+    self.stencil_test.hash(state);
+    self.stencil_front.hash(state);
+    self.stencil_back.hash(state);
+    self.sample_count.hash(state);
+    self.alpha_to_coverage.hash(state);
+    self.color_blend.hash(state);
+    self.dynamic_states.hash(state);
+    self.float_bits().hash(state);
+  }
 }
 
 pub struct ShaderStage {
@@ -25,15 +457,14 @@ pub struct ShaderStage {
 }
 
 impl ShaderStage {
-  fn new(device: &Device, config: &ShaderStageConfig) -> Self {
-    let shader_code      = GraphicsPipeline::load_shader(&config.shader_path);
-    let module      = GraphicsPipeline::create_shader_module(device, &shader_code);
+  fn new(device: &Device, config: &ShaderStageConfig, shader_code: &[u32]) -> Result<Self, vk::Result> {
+    let module           = GraphicsPipeline::create_shader_module(device, shader_code)?;
     let entry_point_name = CString::new(config.entry_point.as_str()).unwrap();
-    ShaderStage {
+    Ok(ShaderStage {
       stage: config.stage,
       module,
       entry_point_name
-    }
+    })
   }
 }
 
@@ -44,132 +475,222 @@ pub struct GraphicsPipeline {
 }
 
 impl GraphicsPipeline {
+  // `shader_code` holds the SPIR-V of each of the config's shader stages, in the same order.
+  // `pipeline_cache` may be null. On failure nothing created here is left behind
   pub fn new(
-    device: &Device, 
-    render_pass     : vk::RenderPass, 
-    pipeline_layout : vk::PipelineLayout, 
-    pipeline_config : PipelineConfig,
-    extent          : Extent2D
-  ) -> Self {
-    
-    let shader_stages: Vec<ShaderStage> = pipeline_config
-      .shader_stages
-      .iter()
-      .map(|config| ShaderStage::new(device, config))
-      .collect();
-    
+    device          : &Device,
+    render_pass     : vk::RenderPass,
+    pipeline_layout : vk::PipelineLayout,
+    pipeline_config : &PipelineConfig,
+    shader_code     : &[&[u32]],
+    pipeline_cache  : PipelineCache
+  ) -> Result<Self, vk::Result> {
+
+    let mut shader_stages: Vec<ShaderStage> = Vec::with_capacity(shader_code.len());
+    for (config, code) in pipeline_config.shader_stages.iter().zip(shader_code) {
+      match ShaderStage::new(device, config, code) {
+        Ok(stage) => shader_stages.push(stage),
+        Err(err) => {
+          GraphicsPipeline::destroy_shader_modules(device, &shader_stages);
+          return Err(err);
+        }
+      }
+    }
+
     let input_assembly = PipelineInputAssemblyStateCreateInfo::builder()
-      .topology(PrimitiveTopology::TRIANGLE_LIST)
-      .primitive_restart_enable(false)
+      .topology(pipeline_config.topology)
+      .primitive_restart_enable(pipeline_config.primitive_restart)
       .build();
 
-    let viewport = Viewport {
-      x: 0.0,
-      y: 0.0,
-      width    : extent.width as f32,
-      height   : extent.height as f32,
-      min_depth : 0.0,
-      max_depth : 1.0
-    };
-
-    let scissor = Rect2D {
-      offset: Offset2D {x: 0, y: 0},
-      extent
-    };
+    let dynamic_state = PipelineDynamicStateCreateInfo::builder()
+      .dynamic_states(&pipeline_config.dynamic_states)
+      .build();
 
+    // A dynamic depth bias still has to be enabled here, its factors come from vkCmdSetDepthBias
+    let depth_bias = pipeline_config.depth_bias.unwrap_or(DepthBias::new(0.0, 0.0));
     let rasterizer = PipelineRasterizationStateCreateInfo::builder()
-      .depth_clamp_enable(false)
+      .depth_clamp_enable(pipeline_config.depth_clamp)
       .rasterizer_discard_enable(false) // Disables output to framebuffer
-      .polygon_mode(PolygonMode::FILL)  // Solid: FILL, Wireframe: LINE
-      .line_width(1.0)                  // Wireframe Line Width
-      .cull_mode(CullModeFlags::BACK)   // Backface culling (NONE, BACK, FRONT, FRONT_AND_BACK)
-      .front_face(FrontFace::CLOCKWISE) // (CLOCKWISE, COUNTER_CLOCKWISE)
+      .polygon_mode(pipeline_config.polygon_mode)
+      .line_width(pipeline_config.line_width)
+      .cull_mode(pipeline_config.cull_mode)
+      .front_face(pipeline_config.front_face)
+      .depth_bias_enable(pipeline_config.depth_bias.is_some() || pipeline_config.dynamic_states.contains(&DynamicState::DEPTH_BIAS))
+      .depth_bias_constant_factor(depth_bias.constant_factor)
+      .depth_bias_clamp(depth_bias.clamp)
+      .depth_bias_slope_factor(depth_bias.slope_factor)
       .build();
 
     let multisampling = PipelineMultisampleStateCreateInfo::builder()
       .sample_shading_enable(false)
-      .rasterization_samples(SampleCountFlags::TYPE_1) // No Multisampling
+      .rasterization_samples(pipeline_config.sample_count)
+      .alpha_to_coverage_enable(pipeline_config.alpha_to_coverage)
       .build();
 
-    let color_blend_attachment = PipelineColorBlendAttachmentState::builder()
-      .color_write_mask(ColorComponentFlags::R | ColorComponentFlags::G | ColorComponentFlags::B | ColorComponentFlags::A)
-      .blend_enable(false)
+    let depth_stencil = PipelineDepthStencilStateCreateInfo::builder()
+      .depth_test_enable(pipeline_config.depth_test)
+      .depth_write_enable(pipeline_config.depth_write)
+      .depth_compare_op(pipeline_config.depth_compare_op)
+      .depth_bounds_test_enable(false)
+      .stencil_test_enable(pipeline_config.stencil_test)
+      .front(pipeline_config.stencil_front.to_vk())
+      .back(pipeline_config.stencil_back.to_vk())
       .build();
 
+    let color_blend_attachments: Vec<PipelineColorBlendAttachmentState> = pipeline_config.color_blend.iter().map(|blend| blend.to_vk()).collect();
     let color_blending = PipelineColorBlendStateCreateInfo::builder()
       .logic_op_enable(false)
-      .attachments(&[color_blend_attachment])
+      .attachments(&color_blend_attachments)
+      .blend_constants(pipeline_config.blend_constants)
       .build();
 
-      let pipeline_shader_stages: Vec<PipelineShaderStageCreateInfo> = shader_stages.iter().map(|stage| {
-        PipelineShaderStageCreateInfo::builder()
-          .stage(stage.stage)
-          .module(stage.module)
-          .name(stage.entry_point_name.as_c_str())
-          .build()
-      }).collect();
-
-      let vertex_binding_description = Vertex::binding_description();
-      let vertex_attribute_descriptions = Vertex::attribute_descriptions();
-      let vertex_input_info = PipelineVertexInputStateCreateInfo::builder()
-        .vertex_binding_descriptions(&[vertex_binding_description])
-        .vertex_attribute_descriptions(&vertex_attribute_descriptions)
-        .build();
-  
-      let viewport_state = PipelineViewportStateCreateInfo::builder()
-        .viewports(&[viewport])
-        .scissors(&[scissor])
-        .build();
-
-      let pipeline_info = GraphicsPipelineCreateInfo::builder()
-        .stages(&pipeline_shader_stages)
-        .vertex_input_state(&vertex_input_info)
-        .input_assembly_state(&input_assembly)
-        .viewport_state(&viewport_state)
-        .rasterization_state(&rasterizer)
-        .multisample_state(&multisampling)
-        .color_blend_state(&color_blending)
-        .layout(pipeline_layout)
-        .render_pass(render_pass)
-        .subpass(0)
-        .build();
-
-      let graphics_pipeline = unsafe {
-        device.create_graphics_pipelines(PipelineCache::null(), &[pipeline_info], None)
-          .expect("Failed to create Graphics Pipeline")[0]
-      };
-      
-    Self {
-      pipeline: vk::Pipeline::null(),
+    let pipeline_shader_stages: Vec<PipelineShaderStageCreateInfo> = shader_stages.iter().map(|stage| {
+      PipelineShaderStageCreateInfo::builder()
+        .stage(stage.stage)
+        .module(stage.module)
+        .name(stage.entry_point_name.as_c_str())
+        .build()
+    }).collect();
+
+    let vertex_input_info = PipelineVertexInputStateCreateInfo::builder()
+      .vertex_binding_descriptions(&pipeline_config.vertex_bindings)
+      .vertex_attribute_descriptions(&pipeline_config.vertex_attributes)
+      .build();
+
+    let viewport_state = PipelineViewportStateCreateInfo::builder()
+      .viewport_count(1)
+      .scissor_count(1)
+      .build();
+
+    let pipeline_info = GraphicsPipelineCreateInfo::builder()
+      .stages(&pipeline_shader_stages)
+      .vertex_input_state(&vertex_input_info)
+      .input_assembly_state(&input_assembly)
+      .viewport_state(&viewport_state)
+      .rasterization_state(&rasterizer)
+      .multisample_state(&multisampling)
+      .depth_stencil_state(&depth_stencil)
+      .color_blend_state(&color_blending)
+      .dynamic_state(&dynamic_state)
+      .layout(pipeline_layout)
+      .render_pass(render_pass)
+      .subpass(0)
+      .build();
+
+    let graphics_pipeline = match unsafe { device.create_graphics_pipelines(pipeline_cache, &[pipeline_info], None) } {
+      Ok(pipelines) => pipelines[0],
+      Err((_, err)) => {
+        GraphicsPipeline::destroy_shader_modules(device, &shader_stages);
+        return Err(err);
+      }
+    };
+
+    Ok(Self {
+      pipeline: graphics_pipeline,
       pipeline_layout,
       shader_stages
-    }
+    })
   }
 
-  pub fn load_shader(path: &str) -> Vec<u8> {
-    let msg = format!("Failure loading shader from source: {}", path);
-    let mut file = std::fs::File::open(path).expect(&msg);
-    let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer).expect("Failure reading file");
-    buffer
+  pub fn shader_modules(&self) -> impl Iterator<Item = (ShaderStageFlags, ShaderModule)> + '_ {
+    self.shader_stages.iter().map(|stage| (stage.stage, stage.module))
   }
 
-  fn bytes_to_u32_slice(bytes: &[u8]) -> Vec<u32> {
-    assert!(bytes.len() % 4 == 0, "Shader byte code length is not algined to 4");
-    bytes.chunks(4).map(|chunk| {
-      u32::from_le_bytes(chunk.try_into().expect("Slice with incorrect length"))
-    }).collect()
+  // Destroys the pipeline and its shader modules but keeps the layout, which other pipelines may share
+  pub fn retire(&self, device: &Device) {
+    unsafe {
+      device.destroy_pipeline(self.pipeline, None);
+    }
+    GraphicsPipeline::destroy_shader_modules(device, &self.shader_stages);
+  }
+
+  fn destroy_shader_modules(device: &Device, shader_stages: &[ShaderStage]) {
+    for stage in shader_stages {
+      unsafe { device.destroy_shader_module(stage.module, None) };
+    }
   }
 
-  fn create_shader_module(device: &Device, src: &[u8]) -> ShaderModule {
-    let code_u32 = Self::bytes_to_u32_slice(src);
+  fn create_shader_module(device: &Device, code: &[u32]) -> Result<ShaderModule, vk::Result> {
     let create_info = vk::ShaderModuleCreateInfo::builder()
-      .code(&code_u32)
+      .code(code)
       .build();
 
-    unsafe {
-      device.create_shader_module(&create_info, None)
-        .expect("Failed to create Shader Module")
+    unsafe { device.create_shader_module(&create_info, None) }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::hash_map::DefaultHasher;
+  use crate::drivers::renderer::VertexAttribute;
+  use super::*;
+
+  fn config() -> PipelineConfig {
+    PipelineConfig::new(vec![ShaderStageConfig {
+      stage       : ShaderStageFlags::VERTEX,
+      shader_path : "mesh.vert".to_string(),
+      entry_point : "main".to_string(),
+      defines     : Vec::new()
+    }])
+  }
+
+  fn hash(config: &PipelineConfig) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    config.hash(&mut hasher);
+    hasher.finish()
+  }
+
+  #[test]
+  fn vertex_layouts_map_to_float_formats() {
+    let config = config().with_vertex_layout(&VertexLayout::position_tex_normal()).unwrap();
+    assert_eq!(config.vertex_bindings[0].stride, 32);
+    let formats: Vec<Format> = config.vertex_attributes.iter().map(|attribute| attribute.format).collect();
+    assert_eq!(formats, [Format::R32G32B32_SFLOAT, Format::R32G32_SFLOAT, Format::R32G32B32_SFLOAT]);
+  }
+
+  #[test]
+  fn vertex_layouts_reject_unsupported_component_counts() {
+    for components in [0, 5] {
+      let layout = VertexLayout { stride: 16, attributes: vec![VertexAttribute { location: 0, components, offset: 0 }] };
+      assert!(config().with_vertex_layout(&layout).is_err());
+    }
+  }
+
+  #[test]
+  fn configs_differing_in_fixed_function_state_are_distinct_keys() {
+    assert!(config() == config());
+    assert_eq!(hash(&config()), hash(&config()));
+
+    let wireframe = config().with_polygon_mode(PolygonMode::LINE, 1.0);
+    let biased = config().with_depth_bias(Some(DepthBias::new(1.25, 1.75)));
+    let wide = config().with_polygon_mode(PolygonMode::FILL, 2.0);
+    for variant in [wireframe, biased, wide] {
+      assert!(variant != config());
+      assert_ne!(hash(&variant), hash(&config()));
     }
   }
-}
\ No newline at end of file
+
+  #[test]
+  fn presets_adjust_the_fixed_function_state() {
+    let wireframe = config().wireframe();
+    assert_eq!((wireframe.polygon_mode, wireframe.cull_mode), (PolygonMode::LINE, CullModeFlags::NONE));
+
+    let transparent = config().transparent();
+    assert!(transparent.color_blend.iter().all(|blend| blend.enable && blend.dst_color == BlendFactor::ONE_MINUS_SRC_ALPHA));
+    assert!(transparent.depth_test && !transparent.depth_write);
+
+    let shadow = config().shadow();
+    assert!(shadow.color_blend.is_empty());
+    assert_eq!(shadow.cull_mode, CullModeFlags::FRONT);
+    assert!(shadow.depth_bias.is_some());
+  }
+
+  #[test]
+  fn missing_features_are_reported() {
+    let config = config()
+      .wireframe()
+      .with_attachment_blends(vec![BlendState::opaque(), BlendState { write_mask: ColorComponentFlags::empty(), ..BlendState::opaque() }], [0.0; 4]);
+    assert_eq!(config.missing_features(&PhysicalDeviceFeatures::default()), ["fillModeNonSolid", "independentBlend"]);
+    assert!(config.missing_features(&PhysicalDeviceFeatures { fill_mode_non_solid: 1, independent_blend: 1, ..Default::default() }).is_empty());
+  }
+}
@@ -0,0 +1,143 @@
+use std::{error::Error, fmt, fs, io, path::{Path, PathBuf}};
+use ash::{
+  vk::{self, PhysicalDeviceProperties, PipelineCacheCreateInfo, PipelineCacheHeaderVersion},
+  Device
+};
+
+// Length, header version, vendor ID and device ID, then the pipeline cache UUID. All little endian
+const HEADER_SIZE : usize = 16 + vk::UUID_SIZE;
+
+// Why a cache file was not handed to the driver
+#[derive(Debug)]
+pub enum CacheRejection {
+  Io(io::Error),
+  Truncated { length: usize },
+  HeaderVersion(u32),
+  Device { vendor_id: u32, device_id: u32 },
+  Uuid,
+}
+
+impl fmt::Display for CacheRejection {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      CacheRejection::Io(err)                         => write!(f, "I/O error: {}", err),
+      CacheRejection::Truncated { length }            => write!(f, "{} bytes is too short for its header", length),
+      CacheRejection::HeaderVersion(version)          => write!(f, "unknown header version {}", version),
+      CacheRejection::Device { vendor_id, device_id } => write!(f, "written for device {:04x}:{:04x}", vendor_id, device_id),
+      CacheRejection::Uuid                            => write!(f, "written by a different driver version"),
+    }
+  }
+}
+
+impl Error for CacheRejection {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    match self {
+      CacheRejection::Io(err) => Some(err),
+      _ => None,
+    }
+  }
+}
+
+// Drivers are supposed to reject foreign cache data themselves, not all of them do
+pub fn validate_header(data: &[u8], properties: &PhysicalDeviceProperties) -> Result<(), CacheRejection> {
+  let read_u32 = |offset: usize| u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+  if data.len() < HEADER_SIZE || (read_u32(0) as usize) < HEADER_SIZE || read_u32(0) as usize > data.len() {
+    return Err(CacheRejection::Truncated { length: data.len() });
+  }
+  let version = read_u32(4);
+  if version != PipelineCacheHeaderVersion::ONE.as_raw() as u32 {
+    return Err(CacheRejection::HeaderVersion(version));
+  }
+  let (vendor_id, device_id) = (read_u32(8), read_u32(12));
+  if (vendor_id, device_id) != (properties.vendor_id, properties.device_id) {
+    return Err(CacheRejection::Device { vendor_id, device_id });
+  }
+  if data[16..HEADER_SIZE] != properties.pipeline_cache_uuid {
+    return Err(CacheRejection::Uuid);
+  }
+  Ok(())
+}
+
+// A vk::PipelineCache backed by a file, so pipelines compiled by one run are reused by the next
+pub struct PipelineCacheFile {
+  pub cache  : vk::PipelineCache,
+  path       : PathBuf,
+  properties : PhysicalDeviceProperties
+}
+
+impl PipelineCacheFile {
+  // One file per device in the user's cache directory, None on platforms without one
+  pub fn default_path(properties: &PhysicalDeviceProperties) -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| {
+      dir.join("rust_renderer").join(format!("pipelines-{:04x}-{:04x}.bin", properties.vendor_id, properties.device_id))
+    })
+  }
+
+  // Starts from the file's contents when they were written for this device and driver, empty otherwise
+  pub fn load(device: &Device, properties: &PhysicalDeviceProperties, path: PathBuf) -> Result<Self, vk::Result> {
+    let initial_data = match PipelineCacheFile::read(&path, properties) {
+      Ok(data) => data,
+      Err(CacheRejection::Io(err)) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+      Err(rejection) => {
+        println!("Discarding pipeline cache {}: {}", path.display(), rejection);
+        Vec::new()
+      }
+    };
+    let cache = match PipelineCacheFile::create_cache(device, &initial_data) {
+      Ok(cache) => cache,
+      Err(err) if !initial_data.is_empty() => {
+        println!("Discarding pipeline cache {}: the driver rejected it ({})", path.display(), err);
+        PipelineCacheFile::create_cache(device, &[])?
+      },
+      Err(err) => return Err(err),
+    };
+    Ok(PipelineCacheFile { cache, path, properties: *properties })
+  }
+
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  // Folds pipelines compiled into other caches, e.g. one per loader thread, into this one
+  pub fn merge(&self, device: &Device, sources: &[vk::PipelineCache]) -> Result<(), vk::Result> {
+    unsafe { device.merge_pipeline_caches(self.cache, sources) }
+  }
+
+  // Writes the cache back to its file. Whatever another run saved there since this one loaded is merged in
+  // first, and the file is replaced in one rename so a crash cannot leave it half written
+  pub fn save(&self, device: &Device) -> io::Result<()> {
+    let vulkan_error = |err: vk::Result| io::Error::other(err);
+    if let Ok(on_disk) = PipelineCacheFile::read(&self.path, &self.properties) {
+      if let Ok(other) = PipelineCacheFile::create_cache(device, &on_disk) {
+        let merged = self.merge(device, &[other]);
+        unsafe { device.destroy_pipeline_cache(other, None) };
+        merged.map_err(vulkan_error)?;
+      }
+    }
+    let data = unsafe { device.get_pipeline_cache_data(self.cache) }.map_err(vulkan_error)?;
+
+    if let Some(parent) = self.path.parent() {
+      fs::create_dir_all(parent)?;
+    }
+    let staging_path = self.path.with_extension("tmp");
+    fs::write(&staging_path, &data)?;
+    fs::rename(&staging_path, &self.path)
+  }
+
+  pub fn destroy(&self, device: &Device) {
+    unsafe { device.destroy_pipeline_cache(self.cache, None) };
+  }
+
+  fn read(path: &Path, properties: &PhysicalDeviceProperties) -> Result<Vec<u8>, CacheRejection> {
+    let data = fs::read(path).map_err(CacheRejection::Io)?;
+    validate_header(&data, properties)?;
+    Ok(data)
+  }
+
+  fn create_cache(device: &Device, initial_data: &[u8]) -> Result<vk::PipelineCache, vk::Result> {
+    let create_info = PipelineCacheCreateInfo::builder()
+      .initial_data(initial_data)
+      .build();
+    unsafe { device.create_pipeline_cache(&create_info, None) }
+  }
+}
@@ -0,0 +1,688 @@
+use std::{collections::HashMap, error::Error, fmt, io};
+use ash::vk::{ DescriptorType, Format, PushConstantRange, ShaderStageFlags, VertexInputAttributeDescription };
+
+const SPIRV_MAGIC  : u32 = 0x0723_0203;
+const HEADER_WORDS : usize = 5;
+
+// The subset of the SPIR-V grammar reflection needs
+mod op {
+  pub const NAME                : u32 = 5;
+  pub const ENTRY_POINT         : u32 = 15;
+  pub const TYPE_BOOL           : u32 = 20;
+  pub const TYPE_INT            : u32 = 21;
+  pub const TYPE_FLOAT          : u32 = 22;
+  pub const TYPE_VECTOR         : u32 = 23;
+  pub const TYPE_MATRIX         : u32 = 24;
+  pub const TYPE_IMAGE          : u32 = 25;
+  pub const TYPE_SAMPLER        : u32 = 26;
+  pub const TYPE_SAMPLED_IMAGE  : u32 = 27;
+  pub const TYPE_ARRAY          : u32 = 28;
+  pub const TYPE_RUNTIME_ARRAY  : u32 = 29;
+  pub const TYPE_STRUCT         : u32 = 30;
+  pub const TYPE_POINTER        : u32 = 32;
+  pub const CONSTANT            : u32 = 43;
+  pub const VARIABLE            : u32 = 59;
+  pub const DECORATE            : u32 = 71;
+  pub const MEMBER_DECORATE     : u32 = 72;
+}
+
+mod decoration {
+  pub const BLOCK          : u32 = 2;
+  pub const BUFFER_BLOCK   : u32 = 3;
+  pub const ARRAY_STRIDE   : u32 = 6;
+  pub const MATRIX_STRIDE  : u32 = 7;
+  pub const BUILT_IN       : u32 = 11;
+  pub const LOCATION       : u32 = 30;
+  pub const BINDING        : u32 = 33;
+  pub const DESCRIPTOR_SET : u32 = 34;
+  pub const OFFSET         : u32 = 35;
+}
+
+mod storage_class {
+  pub const UNIFORM_CONSTANT : u32 = 0;
+  pub const INPUT            : u32 = 1;
+  pub const UNIFORM          : u32 = 2;
+  pub const PUSH_CONSTANT    : u32 = 9;
+  pub const STORAGE_BUFFER   : u32 = 12;
+}
+
+const DIM_BUFFER       : u32 = 5;
+const DIM_SUBPASS_DATA : u32 = 6;
+
+#[derive(Debug)]
+pub enum SpirvError {
+  Io(io::Error),
+  InvalidMagic(u32),
+  Malformed { word: usize, message: String },
+  Unsupported(String),
+  MissingEntryPoint { name: String, stage: ShaderStageFlags },
+  BindingConflict { set: u32, binding: u32, message: String },
+  VertexInputMismatch { location: u32, name: String, expected: Format, provided: Option<Format> },
+}
+
+impl fmt::Display for SpirvError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      SpirvError::Io(err)                            => write!(f, "I/O error reading SPIR-V: {}", err),
+      SpirvError::InvalidMagic(magic)                => write!(f, "Not a SPIR-V module, magic number is {:#010x}", magic),
+      SpirvError::Malformed { word, message }        => write!(f, "Malformed SPIR-V at word {}: {}", word, message),
+      SpirvError::Unsupported(what)                  => write!(f, "Unsupported SPIR-V construct: {}", what),
+      SpirvError::MissingEntryPoint { name, stage }  => write!(f, "SPIR-V module has no {:?} entry point named '{}'", stage, name),
+      SpirvError::BindingConflict { set, binding, message } => write!(f, "Descriptor set {} binding {}: {}", set, binding, message),
+      SpirvError::VertexInputMismatch { location, name, expected, provided: Some(provided) } => write!(
+        f, "Vertex input '{}' (location {}) expects {:?} but the pipeline provides {:?}", name, location, expected, provided
+      ),
+      SpirvError::VertexInputMismatch { location, name, expected, provided: None } => write!(
+        f, "Vertex input '{}' (location {}) expects {:?} but the pipeline provides no attribute at that location", name, location, expected
+      ),
+    }
+  }
+}
+
+impl Error for SpirvError {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    match self {
+      SpirvError::Io(err) => Some(err),
+      _ => None,
+    }
+  }
+}
+
+impl From<io::Error> for SpirvError {
+  fn from(err: io::Error) -> Self {
+    SpirvError::Io(err)
+  }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct EntryPoint {
+  pub name  : String,
+  pub stage : ShaderStageFlags,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DescriptorBinding {
+  pub set             : u32,
+  pub binding         : u32,
+  pub descriptor_type : DescriptorType,
+  pub count           : u32,
+  pub name            : String, // Instance name, or the block's type name for anonymous blocks
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct VertexInput {
+  pub location : u32,
+  pub format   : Format,
+  pub name     : String,
+}
+
+#[derive(Clone, Debug)]
+pub struct ShaderReflection {
+  pub entry_points        : Vec<EntryPoint>,
+  pub stage               : ShaderStageFlags, // Union of every entry point's stage
+  pub descriptor_bindings : Vec<DescriptorBinding>,
+  pub push_constants      : Vec<PushConstantRange>,
+  pub vertex_inputs       : Vec<VertexInput>, // Sorted by location, empty unless the module has a vertex entry point
+}
+
+#[derive(Clone, Copy, Debug)]
+enum SpirvType {
+  Scalar { class: NumericClass, width: u32 },
+  Vector { component: u32, count: u32 },
+  Matrix { column: u32, count: u32 },
+  Image { dim: u32, sampled: u32 },
+  Sampler,
+  SampledImage,
+  Array { element: u32, length: u32 },
+  RuntimeArray { element: u32 },
+  Struct,
+  Pointer { pointee: u32 },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum NumericClass {
+  Float,
+  Sint,
+  Uint,
+  Bool,
+}
+
+#[derive(Default)]
+struct Decorations {
+  set          : Option<u32>,
+  binding      : Option<u32>,
+  location     : Option<u32>,
+  array_stride : Option<u32>,
+  built_in     : bool,
+  block        : bool,
+  buffer_block : bool,
+}
+
+#[derive(Default)]
+struct MemberDecorations {
+  offset        : Option<u32>,
+  matrix_stride : Option<u32>,
+}
+
+// Everything the instruction stream declares that reflection cares about, keyed by result id
+#[derive(Default)]
+struct Module {
+  names              : HashMap<u32, String>,
+  decorations        : HashMap<u32, Decorations>,
+  member_decorations : HashMap<(u32, u32), MemberDecorations>,
+  types              : HashMap<u32, SpirvType>,
+  struct_members     : HashMap<u32, Vec<u32>>,
+  constants          : HashMap<u32, u32>,
+  variables          : Vec<(u32, u32, u32)>, // (id, pointer type, storage class)
+  entry_points       : Vec<(EntryPoint, Vec<u32>)>, // With the interface ids
+}
+
+impl ShaderReflection {
+
+  pub fn from_words(words: &[u32]) -> Result<Self, SpirvError> {
+    let module = Module::parse(words)?;
+
+    let stage = module.entry_points.iter().fold(ShaderStageFlags::empty(), |stage, (entry_point, _)| stage | entry_point.stage);
+    let vertex_interface: Vec<u32> = module.entry_points.iter()
+      .filter(|(entry_point, _)| entry_point.stage == ShaderStageFlags::VERTEX)
+      .flat_map(|(_, interface)| interface.iter().copied())
+      .collect();
+
+    let mut descriptor_bindings = Vec::new();
+    let mut push_constants = Vec::new();
+    let mut vertex_inputs = Vec::new();
+
+    for &(id, pointer_type, storage_class) in &module.variables {
+      let pointee = match module.types.get(&pointer_type) {
+        Some(SpirvType::Pointer { pointee }) => *pointee,
+        _ => return Err(SpirvError::Malformed { word: 0, message: format!("variable %{} does not have a pointer type", id) }),
+      };
+      let decorations = module.decorations.get(&id);
+
+      match storage_class {
+        storage_class::UNIFORM_CONSTANT | storage_class::UNIFORM | storage_class::STORAGE_BUFFER => {
+          let (set, binding) = match decorations.map(|decorations| (decorations.set, decorations.binding)) {
+            Some((Some(set), Some(binding))) => (set, binding),
+            // Bindings without both decorations are not resources of a Vulkan shader
+            _ => continue,
+          };
+          let (element, count) = module.array_element(pointee);
+          let descriptor_type = module.descriptor_type(element, storage_class)
+            .ok_or(SpirvError::Unsupported(format!("resource %{} at set {} binding {}", id, set, binding)))?;
+          descriptor_bindings.push(DescriptorBinding {
+            set,
+            binding,
+            descriptor_type,
+            count,
+            name : module.name_of(id, element),
+          });
+        },
+        storage_class::PUSH_CONSTANT => {
+          let members = module.struct_members.get(&pointee)
+            .ok_or(SpirvError::Unsupported(format!("push constant %{} is not a struct", id)))?;
+          let offset = (0..members.len() as u32)
+            .filter_map(|member| module.member_decorations.get(&(pointee, member)).and_then(|decorations| decorations.offset))
+            .min()
+            .unwrap_or(0);
+          let size = module.size_of(pointee, None)
+            .ok_or(SpirvError::Unsupported(format!("size of push constant block %{}", id)))?;
+          push_constants.push(PushConstantRange { stage_flags: stage, offset, size: size - offset });
+        },
+        storage_class::INPUT if vertex_interface.contains(&id) => {
+          let location = match decorations {
+            Some(decorations) if !decorations.built_in => match decorations.location {
+              Some(location) => location,
+              None => continue,
+            },
+            _ => continue,
+          };
+          let format = module.vertex_format(pointee)
+            .ok_or(SpirvError::Unsupported(format!("type of vertex input at location {}", location)))?;
+          vertex_inputs.push(VertexInput { location, format, name: module.names.get(&id).cloned().unwrap_or_default() });
+        },
+        _ => (),
+      }
+    }
+
+    descriptor_bindings.sort_by_key(|binding| (binding.set, binding.binding));
+    vertex_inputs.sort_by_key(|input| input.location);
+
+    Ok(ShaderReflection {
+      entry_points : module.entry_points.into_iter().map(|(entry_point, _)| entry_point).collect(),
+      stage,
+      descriptor_bindings,
+      push_constants,
+      vertex_inputs,
+    })
+  }
+
+  pub fn has_entry_point(&self, name: &str, stage: ShaderStageFlags) -> bool {
+    self.entry_points.iter().any(|entry_point| entry_point.name == name && entry_point.stage == stage)
+  }
+
+  // Every input the shader reads needs an attribute with the same numeric type. Component counts may differ,
+  // Vulkan drops extra components and fills missing ones from (0, 0, 0, 1)
+  pub fn validate_vertex_input(&self, attributes: &[VertexInputAttributeDescription]) -> Result<(), SpirvError> {
+    for input in &self.vertex_inputs {
+      let provided = attributes.iter().find(|attribute| attribute.location == input.location).map(|attribute| attribute.format);
+      let compatible = match (provided, format_info(input.format)) {
+        (None, _) => false,
+        (Some(provided), Some((expected_class, _, expected_width))) => match format_info(provided) {
+          Some((class, _, width)) => class == expected_class && width == expected_width,
+          None => true, // Normalized and packed formats are not checked
+        },
+        (Some(_), None) => true,
+      };
+      if !compatible {
+        return Err(SpirvError::VertexInputMismatch {
+          location : input.location,
+          name     : input.name.clone(),
+          expected : input.format,
+          provided,
+        });
+      }
+    }
+    Ok(())
+  }
+}
+
+impl Module {
+
+  fn parse(words: &[u32]) -> Result<Self, SpirvError> {
+    if words.len() < HEADER_WORDS {
+      return Err(SpirvError::Malformed { word: words.len(), message: "module is shorter than its header".to_string() });
+    }
+    if words[0] != SPIRV_MAGIC {
+      return Err(SpirvError::InvalidMagic(words[0]));
+    }
+
+    let mut module = Module::default();
+    let mut position = HEADER_WORDS;
+    while position < words.len() {
+      let word_count = (words[position] >> 16) as usize;
+      let opcode = words[position] & 0xffff;
+      if word_count == 0 || position + word_count > words.len() {
+        return Err(SpirvError::Malformed { word: position, message: format!("instruction with opcode {} overruns the module", opcode) });
+      }
+      let operands = &words[position + 1..position + word_count];
+      module.parse_instruction(opcode, operands).map_err(|message| SpirvError::Malformed { word: position, message })?;
+      position += word_count;
+    }
+    Ok(module)
+  }
+
+  fn parse_instruction(&mut self, opcode: u32, operands: &[u32]) -> Result<(), String> {
+    let operand = |index: usize| operands.get(index).copied().ok_or(format!("opcode {} is missing operand {}", opcode, index));
+    let operands_from = |index: usize| operands.get(index..).ok_or(format!("opcode {} is missing operand {}", opcode, index));
+
+    match opcode {
+      op::NAME => {
+        self.names.insert(operand(0)?, decode_string(operands_from(1)?)?.0);
+      },
+      op::ENTRY_POINT => {
+        let stage = match operand(0)? {
+          0 => ShaderStageFlags::VERTEX,
+          1 => ShaderStageFlags::TESSELLATION_CONTROL,
+          2 => ShaderStageFlags::TESSELLATION_EVALUATION,
+          3 => ShaderStageFlags::GEOMETRY,
+          4 => ShaderStageFlags::FRAGMENT,
+          5 => ShaderStageFlags::COMPUTE,
+          model => return Err(format!("unsupported execution model {}", model)),
+        };
+        let (name, name_words) = decode_string(operands_from(2)?)?;
+        let interface = operands_from(2 + name_words)?.to_vec();
+        self.entry_points.push((EntryPoint { name, stage }, interface));
+      },
+      op::TYPE_BOOL => {
+        self.types.insert(operand(0)?, SpirvType::Scalar { class: NumericClass::Bool, width: 32 });
+      },
+      op::TYPE_INT => {
+        let class = if operand(2)? == 1 { NumericClass::Sint } else { NumericClass::Uint };
+        self.types.insert(operand(0)?, SpirvType::Scalar { class, width: operand(1)? });
+      },
+      op::TYPE_FLOAT => {
+        self.types.insert(operand(0)?, SpirvType::Scalar { class: NumericClass::Float, width: operand(1)? });
+      },
+      op::TYPE_VECTOR => {
+        self.types.insert(operand(0)?, SpirvType::Vector { component: operand(1)?, count: operand(2)? });
+      },
+      op::TYPE_MATRIX => {
+        self.types.insert(operand(0)?, SpirvType::Matrix { column: operand(1)?, count: operand(2)? });
+      },
+      op::TYPE_IMAGE => {
+        self.types.insert(operand(0)?, SpirvType::Image { dim: operand(2)?, sampled: operand(6)? });
+      },
+      op::TYPE_SAMPLER => {
+        self.types.insert(operand(0)?, SpirvType::Sampler);
+      },
+      op::TYPE_SAMPLED_IMAGE => {
+        self.types.insert(operand(0)?, SpirvType::SampledImage);
+      },
+      op::TYPE_ARRAY => {
+        let length = *self.constants.get(&operand(2)?).ok_or("array length is not a known constant".to_string())?;
+        self.types.insert(operand(0)?, SpirvType::Array { element: operand(1)?, length });
+      },
+      op::TYPE_RUNTIME_ARRAY => {
+        self.types.insert(operand(0)?, SpirvType::RuntimeArray { element: operand(1)? });
+      },
+      op::TYPE_STRUCT => {
+        self.types.insert(operand(0)?, SpirvType::Struct);
+        self.struct_members.insert(operand(0)?, operands_from(1)?.to_vec());
+      },
+      op::TYPE_POINTER => {
+        self.types.insert(operand(0)?, SpirvType::Pointer { pointee: operand(2)? });
+      },
+      // Only the low word is kept, enough for array lengths
+      op::CONSTANT => {
+        self.constants.insert(operand(1)?, operand(2)?);
+      },
+      op::VARIABLE => {
+        self.variables.push((operand(1)?, operand(0)?, operand(2)?));
+      },
+      op::DECORATE => {
+        let decorations = self.decorations.entry(operand(0)?).or_default();
+        match operand(1)? {
+          decoration::BLOCK          => decorations.block = true,
+          decoration::BUFFER_BLOCK   => decorations.buffer_block = true,
+          decoration::BUILT_IN       => decorations.built_in = true,
+          decoration::ARRAY_STRIDE   => decorations.array_stride = Some(operand(2)?),
+          decoration::LOCATION       => decorations.location = Some(operand(2)?),
+          decoration::BINDING        => decorations.binding = Some(operand(2)?),
+          decoration::DESCRIPTOR_SET => decorations.set = Some(operand(2)?),
+          _ => (),
+        }
+      },
+      op::MEMBER_DECORATE => {
+        let decorations = self.member_decorations.entry((operand(0)?, operand(1)?)).or_default();
+        match operand(2)? {
+          decoration::OFFSET        => decorations.offset = Some(operand(3)?),
+          decoration::MATRIX_STRIDE => decorations.matrix_stride = Some(operand(3)?),
+          _ => (),
+        }
+      },
+      _ => (),
+    }
+    Ok(())
+  }
+
+  // Arrays of resources become one binding with a descriptor count
+  fn array_element(&self, type_id: u32) -> (u32, u32) {
+    match self.types.get(&type_id) {
+      Some(SpirvType::Array { element, length }) => (*element, *length),
+      Some(SpirvType::RuntimeArray { element }) => (*element, 1),
+      _ => (type_id, 1),
+    }
+  }
+
+  fn descriptor_type(&self, type_id: u32, storage_class: u32) -> Option<DescriptorType> {
+    let decorations = self.decorations.get(&type_id);
+    match (self.types.get(&type_id)?, storage_class) {
+      (SpirvType::SampledImage, _) => Some(DescriptorType::COMBINED_IMAGE_SAMPLER),
+      (SpirvType::Sampler, _) => Some(DescriptorType::SAMPLER),
+      (SpirvType::Image { dim: DIM_BUFFER, sampled: 2 }, _) => Some(DescriptorType::STORAGE_TEXEL_BUFFER),
+      (SpirvType::Image { dim: DIM_BUFFER, .. }, _) => Some(DescriptorType::UNIFORM_TEXEL_BUFFER),
+      (SpirvType::Image { dim: DIM_SUBPASS_DATA, .. }, _) => Some(DescriptorType::INPUT_ATTACHMENT),
+      (SpirvType::Image { sampled: 2, .. }, _) => Some(DescriptorType::STORAGE_IMAGE),
+      (SpirvType::Image { .. }, _) => Some(DescriptorType::SAMPLED_IMAGE),
+      (SpirvType::Struct, storage_class::STORAGE_BUFFER) => Some(DescriptorType::STORAGE_BUFFER),
+      (SpirvType::Struct, storage_class::UNIFORM) if decorations.is_some_and(|decorations| decorations.buffer_block) => Some(DescriptorType::STORAGE_BUFFER),
+      (SpirvType::Struct, storage_class::UNIFORM) if decorations.is_some_and(|decorations| decorations.block) => Some(DescriptorType::UNIFORM_BUFFER),
+      _ => None,
+    }
+  }
+
+  // Anonymous blocks (`uniform Matrices { ... };`) only carry a name on their struct type
+  fn name_of(&self, variable: u32, type_id: u32) -> String {
+    match self.names.get(&variable) {
+      Some(name) if !name.is_empty() => name.clone(),
+      _ => self.names.get(&type_id).cloned().unwrap_or_default(),
+    }
+  }
+
+  fn size_of(&self, type_id: u32, matrix_stride: Option<u32>) -> Option<u32> {
+    match *self.types.get(&type_id)? {
+      SpirvType::Scalar { width, .. } => Some(width / 8),
+      SpirvType::Vector { component, count } => Some(count * self.size_of(component, None)?),
+      SpirvType::Matrix { column, count } => Some(count * matrix_stride.or(self.size_of(column, None))?),
+      SpirvType::Array { element, length } => {
+        let stride = self.decorations.get(&type_id).and_then(|decorations| decorations.array_stride);
+        Some(length * stride.or(self.size_of(element, matrix_stride))?)
+      },
+      SpirvType::Struct => {
+        let members = self.struct_members.get(&type_id)?;
+        members.iter().enumerate().map(|(index, &member)| {
+          let decorations = self.member_decorations.get(&(type_id, index as u32));
+          let offset = decorations.and_then(|decorations| decorations.offset)?;
+          Some(offset + self.size_of(member, decorations.and_then(|decorations| decorations.matrix_stride))?)
+        }).try_fold(0, |size, end| Some(u32::max(size, end?)))
+      },
+      _ => None,
+    }
+  }
+
+  fn vertex_format(&self, type_id: u32) -> Option<Format> {
+    match *self.types.get(&type_id)? {
+      SpirvType::Scalar { class, width } => vertex_format(class, width, 1),
+      SpirvType::Vector { component, count } => match *self.types.get(&component)? {
+        SpirvType::Scalar { class, width } => vertex_format(class, width, count),
+        _ => None,
+      },
+      _ => None,
+    }
+  }
+}
+
+// Literal strings are nul terminated UTF-8 packed four bytes per word. Returns the string and the words it used
+fn decode_string(words: &[u32]) -> Result<(String, usize), String> {
+  let mut bytes = Vec::new();
+  for (index, word) in words.iter().enumerate() {
+    for byte in word.to_le_bytes() {
+      if byte == 0 {
+        return Ok((String::from_utf8_lossy(&bytes).into_owned(), index + 1));
+      }
+      bytes.push(byte);
+    }
+  }
+  Err("string literal is not nul terminated".to_string())
+}
+
+fn vertex_format(class: NumericClass, width: u32, components: u32) -> Option<Format> {
+  let format = match (class, width, components) {
+    (NumericClass::Float, 32, 1) => Format::R32_SFLOAT,
+    (NumericClass::Float, 32, 2) => Format::R32G32_SFLOAT,
+    (NumericClass::Float, 32, 3) => Format::R32G32B32_SFLOAT,
+    (NumericClass::Float, 32, 4) => Format::R32G32B32A32_SFLOAT,
+    (NumericClass::Float, 64, 1) => Format::R64_SFLOAT,
+    (NumericClass::Float, 64, 2) => Format::R64G64_SFLOAT,
+    (NumericClass::Float, 64, 3) => Format::R64G64B64_SFLOAT,
+    (NumericClass::Float, 64, 4) => Format::R64G64B64A64_SFLOAT,
+    (NumericClass::Sint, 32, 1)  => Format::R32_SINT,
+    (NumericClass::Sint, 32, 2)  => Format::R32G32_SINT,
+    (NumericClass::Sint, 32, 3)  => Format::R32G32B32_SINT,
+    (NumericClass::Sint, 32, 4)  => Format::R32G32B32A32_SINT,
+    (NumericClass::Uint, 32, 1)  => Format::R32_UINT,
+    (NumericClass::Uint, 32, 2)  => Format::R32G32_UINT,
+    (NumericClass::Uint, 32, 3)  => Format::R32G32B32_UINT,
+    (NumericClass::Uint, 32, 4)  => Format::R32G32B32A32_UINT,
+    _ => return None,
+  };
+  Some(format)
+}
+
+// (numeric class, component count, bits per component) of the formats `vertex_format` produces
+fn format_info(format: Format) -> Option<(NumericClass, u32, u32)> {
+  let info = match format {
+    Format::R32_SFLOAT          => (NumericClass::Float, 1, 32),
+    Format::R32G32_SFLOAT       => (NumericClass::Float, 2, 32),
+    Format::R32G32B32_SFLOAT    => (NumericClass::Float, 3, 32),
+    Format::R32G32B32A32_SFLOAT => (NumericClass::Float, 4, 32),
+    Format::R64_SFLOAT          => (NumericClass::Float, 1, 64),
+    Format::R64G64_SFLOAT       => (NumericClass::Float, 2, 64),
+    Format::R64G64B64_SFLOAT    => (NumericClass::Float, 3, 64),
+    Format::R64G64B64A64_SFLOAT => (NumericClass::Float, 4, 64),
+    Format::R32_SINT            => (NumericClass::Sint, 1, 32),
+    Format::R32G32_SINT         => (NumericClass::Sint, 2, 32),
+    Format::R32G32B32_SINT      => (NumericClass::Sint, 3, 32),
+    Format::R32G32B32A32_SINT   => (NumericClass::Sint, 4, 32),
+    Format::R32_UINT            => (NumericClass::Uint, 1, 32),
+    Format::R32G32_UINT         => (NumericClass::Uint, 2, 32),
+    Format::R32G32B32_UINT      => (NumericClass::Uint, 3, 32),
+    Format::R32G32B32A32_UINT   => (NumericClass::Uint, 4, 32),
+    _ => return None,
+  };
+  Some(info)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // Hand assembled modules, so every word the parser sees is known
+  fn instruction(opcode: u32, operands: &[u32]) -> Vec<u32> {
+    let mut words = vec![((operands.len() as u32 + 1) << 16) | opcode];
+    words.extend_from_slice(operands);
+    words
+  }
+
+  fn string(text: &str) -> Vec<u32> {
+    let mut bytes = text.as_bytes().to_vec();
+    bytes.resize(bytes.len() / 4 * 4 + 4, 0);
+    bytes.chunks(4).map(|chunk| u32::from_le_bytes(chunk.try_into().unwrap())).collect()
+  }
+
+  fn module(instructions: &[Vec<u32>]) -> Vec<u32> {
+    let mut words = vec![SPIRV_MAGIC, 0x0001_0000, 0, 100, 0];
+    instructions.iter().for_each(|instruction| words.extend_from_slice(instruction));
+    words
+  }
+
+  fn named(opcode: u32, leading: &[u32], name: &str, trailing: &[u32]) -> Vec<u32> {
+    instruction(opcode, &[leading, &string(name), trailing].concat())
+  }
+
+  // A vertex shader with `uniform Matrices { mat4 model; } matrices`, `sampler2D texture1` and a vec3 input at location 0
+  fn vertex_module() -> Vec<u32> {
+    module(&[
+      named(op::ENTRY_POINT, &[0, 1], "main", &[20]),
+      named(op::NAME, &[10], "matrices", &[]),
+      named(op::NAME, &[11], "texture1", &[]),
+      named(op::NAME, &[20], "position", &[]),
+      instruction(op::DECORATE, &[5, decoration::BLOCK]),
+      instruction(op::MEMBER_DECORATE, &[5, 0, decoration::OFFSET, 0]),
+      instruction(op::MEMBER_DECORATE, &[5, 0, decoration::MATRIX_STRIDE, 16]),
+      instruction(op::DECORATE, &[10, decoration::DESCRIPTOR_SET, 0]),
+      instruction(op::DECORATE, &[10, decoration::BINDING, 1]),
+      instruction(op::DECORATE, &[11, decoration::DESCRIPTOR_SET, 0]),
+      instruction(op::DECORATE, &[11, decoration::BINDING, 2]),
+      instruction(op::DECORATE, &[20, decoration::LOCATION, 0]),
+      instruction(op::TYPE_FLOAT, &[2, 32]),
+      instruction(op::TYPE_VECTOR, &[3, 2, 4]),
+      instruction(op::TYPE_MATRIX, &[4, 3, 4]),
+      instruction(op::TYPE_STRUCT, &[5, 4]),
+      instruction(op::TYPE_POINTER, &[6, storage_class::UNIFORM, 5]),
+      instruction(op::TYPE_IMAGE, &[7, 2, 1, 0, 0, 0, 1, 0]),
+      instruction(op::TYPE_SAMPLED_IMAGE, &[8, 7]),
+      instruction(op::TYPE_POINTER, &[9, storage_class::UNIFORM_CONSTANT, 8]),
+      instruction(op::TYPE_VECTOR, &[12, 2, 3]),
+      instruction(op::TYPE_POINTER, &[13, storage_class::INPUT, 12]),
+      instruction(op::VARIABLE, &[6, 10, storage_class::UNIFORM]),
+      instruction(op::VARIABLE, &[9, 11, storage_class::UNIFORM_CONSTANT]),
+      instruction(op::VARIABLE, &[13, 20, storage_class::INPUT]),
+    ])
+  }
+
+  #[test]
+  fn reflects_bindings_and_vertex_inputs() {
+    let reflection = ShaderReflection::from_words(&vertex_module()).unwrap();
+    assert!(reflection.has_entry_point("main", ShaderStageFlags::VERTEX));
+    assert_eq!(reflection.stage, ShaderStageFlags::VERTEX);
+    assert_eq!(reflection.descriptor_bindings, vec![
+      DescriptorBinding { set: 0, binding: 1, descriptor_type: DescriptorType::UNIFORM_BUFFER, count: 1, name: "matrices".to_string() },
+      DescriptorBinding { set: 0, binding: 2, descriptor_type: DescriptorType::COMBINED_IMAGE_SAMPLER, count: 1, name: "texture1".to_string() },
+    ]);
+    assert_eq!(reflection.vertex_inputs, vec![VertexInput { location: 0, format: Format::R32G32B32_SFLOAT, name: "position".to_string() }]);
+    assert!(reflection.push_constants.is_empty());
+  }
+
+  #[test]
+  fn sizes_nested_structs_and_arrays() {
+    // struct Light { vec4 color; float range; }, push_constant { Light lights[3]; mat4 transform; }, sampler2D shadows[4]
+    let words = module(&[
+      named(op::ENTRY_POINT, &[4, 1], "main", &[]),
+      instruction(op::DECORATE, &[33, decoration::ARRAY_STRIDE, 32]),
+      instruction(op::MEMBER_DECORATE, &[32, 0, decoration::OFFSET, 0]),
+      instruction(op::MEMBER_DECORATE, &[32, 1, decoration::OFFSET, 16]),
+      instruction(op::MEMBER_DECORATE, &[34, 0, decoration::OFFSET, 0]),
+      instruction(op::MEMBER_DECORATE, &[34, 1, decoration::OFFSET, 96]),
+      instruction(op::MEMBER_DECORATE, &[34, 1, decoration::MATRIX_STRIDE, 16]),
+      instruction(op::DECORATE, &[41, decoration::DESCRIPTOR_SET, 1]),
+      instruction(op::DECORATE, &[41, decoration::BINDING, 0]),
+      instruction(op::TYPE_FLOAT, &[2, 32]),
+      instruction(op::TYPE_VECTOR, &[3, 2, 4]),
+      instruction(op::TYPE_MATRIX, &[4, 3, 4]),
+      instruction(op::TYPE_INT, &[30, 32, 0]),
+      instruction(op::CONSTANT, &[30, 31, 3]),
+      instruction(op::CONSTANT, &[30, 37, 4]),
+      instruction(op::TYPE_STRUCT, &[32, 3, 2]),
+      instruction(op::TYPE_ARRAY, &[33, 32, 31]),
+      instruction(op::TYPE_STRUCT, &[34, 33, 4]),
+      instruction(op::TYPE_POINTER, &[35, storage_class::PUSH_CONSTANT, 34]),
+      instruction(op::VARIABLE, &[35, 36, storage_class::PUSH_CONSTANT]),
+      instruction(op::TYPE_IMAGE, &[7, 2, 1, 0, 0, 0, 1, 0]),
+      instruction(op::TYPE_SAMPLED_IMAGE, &[8, 7]),
+      instruction(op::TYPE_ARRAY, &[38, 8, 37]),
+      instruction(op::TYPE_POINTER, &[39, storage_class::UNIFORM_CONSTANT, 38]),
+      instruction(op::VARIABLE, &[39, 41, storage_class::UNIFORM_CONSTANT]),
+    ]);
+
+    let reflection = ShaderReflection::from_words(&words).unwrap();
+    assert_eq!(reflection.stage, ShaderStageFlags::FRAGMENT);
+    assert_eq!(reflection.push_constants.len(), 1);
+    let range = reflection.push_constants[0];
+    assert_eq!((range.stage_flags, range.offset, range.size), (ShaderStageFlags::FRAGMENT, 0, 160));
+    assert_eq!(reflection.descriptor_bindings.len(), 1);
+    assert_eq!(reflection.descriptor_bindings[0].count, 4);
+    assert_eq!(reflection.descriptor_bindings[0].descriptor_type, DescriptorType::COMBINED_IMAGE_SAMPLER);
+  }
+
+  #[test]
+  fn rejects_bad_magic() {
+    let mut words = vertex_module();
+    words[0] = 0xdead_beef;
+    assert!(matches!(ShaderReflection::from_words(&words), Err(SpirvError::InvalidMagic(0xdead_beef))));
+  }
+
+  #[test]
+  fn rejects_truncated_modules() {
+    let words = vertex_module();
+    assert!(matches!(ShaderReflection::from_words(&words[..3]), Err(SpirvError::Malformed { .. })));
+    assert!(matches!(ShaderReflection::from_words(&words[..words.len() - 1]), Err(SpirvError::Malformed { .. })));
+  }
+
+  #[test]
+  fn rejects_short_instructions() {
+    // An entry point without its name, and names that are cut off before their nul terminator
+    let short_entry_point = module(&[instruction(op::ENTRY_POINT, &[0])]);
+    let unterminated_name = module(&[instruction(op::NAME, &[1, u32::from_le_bytes(*b"main")])]);
+    let name_without_target = module(&[instruction(op::NAME, &[])]);
+    for words in [short_entry_point, unterminated_name, name_without_target] {
+      assert!(matches!(ShaderReflection::from_words(&words), Err(SpirvError::Malformed { .. })));
+    }
+  }
+
+  #[test]
+  fn validates_vertex_input_formats() {
+    let reflection = ShaderReflection::from_words(&vertex_module()).unwrap();
+    let attribute = |format| VertexInputAttributeDescription { location: 0, binding: 0, format, offset: 0 };
+    assert!(reflection.validate_vertex_input(&[attribute(Format::R32G32B32_SFLOAT)]).is_ok());
+    assert!(reflection.validate_vertex_input(&[attribute(Format::R32G32_SFLOAT)]).is_ok());
+    assert!(matches!(
+      reflection.validate_vertex_input(&[attribute(Format::R32G32B32_SINT)]),
+      Err(SpirvError::VertexInputMismatch { location: 0, .. })
+    ));
+    assert!(matches!(
+      reflection.validate_vertex_input(&[]),
+      Err(SpirvError::VertexInputMismatch { provided: None, .. })
+    ));
+  }
+}
@@ -0,0 +1,607 @@
+use std::{
+  collections::{HashMap, HashSet},
+  env,
+  error::Error,
+  fmt, fs, io,
+  path::{Path, PathBuf},
+  sync::mpsc::{self, Receiver},
+};
+use ash::vk::ShaderStageFlags;
+use naga::{
+  back::spv,
+  front::{glsl, wgsl},
+  valid::{Capabilities, ValidationFlags, Validator},
+};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+
+const SPIRV_MAGIC: u32 = 0x0723_0203;
+
+// Part of every cache key, bump it when the naga version or the writer flags change
+const COMPILER_VERSION: &str = "naga-0.19";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShaderLanguage {
+  Glsl,
+  Wgsl,
+  Spirv,
+}
+
+impl ShaderLanguage {
+  pub fn from_path(path: &Path) -> Option<Self> {
+    match path.extension()?.to_str()? {
+      "glsl" | "vert" | "frag" | "comp" => Some(ShaderLanguage::Glsl),
+      "wgsl" => Some(ShaderLanguage::Wgsl),
+      "spv" => Some(ShaderLanguage::Spirv),
+      _ => None,
+    }
+  }
+}
+
+// A message pointing back at the file and line it came from, before includes were expanded.
+// Line 0 means the compiler gave no location
+#[derive(Debug)]
+pub struct Diagnostic {
+  pub path    : PathBuf,
+  pub line    : u32,
+  pub column  : u32,
+  pub message : String,
+}
+
+impl fmt::Display for Diagnostic {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self.line {
+      0 => write!(f, "{}: {}", self.path.display(), self.message),
+      _ => write!(f, "{}:{}:{}: {}", self.path.display(), self.line, self.column, self.message),
+    }
+  }
+}
+
+#[derive(Debug)]
+pub enum ShaderCompileError {
+  Io { path: PathBuf, error: io::Error },
+  UnknownLanguage(PathBuf),
+  UnsupportedStage(ShaderStageFlags),
+  Preprocess(Diagnostic),
+  Compile(Vec<Diagnostic>),
+}
+
+impl fmt::Display for ShaderCompileError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ShaderCompileError::Io { path, error } => write!(f, "{}: {}", path.display(), error),
+      ShaderCompileError::UnknownLanguage(path) => write!(f, "{}: unknown shader language, expected .vert, .frag, .comp, .glsl, .wgsl or .spv", path.display()),
+      ShaderCompileError::UnsupportedStage(stage) => write!(f, "Cannot compile shaders for stage {:?}", stage),
+      ShaderCompileError::Preprocess(diagnostic) => write!(f, "{}", diagnostic),
+      ShaderCompileError::Compile(diagnostics) => {
+        for (index, diagnostic) in diagnostics.iter().enumerate() {
+          if index > 0 {
+            writeln!(f)?;
+          }
+          write!(f, "{}", diagnostic)?;
+        }
+        Ok(())
+      },
+    }
+  }
+}
+
+impl Error for ShaderCompileError {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    match self {
+      ShaderCompileError::Io { error, .. } => Some(error),
+      _ => None,
+    }
+  }
+}
+
+pub struct CompiledShader {
+  pub spirv        : Vec<u32>,
+  pub dependencies : Vec<PathBuf>, // The source file and everything it includes, canonicalized
+}
+
+// Source with every #include expanded, and where each of its lines came from
+struct Preprocessed {
+  source       : String,
+  line_origins : Vec<(usize, u32)>, // (index into files, line in that file) per line of source
+  files        : Vec<PathBuf>,
+}
+
+impl Preprocessed {
+  fn locate(&self, line: u32, column: u32, message: String) -> Diagnostic {
+    match self.line_origins.get((line as usize).wrapping_sub(1)) {
+      Some(&(file, origin_line)) => Diagnostic { path: self.files[file].clone(), line: origin_line, column, message },
+      None => self.unlocated(message),
+    }
+  }
+
+  fn unlocated(&self, message: String) -> Diagnostic {
+    Diagnostic { path: self.files[0].clone(), line: 0, column: 0, message }
+  }
+}
+
+// Compiles GLSL and WGSL to SPIR-V with naga. `#include "file"` is resolved next to the including file first,
+// `#include <file>` only through the include directories. Results are cached on disk by a hash of the expanded
+// source, defines, stage and entry point, so an unchanged shader is never compiled twice
+pub struct ShaderCompiler {
+  cache_dir    : Option<PathBuf>,
+  include_dirs : Vec<PathBuf>,
+}
+
+impl Default for ShaderCompiler {
+  fn default() -> Self {
+    ShaderCompiler::new()
+  }
+}
+
+impl ShaderCompiler {
+  pub fn new() -> Self {
+    ShaderCompiler {
+      cache_dir    : Some(env::temp_dir().join("rust_renderer").join("shaders")),
+      include_dirs : Vec::new(),
+    }
+  }
+
+  // None disables the disk cache
+  pub fn set_cache_dir(&mut self, cache_dir: Option<PathBuf>) -> &mut Self {
+    self.cache_dir = cache_dir;
+    self
+  }
+
+  pub fn add_include_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Self {
+    self.include_dirs.push(dir.as_ref().to_path_buf());
+    self
+  }
+
+  // The language comes from the extension, .spv files are loaded as they are
+  pub fn compile(
+    &self,
+    path        : &Path,
+    stage       : ShaderStageFlags,
+    entry_point : &str,
+    defines     : &[(String, String)]
+  ) -> Result<CompiledShader, ShaderCompileError> {
+    let language = ShaderLanguage::from_path(path).ok_or_else(|| ShaderCompileError::UnknownLanguage(path.to_path_buf()))?;
+    if language == ShaderLanguage::Spirv {
+      let canonical = canonicalize(path)?;
+      let bytes = fs::read(&canonical).map_err(|error| ShaderCompileError::Io { path: canonical.clone(), error })?;
+      let spirv = spirv_words(&bytes).ok_or_else(|| ShaderCompileError::Compile(vec![Diagnostic {
+        path    : canonical.clone(),
+        line    : 0,
+        column  : 0,
+        message : "not a SPIR-V module".to_string(),
+      }]))?;
+      return Ok(CompiledShader { spirv, dependencies: vec![canonical] });
+    }
+
+    let preprocessed = self.preprocess(path, language, defines)?;
+    let cache_path = self.cache_dir.as_ref().map(|dir| {
+      dir.join(format!("{:016x}.spv", cache_key(&preprocessed.source, language, stage, entry_point, defines)))
+    });
+    let cached = cache_path.as_ref()
+      .and_then(|cache_path| fs::read(cache_path).ok())
+      .and_then(|bytes| spirv_words(&bytes));
+    if let Some(spirv) = cached {
+      return Ok(CompiledShader { spirv, dependencies: preprocessed.files });
+    }
+
+    let spirv = translate(&preprocessed, language, stage, entry_point, defines)?;
+    if let Some(cache_path) = cache_path {
+      if let Err(err) = write_cache(&cache_path, &spirv) {
+        eprintln!("Failed to cache compiled shader {}: {}", cache_path.display(), err);
+      }
+    }
+    Ok(CompiledShader { spirv, dependencies: preprocessed.files })
+  }
+
+  fn preprocess(&self, path: &Path, language: ShaderLanguage, defines: &[(String, String)]) -> Result<Preprocessed, ShaderCompileError> {
+    let mut preprocessed = Preprocessed {
+      source       : String::new(),
+      line_origins : Vec::new(),
+      files        : Vec::new(),
+    };
+    let mut defines: HashMap<String, String> = defines.iter().cloned().collect();
+    self.expand(&canonicalize(path)?, language, &mut defines, &mut Vec::new(), &mut preprocessed)?;
+    Ok(preprocessed)
+  }
+
+  // Every file is expanded once, so a header reached along two include paths is not declared twice.
+  // GLSL conditionals are left to naga's preprocessor, so includes inside them are always expanded.
+  // WGSL has no preprocessor, #define, #undef, #ifdef, #ifndef, #else and #endif are handled here
+  fn expand(
+    &self,
+    path         : &Path,
+    language     : ShaderLanguage,
+    defines      : &mut HashMap<String, String>,
+    stack        : &mut Vec<PathBuf>,
+    preprocessed : &mut Preprocessed
+  ) -> Result<(), ShaderCompileError> {
+    let text = fs::read_to_string(path).map_err(|error| ShaderCompileError::Io { path: path.to_path_buf(), error })?;
+    preprocessed.files.push(path.to_path_buf());
+    let file = preprocessed.files.len() - 1;
+    stack.push(path.to_path_buf());
+
+    let error = |line: u32, message: String| ShaderCompileError::Preprocess(Diagnostic { path: path.to_path_buf(), line, column: 1, message });
+
+    // (enclosing block active, condition) per open #ifdef
+    let mut conditions: Vec<(bool, bool)> = Vec::new();
+    let mut comment_depth = 0;
+    for (index, line) in text.lines().enumerate() {
+      let line_number = index as u32 + 1;
+      let active = conditions.last().is_none_or(|&(enclosing, condition)| enclosing && condition);
+      let directive = line.trim_start().strip_prefix('#').map(|directive| {
+        let directive = directive.trim_start();
+        let name_end = directive.find(char::is_whitespace).unwrap_or(directive.len());
+        (&directive[..name_end], directive[name_end..].trim())
+      });
+
+      match (directive, language) {
+        (Some(("include", argument)), _) => {
+          if !active {
+            continue;
+          }
+          let target = self.resolve_include(path, argument)
+            .ok_or_else(|| error(line_number, format!("cannot find include {}", argument)))?;
+          if stack.contains(&target) {
+            return Err(error(line_number, format!("{} includes itself", target.display())));
+          }
+          if !preprocessed.files.contains(&target) {
+            self.expand(&target, language, defines, stack, preprocessed)?;
+          }
+        },
+        (Some(("extension", argument)), ShaderLanguage::Glsl) if argument.starts_with("GL_GOOGLE_include_directive") => {},
+        (Some(("ifdef", name)), ShaderLanguage::Wgsl) => conditions.push((active, defines.contains_key(name))),
+        (Some(("ifndef", name)), ShaderLanguage::Wgsl) => conditions.push((active, !defines.contains_key(name))),
+        (Some(("else", _)), ShaderLanguage::Wgsl) => match conditions.last_mut() {
+          Some((_, condition)) => *condition = !*condition,
+          None => return Err(error(line_number, "#else without #ifdef".to_string())),
+        },
+        (Some(("endif", _)), ShaderLanguage::Wgsl) => {
+          if conditions.pop().is_none() {
+            return Err(error(line_number, "#endif without #ifdef".to_string()));
+          }
+        },
+        (Some(("define", definition)), ShaderLanguage::Wgsl) => {
+          if active {
+            let name_end = definition.find(char::is_whitespace).unwrap_or(definition.len());
+            defines.insert(definition[..name_end].to_string(), definition[name_end..].trim().to_string());
+          }
+        },
+        (Some(("undef", name)), ShaderLanguage::Wgsl) => {
+          if active {
+            defines.remove(name);
+          }
+        },
+        (Some((name, _)), ShaderLanguage::Wgsl) => return Err(error(line_number, format!("unknown directive #{}", name))),
+        _ => {
+          if !active {
+            continue;
+          }
+          match language {
+            ShaderLanguage::Wgsl => preprocessed.source.push_str(&substitute_defines(line, defines, &mut comment_depth)),
+            _ => preprocessed.source.push_str(line),
+          }
+          preprocessed.source.push('\n');
+          preprocessed.line_origins.push((file, line_number));
+        },
+      }
+    }
+
+    if !conditions.is_empty() {
+      return Err(error(text.lines().count() as u32, "unterminated #ifdef".to_string()));
+    }
+    stack.pop();
+    Ok(())
+  }
+
+  fn resolve_include(&self, from: &Path, argument: &str) -> Option<PathBuf> {
+    let (name, relative) = if let Some(name) = argument.strip_prefix('"').and_then(|name| name.strip_suffix('"')) {
+      (name, true)
+    } else {
+      (argument.strip_prefix('<')?.strip_suffix('>')?, false)
+    };
+    let local = from.parent().filter(|_| relative).map(|dir| dir.join(name));
+    local.into_iter()
+      .chain(self.include_dirs.iter().map(|dir| dir.join(name)))
+      .find(|candidate| candidate.is_file())
+      .and_then(|candidate| fs::canonicalize(candidate).ok())
+  }
+}
+
+fn canonicalize(path: &Path) -> Result<PathBuf, ShaderCompileError> {
+  fs::canonicalize(path).map_err(|error| ShaderCompileError::Io { path: path.to_path_buf(), error })
+}
+
+// Replaces whole identifiers that name a define with a value, WGSL has no macros of its own.
+// Comments and quoted text are copied as they are, `comment_depth` carries open block comments (which nest) across lines
+fn substitute_defines(line: &str, defines: &HashMap<String, String>, comment_depth: &mut usize) -> String {
+  let is_word = |ch: char| ch == '_' || ch.is_ascii_alphanumeric();
+  let mut result = String::with_capacity(line.len());
+  let mut index = 0;
+  while index < line.len() {
+    let rest = &line[index..];
+    let (copied, replaced) = if rest.starts_with("/*") {
+      *comment_depth += 1;
+      (2, None)
+    } else if *comment_depth > 0 && rest.starts_with("*/") {
+      *comment_depth -= 1;
+      (2, None)
+    } else if *comment_depth > 0 {
+      (rest.chars().next().unwrap().len_utf8(), None)
+    } else if rest.starts_with("//") {
+      (rest.len(), None)
+    } else if let Some(quoted) = rest.strip_prefix('"') {
+      (quoted.find('"').map_or(rest.len(), |end| end + 2), None)
+    } else if rest.starts_with(is_word) {
+      let end = rest.find(|ch: char| !is_word(ch)).unwrap_or(rest.len());
+      (end, defines.get(&rest[..end]).filter(|value| !value.is_empty()))
+    } else {
+      (rest.chars().next().unwrap().len_utf8(), None)
+    };
+    result.push_str(replaced.map_or(&rest[..copied], String::as_str));
+    index += copied;
+  }
+  result
+}
+
+fn translate(
+  preprocessed : &Preprocessed,
+  language     : ShaderLanguage,
+  stage        : ShaderStageFlags,
+  entry_point  : &str,
+  defines      : &[(String, String)]
+) -> Result<Vec<u32>, ShaderCompileError> {
+  let naga_stage = match stage {
+    ShaderStageFlags::VERTEX => naga::ShaderStage::Vertex,
+    ShaderStageFlags::FRAGMENT => naga::ShaderStage::Fragment,
+    ShaderStageFlags::COMPUTE => naga::ShaderStage::Compute,
+    other => return Err(ShaderCompileError::UnsupportedStage(other)),
+  };
+  let source = &preprocessed.source;
+
+  let module = match language {
+    ShaderLanguage::Glsl => {
+      let mut options = glsl::Options::from(naga_stage);
+      options.defines.extend(defines.iter().cloned());
+      glsl::Frontend::default().parse(&options, source).map_err(|errors| {
+        ShaderCompileError::Compile(errors.iter().map(|error| {
+          let location = error.meta.location(source);
+          preprocessed.locate(location.line_number, location.line_position, error.kind.to_string())
+        }).collect())
+      })?
+    },
+    ShaderLanguage::Wgsl => wgsl::parse_str(source).map_err(|error| {
+      let message = error.message().to_string();
+      ShaderCompileError::Compile(vec![match error.location(source) {
+        Some(location) => preprocessed.locate(location.line_number, location.line_position, message),
+        None => preprocessed.unlocated(message),
+      }])
+    })?,
+    ShaderLanguage::Spirv => unreachable!("SPIR-V is loaded without translation"),
+  };
+
+  let info = Validator::new(ValidationFlags::all(), Capabilities::all()).validate(&module).map_err(|error| {
+    let mut message = error.to_string();
+    let mut cause = error.source();
+    while let Some(inner) = cause {
+      message.push_str(": ");
+      message.push_str(&inner.to_string());
+      cause = inner.source();
+    }
+    ShaderCompileError::Compile(vec![match error.location(source) {
+      Some(location) => preprocessed.locate(location.line_number, location.line_position, message),
+      None => preprocessed.unlocated(message),
+    }])
+  })?;
+
+  // DEBUG keeps the variable names reflection resolves bindings by. Sources follow GL clip space, the
+  // writer flips Y for Vulkan
+  let options = spv::Options {
+    flags: spv::WriterFlags::DEBUG | spv::WriterFlags::ADJUST_COORDINATE_SPACE | spv::WriterFlags::LABEL_VARYINGS | spv::WriterFlags::CLAMP_FRAG_DEPTH,
+    ..spv::Options::default()
+  };
+  let pipeline_options = spv::PipelineOptions { shader_stage: naga_stage, entry_point: entry_point.to_string() };
+  spv::write_vec(&module, &info, &options, Some(&pipeline_options)).map_err(|error| {
+    ShaderCompileError::Compile(vec![preprocessed.unlocated(format!("SPIR-V generation failed: {}", error))])
+  })
+}
+
+// FNV-1a, stable across runs and platforms unlike std's hasher
+fn cache_key(source: &str, language: ShaderLanguage, stage: ShaderStageFlags, entry_point: &str, defines: &[(String, String)]) -> u64 {
+  let mut defines: Vec<String> = defines.iter().map(|(name, value)| format!("{}={}", name, value)).collect();
+  defines.sort();
+
+  let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+  let mut write = |bytes: &[u8]| {
+    for &byte in bytes.iter().chain(std::iter::once(&0)) {
+      hash ^= byte as u64;
+      hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+  };
+  write(COMPILER_VERSION.as_bytes());
+  write(format!("{:?}", language).as_bytes());
+  write(&stage.as_raw().to_le_bytes());
+  write(entry_point.as_bytes());
+  for define in &defines {
+    write(define.as_bytes());
+  }
+  write(source.as_bytes());
+  hash
+}
+
+// Accepts either byte order, None when the bytes are not SPIR-V
+fn spirv_words(bytes: &[u8]) -> Option<Vec<u32>> {
+  if bytes.len() < 20 || !bytes.len().is_multiple_of(4) {
+    return None;
+  }
+  let words = bytes.chunks_exact(4).map(|chunk| u32::from_le_bytes(chunk.try_into().unwrap()));
+  match u32::from_le_bytes(bytes[..4].try_into().unwrap()) {
+    SPIRV_MAGIC => Some(words.collect()),
+    magic if magic.swap_bytes() == SPIRV_MAGIC => Some(words.map(u32::swap_bytes).collect()),
+    _ => None,
+  }
+}
+
+// Written next to its final name and renamed, so a reader never sees half a module
+fn write_cache(path: &Path, spirv: &[u32]) -> io::Result<()> {
+  if let Some(dir) = path.parent() {
+    fs::create_dir_all(dir)?;
+  }
+  let bytes: Vec<u8> = spirv.iter().flat_map(|word| word.to_le_bytes()).collect();
+  let partial = path.with_extension(format!("{}.tmp", std::process::id()));
+  fs::write(&partial, bytes)?;
+  fs::rename(&partial, path)
+}
+
+// Reports shader source files that changed on disk. Directories are watched rather than files, editors
+// that save by replacing the file would otherwise drop the watch
+pub struct ShaderWatcher {
+  watcher     : RecommendedWatcher,
+  events      : Receiver<notify::Result<notify::Event>>,
+  directories : HashSet<PathBuf>,
+}
+
+impl ShaderWatcher {
+  pub fn new() -> notify::Result<Self> {
+    let (sender, events) = mpsc::channel();
+    Ok(ShaderWatcher {
+      watcher     : notify::recommended_watcher(sender)?,
+      events,
+      directories : HashSet::new(),
+    })
+  }
+
+  pub fn watch(&mut self, file: &Path) -> notify::Result<()> {
+    let Some(directory) = file.parent() else {
+      return Ok(());
+    };
+    if self.directories.insert(directory.to_path_buf()) {
+      self.watcher.watch(directory, RecursiveMode::NonRecursive)?;
+    }
+    Ok(())
+  }
+
+  // Drains the events received since the last call
+  pub fn changed_files(&self) -> HashSet<PathBuf> {
+    let mut changed = HashSet::new();
+    for event in self.events.try_iter() {
+      match event {
+        Ok(event) if event.kind.is_create() || event.kind.is_modify() || event.kind.is_remove() => changed.extend(event.paths),
+        Ok(_) => {},
+        Err(err) => eprintln!("Shader watcher error: {}", err),
+      }
+    }
+    changed
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::drivers::vulkan::reflect::ShaderReflection;
+
+  // A fresh directory per test, with `files` written into it
+  fn scratch_dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = env::temp_dir().join(format!("rust_renderer_shader_tests_{}", std::process::id())).join(name);
+    let _ = fs::remove_dir_all(&dir);
+    for (file, contents) in files {
+      let path = dir.join(file);
+      fs::create_dir_all(path.parent().unwrap()).unwrap();
+      fs::write(path, contents).unwrap();
+    }
+    dir
+  }
+
+  fn uncached() -> ShaderCompiler {
+    let mut compiler = ShaderCompiler::new();
+    compiler.set_cache_dir(None);
+    compiler
+  }
+
+  #[test]
+  fn diamond_includes_expand_once() {
+    let dir = scratch_dir("diamond", &[
+      ("main.glsl", "#include \"left.glsl\"\n#include \"right.glsl\"\nvoid main() {}\n"),
+      ("left.glsl", "#include \"common.glsl\"\nfloat left;\n"),
+      ("right.glsl", "#include \"common.glsl\"\nfloat right;\n"),
+      ("common.glsl", "float common_value;\n"),
+    ]);
+    let preprocessed = uncached().preprocess(&dir.join("main.glsl"), ShaderLanguage::Glsl, &[]).unwrap();
+    assert_eq!(preprocessed.source.matches("common_value").count(), 1);
+    assert_eq!(preprocessed.source, "float common_value;\nfloat left;\nfloat right;\nvoid main() {}\n");
+    assert_eq!(preprocessed.files.len(), 4);
+  }
+
+  #[test]
+  fn include_cycles_are_errors() {
+    let dir = scratch_dir("cycle", &[
+      ("a.glsl", "#include \"b.glsl\"\n"),
+      ("b.glsl", "#include \"a.glsl\"\n"),
+    ]);
+    let result = uncached().preprocess(&dir.join("a.glsl"), ShaderLanguage::Glsl, &[]);
+    assert!(matches!(result, Err(ShaderCompileError::Preprocess(Diagnostic { line: 1, .. }))));
+  }
+
+  #[test]
+  fn angle_includes_use_the_include_dirs() {
+    let dir = scratch_dir("include_dirs", &[
+      ("shaders/main.glsl", "#include <shared.glsl>\n"),
+      ("library/shared.glsl", "float shared_value;\n"),
+    ]);
+    let mut compiler = uncached();
+    assert!(compiler.preprocess(&dir.join("shaders/main.glsl"), ShaderLanguage::Glsl, &[]).is_err());
+    compiler.add_include_dir(dir.join("library"));
+    let preprocessed = compiler.preprocess(&dir.join("shaders/main.glsl"), ShaderLanguage::Glsl, &[]).unwrap();
+    assert_eq!(preprocessed.source, "float shared_value;\n");
+  }
+
+  #[test]
+  fn wgsl_conditionals_follow_defines() {
+    let dir = scratch_dir("wgsl_conditionals", &[
+      ("main.wgsl", "#ifdef SHADOWS\nshadowed\n#else\nunshadowed\n#endif\n#define SCALE 2.0\nscale = SCALE;\n"),
+    ]);
+    let defines = [("SHADOWS".to_string(), String::new())];
+    let compiler = uncached();
+    let with_shadows = compiler.preprocess(&dir.join("main.wgsl"), ShaderLanguage::Wgsl, &defines).unwrap();
+    assert_eq!(with_shadows.source, "shadowed\nscale = 2.0;\n");
+    assert_eq!(with_shadows.line_origins, vec![(0, 2), (0, 7)]);
+    let without = compiler.preprocess(&dir.join("main.wgsl"), ShaderLanguage::Wgsl, &[]).unwrap();
+    assert_eq!(without.source, "unshadowed\nscale = 2.0;\n");
+  }
+
+  #[test]
+  fn defines_skip_comments_and_strings() {
+    let defines: HashMap<String, String> = [("SIZE".to_string(), "4".to_string())].into_iter().collect();
+    let mut depth = 0;
+    assert_eq!(substitute_defines("let a = SIZE; // SIZE", &defines, &mut depth), "let a = 4; // SIZE");
+    assert_eq!(substitute_defines("let b = \"SIZE\" + SIZE_2 + SIZE;", &defines, &mut depth), "let b = \"SIZE\" + SIZE_2 + 4;");
+    assert_eq!(substitute_defines("/* SIZE /* nested */ SIZE", &defines, &mut depth), "/* SIZE /* nested */ SIZE");
+    assert_eq!(depth, 1);
+    assert_eq!(substitute_defines("SIZE */ SIZE", &defines, &mut depth), "SIZE */ 4");
+    assert_eq!(depth, 0);
+    assert_eq!(substitute_defines("SIZE", &HashMap::new(), &mut depth), "SIZE");
+  }
+
+  #[test]
+  fn bundled_mesh_shaders_compile() {
+    let shaders_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("src").join("shaders");
+    let compiler = uncached();
+    let vertex = compiler.compile(&shaders_dir.join("mesh.vert"), ShaderStageFlags::VERTEX, "main", &[]).unwrap();
+    let fragment = compiler.compile(&shaders_dir.join("mesh.frag"), ShaderStageFlags::FRAGMENT, "main", &[]).unwrap();
+    assert!(vertex.dependencies.iter().any(|path| path.ends_with("include/matrices.glsl")));
+
+    let vertex = ShaderReflection::from_words(&vertex.spirv).unwrap();
+    assert!(vertex.has_entry_point("main", ShaderStageFlags::VERTEX));
+    assert!(vertex.descriptor_bindings.iter().any(|binding| binding.name == "matrices"));
+    let fragment = ShaderReflection::from_words(&fragment.spirv).unwrap();
+    assert!(fragment.has_entry_point("main", ShaderStageFlags::FRAGMENT));
+  }
+
+  #[test]
+  fn cache_keys_change_with_defines() {
+    let key = |defines: &[(String, String)]| cache_key("void main() {}", ShaderLanguage::Glsl, ShaderStageFlags::VERTEX, "main", defines);
+    let a = [("A".to_string(), "1".to_string()), ("B".to_string(), "2".to_string())];
+    let b = [("B".to_string(), "2".to_string()), ("A".to_string(), "1".to_string())];
+    assert_eq!(key(&a), key(&b));
+    assert_ne!(key(&a), key(&[]));
+  }
+}
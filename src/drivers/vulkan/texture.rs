@@ -0,0 +1,419 @@
+use std::ops::Range;
+use ash::{
+  vk::{
+    self, AccessFlags, BorderColor, BufferImageCopy, BufferUsageFlags, CommandBuffer, CompareOp, DependencyFlags, DeviceSize, Extent2D, Extent3D, Filter, Format, FormatFeatureFlags, Image, ImageAspectFlags, ImageBlit, ImageCopy, ImageLayout, ImageMemoryBarrier, ImageSubresourceLayers, ImageSubresourceRange, ImageUsageFlags, ImageView, MemoryPropertyFlags, Offset3D, PhysicalDevice, PipelineStageFlags, Queue, Sampler, SamplerAddressMode, SamplerCreateInfo, SamplerMipmapMode, CommandPool
+  },
+  Device, Instance
+};
+use image::RgbaImage;
+
+use super::attachment;
+use super::buffer::{submit_one_time, GpuBuffer, UploadQueues};
+use super::memory::{Allocation, AllocationStrategy, GpuAllocator};
+
+// Not sRGB, texels are sampled as stored like the GL backend's GL_RGBA textures
+pub const TEXTURE_FORMAT: Format = Format::R8G8B8A8_UNORM;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SamplerDesc {
+  pub min_filter     : Filter,
+  pub mag_filter     : Filter,
+  pub mipmap_mode    : SamplerMipmapMode,
+  pub wrap_u         : SamplerAddressMode,
+  pub wrap_v         : SamplerAddressMode,
+  pub max_anisotropy : Option<f32>, // Clamped to the device limit, ignored when the feature is unavailable
+}
+
+// Same filtering and wrapping as the GL device: trilinear, repeat
+impl Default for SamplerDesc {
+  fn default() -> Self {
+    SamplerDesc {
+      min_filter     : Filter::LINEAR,
+      mag_filter     : Filter::LINEAR,
+      mipmap_mode    : SamplerMipmapMode::LINEAR,
+      wrap_u         : SamplerAddressMode::REPEAT,
+      wrap_v         : SamplerAddressMode::REPEAT,
+      max_anisotropy : None,
+    }
+  }
+}
+
+// What the physical device offers for textures, `max_anisotropy` is None when samplerAnisotropy is not enabled
+#[derive(Clone, Copy, Debug)]
+pub struct TextureSupport {
+  pub linear_blit    : bool,
+  pub max_anisotropy : Option<f32>,
+}
+
+impl TextureSupport {
+  pub fn query(instance: &Instance, physical_device: PhysicalDevice, max_anisotropy: Option<f32>) -> Self {
+    // Blitting with a linear filter needs format support, otherwise only the base level is kept
+    let format_properties = unsafe { instance.get_physical_device_format_properties(physical_device, TEXTURE_FORMAT) };
+    TextureSupport {
+      linear_blit : format_properties.optimal_tiling_features.contains(FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR),
+      max_anisotropy,
+    }
+  }
+}
+
+pub struct Texture {
+  pub image      : Image,
+  pub view       : ImageView,
+  pub sampler    : Sampler,
+  pub extent     : Extent2D,
+  pub mip_levels : u32,
+  allocation     : Allocation,
+}
+
+impl Texture {
+
+  // Uploads `pixels` through a staging buffer on the transfer queue and fills the mip chain with linear blits,
+  // which need the graphics queue
+  pub fn upload(
+    allocator    : &mut GpuAllocator,
+    device       : &Device,
+    queues       : &UploadQueues,
+    pixels       : &RgbaImage,
+    sampler_desc : &SamplerDesc,
+    support      : TextureSupport
+  ) -> Result<Self, vk::Result> {
+
+    let extent = Extent2D { width: pixels.width(), height: pixels.height() };
+    if extent.width == 0 || extent.height == 0 {
+      return Err(vk::Result::ERROR_FORMAT_NOT_SUPPORTED);
+    }
+
+    let mip_levels = if support.linear_blit { Texture::mip_level_count(extent) } else { 1 };
+
+    let staging = GpuBuffer::new(
+      allocator, device, pixels.as_raw().len() as DeviceSize,
+      BufferUsageFlags::TRANSFER_SRC,
+      MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT,
+      AllocationStrategy::Linear
+    )?;
+    staging.write(pixels.as_raw());
+
+    let (image, allocation) = match attachment::create_image(
+      allocator, device, extent, mip_levels, TEXTURE_FORMAT,
+      ImageUsageFlags::TRANSFER_SRC | ImageUsageFlags::TRANSFER_DST | ImageUsageFlags::SAMPLED
+    ) {
+      Ok(image) => image,
+      Err(err) => {
+        staging.destroy(device, allocator);
+        return Err(err);
+      }
+    };
+
+    let (transfer, graphics) = (queues.transfer, queues.graphics);
+    let uploaded = match queues.ownership_transfer() {
+      None => submit_one_time(device, graphics.command_pool, graphics.queue, |command_buffer| {
+        Texture::record_staging_copy(device, command_buffer, staging.buffer, image, extent, mip_levels);
+        Texture::record_mipmaps(device, command_buffer, image, extent, mip_levels);
+      }),
+      // Every level is released in TRANSFER_DST_OPTIMAL, `submit_one_time` waits so the acquire comes after it
+      Some(families) => submit_one_time(device, transfer.command_pool, transfer.queue, |command_buffer| {
+        Texture::record_staging_copy(device, command_buffer, staging.buffer, image, extent, mip_levels);
+        Texture::ownership_barrier(
+          device, command_buffer, image, mip_levels, families,
+          (AccessFlags::TRANSFER_WRITE, AccessFlags::empty()),
+          (PipelineStageFlags::TRANSFER, PipelineStageFlags::BOTTOM_OF_PIPE)
+        );
+      }).and_then(|_| submit_one_time(device, graphics.command_pool, graphics.queue, |command_buffer| {
+        Texture::ownership_barrier(
+          device, command_buffer, image, mip_levels, families,
+          (AccessFlags::empty(), AccessFlags::TRANSFER_READ | AccessFlags::TRANSFER_WRITE),
+          (PipelineStageFlags::TOP_OF_PIPE, PipelineStageFlags::TRANSFER)
+        );
+        Texture::record_mipmaps(device, command_buffer, image, extent, mip_levels);
+      })),
+    };
+    staging.destroy(device, allocator);
+
+    let view = uploaded.and_then(|_| attachment::create_view(device, image, TEXTURE_FORMAT, ImageAspectFlags::COLOR, mip_levels));
+    let view = match view {
+      Ok(view) => view,
+      Err(err) => {
+        unsafe { device.destroy_image(image, None) };
+        allocator.free(device, allocation);
+        return Err(err);
+      }
+    };
+
+    let sampler = match Texture::create_sampler(device, sampler_desc, mip_levels, support.max_anisotropy) {
+      Ok(sampler) => sampler,
+      Err(err) => {
+        unsafe {
+          device.destroy_image_view(view, None);
+          device.destroy_image(image, None);
+        }
+        allocator.free(device, allocation);
+        return Err(err);
+      }
+    };
+
+    Ok(Texture { image, view, sampler, extent, mip_levels, allocation })
+  }
+
+  pub fn allocation(&self) -> Allocation {
+    self.allocation
+  }
+
+  // Binds a new image to `destination`, which defragmentation reserved, and copies every level over.
+  // The old image and view are released once the copy is done, the sampler is kept. The device must be idle
+  pub fn relocate(
+    &mut self,
+    device       : &Device,
+    allocator    : &mut GpuAllocator,
+    command_pool : CommandPool,
+    queue        : Queue,
+    destination  : Allocation
+  ) -> Result<(), vk::Result> {
+    let usage = ImageUsageFlags::TRANSFER_SRC | ImageUsageFlags::TRANSFER_DST | ImageUsageFlags::SAMPLED;
+    let image = match attachment::create_image_handle(device, self.extent, self.mip_levels, TEXTURE_FORMAT, usage) {
+      Ok(image) => image,
+      Err(err) => {
+        allocator.free(device, destination);
+        return Err(err);
+      }
+    };
+
+    let view = unsafe { device.bind_image_memory(image, destination.memory, destination.offset) }
+      .and_then(|_| submit_one_time(device, command_pool, queue, |command_buffer| {
+        Texture::record_copy(device, command_buffer, self.image, image, self.extent, self.mip_levels);
+      }))
+      .and_then(|_| attachment::create_view(device, image, TEXTURE_FORMAT, ImageAspectFlags::COLOR, self.mip_levels));
+    let view = match view {
+      Ok(view) => view,
+      Err(err) => {
+        unsafe { device.destroy_image(image, None) };
+        allocator.free(device, destination);
+        return Err(err);
+      }
+    };
+
+    unsafe {
+      device.destroy_image_view(self.view, None);
+      device.destroy_image(self.image, None);
+    }
+    allocator.free(device, self.allocation);
+    (self.image, self.view, self.allocation) = (image, view, destination);
+    Ok(())
+  }
+
+  pub fn mip_level_count(extent: Extent2D) -> u32 {
+    32 - extent.width.max(extent.height).leading_zeros()
+  }
+
+  pub fn create_sampler(device: &Device, desc: &SamplerDesc, mip_levels: u32, max_anisotropy: Option<f32>) -> Result<Sampler, vk::Result> {
+    let anisotropy = match (desc.max_anisotropy, max_anisotropy) {
+      (Some(requested), Some(limit)) if requested > 1.0 => Some(requested.min(limit)),
+      _ => None,
+    };
+
+    let sampler_info = SamplerCreateInfo::builder()
+      .mag_filter(desc.mag_filter)
+      .min_filter(desc.min_filter)
+      .mipmap_mode(desc.mipmap_mode)
+      .address_mode_u(desc.wrap_u)
+      .address_mode_v(desc.wrap_v)
+      .address_mode_w(SamplerAddressMode::REPEAT)
+      .anisotropy_enable(anisotropy.is_some())
+      .max_anisotropy(anisotropy.unwrap_or(1.0))
+      .compare_enable(false)
+      .compare_op(CompareOp::ALWAYS)
+      .min_lod(0.0)
+      .max_lod(mip_levels as f32)
+      .border_color(BorderColor::INT_OPAQUE_BLACK)
+      .unnormalized_coordinates(false)
+      .build();
+    unsafe { device.create_sampler(&sampler_info, None) }
+  }
+
+  // Level 0 from the staging buffer, every level is left in TRANSFER_DST_OPTIMAL for `record_mipmaps`
+  fn record_staging_copy(device: &Device, command_buffer: CommandBuffer, staging: vk::Buffer, image: Image, extent: Extent2D, mip_levels: u32) {
+    Texture::transition(
+      device, command_buffer, image, 0..mip_levels,
+      (ImageLayout::UNDEFINED, ImageLayout::TRANSFER_DST_OPTIMAL),
+      (AccessFlags::empty(), AccessFlags::TRANSFER_WRITE),
+      (PipelineStageFlags::TOP_OF_PIPE, PipelineStageFlags::TRANSFER)
+    );
+
+    let region = BufferImageCopy::builder()
+      .buffer_offset(0)
+      .buffer_row_length(0)
+      .buffer_image_height(0)
+      .image_subresource(Texture::color_layers(0))
+      .image_offset(Offset3D { x: 0, y: 0, z: 0 })
+      .image_extent(Extent3D { width: extent.width, height: extent.height, depth: 1 })
+      .build();
+    unsafe { device.cmd_copy_buffer_to_image(command_buffer, staging, image, ImageLayout::TRANSFER_DST_OPTIMAL, &[region]) };
+  }
+
+  // Blits each level down from the one above, every level ends up in SHADER_READ_ONLY_OPTIMAL
+  fn record_mipmaps(device: &Device, command_buffer: CommandBuffer, image: Image, extent: Extent2D, mip_levels: u32) {
+    let (mut width, mut height) = (extent.width as i32, extent.height as i32);
+    for level in 1..mip_levels {
+      Texture::transition(
+        device, command_buffer, image, level - 1..level,
+        (ImageLayout::TRANSFER_DST_OPTIMAL, ImageLayout::TRANSFER_SRC_OPTIMAL),
+        (AccessFlags::TRANSFER_WRITE, AccessFlags::TRANSFER_READ),
+        (PipelineStageFlags::TRANSFER, PipelineStageFlags::TRANSFER)
+      );
+
+      let (next_width, next_height) = ((width / 2).max(1), (height / 2).max(1));
+      let blit = ImageBlit::builder()
+        .src_subresource(Texture::color_layers(level - 1))
+        .src_offsets([Offset3D { x: 0, y: 0, z: 0 }, Offset3D { x: width, y: height, z: 1 }])
+        .dst_subresource(Texture::color_layers(level))
+        .dst_offsets([Offset3D { x: 0, y: 0, z: 0 }, Offset3D { x: next_width, y: next_height, z: 1 }])
+        .build();
+      unsafe {
+        device.cmd_blit_image(
+          command_buffer,
+          image, ImageLayout::TRANSFER_SRC_OPTIMAL,
+          image, ImageLayout::TRANSFER_DST_OPTIMAL,
+          &[blit],
+          Filter::LINEAR
+        );
+      }
+
+      Texture::transition(
+        device, command_buffer, image, level - 1..level,
+        (ImageLayout::TRANSFER_SRC_OPTIMAL, ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+        (AccessFlags::TRANSFER_READ, AccessFlags::SHADER_READ),
+        (PipelineStageFlags::TRANSFER, PipelineStageFlags::FRAGMENT_SHADER)
+      );
+      (width, height) = (next_width, next_height);
+    }
+
+    Texture::transition(
+      device, command_buffer, image, mip_levels - 1..mip_levels,
+      (ImageLayout::TRANSFER_DST_OPTIMAL, ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+      (AccessFlags::TRANSFER_WRITE, AccessFlags::SHADER_READ),
+      (PipelineStageFlags::TRANSFER, PipelineStageFlags::FRAGMENT_SHADER)
+    );
+  }
+
+  // Every level of `source` into the same level of `destination`, both end up in SHADER_READ_ONLY_OPTIMAL
+  fn record_copy(device: &Device, command_buffer: CommandBuffer, source: Image, destination: Image, extent: Extent2D, mip_levels: u32) {
+    Texture::transition(
+      device, command_buffer, source, 0..mip_levels,
+      (ImageLayout::SHADER_READ_ONLY_OPTIMAL, ImageLayout::TRANSFER_SRC_OPTIMAL),
+      (AccessFlags::SHADER_READ, AccessFlags::TRANSFER_READ),
+      (PipelineStageFlags::FRAGMENT_SHADER, PipelineStageFlags::TRANSFER)
+    );
+    Texture::transition(
+      device, command_buffer, destination, 0..mip_levels,
+      (ImageLayout::UNDEFINED, ImageLayout::TRANSFER_DST_OPTIMAL),
+      (AccessFlags::empty(), AccessFlags::TRANSFER_WRITE),
+      (PipelineStageFlags::TOP_OF_PIPE, PipelineStageFlags::TRANSFER)
+    );
+
+    let regions: Vec<ImageCopy> = (0..mip_levels).map(|level| {
+      ImageCopy::builder()
+        .src_subresource(Texture::color_layers(level))
+        .src_offset(Offset3D { x: 0, y: 0, z: 0 })
+        .dst_subresource(Texture::color_layers(level))
+        .dst_offset(Offset3D { x: 0, y: 0, z: 0 })
+        .extent(Extent3D { width: (extent.width >> level).max(1), height: (extent.height >> level).max(1), depth: 1 })
+        .build()
+    }).collect();
+    unsafe {
+      device.cmd_copy_image(
+        command_buffer,
+        source, ImageLayout::TRANSFER_SRC_OPTIMAL,
+        destination, ImageLayout::TRANSFER_DST_OPTIMAL,
+        &regions
+      );
+    }
+
+    // The source stays usable if anything after the copy fails
+    Texture::transition(
+      device, command_buffer, source, 0..mip_levels,
+      (ImageLayout::TRANSFER_SRC_OPTIMAL, ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+      (AccessFlags::TRANSFER_READ, AccessFlags::SHADER_READ),
+      (PipelineStageFlags::TRANSFER, PipelineStageFlags::FRAGMENT_SHADER)
+    );
+    Texture::transition(
+      device, command_buffer, destination, 0..mip_levels,
+      (ImageLayout::TRANSFER_DST_OPTIMAL, ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+      (AccessFlags::TRANSFER_WRITE, AccessFlags::SHADER_READ),
+      (PipelineStageFlags::TRANSFER, PipelineStageFlags::FRAGMENT_SHADER)
+    );
+  }
+
+  fn transition(
+    device         : &Device,
+    command_buffer : CommandBuffer,
+    image          : Image,
+    levels         : Range<u32>,
+    layouts        : (ImageLayout, ImageLayout),
+    access         : (AccessFlags, AccessFlags),
+    stages         : (PipelineStageFlags, PipelineStageFlags)
+  ) {
+    let barrier = ImageMemoryBarrier::builder()
+      .old_layout(layouts.0)
+      .new_layout(layouts.1)
+      .src_access_mask(access.0)
+      .dst_access_mask(access.1)
+      .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+      .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+      .image(image)
+      .subresource_range(ImageSubresourceRange {
+        aspect_mask      : ImageAspectFlags::COLOR,
+        base_mip_level   : levels.start,
+        level_count      : levels.len() as u32,
+        base_array_layer : 0,
+        layer_count      : 1,
+      })
+      .build();
+    unsafe { device.cmd_pipeline_barrier(command_buffer, stages.0, stages.1, DependencyFlags::empty(), &[], &[], &[barrier]) };
+  }
+
+  // Half of a queue family ownership transfer of every level, recorded once on each queue. The layout is kept
+  fn ownership_barrier(
+    device         : &Device,
+    command_buffer : CommandBuffer,
+    image          : Image,
+    mip_levels     : u32,
+    families       : (u32, u32),
+    access         : (AccessFlags, AccessFlags),
+    stages         : (PipelineStageFlags, PipelineStageFlags)
+  ) {
+    let barrier = ImageMemoryBarrier::builder()
+      .old_layout(ImageLayout::TRANSFER_DST_OPTIMAL)
+      .new_layout(ImageLayout::TRANSFER_DST_OPTIMAL)
+      .src_access_mask(access.0)
+      .dst_access_mask(access.1)
+      .src_queue_family_index(families.0)
+      .dst_queue_family_index(families.1)
+      .image(image)
+      .subresource_range(ImageSubresourceRange {
+        aspect_mask      : ImageAspectFlags::COLOR,
+        base_mip_level   : 0,
+        level_count      : mip_levels,
+        base_array_layer : 0,
+        layer_count      : 1,
+      })
+      .build();
+    unsafe { device.cmd_pipeline_barrier(command_buffer, stages.0, stages.1, DependencyFlags::empty(), &[], &[], &[barrier]) };
+  }
+
+  fn color_layers(mip_level: u32) -> ImageSubresourceLayers {
+    ImageSubresourceLayers {
+      aspect_mask      : ImageAspectFlags::COLOR,
+      mip_level,
+      base_array_layer : 0,
+      layer_count      : 1,
+    }
+  }
+
+  pub fn destroy(self, device: &Device, allocator: &mut GpuAllocator) {
+    unsafe {
+      device.destroy_sampler(self.sampler, None);
+      device.destroy_image_view(self.view, None);
+      device.destroy_image(self.image, None);
+    }
+    allocator.free(device, self.allocation);
+  }
+}
@@ -0,0 +1,174 @@
+use std::marker::PhantomData;
+
+use ash::{
+  vk::{ self, BufferUsageFlags, DeviceSize, MemoryPropertyFlags },
+  Device
+};
+use nalgebra::Matrix4;
+
+use crate::drivers::renderer::{Lighting, Pod, MAX_LIGHTS};
+use super::buffer::GpuBuffer;
+use super::memory::{AllocationStrategy, GpuAllocator};
+
+// Same matrices and lights the GL device sets by name. Column major, matching the std140 `Matrices` block
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct Matrices {
+  pub model           : [[f32; 4]; 4],
+  pub view            : [[f32; 4]; 4],
+  pub projection      : [[f32; 4]; 4],
+  pub light_positions : [[f32; 4]; MAX_LIGHTS],
+  pub light_colors    : [[f32; 4]; MAX_LIGHTS],
+  pub light_count     : u32,
+  _padding            : [u32; 3],
+}
+
+// Only 16 byte aligned f32/u32 arrays and explicit padding, 336 bytes without holes
+unsafe impl Pod for Matrices {}
+
+impl Matrices {
+  pub fn new(model: &Matrix4<f32>, view: &Matrix4<f32>, projection: &Matrix4<f32>, lighting: &Lighting) -> Self {
+    Matrices {
+      model           : (*model).into(),
+      view            : (*view).into(),
+      projection      : (*projection).into(),
+      light_positions : lighting.positions,
+      light_colors    : lighting.colors,
+      light_count     : lighting.count.min(MAX_LIGHTS as u32),
+      _padding        : [0; 3],
+    }
+  }
+}
+
+// Typed handle to a uniform ring owned by VulkanResources
+pub struct UniformHandle<T: Pod> {
+  pub(super) index : usize,
+  _marker          : PhantomData<T>,
+}
+
+impl<T: Pod> UniformHandle<T> {
+  pub(super) fn new(index: usize) -> Self {
+    UniformHandle { index, _marker: PhantomData }
+  }
+}
+
+impl<T: Pod> Clone for UniformHandle<T> {
+  fn clone(&self) -> Self {
+    *self
+  }
+}
+
+impl<T: Pod> Copy for UniformHandle<T> {}
+
+// One host visible buffer per frame in flight, each split into `capacity` slots read through
+// UNIFORM_BUFFER_DYNAMIC offsets. A frame's slots are only rewritten once its fence has signalled
+pub struct UniformRing {
+  frames   : Vec<GpuBuffer>,
+  range    : DeviceSize,
+  stride   : DeviceSize,
+  capacity : u32,
+  cursors  : Vec<u32>,
+  last     : Vec<u32>,
+}
+
+impl UniformRing {
+
+  pub fn new(
+    allocator     : &mut GpuAllocator,
+    device        : &Device,
+    element_size  : DeviceSize,
+    capacity      : u32,
+    min_alignment : DeviceSize,
+    frame_count   : usize
+  ) -> Result<Self, vk::Result> {
+
+    let alignment = min_alignment.max(1);
+    let stride = element_size.div_ceil(alignment) * alignment;
+
+    let mut frames = Vec::with_capacity(frame_count);
+    for _ in 0..frame_count {
+      let buffer = GpuBuffer::new(
+        allocator, device, stride * capacity as DeviceSize,
+        BufferUsageFlags::UNIFORM_BUFFER,
+        MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT,
+        AllocationStrategy::FreeList
+      );
+      match buffer {
+        Ok(buffer) => frames.push(buffer),
+        Err(err) => {
+          for buffer in frames {
+            buffer.destroy(device, allocator);
+          }
+          return Err(err);
+        }
+      }
+    }
+
+    Ok(UniformRing {
+      frames,
+      range    : element_size,
+      stride,
+      capacity,
+      cursors  : vec![0; frame_count],
+      last     : vec![0; frame_count],
+    })
+  }
+
+  // Called once the frame's fence has been waited on, its previous contents are no longer read
+  pub fn reset(&mut self, frame: usize) {
+    self.cursors[frame] = 0;
+    self.last[frame] = 0;
+  }
+
+  // Copies `data` into the next free slot of `frame`, returns the dynamic offset to bind it with
+  pub fn push(&mut self, frame: usize, data: &[u8]) -> Option<u32> {
+    assert!(data.len() as DeviceSize <= self.range, "Uniform of {} bytes overflows a {} byte slot", data.len(), self.range);
+    let slot = self.cursors[frame];
+    if slot >= self.capacity {
+      return None;
+    }
+
+    let offset = slot as DeviceSize * self.stride;
+    let data_ptr = self.frames[frame].allocation.mapped_ptr().expect("Uniform memory is not host visible");
+    unsafe { data_ptr.add(offset as usize).copy_from_nonoverlapping(data.as_ptr(), data.len()) };
+
+    self.cursors[frame] = slot + 1;
+    self.last[frame] = offset as u32;
+    Some(offset as u32)
+  }
+
+  // Offset of the most recent push, what a draw without its own offset binds
+  pub fn last_offset(&self, frame: usize) -> u32 {
+    self.last[frame]
+  }
+
+  pub fn buffer(&self, frame: usize) -> vk::Buffer {
+    self.frames[frame].buffer
+  }
+
+  pub fn frame_count(&self) -> usize {
+    self.frames.len()
+  }
+
+  pub fn range(&self) -> DeviceSize {
+    self.range
+  }
+
+  pub fn destroy(self, device: &Device, allocator: &mut GpuAllocator) {
+    for buffer in self.frames {
+      buffer.destroy(device, allocator);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn clamps_the_light_count_to_the_arrays() {
+    let lighting = Lighting { count: MAX_LIGHTS as u32 + 3, ..Lighting::default() };
+    let identity = Matrix4::identity();
+    assert_eq!(Matrices::new(&identity, &identity, &identity, &lighting).light_count, MAX_LIGHTS as u32);
+  }
+}
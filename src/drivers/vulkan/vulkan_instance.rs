@@ -1,20 +1,44 @@
 use std::ffi::CString;
+use std::mem::size_of;
 use std::os::raw::c_char;
 use winit::window::Window;
 use ash::extensions::khr::Swapchain;
 use ash::prelude::VkResult;
-use ash::vk::{ Buffer, ClearColorValue, ClearValue, CommandBuffer, CommandBufferAllocateInfo, CommandBufferBeginInfo, CommandBufferLevel, CommandBufferUsageFlags, CommandPool, CommandPoolCreateFlags, CommandPoolCreateInfo, DescriptorSetLayoutBinding, DeviceMemory, DeviceSize, Extent2D, Fence, FenceCreateFlags, FenceCreateInfo, Framebuffer, FramebufferCreateInfo, Offset2D, PhysicalDeviceMemoryProperties, PipelineBindPoint, PipelineLayout, PresentModeKHR, Rect2D, RenderPass, RenderPassBeginInfo, Semaphore, SemaphoreCreateInfo, SubpassContents, SurfaceCapabilitiesKHR, SurfaceFormatKHR, SwapchainKHR };
-use ash::{ vk, vk::QueueFlags, vk::SurfaceKHR,Entry, extensions::khr::Surface };
+use ash::vk::{ ClearColorValue, ClearValue, CommandBuffer, CommandBufferAllocateInfo, CommandBufferBeginInfo, CommandBufferLevel, CommandBufferUsageFlags, CommandPool, CommandPoolCreateFlags, CommandPoolCreateInfo, DescriptorSet, DescriptorSetLayoutBinding, DeviceSize, Extent2D, Fence, FenceCreateFlags, FenceCreateInfo, Framebuffer, Offset2D, PipelineBindPoint, PipelineLayout, PresentModeKHR, Rect2D, RenderPass, RenderPassBeginInfo, Semaphore, SemaphoreCreateInfo, SubpassContents, SurfaceCapabilitiesKHR, SurfaceFormatKHR, SwapchainKHR };
+use ash::{ vk, vk::SurfaceKHR,Entry, extensions::khr::Surface };
 use raw_window_handle::{ HasRawWindowHandle, HasRawDisplayHandle };
 
-use super::pipeline::PipelineConfig;
-use super::vulkan_resources::{Vertex, VulkanResources};
+use image::RgbaImage;
+
+use crate::drivers::renderer::{
+  as_bytes, BufferId, BufferUsage, Device, DrawCall, PipelineDesc, PipelineId, Pod, Renderer, RendererError, ShaderId, ShaderSource, TextureId
+};
+use super::attachment::{self, DepthBuffer};
+use super::buffer::{QueueContext, UploadQueues};
+use super::debug::{self, DebugMessenger, DebugMode, ObjectNamer};
+use super::device_selection::{self, DevicePreference, DeviceRequirements, DeviceSelectionError, QueueFamilies};
+use super::memory::{GpuAllocator, HeapStats};
+use super::texture::{SamplerDesc, TextureSupport};
+use super::uniform::{Matrices, UniformHandle, UniformRing};
+use super::offscreen::{OffscreenTarget, OFFSCREEN_COLOR_FORMAT};
+use super::pipeline::{PipelineConfig, ShaderStageConfig};
+use super::pipeline_cache::PipelineCacheFile;
+use super::reflect::ShaderReflection;
+use super::shader_compiler::ShaderCompiler;
+use super::vulkan_resources::VulkanResources;
 
 pub struct VulkanInstance {
   _entry: Entry,
   instance                        : ash::Instance,
+  debug_messenger                 : Option<DebugMessenger>,
+  object_namer                    : Option<ObjectNamer>,
   physical_device                 : Option<vk::PhysicalDevice>,
+  device_preference               : Option<DevicePreference>,
   logical_device                  : Option<ash::Device>,
+  allocator                       : Option<GpuAllocator>,
+  max_sampler_anisotropy          : Option<f32>,
+  enabled_features                : vk::PhysicalDeviceFeatures,
+  pipeline_cache                  : Option<PipelineCacheFile>,
   surface                         : Option<SurfaceKHR>,
   surface_format                  : Option<SurfaceFormatKHR>,
   surface_capabilities            : Option<SurfaceCapabilitiesKHR>,
@@ -24,30 +48,96 @@ pub struct VulkanInstance {
   surface_loader                  : Option<Surface>,
   graphics_queue_family_index     : Option<u32>,
   presentation_queue_family_index : Option<u32>,
+  transfer_queue_family_index     : Option<u32>,
+  compute_queue_family_index      : Option<u32>,
   graphics_queue                  : Option<vk::Queue>,
+  presentation_queue              : Option<vk::Queue>,
+  transfer_queue                  : Option<vk::Queue>,
+  compute_queue                   : Option<vk::Queue>,
   swapchain_loader                : Option<Swapchain>,
   swapchain                       : Option<SwapchainKHR>,
   swapchain_images                : Option<Vec<vk::Image>>,
   swapchain_framebuffers          : Option<Vec<Framebuffer>>,
   swapchain_image_views           : Option<Vec<vk::ImageView>>,
   render_pass                     : Option<RenderPass>,
+  depth_format                    : Option<vk::Format>,
+  depth_stencil                   : bool,
+  depth_buffers                   : Vec<DepthBuffer>,
   vulkan_resources                : Option<VulkanResources>,
-  vertex_count                    : usize,                // Need to move to vulkan_resources
-  vertex_buffer                   : Option<Buffer>,       // Need to move to vulkan_resources
-  vertex_buffer_memory            : Option<DeviceMemory>, // Need to move to vulkan_resources
   command_pool                    : Option<CommandPool>,
+  transfer_command_pool           : Option<CommandPool>, // Only for a transfer family apart from graphics
   command_buffers                 : Option<Vec<CommandBuffer>>,
   image_available_semaphores      : Vec<Semaphore>,
   render_complete_semaphores      : Vec<Semaphore>,
   in_flight_fences                : Vec<Fence>,
   images_in_flight                : Vec<Option<Fence>>,
+  shader_stages                   : Vec<Vec<ShaderStageConfig>>,
+  pipeline_count                  : usize,
+  draw_uniforms                   : Option<usize>,
+  unbound_texture                 : Option<usize>,
+  current_frame                   : usize,
+  current_image_index             : Option<u32>,
+  offscreen_target                : Option<OffscreenTarget>,
+  window_extent                   : Extent2D,
+  swapchain_out_of_date           : bool,
+  frame_skipped                   : bool,
 }
 
 pub const MAX_FRAMES_IN_FLIGHT: usize = 2;
 
+// How the instance is created. `debug` and `device` start out from the RENDERER_VULKAN_DEBUG and
+// RENDERER_VULKAN_DEVICE env vars
+#[derive(Clone, Debug)]
+pub struct InstanceOptions {
+  pub surface : bool,
+  pub debug   : DebugMode,
+  pub device  : Option<DevicePreference>,
+}
+
+impl InstanceOptions {
+  pub fn windowed() -> Self {
+    InstanceOptions { surface: true, debug: DebugMode::from_env(), device: DevicePreference::from_env() }
+  }
+
+  // No surface extensions are requested, the instance can only render into an OffscreenTarget
+  pub fn offscreen() -> Self {
+    InstanceOptions { surface: false, ..InstanceOptions::windowed() }
+  }
+
+  pub fn with_debug(mut self, debug: DebugMode) -> Self {
+    self.debug = debug;
+    self
+  }
+
+  // Picks the device in `configure_hardware` instead of the best scoring one
+  pub fn with_device(mut self, device: DevicePreference) -> Self {
+    self.device = Some(device);
+    self
+  }
+}
+
+// Binding names of the set every pipeline from `Device::create_pipeline` gets, see `Matrices`
+pub const MATRICES_BINDING : &str = "matrices";
+pub const TEXTURE_BINDING  : &str = "texture1";
+
+// Colors of the command buffer labels capture tools group passes by
+const PASS_LABEL_COLOR     : [f32; 4] = [0.2, 0.6, 1.0, 1.0];
+const READBACK_LABEL_COLOR : [f32; 4] = [1.0, 0.6, 0.2, 1.0];
+
+// Renderer::draw calls one frame can record before the matrices ring runs out
+const DRAW_UNIFORM_CAPACITY: u32 = 4096;
+
 impl VulkanInstance {
   
   pub fn new(app_name: &str, engine_name: &str) -> Result<Self, vk::Result> {
+    VulkanInstance::with_options(app_name, engine_name, InstanceOptions::windowed())
+  }
+
+  pub fn new_offscreen(app_name: &str, engine_name: &str) -> Result<Self, vk::Result> {
+    VulkanInstance::with_options(app_name, engine_name, InstanceOptions::offscreen())
+  }
+
+  pub fn with_options(app_name: &str, engine_name: &str, options: InstanceOptions) -> Result<Self, vk::Result> {
 
     let entry = unsafe { match Entry::load() {
       Ok(entry) => entry,
@@ -66,18 +156,58 @@ impl VulkanInstance {
       .engine_version(vk::make_api_version(0, 0, 0, 0))
       .api_version(vk::API_VERSION_1_0);
 
-    let instance_extensions = VulkanInstance::load_instance_extensions();
-    let create_info = vk::InstanceCreateInfo::builder()
+    let mut instance_extensions = match options.surface {
+      true  => VulkanInstance::load_instance_extensions(),
+      false => Vec::new(),
+    };
+    // Debugging is best effort, a missing layer or extension only costs the messages
+    let mut layers: Vec<*const c_char> = Vec::new();
+    let mut debug_utils = false;
+    if options.debug.enabled() {
+      match debug::validation_layer_available(&entry) {
+        true  => layers.push(debug::VALIDATION_LAYER.as_ptr()),
+        false => println!("{} is not installed, continuing without validation", debug::VALIDATION_LAYER.to_string_lossy()),
+      }
+      debug_utils = debug::debug_utils_available(&entry);
+      match debug_utils {
+        true  => instance_extensions.push(ash::extensions::ext::DebugUtils::name().as_ptr()),
+        false => println!("{} is not available, validation messages cannot be reported", ash::extensions::ext::DebugUtils::name().to_string_lossy()),
+      }
+    }
+
+    let mut messenger_info = debug::messenger_create_info(options.debug);
+    let mut create_info = vk::InstanceCreateInfo::builder()
       .application_info(&app_info)
+      .enabled_layer_names(&layers)
       .enabled_extension_names(&instance_extensions);
+    if debug_utils {
+      create_info = create_info.push_next(&mut messenger_info);
+    }
 
     let instance = unsafe { entry.create_instance(&create_info, None)? };
+    let debug_messenger = match debug_utils {
+      true => match DebugMessenger::new(&entry, &instance, options.debug) {
+        Ok(messenger) => Some(messenger),
+        Err(err) => {
+          unsafe { instance.destroy_instance(None) };
+          return Err(err);
+        }
+      },
+      false => None,
+    };
 
     Ok(VulkanInstance {
       _entry: entry, 
       instance,
+      debug_messenger,
+      object_namer                    : None,
       physical_device                 : None,
+      device_preference               : options.device.clone(),
       logical_device                  : None,
+      allocator                       : None,
+      max_sampler_anisotropy          : None,
+      enabled_features                : vk::PhysicalDeviceFeatures::default(),
+      pipeline_cache                  : None,
       surface                         : None,
       surface_capabilities            : None,
       surface_format                  : None,
@@ -87,29 +217,47 @@ impl VulkanInstance {
       surface_loader                  : None,
       graphics_queue_family_index     : None,
       presentation_queue_family_index : None,
+      transfer_queue_family_index     : None,
+      compute_queue_family_index      : None,
       graphics_queue                  : None,
+      presentation_queue              : None,
+      transfer_queue                  : None,
+      compute_queue                   : None,
       swapchain_loader                : None,
       swapchain                       : None,
       swapchain_images                : None,
       swapchain_framebuffers          : None,
       swapchain_image_views           : None,
       render_pass                     : None,
+      depth_format                    : None,
+      depth_stencil                   : false,
+      depth_buffers                   : Vec::new(),
       vulkan_resources                : None,
-      vertex_buffer                   : None,
-      vertex_buffer_memory            : None,
       command_pool                    : None,
+      transfer_command_pool           : None,
       command_buffers                 : None,
       image_available_semaphores      : Vec::new(),
       render_complete_semaphores      : Vec::new(),
-      vertex_count                    : 0,
       in_flight_fences                : Vec::new(),
       images_in_flight                : Vec::new(), 
+      shader_stages                   : Vec::new(),
+      pipeline_count                  : 0,
+      draw_uniforms                   : None,
+      unbound_texture                 : None,
+      current_frame                   : 0,
+      current_image_index             : None,
+      offscreen_target                : None,
+      window_extent                   : Extent2D::default(),
+      swapchain_out_of_date           : false,
+      frame_skipped                   : false,
     })
   }
 
+  /// # Safety
+  /// `window` must outlive the surface, which lives until the instance is dropped
   pub unsafe fn create_surface(&mut self, window: &Window) -> Result<&mut Self, vk::Result> {
 
-    if (self.surface_loader.is_none()) {
+    if self.surface_loader.is_none() {
       self.surface_loader = Some(Surface::new(&self._entry, &self.instance));
     }
 
@@ -128,61 +276,74 @@ impl VulkanInstance {
     Ok(self)
   }
 
-  pub fn configure_hardware(&mut self) -> &mut Self {
-  
-    let device = self.select_physical_device();
-    self.physical_device = match device {
-      Ok(device) => Some(device),
-      Err(_) => {
-        panic!("No compatible device found");
-      }
-    };
-
-    let physical_device = match self.physical_device {
-      Some(device) => device,
-      None => {
-        panic!("Error referencing VulkanInstance field: physical_device");
-      }
+  // Scores every device, prints the ranking and takes the best usable one, or the one forced through
+  // RENDERER_VULKAN_DEVICE or `InstanceOptions::with_device`. Software devices like lavapipe are the last resort.
+  // Call `enable_stencil` first for stencil support to count. Fails when no device, or not the forced one, can be used
+  pub fn configure_hardware(&mut self) -> Result<&mut Self, DeviceSelectionError> {
+    if self.surface.is_some() && self.surface_loader.is_none() {
+      self.surface_loader = Some(Surface::new(&self._entry, &self.instance));
+    }
+    let requirements = DeviceRequirements {
+      surface       : self.surface.map(|surface| (self.surface_loader.as_ref().unwrap(), surface)),
+      depth_stencil : self.depth_stencil,
     };
+    let candidates = device_selection::rank_devices(&self.instance, &requirements)?;
+    let selected = device_selection::select_device(&candidates, self.device_preference.as_ref());
+    println!("\n{}", device_selection::format_report(&candidates, selected.as_ref().ok().copied()));
+    let selected = selected?.clone();
+    if selected.is_software() {
+      println!("Using the software implementation {}, rendering will be slow", selected.name);
+    }
 
-    let properties = unsafe { self.instance.get_physical_device_properties(physical_device) };
-    let features     = unsafe { self.instance.get_physical_device_features(physical_device) };
-
-    println!("\nDevice Properties -\n{}", VulkanInstance::format_device_properties(properties));
-    println!("\nDevice Features -\n{}", VulkanInstance::format_device_features(features));
-
-    self.surface_loader = Some(Surface::new(&self._entry, &self.instance));
-    let queue_indicies = self.identify_required_queue_family_indices(physical_device, &self.instance);
-    match queue_indicies {
-      Some((graphics_queue_index, presentation_queue_index)) => {
-
-        self.graphics_queue_family_index     = Some(graphics_queue_index);
-        self.presentation_queue_family_index = Some(presentation_queue_index);
+    let properties = unsafe { self.instance.get_physical_device_properties(selected.physical_device) };
+    println!("Device Properties -\n{}", VulkanInstance::format_device_properties(properties));
 
-        println!("Configured Queue indices (Graphics: {}, Presentation: {})", graphics_queue_index, presentation_queue_index);
-        self
-      },
-      None => {panic!("Failed to configure required Vulkan Queues, no indices detected.")}
-    }
+    self.physical_device = Some(selected.physical_device);
+    self.graphics_queue_family_index     = selected.queue_families.graphics;
+    self.presentation_queue_family_index = selected.queue_families.present;
+    self.transfer_queue_family_index     = selected.queue_families.transfer;
+    self.compute_queue_family_index      = selected.queue_families.compute;
+    println!("Configured Queue indices ({})", selected.queue_families);
+    Ok(self)
   }
 
   pub fn create_logical_device(&mut self) -> Result<&mut Self, vk::Result> {
     let queue_priorities = [1.0_f32];
     let queue_family_index = self.graphics_queue_family_index.unwrap();
 
-    let queue_create_info = vk::DeviceQueueCreateInfo::builder()
-      .queue_family_index(queue_family_index)
-      .queue_priorities(&queue_priorities)
-      .build();
-
-    let device_extension_names = [
-      ash::extensions::khr::Swapchain::name().as_ptr(),
-    ];
+    // One queue per family, roles that share a family share its queue
+    let queue_family_indices = self.queue_families().unique();
+    let queue_create_infos: Vec<vk::DeviceQueueCreateInfo> = queue_family_indices.iter().map(|&family_index| {
+      vk::DeviceQueueCreateInfo::builder()
+        .queue_family_index(family_index)
+        .queue_priorities(&queue_priorities)
+        .build()
+    }).collect();
+
+    let device_extension_names = match self.surface {
+      Some(_) => vec![ash::extensions::khr::Swapchain::name().as_ptr()],
+      None    => Vec::new(),
+    };
 
-    let physical_device_features = vk::PhysicalDeviceFeatures::default();
+    // Anisotropic filtering is optional, samplers fall back to plain trilinear without it. The rasterizer features
+    // are enabled when present, pipelines needing a missing one fail in `configure_graphics_pipeline`
+    let supported_features = unsafe { self.instance.get_physical_device_features(self.physical_device.unwrap()) };
+    let physical_device_features = vk::PhysicalDeviceFeatures::builder()
+      .sampler_anisotropy(supported_features.sampler_anisotropy != 0)
+      .fill_mode_non_solid(supported_features.fill_mode_non_solid != 0)
+      .wide_lines(supported_features.wide_lines != 0)
+      .depth_clamp(supported_features.depth_clamp != 0)
+      .depth_bias_clamp(supported_features.depth_bias_clamp != 0)
+      .independent_blend(supported_features.independent_blend != 0)
+      .build();
+    self.enabled_features = physical_device_features;
+    self.max_sampler_anisotropy = match supported_features.sampler_anisotropy != 0 {
+      true  => Some(unsafe { self.instance.get_physical_device_properties(self.physical_device.unwrap()) }.limits.max_sampler_anisotropy),
+      false => None,
+    };
 
     let device_create_info = vk::DeviceCreateInfo::builder()
-      .queue_create_infos(&[queue_create_info])
+      .queue_create_infos(&queue_create_infos)
       .enabled_features(&physical_device_features)
       .enabled_extension_names(&device_extension_names)
       .build();
@@ -191,24 +352,57 @@ impl VulkanInstance {
       self.instance.create_device(self.physical_device.unwrap(), &device_create_info, None)?
     };
 
+    // Without a cache pipelines are compiled from scratch, which is slower but works the same
+    let properties = unsafe { self.instance.get_physical_device_properties(self.physical_device.unwrap()) };
+    self.pipeline_cache = PipelineCacheFile::default_path(&properties).and_then(|path| {
+      PipelineCacheFile::load(&logical_device, &properties, path)
+        .map_err(|err| println!("Pipelines will not be cached: {}", err))
+        .ok()
+    });
+
+    self.object_namer = self.debug_messenger.as_ref().map(|messenger| messenger.object_namer(&logical_device));
     self.logical_device = Some(logical_device);
+    self.allocator = Some(GpuAllocator::new(&self.instance, self.physical_device.unwrap()));
+    if let Some(pipeline_cache) = &self.pipeline_cache {
+      self.name_object(pipeline_cache.cache, "pipeline cache");
+    }
 
     let graphics_queue = unsafe {
       self.logical_device.as_ref().unwrap().get_device_queue(queue_family_index, 0)
     };
 
     self.graphics_queue = Some(graphics_queue);
+    let device = self.logical_device.as_ref().unwrap();
+    let family_queue = |family_index: u32| unsafe { device.get_device_queue(family_index, 0) };
+    self.presentation_queue = self.presentation_queue_family_index.map(family_queue);
+    self.transfer_queue     = self.transfer_queue_family_index.map(family_queue);
+    self.compute_queue      = self.compute_queue_family_index.map(family_queue);
+
+    self.name_object(graphics_queue, "graphics queue");
+    for (queue, name) in [(self.transfer_queue, "transfer queue"), (self.compute_queue, "compute queue")] {
+      if let Some(queue) = queue {
+        self.name_object(queue, name);
+      }
+    }
 
     Ok(self)
   }
 
   pub fn create_swapchain(&mut self, window: &Window) -> Result<&mut Self, vk::Result> {
+    let window_size = window.inner_size();
+    self.window_extent = Extent2D { width: window_size.width, height: window_size.height };
+    self.build_swapchain()
+  }
+
+  // Replaces the current swapchain (if any) using `window_extent`, image views are rebuilt too
+  fn build_swapchain(&mut self) -> Result<&mut Self, vk::Result> {
 
-    let _ = self.query_surface_capabilities().unwrap()
-      .configure_surface_format().unwrap()
-      .configure_presentation_mode().unwrap()
-      .configure_swap_extent(window);
+    self.query_surface_capabilities()?
+      .configure_surface_format()?
+      .configure_presentation_mode()?
+      .configure_swap_extent();
 
+    let old_swapchain = self.swapchain.unwrap_or(SwapchainKHR::null());
     let swapchain_create_info = vk::SwapchainCreateInfoKHR::builder()
       .surface(self.surface.unwrap())
       .min_image_count(self.surface_capabilities.as_ref().unwrap().min_image_count + 1)
@@ -220,18 +414,39 @@ impl VulkanInstance {
       .pre_transform(self.surface_capabilities.as_ref().unwrap().current_transform)
       .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
       .present_mode(self.presentation_mode.unwrap())
-      .clipped(true);
+      .clipped(true)
+      .old_swapchain(old_swapchain);
+
+    // Images are written by the graphics queue and read by the presentation queue. Sharing them concurrently
+    // between the two families saves ownership transfer barriers in every frame
+    let graphics_family = self.graphics_queue_family_index.unwrap();
+    let sharing_families = [graphics_family, self.presentation_queue_family_index.unwrap_or(graphics_family)];
+    let swapchain_create_info = match sharing_families[0] != sharing_families[1] {
+      true  => swapchain_create_info.image_sharing_mode(vk::SharingMode::CONCURRENT).queue_family_indices(&sharing_families),
+      false => swapchain_create_info.image_sharing_mode(vk::SharingMode::EXCLUSIVE),
+    };
 
-    let swapchain_loader = Swapchain::new(&self.instance, self.logical_device.as_ref().unwrap());
+    if self.swapchain_loader.is_none() {
+      self.swapchain_loader = Some(Swapchain::new(&self.instance, self.logical_device.as_ref().unwrap()));
+    }
+    let swapchain_loader = self.swapchain_loader.as_ref().unwrap();
     let swapchain = unsafe { 
       swapchain_loader.create_swapchain(&swapchain_create_info, None)?
     };
 
+    // The retired swapchain is only destroyed once its replacement exists
+    if old_swapchain != SwapchainKHR::null() {
+      unsafe { swapchain_loader.destroy_swapchain(old_swapchain, None) };
+    }
+
     self.swapchain = Some(swapchain);
-    self.swapchain_loader = Some(swapchain_loader);
+    self.name_object(swapchain, "swapchain");
 
     let swapchain_images = self.get_swapchain_images();
     if let Ok(images) = swapchain_images {
+      for (index, &image) in images.iter().enumerate() {
+        self.name_object(image, &format!("swapchain image {}", index));
+      }
       self.swapchain_images = Some(images);
     } else {
       return Err(swapchain_images.err().unwrap())
@@ -240,9 +455,15 @@ impl VulkanInstance {
     if let Some(ref images) = self.swapchain_images {
       let swapchain_image_views = self.create_image_views(&images[..]);
       match swapchain_image_views {
-        Ok(views) => self.swapchain_image_views = Some(views),
+        Ok(views) => {
+          for (index, &view) in views.iter().enumerate() {
+            self.name_object(view, &format!("swapchain image view {}", index));
+          }
+          self.swapchain_image_views = Some(views);
+        },
         Err(e) => return Err(e),
       }
+      self.images_in_flight = vec![None; images.len()];
     } else {
       panic!("Swapchain Image View creation failed");
     }
@@ -250,6 +471,56 @@ impl VulkanInstance {
     Ok(self)
   }
 
+  // Called by the window loop on resize, the swapchain is rebuilt before the next frame
+  pub fn resize(&mut self, width: u32, height: u32) {
+    self.window_extent = Extent2D { width, height };
+    self.swapchain_out_of_date = true;
+  }
+
+  // Framebuffers, image views and depth buffers are tied to the swapchain images, the swapchain itself is retired by build_swapchain
+  fn cleanup_swapchain(&mut self) {
+    let device = self.logical_device.as_ref().unwrap();
+    unsafe {
+      for framebuffer in self.swapchain_framebuffers.take().unwrap_or_default() {
+        device.destroy_framebuffer(framebuffer, None);
+      }
+      for image_view in self.swapchain_image_views.take().unwrap_or_default() {
+        device.destroy_image_view(image_view, None);
+      }
+    }
+    for depth_buffer in self.depth_buffers.drain(..) {
+      depth_buffer.destroy(device, self.allocator.as_mut().unwrap());
+    }
+    self.swapchain_images = None;
+  }
+
+  // Pipelines use dynamic viewport and scissor state, so only swapchain sized resources are rebuilt
+  pub fn recreate_swapchain(&mut self) -> Result<&mut Self, vk::Result> {
+    if self.window_extent.width == 0 || self.window_extent.height == 0 {
+      return Ok(self);
+    }
+
+    unsafe { self.logical_device.as_ref().unwrap().device_wait_idle()? };
+    self.cleanup_swapchain();
+    self.build_swapchain()?;
+    self.create_depth_resources()?;
+    self.create_framebuffers();
+
+    let image_count = self.swapchain_images.as_ref().map_or(0, |images| images.len());
+    if self.command_buffers.as_ref().map_or(0, |buffers| buffers.len()) != image_count {
+      if let Some(command_buffers) = self.command_buffers.take() {
+        unsafe {
+          self.logical_device.as_ref().unwrap().free_command_buffers(self.command_pool.unwrap(), &command_buffers);
+        }
+      }
+      self.allocate_command_buffers();
+    }
+
+    self.swapchain_out_of_date = false;
+    println!("Recreated swapchain ({}x{})", self.swap_extent.unwrap().width, self.swap_extent.unwrap().height);
+    Ok(self)
+  }
+
   fn get_swapchain_images(&self) -> VkResult<Vec<vk::Image>> {
     let swapchain_images = unsafe {
       self.swapchain_loader.as_ref().unwrap().get_swapchain_images(self.swapchain.unwrap())?
@@ -281,14 +552,34 @@ impl VulkanInstance {
     Ok(views)
   }
 
+  // One depth buffer per swapchain image, requires the render pass to have picked the depth format
+  pub fn create_depth_resources(&mut self) -> Result<&mut Self, vk::Result> {
+    let image_count = self.swapchain_image_views.as_ref().expect("Swapchain Image Views not initialized").len();
+    let depth_format = self.depth_format.expect("Depth format not selected, create the Render Pass first");
+    for index in 0..image_count {
+      let depth_buffer = DepthBuffer::new(
+        self.allocator.as_mut().unwrap(),
+        self.logical_device.as_ref().unwrap(),
+        self.swap_extent.unwrap(),
+        depth_format
+      )?;
+      self.name_object(depth_buffer.view, &format!("depth buffer {}", index));
+      self.depth_buffers.push(depth_buffer);
+    }
+    Ok(self)
+  }
+
   pub fn create_framebuffers(&mut self) -> &mut Self {
 
-    self.swapchain_framebuffers = Some(self.swapchain_image_views.iter().filter_map(|image_view| {
+    // One framebuffer per swapchain image, each with its own depth buffer
+    let image_views = self.swapchain_image_views.as_ref().unwrap();
+    assert_eq!(image_views.len(), self.depth_buffers.len(), "Depth buffers must be created before the Framebuffers");
+    self.swapchain_framebuffers = Some(image_views.iter().zip(self.depth_buffers.iter()).map(|(&image_view, depth_buffer)| {
 
-        //let attachments = [image_view];
+        let attachments = [image_view, depth_buffer.view];
         let framebuffer_info = vk::FramebufferCreateInfo::builder()
             .render_pass(self.render_pass.unwrap()) 
-            .attachments(&image_view)
+            .attachments(&attachments)
             .width(self.swap_extent.unwrap().width)
             .height(self.swap_extent.unwrap().height)
             .layers(1)
@@ -296,13 +587,33 @@ impl VulkanInstance {
 
         unsafe {
             self.logical_device.as_ref().unwrap().create_framebuffer(&framebuffer_info, None)
-                .ok()
+                .expect("Failed to create Framebuffer")
         }
     }).collect::<Vec<_>>());
+    for (index, &framebuffer) in self.swapchain_framebuffers.as_ref().unwrap().iter().enumerate() {
+      self.name_object(framebuffer, &format!("framebuffer {}", index));
+    }
     self
 }
 
+  // Asks for a depth format with a stencil component, must be called before create_render_pass
+  pub fn enable_stencil(&mut self) -> &mut Self {
+    self.depth_stencil = true;
+    self
+  }
+
   pub fn create_render_pass(&mut self) -> Result<&mut Self, vk::Result> {
+    let depth_format = attachment::find_depth_format(&self.instance, self.physical_device.expect("Physical device not initialized"), self.depth_stencil)?;
+    let stencil_load_op = match attachment::has_stencil(depth_format) {
+      true  => vk::AttachmentLoadOp::CLEAR,
+      false => vk::AttachmentLoadOp::DONT_CARE,
+    };
+    // Offscreen color images are copied out after the pass instead of being presented
+    let color_final_layout = match self.surface {
+      Some(_) => vk::ImageLayout::PRESENT_SRC_KHR,
+      None    => vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+    };
+
     let color_attachment = vk::AttachmentDescription::builder()
       .format(self.swapchain_image_format.expect("Swapchain Image Format not set"))
       .samples(vk::SampleCountFlags::TYPE_1)
@@ -311,15 +622,15 @@ impl VulkanInstance {
       .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
       .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
       .initial_layout(vk::ImageLayout::UNDEFINED)
-      .final_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+      .final_layout(color_final_layout)
       .build();
 
     let depth_attachment = vk::AttachmentDescription::builder()
-      .format(vk::Format::D32_SFLOAT)
+      .format(depth_format)
       .samples(vk::SampleCountFlags::TYPE_1)
       .load_op(vk::AttachmentLoadOp::CLEAR)
       .store_op(vk::AttachmentStoreOp::DONT_CARE)
-      .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+      .stencil_load_op(stencil_load_op)
       .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
       .initial_layout(vk::ImageLayout::UNDEFINED)
       .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
@@ -343,18 +654,35 @@ impl VulkanInstance {
       .depth_stencil_attachment(&depth_attachment_ref)
       .build();
 
+    // The depth clear must also wait for earlier depth writes to the same image
     let dependency = vk::SubpassDependency::builder()
       .src_subpass(vk::SUBPASS_EXTERNAL)
       .dst_subpass(0)
+      .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS)
+      .dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS)
+      .src_access_mask(vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+      .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+      .build();
+
+    // Color writes must land before they are presented or copied into the readback buffer
+    let (dst_stage_mask, dst_access_mask) = match self.surface {
+      Some(_) => (vk::PipelineStageFlags::BOTTOM_OF_PIPE, vk::AccessFlags::empty()),
+      None    => (vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::TRANSFER_READ),
+    };
+    let output_dependency = vk::SubpassDependency::builder()
+      .src_subpass(0)
+      .dst_subpass(vk::SUBPASS_EXTERNAL)
       .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
-      .dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
-      .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+      .dst_stage_mask(dst_stage_mask)
+      .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+      .dst_access_mask(dst_access_mask)
       .build();
+    let dependencies = [dependency, output_dependency];
 
     let render_pass_info = vk::RenderPassCreateInfo::builder()
       .attachments(&attachments)
       .subpasses(std::slice::from_ref(&subpass))
-      .dependencies(std::slice::from_ref(&dependency))
+      .dependencies(&dependencies)
       .build();
 
     let render_pass = unsafe {
@@ -362,9 +690,81 @@ impl VulkanInstance {
     };
 
     self.render_pass = Some(render_pass);
+    self.depth_format = Some(depth_format);
+    self.name_object(render_pass, "main render pass");
     Ok(self)
   }
 
+  pub fn queue_families(&self) -> QueueFamilies {
+    QueueFamilies {
+      graphics : self.graphics_queue_family_index,
+      present  : self.presentation_queue_family_index,
+      transfer : self.transfer_queue_family_index,
+      compute  : self.compute_queue_family_index,
+    }
+  }
+
+  // Staging copies go to the queue of a family without graphics, the graphics queue when the device has none.
+  // See `UploadQueues` for how what they write is handed to the graphics family
+  fn upload_queues(&self) -> UploadQueues {
+    let graphics = QueueContext {
+      family_index : self.graphics_queue_family_index.unwrap(),
+      queue        : self.graphics_queue.unwrap(),
+      command_pool : self.command_pool.expect("Command Pool not initialized"),
+    };
+    let transfer = match (self.transfer_queue_family_index, self.transfer_queue, self.transfer_command_pool) {
+      (Some(family_index), Some(queue), Some(command_pool)) => QueueContext { family_index, queue, command_pool },
+      _ => graphics,
+    };
+    UploadQueues { transfer, graphics }
+  }
+
+  // Objects are only named when the instance was created with a DebugMode and VK_EXT_debug_utils is present
+  pub fn name_object<H: vk::Handle>(&self, handle: H, name: &str) {
+    if let Some(namer) = &self.object_namer {
+      namer.name(handle, name);
+    }
+  }
+
+  pub fn object_namer(&self) -> Option<&ObjectNamer> {
+    self.object_namer.as_ref()
+  }
+
+  // Stands in for create_swapchain when there is no surface
+  pub fn configure_offscreen_extent(&mut self, width: u32, height: u32) -> &mut Self {
+    self.swap_extent = Some(Extent2D { width, height });
+    self.swapchain_image_format = Some(OFFSCREEN_COLOR_FORMAT);
+    self
+  }
+
+  // Stands in for create_framebuffers, requires the render pass
+  pub fn create_offscreen_target(&mut self) -> Result<&mut Self, vk::Result> {
+    let target = OffscreenTarget::new(
+      self.allocator.as_mut().expect("Logical device not initialized"),
+      self.logical_device.as_ref().unwrap(),
+      self.render_pass.expect("Render Pass not initialized"),
+      self.swap_extent.expect("Offscreen extent not configured"),
+      self.depth_format.expect("Depth format not selected, create the Render Pass first")
+    )?;
+    self.name_object(target.framebuffer, "offscreen framebuffer");
+    self.offscreen_target = Some(target);
+    Ok(self)
+  }
+
+  // Pixels of the last frame rendered into the offscreen target, top row first
+  pub fn read_offscreen_image(&self) -> Result<RgbaImage, RendererError> {
+    let target = self.offscreen_target.as_ref().ok_or(RendererError::Backend("No offscreen target".to_string()))?;
+    Ok(target.read_pixels())
+  }
+
+  pub fn destroy_offscreen_target(&mut self) {
+    if let Some(target) = self.offscreen_target.take() {
+      let device = self.logical_device.as_ref().unwrap();
+      unsafe { device.device_wait_idle().expect("Failed to wait for device idle") };
+      target.destroy(device, self.allocator.as_mut().unwrap());
+    }
+  }
+
   fn query_surface_capabilities(&mut self) -> Result<&mut Self, vk::Result> {
     let physical_device = self.physical_device.expect("Physical device not initialized");
     let surface = self.surface.expect("Surface not initialzied");
@@ -394,7 +794,7 @@ impl VulkanInstance {
       formats.iter()
         .find(|format| format.format == vk::Format::B8G8R8A8_SRGB && format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR)
         .cloned()
-        .or_else(|| formats.get(0).cloned())
+        .or_else(|| formats.first().cloned())
         .ok_or(vk::Result::ERROR_FORMAT_NOT_SUPPORTED)?
     };
 
@@ -425,12 +825,12 @@ impl VulkanInstance {
     Ok(self)
   }
 
-  fn configure_swap_extent(&mut self, window: &winit::window::Window) -> Result<&mut Self, vk::Result> {
+  fn configure_swap_extent(&mut self) -> &mut Self {
     if self.surface_capabilities.as_ref().unwrap().current_extent.width != u32::MAX {
       self.swap_extent = Some(self.surface_capabilities.as_ref().unwrap().current_extent);
-      return Ok(self)
+      return self
     } else {
-      let window_size = window.inner_size();
+      let window_size = self.window_extent;
       let extent = vk::Extent2D {
         width: window_size.width.clamp(
           self.surface_capabilities.as_ref().unwrap().min_image_extent.width, 
@@ -443,82 +843,11 @@ impl VulkanInstance {
       };
       self.swap_extent = Some(extent);
     }
-    Ok(self)
-  }
-
-  fn select_physical_device(&self) -> Result<vk::PhysicalDevice, vk::Result> {
-    let physical_devices = unsafe { self.instance.enumerate_physical_devices()? };
-
-    if physical_devices.is_empty() {
-      return Err(vk::Result::ERROR_INITIALIZATION_FAILED);
-    }
-
-    for &device in physical_devices.iter() {
-      if VulkanInstance::check_device_compatibility(device, &self.instance) {
-        return Ok(device);
-      }
-    }
-
-    Err(vk::Result::ERROR_DEVICE_LOST)
-  }
-
-  fn check_device_compatibility(device: vk::PhysicalDevice, instance: &ash::Instance) -> bool {
-    let properties = unsafe { instance.get_physical_device_properties(device) };
-    let features = unsafe { instance.get_physical_device_features(device) };
-    features.geometry_shader != 0 
-  }
-
-  fn identify_required_queue_family_indices(&self, physical_device: vk::PhysicalDevice, instance: &ash::Instance) -> Option<(u32, u32)> {
-    let queue_families = unsafe { instance.get_physical_device_queue_family_properties(physical_device) };
-    queue_families.iter().enumerate().find_map(|(index, info)| {
-      if info.queue_flags.contains(vk::QueueFlags::GRAPHICS) {
-        Some(index as u32)
-      } else {
-        None
-      }
-    });
-
-    let mut graphics_index = None;
-    let mut presentation_index = None;
-
-    for (index, queue_family) in queue_families.iter().enumerate() {
-
-      let supports_graphics = queue_family.queue_flags.contains(QueueFlags::GRAPHICS);
-
-      let surface_support_result = unsafe {
-        self.surface_loader
-          .as_ref()
-          .expect("VulkanInstance Surface Loader not initialized")
-          .get_physical_device_surface_support(physical_device, index as u32, self.surface.expect("Surface not initialized"))
-      };
-
-      if let Ok(supports_presentation) = surface_support_result {
-        if supports_graphics {
-          graphics_index = Some(index as u32);
-        }
-
-        if supports_presentation {
-          presentation_index = Some(index as u32);
-        }
-      } else {
-        return None;
-      }
-
-      if graphics_index.is_some() && presentation_index.is_some() {
-        break;
-      }
-    }
-
-    match (graphics_index, presentation_index) {
-      (Some(graphics), Some(presentation)) => Some((graphics, presentation)),
-      _ => None,
-    }
+    self
   }
 
   fn load_instance_extensions() -> Vec<*const c_char> {
-    let mut extensions: Vec<*const c_char> = vec![];
-
-    extensions.push(ash::extensions::khr::Surface::name().as_ptr());
+    let mut extensions: Vec<*const c_char> = vec![ash::extensions::khr::Surface::name().as_ptr()];
 
     #[cfg(target_os = "windows")] extensions.push(ash::extensions::khr::Win32Surface::name().as_ptr());
     
@@ -543,18 +872,14 @@ impl VulkanInstance {
     )
   }
 
-  fn format_device_features(features: vk::PhysicalDeviceFeatures) -> String {
-    format!(
-      "Geometry Shader: {}\nTesselation Shader: {}\n",
-      features.geometry_shader != 0,  
-      features.tessellation_shader != 0
-    )
-  }
-
-  pub fn allocate_resources(&mut self, max_sets: u32) -> &mut Self {
+  pub fn allocate_resources(&mut self) -> &mut Self {
     match self.vulkan_resources {
       None => {
-        let resource_manager = VulkanResources::new(self.logical_device.as_ref().unwrap(), max_sets);
+        let mut resource_manager = VulkanResources::new(self.logical_device.as_ref().unwrap());
+        if let Some(pipeline_cache) = self.pipeline_cache.as_ref() {
+          resource_manager.set_pipeline_cache(pipeline_cache.cache);
+        }
+        resource_manager.set_object_namer(self.object_namer.clone());
         self.vulkan_resources = Some(resource_manager);
       },
       Some(_) => panic!("VkResourceManager already bound to VulkanInstance")
@@ -574,6 +899,20 @@ impl VulkanInstance {
           .expect("Failed to create Command Pool")
       }
     );
+    self.name_object(self.command_pool.unwrap(), "command pool");
+
+    // Command buffers only run on queues of their pool's family, uploads need a pool of their own
+    if let Some(transfer_family) = self.transfer_queue_family_index.filter(|&family| Some(family) != self.graphics_queue_family_index) {
+      let transfer_pool_info = CommandPoolCreateInfo::builder()
+        .queue_family_index(transfer_family)
+        .flags(CommandPoolCreateFlags::TRANSIENT)
+        .build();
+      self.transfer_command_pool = Some(unsafe {
+        self.logical_device.as_ref().unwrap().create_command_pool(&transfer_pool_info, None)
+          .expect("Failed to create transfer Command Pool")
+      });
+      self.name_object(self.transfer_command_pool.unwrap(), "transfer command pool");
+    }
     self
   }
 
@@ -602,6 +941,10 @@ impl VulkanInstance {
           .expect("Failed to create In-flight Fence")
       };
 
+      let frame = self.in_flight_fences.len();
+      self.name_object(image_available_semaphore, &format!("image available {}", frame));
+      self.name_object(render_complete_semaphore, &format!("render complete {}", frame));
+      self.name_object(in_flight_fence, &format!("in flight {}", frame));
       self.image_available_semaphores.push(image_available_semaphore);
       self.render_complete_semaphores.push(render_complete_semaphore);
       self.in_flight_fences.push(in_flight_fence);
@@ -610,7 +953,7 @@ impl VulkanInstance {
 
   pub fn allocate_command_buffers(&mut self) -> &mut Self {
     
-    let command_buffer_count = self.swapchain_images.as_ref().unwrap().len();
+    let command_buffer_count = self.swapchain_images.as_ref().map_or(1, |images| images.len());
     let allocate_info = CommandBufferAllocateInfo::builder()
       .command_pool(self.command_pool.unwrap())
       .level(CommandBufferLevel::PRIMARY)
@@ -621,20 +964,83 @@ impl VulkanInstance {
       Some(self.logical_device.as_ref().unwrap().allocate_command_buffers(&allocate_info)
         .expect("Failed to allocate Command Buffer"))
     };
+    for (index, &command_buffer) in self.command_buffers.as_ref().unwrap().iter().enumerate() {
+      self.name_object(command_buffer, &format!("command buffer {}", index));
+    }
 
     self
   }
 
-  pub fn define_shader(&mut self, shader_id: &str, bindings: Vec<DescriptorSetLayoutBinding>) -> &mut Self {
+  // One set with `bindings`, named through `name_binding` before the bind_* calls can use them
+  pub fn define_shader(&mut self, shader_id: &str, bindings: Vec<DescriptorSetLayoutBinding>) -> Result<&mut Self, RendererError> {
     self.vulkan_resources
       .as_mut()
       .unwrap()
       .create_shader_resources(shader_id)
-      .new_descriptor_layout(self.logical_device.as_ref().unwrap(), shader_id, bindings)
-      .allocate_shader_descriptor_sets(self.logical_device.as_ref().unwrap(), shader_id);
-    self
+      .new_descriptor_layout(self.logical_device.as_ref().unwrap(), shader_id, bindings)?
+      .allocate_shader_descriptor_sets(self.logical_device.as_ref().unwrap(), shader_id, MAX_FRAMES_IN_FLIGHT)?;
+    Ok(self)
   }
-  
+
+  // Layouts, binding names and push constants all come from the stages' SPIR-V, see `VulkanResources::define_reflected_shader`
+  pub fn define_reflected_shader(&mut self, shader_id: &str, reflections: &[ShaderReflection], dynamic_uniforms: &[&str]) -> Result<&mut Self, RendererError> {
+    self.vulkan_resources
+      .as_mut()
+      .unwrap()
+      .define_reflected_shader(self.logical_device.as_ref().unwrap(), shader_id, reflections, dynamic_uniforms, MAX_FRAMES_IN_FLIGHT)?;
+    Ok(self)
+  }
+
+  // Compiles the stages and reflects them, for `define_reflected_shader`
+  pub fn reflect_shader_stages(&self, stages: &[ShaderStageConfig]) -> Result<Vec<ShaderReflection>, RendererError> {
+    let compiled = self.vulkan_resources.as_ref().unwrap()
+      .compile_shader_stages(stages)
+      .map_err(|err| RendererError::Backend(err.to_string()))?;
+    stages.iter()
+      .zip(&compiled)
+      .map(|(stage, shader)| stage.reflect(&shader.spirv))
+      .collect::<Result<Vec<_>, _>>()
+      .map_err(|err| RendererError::Backend(err.to_string()))
+  }
+
+  // Include directories and the cache directory of shaders compiled from now on
+  pub fn shader_compiler(&mut self) -> &mut ShaderCompiler {
+    self.vulkan_resources.as_mut().unwrap().shader_compiler()
+  }
+
+  // Pipelines are rebuilt at the start of the next frame when one of their shader files changes
+  pub fn enable_shader_hot_reload(&mut self) -> Result<&mut Self, RendererError> {
+    self.vulkan_resources
+      .as_mut()
+      .unwrap()
+      .enable_hot_reload()
+      .map_err(|err| RendererError::Backend(format!("Failed to watch shader files: {}", err)))?;
+    Ok(self)
+  }
+
+  // Failed reloads are reported and keep the previous pipeline, so a typo never takes the app down
+  pub fn reload_changed_shaders(&mut self) -> usize {
+    let resources = self.vulkan_resources.as_mut().unwrap();
+    let changed = resources.changed_pipelines();
+    if changed.is_empty() {
+      return 0;
+    }
+
+    let device = self.logical_device.as_ref().unwrap();
+    unsafe { device.device_wait_idle().expect("Failed to wait for device idle") };
+    let mut reloaded = 0;
+    for pipeline_id in changed {
+      match resources.reload_pipeline(device, &pipeline_id) {
+        Ok(()) => {
+          println!("Reloaded shaders of pipeline '{}'", pipeline_id);
+          reloaded += 1;
+        },
+        Err(err) => eprintln!("Keeping the previous '{}' pipeline, shader reload failed:\n{}", pipeline_id, err),
+      }
+    }
+    reloaded
+  }
+
   pub fn create_pipeline_layout(&mut self, shader_id: &str) -> PipelineLayout {
     self.vulkan_resources
       .as_mut()
@@ -642,7 +1048,10 @@ impl VulkanInstance {
       .create_pipeline_layout(self.logical_device.as_ref().unwrap(), shader_id)
   }
 
-  pub fn configure_graphics_pipeline(&mut self, pipeline_id: &str, pipeline_layout: vk::PipelineLayout, pipeline_config: PipelineConfig) {
+  // Fails when a shader does not compile, the pipeline's vertex input does not match a reflected vertex shader
+  // or the config needs a device feature that is not enabled
+  pub fn configure_graphics_pipeline(&mut self, pipeline_id: &str, pipeline_layout: vk::PipelineLayout, pipeline_config: PipelineConfig) -> Result<(), RendererError> {
+    self.check_pipeline_features(pipeline_id, &pipeline_config)?;
     self.vulkan_resources
       .as_mut()
       .unwrap()
@@ -651,22 +1060,40 @@ impl VulkanInstance {
         self.render_pass.unwrap(), 
         pipeline_id,
         pipeline_layout, 
-        pipeline_config,
-        self.swap_extent.unwrap(),
-      );
+        pipeline_config
+      )
   }
 
-  pub fn allocate_vertex_buffer(&mut self, vertices: &[Vertex]) {
-    let vbo = self.vulkan_resources.as_mut().unwrap().allocate_vertex_buffer(vertices, &self.instance, self.physical_device.unwrap(), &self.logical_device.as_mut().unwrap());
-    self.vertex_count = vertices.len();
-    self.vertex_buffer        = Some(vbo.0);
-    self.vertex_buffer_memory = Some(vbo.1);
+  // See `VulkanResources::create_pipeline_variant`, e.g. `create_pipeline_variant("PRIMARY", "PRIMARY_WIREFRAME", PipelineConfig::wireframe)`
+  pub fn create_pipeline_variant(
+    &mut self,
+    base_id    : &str,
+    variant_id : &str,
+    configure  : impl FnOnce(PipelineConfig) -> PipelineConfig
+  ) -> Result<(), RendererError> {
+    let resources = self.vulkan_resources.as_ref().unwrap();
+    let base_config = resources.pipeline_config(base_id).ok_or(RendererError::InvalidHandle("pipeline"))?.clone();
+    let config = configure(base_config);
+    self.check_pipeline_features(variant_id, &config)?;
+    self.vulkan_resources
+      .as_mut()
+      .unwrap()
+      .create_pipeline_variant(self.logical_device.as_ref().unwrap(), base_id, variant_id, |_| config)
+  }
+
+  fn check_pipeline_features(&self, pipeline_id: &str, pipeline_config: &PipelineConfig) -> Result<(), RendererError> {
+    let missing = pipeline_config.missing_features(&self.enabled_features);
+    if missing.is_empty() {
+      return Ok(());
+    }
+    Err(RendererError::Backend(format!("Pipeline '{}' needs unsupported device features: {}", pipeline_id, missing.join(", "))))
   }
 
-  pub fn acquire_next_image_index(&self, semaphore_index: usize) -> Result<u32, vk::Result> {
+  // Also returns whether the swapchain is suboptimal for the surface and should be recreated
+  pub fn acquire_next_image_index(&self, semaphore_index: usize) -> Result<(u32, bool), vk::Result> {
     let timeout = u64::MAX;
     let semaphore = self.image_available_semaphores[semaphore_index];
-    let (image_index, _is_suboptimal) = unsafe {
+    let (image_index, is_suboptimal) = unsafe {
       self.swapchain_loader.as_ref().unwrap().acquire_next_image(
         self.swapchain.unwrap(), 
         timeout, 
@@ -675,73 +1102,545 @@ impl VulkanInstance {
       )?
     };
 
-    Ok(image_index)
+    Ok((image_index, is_suboptimal))
   }
 
-  pub fn record_command_buffer(&mut self, pipeline_id: &str, image_index: usize) {
-    
-    let command_buffer = self.command_buffers.as_ref().unwrap()[image_index];
+  // Records an indexed draw into the current frame, for callers that name pipelines directly
+  pub fn record_command_buffer(&mut self, pipeline_id: &str, vertex_buffer: BufferId, index_buffer: BufferId, index_count: u32) -> Result<(), RendererError> {
+    let resources = self.vulkan_resources.as_ref().unwrap();
+    let pipeline = resources.get_graphics_pipeline(pipeline_id).ok_or(RendererError::InvalidHandle("pipeline"))?;
+    let descriptors = resources.descriptor_bindings(pipeline_id, self.current_frame)
+      .map(|(pipeline_layout, descriptor_sets, dynamic_offsets)| (pipeline_layout, 0, descriptor_sets, dynamic_offsets));
+    self.record_draw(pipeline, descriptors, vertex_buffer, Some(index_buffer), index_count)
+  }
+
+  // `descriptors` is the layout, first set index, sets and dynamic offsets bound before the draw
+  fn record_draw(
+    &self,
+    pipeline      : vk::Pipeline,
+    descriptors   : Option<(PipelineLayout, u32, Vec<DescriptorSet>, Vec<u32>)>,
+    vertex_buffer : BufferId,
+    index_buffer  : Option<BufferId>,
+    element_count : u32
+  ) -> Result<(), RendererError> {
+    let image_index = self.current_image_index.ok_or(RendererError::Backend("Draw recorded outside of a frame".to_string()))?;
+    let command_buffer = self.command_buffers.as_ref().unwrap()[image_index as usize];
+
+    let resources = self.vulkan_resources.as_ref().unwrap();
+    let vertex_buffer = resources.get_buffer(vertex_buffer.0).ok_or(RendererError::InvalidHandle("buffer"))?;
+    let index_buffer = match index_buffer {
+      Some(id) => Some(resources.get_buffer(id.0).ok_or(RendererError::InvalidHandle("buffer"))?),
+      None => None,
+    };
+
+    let device = self.logical_device.as_ref().unwrap();
+    unsafe {
+      device.cmd_bind_pipeline(command_buffer, PipelineBindPoint::GRAPHICS, pipeline);
+      if let Some((pipeline_layout, first_set, descriptor_sets, dynamic_offsets)) = descriptors {
+        device.cmd_bind_descriptor_sets(command_buffer, PipelineBindPoint::GRAPHICS, pipeline_layout, first_set, &descriptor_sets, &dynamic_offsets);
+      }
+      device.cmd_bind_vertex_buffers(command_buffer, 0, &[vertex_buffer], &[0]);
+      match index_buffer {
+        Some(index_buffer) => {
+          device.cmd_bind_index_buffer(command_buffer, index_buffer, 0, vk::IndexType::UINT32);
+          device.cmd_draw_indexed(command_buffer, element_count, 1, 0, 0, 0);
+        },
+        None => device.cmd_draw(command_buffer, element_count, 1, 0, 0),
+      }
+    }
+    Ok(())
+  }
+
+  // Writes `value` into the current frame's ring, draws recorded after this bind it
+  pub fn push_uniform<T: Pod>(&mut self, uniform: UniformHandle<T>, value: &T) -> Result<u32, RendererError> {
+    self.vulkan_resources.as_mut().unwrap().push_uniform(uniform.index, self.current_frame, as_bytes(std::slice::from_ref(value)))
+  }
+
+  pub fn bind_uniform<T: Pod>(&mut self, shader_id: &str, name: &str, uniform: UniformHandle<T>) -> Result<(), RendererError> {
+    let ready_frame = self.ready_frame();
+    self.vulkan_resources.as_mut().unwrap().bind_uniform(self.logical_device.as_ref().unwrap(), shader_id, name, uniform.index, ready_frame)
+  }
+
+  pub fn bind_texture(&mut self, shader_id: &str, name: &str, texture: TextureId) -> Result<(), RendererError> {
+    let ready_frame = self.ready_frame();
+    self.vulkan_resources.as_mut().unwrap().bind_texture(self.logical_device.as_ref().unwrap(), shader_id, name, texture.0, ready_frame)
+  }
+
+  // The frame whose sets can be written right now: the one being recorded, its fence was waited on in begin_frame.
+  // Offscreen frames finish within end_frame, so between them frame 0 is idle as well
+  fn ready_frame(&self) -> Option<usize> {
+    (self.current_image_index.is_some() || self.offscreen_target.is_some()).then_some(self.current_frame)
+  }
+
+  // Frees the buffer once the frames that may still read it have finished, see `retire_frame`
+  pub fn destroy_buffer(&mut self, buffer: BufferId) -> Result<(), RendererError> {
+    let retire_frame = retire_frame(self.current_frame, self.current_image_index.is_some(), self.offscreen_target.is_some());
+    let device = self.logical_device.as_ref().unwrap();
+    match self.vulkan_resources.as_mut().unwrap().destroy_buffer(device, self.allocator.as_mut().unwrap(), buffer.0, retire_frame) {
+      true  => Ok(()),
+      false => Err(RendererError::InvalidHandle("buffer")),
+    }
+  }
+
+  // Frees the texture and its draw sets like `destroy_buffer`
+  pub fn destroy_texture(&mut self, texture: TextureId) -> Result<(), RendererError> {
+    let retire_frame = retire_frame(self.current_frame, self.current_image_index.is_some(), self.offscreen_target.is_some());
+    let device = self.logical_device.as_ref().unwrap();
+    match self.vulkan_resources.as_mut().unwrap().destroy_texture(device, self.allocator.as_mut().unwrap(), texture.0, retire_frame) {
+      true  => Ok(()),
+      false => Err(RendererError::InvalidHandle("texture")),
+    }
+  }
+
+  pub fn create_texture_with_sampler(&mut self, image: &RgbaImage, sampler_desc: &SamplerDesc) -> Result<TextureId, RendererError> {
+    let queues = self.upload_queues();
+    let support = TextureSupport::query(&self.instance, self.physical_device.unwrap(), self.max_sampler_anisotropy);
+    let index = self.vulkan_resources.as_mut().unwrap().upload_texture(
+      image,
+      sampler_desc,
+      self.allocator.as_mut().unwrap(),
+      self.logical_device.as_ref().unwrap(),
+      &queues,
+      support
+    )?;
+    Ok(TextureId(index))
+  }
+
+  // Gives a binding of a shader defined with `define_shader` a name the bind_* calls can refer to
+  pub fn name_binding(&mut self, shader_id: &str, name: &str, set: usize, binding: u32) -> Result<(), RendererError> {
+    self.vulkan_resources.as_mut().unwrap().name_binding(shader_id, name, set, binding)
+  }
+
+  // Ring of `capacity` slots of `T` per frame in flight, for UNIFORM_BUFFER_DYNAMIC bindings
+  pub fn create_uniform<T: Pod>(&mut self, capacity: u32) -> Result<UniformHandle<T>, RendererError> {
+    let min_alignment = unsafe { self.instance.get_physical_device_properties(self.physical_device.unwrap()) }
+      .limits
+      .min_uniform_buffer_offset_alignment;
+    let ring = UniformRing::new(
+      self.allocator.as_mut().unwrap(),
+      self.logical_device.as_ref().unwrap(),
+      size_of::<T>() as DeviceSize,
+      capacity,
+      min_alignment,
+      MAX_FRAMES_IN_FLIGHT
+    )?;
+    Ok(UniformHandle::new(self.vulkan_resources.as_mut().unwrap().create_uniform(ring)))
+  }
+
+  pub fn memory_stats(&self) -> Vec<HeapStats> {
+    self.allocator.as_ref().map_or(Vec::new(), |allocator| allocator.stats())
+  }
+
+  // Compacts buffer and texture memory between frames, returns how many resources were moved
+  pub fn defragment(&mut self) -> Result<usize, RendererError> {
+    let device = self.logical_device.as_ref().unwrap();
+    unsafe { device.device_wait_idle()? };
+    let moved = self.vulkan_resources.as_mut().unwrap().defragment(
+      self.allocator.as_mut().unwrap(),
+      device,
+      self.command_pool.expect("Command Pool not initialized"),
+      self.graphics_queue.unwrap()
+    )?;
+    if moved > 0 {
+      println!("Defragmented GPU memory, moved {} resources", moved);
+      for heap in self.memory_stats().iter().filter(|heap| heap.block_count > 0) {
+        println!("  {}", heap);
+      }
+    }
+    Ok(moved)
+  }
+
+  // One full frame: wait for the frame's fence, acquire, record through `record`, submit and present
+  pub fn draw_frame<F>(&mut self, clear_color: [f32; 4], record: F) -> Result<(), RendererError>
+  where
+    F: FnOnce(&mut Self) -> Result<(), RendererError>
+  {
+    self.begin_frame(clear_color)?;
+    if !self.frame_skipped {
+      record(self)?;
+    }
+    self.end_frame()
+  }
+
+  pub fn wait_idle(&self) {
+    if let Some(device) = self.logical_device.as_ref() {
+      unsafe { device.device_wait_idle().expect("Failed to wait for device idle") };
+    }
+  }
+
+  fn pipeline_name(pipeline_id: PipelineId) -> String {
+    format!("pipeline_{}", pipeline_id.0)
+  }
+}
+
+impl Device for VulkanInstance {
+
+  fn create_buffer(&mut self, usage: BufferUsage, data: &[u8]) -> Result<BufferId, RendererError> {
+    let usage = match usage {
+      BufferUsage::Vertex => vk::BufferUsageFlags::VERTEX_BUFFER,
+      BufferUsage::Index  => vk::BufferUsageFlags::INDEX_BUFFER,
+    };
+    if data.is_empty() {
+      return Err(RendererError::Backend("Cannot create an empty buffer".to_string()));
+    }
+    let queues = self.upload_queues();
+    let index = self.vulkan_resources.as_mut().unwrap().upload_buffer(
+      data,
+      usage,
+      self.allocator.as_mut().unwrap(),
+      self.logical_device.as_ref().unwrap(),
+      &queues
+    )?;
+    Ok(BufferId(index))
+  }
+
+  fn create_texture(&mut self, image: &RgbaImage) -> Result<TextureId, RendererError> {
+    self.create_texture_with_sampler(image, &SamplerDesc::default())
+  }
+
+  fn create_shader(&mut self, sources: &[ShaderSource]) -> Result<ShaderId, RendererError> {
+    // Both are compiled by the ShaderCompiler, which loads .spv files as they are
+    let (vertex_path, fragment_path, defines) = sources.iter()
+      .find_map(|source| match source {
+        ShaderSource::Files { vertex_path, fragment_path, defines } => Some((vertex_path, fragment_path, defines.clone())),
+        ShaderSource::SpirvFiles { vertex_path, fragment_path } => Some((vertex_path, fragment_path, Vec::new())),
+        ShaderSource::Glsl { .. } => None,
+      })
+      .ok_or(RendererError::Unsupported("Vulkan requires shader files"))?;
+
+    self.shader_stages.push(vec![
+      ShaderStageConfig {
+        stage       : vk::ShaderStageFlags::VERTEX,
+        shader_path : vertex_path.clone(),
+        entry_point : "main".to_string(),
+        defines     : defines.clone()
+      },
+      ShaderStageConfig {
+        stage       : vk::ShaderStageFlags::FRAGMENT,
+        shader_path : fragment_path.clone(),
+        entry_point : "main".to_string(),
+        defines
+      },
+    ]);
+    Ok(ShaderId(self.shader_stages.len() - 1))
+  }
+
+  fn create_pipeline(&mut self, desc: &PipelineDesc) -> Result<PipelineId, RendererError> {
+    let stages = self.shader_stages.get(desc.shader.0).ok_or(RendererError::InvalidHandle("shader"))?.clone();
+    let pipeline_id = PipelineId(self.pipeline_count);
+    let pipeline_name = VulkanInstance::pipeline_name(pipeline_id);
+
+    // Layouts come from the SPIR-V. The GL device's inputs keep their names: a `matrices` block holding
+    // model, view and projection, and the `texture1` sampler
+    let reflections = self.reflect_shader_stages(&stages)?;
+    self.define_reflected_shader(&pipeline_name, &reflections, &[MATRICES_BINDING])?;
+    if self.draw_uniforms.is_none() {
+      self.draw_uniforms = Some(self.create_uniform::<Matrices>(DRAW_UNIFORM_CAPACITY)?.index);
+    }
+    // Untextured draws sample black, like GL with texture 0 bound
+    if self.unbound_texture.is_none() {
+      let black = RgbaImage::from_pixel(1, 1, image::Rgba([0, 0, 0, 255]));
+      self.unbound_texture = Some(self.create_texture(&black)?.0);
+    }
+    let pipeline_layout = self.create_pipeline_layout(&pipeline_name);
+    // The SPIR-V writer flips y, which turns GL's counter-clockwise front faces clockwise
+    let cull_mode = match desc.cull_back {
+      true  => vk::CullModeFlags::BACK,
+      false => vk::CullModeFlags::NONE,
+    };
+    let pipeline_config = PipelineConfig::new(stages)
+      .with_vertex_layout(&desc.vertex_layout)?
+      .with_depth(desc.depth_test, desc.depth_test, vk::CompareOp::LESS)
+      .with_culling(cull_mode, vk::FrontFace::CLOCKWISE);
+    self.configure_graphics_pipeline(&pipeline_name, pipeline_layout, pipeline_config)?;
+
+    self.pipeline_count += 1;
+    Ok(pipeline_id)
+  }
+}
+
+impl Renderer for VulkanInstance {
+
+  fn begin_frame(&mut self, clear_color: [f32; 4]) -> Result<(), RendererError> {
+    self.reload_changed_shaders();
+    // Frees since the last frame may have left a block sparse enough to compact
+    if self.allocator.as_ref().is_some_and(|allocator| allocator.needs_defragmentation()) {
+      if let Err(err) = self.defragment() {
+        eprintln!("Defragmentation failed: {}", err);
+      }
+    }
+    let (image_index, framebuffer) = match &self.offscreen_target {
+      Some(target) => (0, target.framebuffer),
+      None => {
+        // Minimized windows have nothing to present to
+        if self.window_extent.width == 0 || self.window_extent.height == 0 {
+          self.frame_skipped = true;
+          return Ok(());
+        }
+        if self.swapchain_out_of_date {
+          self.recreate_swapchain()?;
+        }
+
+        // The fence is only reset right before submitting, so bailing out below cannot deadlock the next frame
+        let frame_fence = self.in_flight_fences[self.current_frame];
+        unsafe { self.logical_device.as_ref().unwrap().wait_for_fences(&[frame_fence], true, u64::MAX)? };
+
+        let image_index = match self.acquire_next_image_index(self.current_frame) {
+          Ok((image_index, is_suboptimal)) => {
+            self.swapchain_out_of_date |= is_suboptimal;
+            image_index
+          },
+          Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+            self.recreate_swapchain()?;
+            self.frame_skipped = true;
+            return Ok(());
+          },
+          Err(err) => return Err(err.into()),
+        };
+
+        // Another frame in flight may still be rendering to this image
+        if let Some(image_fence) = self.images_in_flight[image_index as usize] {
+          unsafe { self.logical_device.as_ref().unwrap().wait_for_fences(&[image_fence], true, u64::MAX)? };
+        }
+        self.images_in_flight[image_index as usize] = Some(frame_fence);
+
+        (image_index, self.swapchain_framebuffers.as_ref().unwrap()[image_index as usize])
+      }
+    };
+    self.current_image_index = Some(image_index);
+    self.vulkan_resources.as_mut().unwrap().begin_frame(self.logical_device.as_ref().unwrap(), self.allocator.as_mut().unwrap(), self.current_frame);
+
+    let device = self.logical_device.as_ref().unwrap();
+    let command_buffer = self.command_buffers.as_ref().unwrap()[image_index as usize];
     let begin_info = CommandBufferBeginInfo::builder()
-      .flags(CommandBufferUsageFlags::SIMULTANEOUS_USE)
+      .flags(CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+      .build();
+
+    let clear_values = [
+      ClearValue { color: ClearColorValue { float32: clear_color } },
+      ClearValue { depth_stencil: vk::ClearDepthStencilValue { depth: 1.0, stencil: 0 } },
+    ];
+
+    let render_pass_begin_info = RenderPassBeginInfo::builder()
+      .render_pass(self.render_pass.unwrap())
+      .framebuffer(framebuffer)
+      .render_area(Rect2D {
+        offset: Offset2D { x: 0, y: 0 },
+        extent: self.swap_extent.unwrap()
+      })
+      .clear_values(&clear_values)
       .build();
 
     unsafe {
-      self.logical_device.as_ref().unwrap().begin_command_buffer(command_buffer, &begin_info)
-        .expect("Failed to begin recording command buffer");
-
-      // Begin Render Pass
-      let render_pass_begin_info = RenderPassBeginInfo::builder()
-        .render_pass(self.render_pass.unwrap())
-        .framebuffer(self.swapchain_framebuffers.as_ref().unwrap()[image_index])
-        .render_area(Rect2D {
-          offset: Offset2D { x: 0, y: 0 },
-          extent: self.swap_extent.unwrap()
-        })
-        .clear_values(&[ClearValue {
-          color: ClearColorValue {
-            float32: [1.0, 0.0, 0.0, 1.0], // Clear Value
-          },
-        }])
+      device.reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())?;
+      device.begin_command_buffer(command_buffer, &begin_info)?;
+      if let Some(namer) = &self.object_namer {
+        let pass_name = match self.offscreen_target {
+          Some(_) => "Offscreen pass",
+          None    => "Main pass",
+        };
+        namer.begin_label(command_buffer, pass_name, PASS_LABEL_COLOR);
+      }
+      device.cmd_begin_render_pass(command_buffer, &render_pass_begin_info, SubpassContents::INLINE);
+
+      let extent = self.swap_extent.unwrap();
+      let viewport = vk::Viewport {
+        x         : 0.0,
+        y         : 0.0,
+        width     : extent.width as f32,
+        height    : extent.height as f32,
+        min_depth : 0.0,
+        max_depth : 1.0,
+      };
+      device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+      device.cmd_set_scissor(command_buffer, 0, &[Rect2D { offset: Offset2D { x: 0, y: 0 }, extent }]);
+    }
+    Ok(())
+  }
+
+  fn draw(&mut self, draw: &DrawCall) -> Result<(), RendererError> {
+    if self.frame_skipped {
+      return Ok(());
+    }
+    let pipeline_name = VulkanInstance::pipeline_name(draw.pipeline);
+    let texture = draw.texture.map_or(self.unbound_texture.unwrap(), |texture| texture.0);
+    let ring_index = self.draw_uniforms.unwrap();
+    let frame = self.current_frame;
+
+    let matrices = Matrices::new(draw.model, draw.view, draw.projection, draw.lighting);
+
+    let resources = self.vulkan_resources.as_mut().unwrap();
+    let pipeline = resources.get_graphics_pipeline(&pipeline_name).ok_or(RendererError::InvalidHandle("pipeline"))?;
+    let pipeline_layout = resources.pipeline_layout(&pipeline_name).ok_or(RendererError::InvalidHandle("pipeline"))?;
+    let descriptors = resources.draw_descriptors(
+      self.logical_device.as_ref().unwrap(),
+      &pipeline_name,
+      frame,
+      (MATRICES_BINDING, ring_index, as_bytes(std::slice::from_ref(&matrices))),
+      (TEXTURE_BINDING, texture)
+    )?;
+    let descriptors = descriptors.map(|(set, descriptor_set, dynamic_offsets)| (pipeline_layout, set, vec![descriptor_set], dynamic_offsets));
+    self.record_draw(pipeline, descriptors, draw.vertex_buffer, draw.index_buffer, draw.element_count)
+  }
+
+  fn end_frame(&mut self) -> Result<(), RendererError> {
+    if self.frame_skipped {
+      self.frame_skipped = false;
+      return Ok(());
+    }
+    let image_index = self.current_image_index.take().ok_or(RendererError::Backend("end_frame called outside of a frame".to_string()))?;
+    let command_buffer = self.command_buffers.as_ref().unwrap()[image_index as usize];
+    let device = self.logical_device.as_ref().unwrap();
+    unsafe {
+      device.cmd_end_render_pass(command_buffer);
+      if let Some(namer) = &self.object_namer {
+        namer.end_label(command_buffer);
+      }
+      if let Some(target) = &self.offscreen_target {
+        if let Some(namer) = &self.object_namer {
+          namer.begin_label(command_buffer, "Readback", READBACK_LABEL_COLOR);
+        }
+        target.record_readback(device, command_buffer);
+        if let Some(namer) = &self.object_namer {
+          namer.end_label(command_buffer);
+        }
+      }
+      device.end_command_buffer(command_buffer)?;
+    }
+
+    // Offscreen frames are submitted synchronously so the readback buffer is valid on return
+    if self.offscreen_target.is_some() {
+      let command_buffers = [command_buffer];
+      let submit_info = vk::SubmitInfo::builder()
+        .command_buffers(&command_buffers)
         .build();
+      let queue = self.graphics_queue.unwrap();
+      unsafe {
+        device.queue_submit(queue, &[submit_info], Fence::null())?;
+        device.queue_wait_idle(queue)?;
+      }
+      return Ok(());
+    }
+
+    let wait_semaphores = [self.image_available_semaphores[self.current_frame]];
+    let wait_stages = [vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
+    let signal_semaphores = [self.render_complete_semaphores[self.current_frame]];
+    let command_buffers = [command_buffer];
+    let submit_info = vk::SubmitInfo::builder()
+      .wait_semaphores(&wait_semaphores)
+      .wait_dst_stage_mask(&wait_stages)
+      .command_buffers(&command_buffers)
+      .signal_semaphores(&signal_semaphores)
+      .build();
+
+    let frame_fence = self.in_flight_fences[self.current_frame];
+    unsafe {
+      device.reset_fences(&[frame_fence])?;
+      device.queue_submit(self.graphics_queue.unwrap(), &[submit_info], frame_fence)?;
+    }
+
+    let swapchains = [self.swapchain.unwrap()];
+    let image_indices = [image_index];
+    let present_info = vk::PresentInfoKHR::builder()
+      .wait_semaphores(&signal_semaphores)
+      .swapchains(&swapchains)
+      .image_indices(&image_indices)
+      .build();
+
+    let present_result = unsafe {
+      self.swapchain_loader.as_ref().unwrap().queue_present(self.presentation_queue.unwrap(), &present_info)
+    };
+    match present_result {
+      Ok(is_suboptimal) => self.swapchain_out_of_date |= is_suboptimal,
+      Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => self.swapchain_out_of_date = true,
+      Err(err) => return Err(err.into()),
+    }
+
+    self.current_frame = (self.current_frame + 1) % MAX_FRAMES_IN_FLIGHT;
+    Ok(())
+  }
+
+  fn extent(&self) -> (u32, u32) {
+    let extent = self.swap_extent.unwrap_or_default();
+    (extent.width, extent.height)
+  }
+}
+
+// Everything is torn down in reverse creation order once the device is idle
+// The frame whose fence covers every submission that may reference a resource destroyed now: the frame being
+// recorded, otherwise the last one submitted. Offscreen frames finish within `end_frame`, so between them nothing
+// is in flight and None means the resource can go right away
+fn retire_frame(current_frame: usize, recording: bool, offscreen: bool) -> Option<usize> {
+  match (recording, offscreen) {
+    (true, _)      => Some(current_frame),
+    (false, true)  => None,
+    (false, false) => Some((current_frame + MAX_FRAMES_IN_FLIGHT - 1) % MAX_FRAMES_IN_FLIGHT),
+  }
+}
+
+impl Drop for VulkanInstance {
+  fn drop(&mut self) {
+    if self.logical_device.is_some() {
+      self.wait_idle();
+      self.destroy_offscreen_target();
+      self.cleanup_swapchain();
 
-      self.logical_device.as_ref().unwrap().cmd_begin_render_pass(
-        command_buffer, 
-        &render_pass_begin_info,
-        SubpassContents::INLINE
-      );
-
-      // Bind Pipeline
-      self.logical_device.as_ref().unwrap().cmd_bind_pipeline(
-        command_buffer, 
-        PipelineBindPoint::GRAPHICS, 
-        self.vulkan_resources.as_mut().unwrap().get_graphics_pipeline(pipeline_id)
-      );
-
-      let vertex_buffers = [self.vertex_buffer.unwrap()];
-      let offsets = [0];
+      let device = self.logical_device.as_ref().unwrap();
       unsafe {
-        self.logical_device.as_ref().unwrap().cmd_bind_vertex_buffers(
-          command_buffer, 
-          0, 
-          &vertex_buffers, 
-          &offsets
-        );
-
-        println!("Debug draw");
-        
-        /* Draw */
-        self.logical_device.as_ref().unwrap().cmd_draw(
-          command_buffer, 
-          self.vertex_count as u32, 
-          2, 
-          0, 
-          0
-        )
+        if let Some(swapchain) = self.swapchain.take() {
+          self.swapchain_loader.as_ref().unwrap().destroy_swapchain(swapchain, None);
+        }
+        if let Some(resources) = self.vulkan_resources.as_mut() {
+          resources.destroy(device, self.allocator.as_mut().unwrap());
+        }
+        if let Some(pipeline_cache) = self.pipeline_cache.take() {
+          if let Err(err) = pipeline_cache.save(device) {
+            println!("Failed to save pipeline cache {}: {}", pipeline_cache.path().display(), err);
+          }
+          pipeline_cache.destroy(device);
+        }
+        for &semaphore in self.image_available_semaphores.iter().chain(self.render_complete_semaphores.iter()) {
+          device.destroy_semaphore(semaphore, None);
+        }
+        for &fence in &self.in_flight_fences {
+          device.destroy_fence(fence, None);
+        }
+        for command_pool in [self.command_pool.take(), self.transfer_command_pool.take()].into_iter().flatten() {
+          device.destroy_command_pool(command_pool, None);
+        }
+        if let Some(render_pass) = self.render_pass.take() {
+          device.destroy_render_pass(render_pass, None);
+        }
+        if let Some(mut allocator) = self.allocator.take() {
+          allocator.destroy(device);
+        }
+        device.destroy_device(None);
+      }
+      self.logical_device = None;
+    }
+
+    unsafe {
+      if let (Some(surface), Some(surface_loader)) = (self.surface.take(), self.surface_loader.as_ref()) {
+        surface_loader.destroy_surface(surface, None);
+      }
+      if let Some(debug_messenger) = self.debug_messenger.take() {
+        debug_messenger.destroy();
       }
+      self.instance.destroy_instance(None);
     }
   }
+}
 
-  pub fn get_image_in_flight(&mut self, image_index: usize) -> Option<Fence> {
-    self.images_in_flight[image_index]
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn retires_into_the_last_frame_that_may_use_a_resource() {
+    assert_eq!(retire_frame(1, true, false), Some(1));
+    assert_eq!(retire_frame(1, false, false), Some(0));
+    assert_eq!(retire_frame(0, false, false), Some(MAX_FRAMES_IN_FLIGHT - 1));
+    assert_eq!(retire_frame(0, true, true), Some(0));
+    assert_eq!(retire_frame(0, false, true), None);
   }
-}
\ No newline at end of file
+}
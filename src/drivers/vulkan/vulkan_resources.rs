@@ -1,11 +1,21 @@
-use std::{ collections::HashMap, mem::{align_of, offset_of, size_of} };
+use std::{ collections::{HashMap, HashSet}, mem::{offset_of, size_of}, path::PathBuf };
 use ash::{
-  util::Align, vk::{
-    self, Buffer, BufferCreateInfo, BufferUsageFlags, DescriptorPool, DescriptorPoolCreateInfo, DescriptorPoolSize, DescriptorSet, DescriptorSetAllocateInfo, DescriptorSetLayout, DescriptorSetLayoutBinding, DescriptorSetLayoutCreateInfo, DescriptorType, DeviceMemory, DeviceSize, Extent2D, Format, MemoryAllocateInfo, MemoryMapFlags, MemoryPropertyFlags, PhysicalDevice, PhysicalDeviceMemoryProperties, Pipeline, PipelineLayout, PipelineLayoutCreateInfo, ShaderStageFlags, SharingMode, VertexInputAttributeDescription, VertexInputBindingDescription, VertexInputRate
-  }, Device, Instance
+  vk::{
+    self, Buffer, BufferUsageFlags, CommandPool, DescriptorPool, DescriptorPoolCreateFlags, DescriptorPoolCreateInfo, DescriptorPoolSize, DescriptorSet, DescriptorSetAllocateInfo, DescriptorSetLayout, DescriptorSetLayoutBinding, DescriptorSetLayoutCreateInfo, DescriptorType, DeviceSize, Format, Pipeline, PipelineLayout, PipelineLayoutCreateInfo, PushConstantRange, Queue, ShaderStageFlags, VertexInputAttributeDescription, VertexInputBindingDescription, VertexInputRate
+  }, Device
 };
-
-use super::pipeline::{ GraphicsPipeline, PipelineConfig };
+use image::RgbaImage;
+
+use crate::drivers::renderer::{Pod, RendererError};
+use super::buffer::{GpuBuffer, UploadQueues};
+use super::debug::ObjectNamer;
+use super::descriptor::{DescriptorWriter, TextureBinding};
+use super::memory::{Allocation, DefragmentationMove, GpuAllocator};
+use super::texture::{SamplerDesc, Texture, TextureSupport};
+use super::uniform::UniformRing;
+use super::pipeline::{ GraphicsPipeline, PipelineConfig, ShaderStageConfig };
+use super::reflect::{ DescriptorBinding, ShaderReflection, SpirvError };
+use super::shader_compiler::{ CompiledShader, ShaderCompileError, ShaderCompiler, ShaderWatcher };
 
 #[repr(C, align(4))]
 #[derive(Copy)]
@@ -15,6 +25,9 @@ pub struct Vertex {
   pub color    : [f32; 3]
 }
 
+// Two f32 vec3s, 4 byte aligned, so there is no padding
+unsafe impl Pod for Vertex {}
+
 impl Vertex {
 
   pub fn binding_description() -> VertexInputBindingDescription {
@@ -43,48 +56,166 @@ impl Vertex {
   }
 }
 
+// A set written by `draw_descriptors` and the pool it came from
+type DrawSet = (DescriptorSet, DescriptorPool);
+
 pub struct ShaderResources {
   descriptor_layouts : Vec<DescriptorSetLayout>,
-  descriptor_sets    : Vec<DescriptorSet>,
+  layout_bindings    : Vec<Vec<DescriptorSetLayoutBinding>>,
+  binding_names      : HashMap<String, (usize, u32)>,
+  descriptor_sets    : Vec<Vec<DescriptorSet>>,         // [frame][set], one copy per frame in flight
+  dynamic_uniforms   : Vec<(usize, u32, usize)>,        // (set, binding, uniform ring) ordered like the dynamic offsets
+  bound_textures     : Vec<(usize, TextureBinding, usize)>, // (set, binding, texture) from bind_texture
+  stale_frames       : Vec<bool>,                       // Frames whose sets miss a bind_* call, written once their fence has signalled
+  draw_sets          : HashMap<(usize, usize), DrawSet>, // (frame, texture) sets written for Renderer::draw
+  pipeline_layout    : Option<PipelineLayout>,
+  push_constants     : Vec<PushConstantRange>,
+  vertex_stage       : Option<ShaderReflection>, // Checked against the vertex input of every pipeline using the shader
+  reflected_bindings : Option<Vec<(DescriptorBinding, ShaderStageFlags)>>, // What the layouts were built from, reloads must match it
 }
 
 impl ShaderResources {
   fn new() -> Self {
     ShaderResources {
       descriptor_layouts : Vec::new(),
+      layout_bindings    : Vec::new(),
+      binding_names      : HashMap::new(),
       descriptor_sets    : Vec::new(),
+      dynamic_uniforms   : Vec::new(),
+      bound_textures     : Vec::new(),
+      stale_frames       : Vec::new(),
+      draw_sets          : HashMap::new(),
+      pipeline_layout    : None,
+      push_constants     : Vec::new(),
+      vertex_stage       : None,
+      reflected_bindings : None,
     }
   }
+
+  fn binding_type(&self, set: usize, binding: u32) -> Option<DescriptorType> {
+    self.layout_bindings.get(set)?
+      .iter()
+      .find(|layout_binding| layout_binding.binding == binding)
+      .map(|layout_binding| layout_binding.descriptor_type)
+  }
+}
+
+// What a pipeline was built from, so it can be rebuilt when one of its shader files changes
+struct PipelineSource {
+  render_pass  : vk::RenderPass,
+  config       : PipelineConfig,
+  dependencies : Vec<PathBuf>,
 }
 
+// Sets per descriptor pool, and descriptors of each type. Another pool is added whenever the last one runs out
+const SETS_PER_POOL: u32 = 256;
+
 pub struct VulkanResources {
-  descriptor_pool  : DescriptorPool,
+  descriptor_pools : Vec<DescriptorPool>,
   shader_resources : HashMap<String, ShaderResources>,
-  pipelines        : HashMap<String, GraphicsPipeline>
+  pipelines        : HashMap<String, GraphicsPipeline>,
+  pipeline_cache   : vk::PipelineCache,
+  pipeline_shaders : HashMap<String, String>,
+  pipeline_sources : HashMap<String, PipelineSource>,
+  shader_compiler  : ShaderCompiler,
+  shader_watcher   : Option<ShaderWatcher>,
+  object_namer     : Option<ObjectNamer>,
+  buffers          : Vec<Option<GpuBuffer>>,
+  textures         : Vec<Option<Texture>>,
+  uniforms         : Vec<UniformRing>,
+  retired_buffers  : Vec<(usize, GpuBuffer)>, // Destroyed buffers and the frame whose fence has to signal before they are freed
+  retired_textures : Vec<(usize, Texture, Vec<DrawSet>)>, // Same for textures, with their draw sets
 }
 
 impl VulkanResources {
-  pub fn new(device: &ash::Device, max_sets: u32) -> Self {
+  pub fn new(device: &ash::Device) -> Self {
+    let descriptor_pool = VulkanResources::create_descriptor_pool(device)
+      .expect("Failed to create Descriptor Pool");
 
-    let pool_sizes = [DescriptorPoolSize {
-      ty: DescriptorType::UNIFORM_BUFFER,
-      descriptor_count: max_sets,
-    }];
+    VulkanResources {
+      shader_resources : HashMap::new(),
+      pipelines        : HashMap::new(),
+      pipeline_cache   : vk::PipelineCache::null(),
+      pipeline_shaders : HashMap::new(),
+      pipeline_sources : HashMap::new(),
+      shader_compiler  : ShaderCompiler::new(),
+      shader_watcher   : None,
+      object_namer     : None,
+      buffers          : Vec::new(),
+      textures         : Vec::new(),
+      uniforms         : Vec::new(),
+      retired_buffers  : Vec::new(),
+      retired_textures : Vec::new(),
+      descriptor_pools : vec![descriptor_pool],
+    }
+  }
 
+  fn create_descriptor_pool(device: &Device) -> Result<DescriptorPool, vk::Result> {
+    let max_sets = SETS_PER_POOL;
+    let pool_sizes = [
+      DescriptorPoolSize {
+        ty: DescriptorType::UNIFORM_BUFFER,
+        descriptor_count: max_sets,
+      },
+      DescriptorPoolSize {
+        ty: DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+        descriptor_count: max_sets,
+      },
+      DescriptorPoolSize {
+        ty: DescriptorType::COMBINED_IMAGE_SAMPLER,
+        descriptor_count: max_sets,
+      },
+      DescriptorPoolSize {
+        ty: DescriptorType::SAMPLED_IMAGE,
+        descriptor_count: max_sets,
+      },
+      DescriptorPoolSize {
+        ty: DescriptorType::SAMPLER,
+        descriptor_count: max_sets,
+      },
+    ];
+
+    // Sets cached per texture are freed again when the texture goes away
     let pool_info = DescriptorPoolCreateInfo::builder()
+      .flags(DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET)
       .pool_sizes(&pool_sizes)
       .max_sets(max_sets)
       .build();
+    unsafe { device.create_descriptor_pool(&pool_info, None) }
+  }
 
-    let descriptor_pool = unsafe {
-      device.create_descriptor_pool(&pool_info, None)
-        .expect("Failed to create Descriptor Pool")
-    };
+  // One set per layout, from the last pool or a new one once it is exhausted
+  fn allocate_descriptor_sets(&mut self, device: &Device, layouts: &[DescriptorSetLayout]) -> Result<(Vec<DescriptorSet>, DescriptorPool), vk::Result> {
+    let descriptor_pool = *self.descriptor_pools.last().unwrap();
+    let allocate_info = DescriptorSetAllocateInfo::builder()
+      .descriptor_pool(descriptor_pool)
+      .set_layouts(layouts)
+      .build();
+    match unsafe { device.allocate_descriptor_sets(&allocate_info) } {
+      Ok(sets) => return Ok((sets, descriptor_pool)),
+      Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY | vk::Result::ERROR_FRAGMENTED_POOL) => (),
+      Err(err) => return Err(err),
+    }
 
-    VulkanResources {
-      shader_resources : HashMap::new(),
-      pipelines        : HashMap::new(),
-      descriptor_pool,
+    let descriptor_pool = VulkanResources::create_descriptor_pool(device)?;
+    self.name_object(descriptor_pool, &format!("descriptor pool {}", self.descriptor_pools.len()));
+    self.descriptor_pools.push(descriptor_pool);
+    let allocate_info = DescriptorSetAllocateInfo::builder()
+      .descriptor_pool(descriptor_pool)
+      .set_layouts(layouts)
+      .build();
+    Ok((unsafe { device.allocate_descriptor_sets(&allocate_info)? }, descriptor_pool))
+  }
+
+  // Everything created from here on is named after its ID, see `ObjectNamer`
+  pub fn set_object_namer(&mut self, object_namer: Option<ObjectNamer>) {
+    self.object_namer = object_namer;
+    self.name_object(self.descriptor_pools[0], "descriptor pool 0");
+  }
+
+  fn name_object<H: vk::Handle>(&self, handle: H, name: &str) {
+    if let Some(namer) = &self.object_namer {
+      namer.name(handle, name);
     }
   }
 
@@ -93,53 +224,54 @@ impl VulkanResources {
     self
   }
 
-  pub fn new_descriptor_layout(&mut self, device: &Device, shader_id: &str, bindings: Vec<DescriptorSetLayoutBinding>) -> &mut Self {
+  // Adds the next set's layout, `create_shader_resources` must have been called for the shader
+  pub fn new_descriptor_layout(&mut self, device: &Device, shader_id: &str, bindings: Vec<DescriptorSetLayoutBinding>) -> Result<&mut Self, RendererError> {
+    let set = self.shader_resources.get(shader_id)
+      .ok_or(RendererError::Backend(format!("Shader '{}' not found, create its resources before adding a layout", shader_id)))?
+      .descriptor_layouts.len();
 
     let layout_info = DescriptorSetLayoutCreateInfo::builder()
       .bindings(&bindings)
       .build();
+    let descriptor_layout = unsafe { device.create_descriptor_set_layout(&layout_info, None)? };
+    self.name_object(descriptor_layout, &format!("{} set {}", shader_id, set));
 
-    let descriptor_layout = unsafe {
-      device.create_descriptor_set_layout(&layout_info, None)
-        .expect("Failed to create Descriptor Set Layout")
-    };
-
-    if let Some(shader_resources) = self.shader_resources.get_mut(shader_id) {
-      shader_resources.descriptor_layouts.push(descriptor_layout);
-    } else {
-      println!("Shader ID not found. Ensure shader resources have been allocated before attempting to add a layout");
-    }
-
-    self
+    let shader_resources = self.shader_resources.get_mut(shader_id).unwrap();
+    shader_resources.descriptor_layouts.push(descriptor_layout);
+    shader_resources.layout_bindings.push(bindings);
+    Ok(self)
   }
 
-  pub fn allocate_shader_descriptor_sets(&mut self, device: &Device, shader_id: &str) {
-
-    if let Some(shader_resources) = self.shader_resources.get(shader_id) {
-      let layouts: Vec<DescriptorSetLayout> = shader_resources.descriptor_layouts.iter().copied().collect();
-      let allocate_info = DescriptorSetAllocateInfo::builder()
-        .descriptor_pool(self.descriptor_pool)
-        .set_layouts(&layouts)
-        .build();
-
-      let sets = unsafe {
-        device.allocate_descriptor_sets(&allocate_info)
-          .expect("Failed to allocate Descriptor Sets")
+  // Every set is allocated once per frame in flight, so a frame's copy can be written while another is still executing
+  pub fn allocate_shader_descriptor_sets(&mut self, device: &Device, shader_id: &str, frame_count: usize) -> Result<&mut Self, RendererError> {
+    let layouts = self.shader_resources.get(shader_id)
+      .ok_or(RendererError::Backend(format!("Shader '{}' not found, create its resources before allocating sets", shader_id)))?
+      .descriptor_layouts.clone();
+
+    let mut sets = Vec::with_capacity(frame_count);
+    for frame in 0..frame_count {
+      let frame_sets = match layouts.is_empty() {
+        true  => Vec::new(),
+        false => self.allocate_descriptor_sets(device, &layouts)?.0,
       };
-
-      if let Some(shader_resources) = self.shader_resources.get_mut(shader_id) {
-        shader_resources.descriptor_sets = sets;
+      for (set, &descriptor_set) in frame_sets.iter().enumerate() {
+        self.name_object(descriptor_set, &format!("{} set {} frame {}", shader_id, set, frame));
       }
-    } else {
-      println!("Shader ID not found. Ensure shader resources have been allocated before attempting to add a layout");
+      sets.push(frame_sets);
     }
+
+    let shader_resources = self.shader_resources.get_mut(shader_id).unwrap();
+    shader_resources.descriptor_sets = sets;
+    shader_resources.stale_frames = vec![false; frame_count];
+    Ok(self)
   }
 
   pub fn create_pipeline_layout(&mut self, device: &Device, shader_id: &str) -> PipelineLayout {
-    if let Some(shader_resources) = self.shader_resources.get(shader_id) {
-      let layouts: Vec<DescriptorSetLayout> = shader_resources.descriptor_layouts.iter().copied().collect();
+    if let Some(shader_resources) = self.shader_resources.get_mut(shader_id) {
+      let layouts: Vec<DescriptorSetLayout> = shader_resources.descriptor_layouts.to_vec();
       let pipeline_layout_info = PipelineLayoutCreateInfo::builder()
         .set_layouts(&layouts)
+        .push_constant_ranges(&shader_resources.push_constants)
         .build();
 
       let pipeline_layout = unsafe {
@@ -147,6 +279,8 @@ impl VulkanResources {
           .expect("Failed to create Pipeline Layout")
       };
 
+      shader_resources.pipeline_layout = Some(pipeline_layout);
+      self.name_object(pipeline_layout, shader_id);
       pipeline_layout
     } else {
       panic!("Shader ID not found. Ensure shader resources have been allocated before attemtping to create Pipeline Layout");
@@ -159,67 +293,684 @@ impl VulkanResources {
     render_pass     : vk::RenderPass, 
     pipeline_id     : &str, 
     pipeline_layout : vk::PipelineLayout, 
-    pipeline_config : PipelineConfig,
-    extent          : Extent2D
-  ) {
-    let pipeline = GraphicsPipeline::new(device, render_pass, pipeline_layout, pipeline_config, extent);
-    self.pipelines.insert(pipeline_id.to_string(), pipeline);
-  }
-
-  pub fn allocate_vertex_buffer(&mut self, vertices: &[Vertex], instance: &Instance, physical_device: PhysicalDevice, device: &Device) -> (Buffer, DeviceMemory) {
-    
-    let buffer_info = BufferCreateInfo {
-      size         : (size_of::<Vertex>() * vertices.len()) as DeviceSize,
-      usage        : BufferUsageFlags::VERTEX_BUFFER,
-      sharing_mode : SharingMode::EXCLUSIVE,
-      ..Default::default()
+    pipeline_config : PipelineConfig
+  ) -> Result<(), RendererError> {
+    let shader = self.shader_resources.iter()
+      .find(|(_, shader_resources)| shader_resources.pipeline_layout == Some(pipeline_layout));
+    if let Some(vertex_stage) = shader.and_then(|(_, shader_resources)| shader_resources.vertex_stage.as_ref()) {
+      vertex_stage.validate_vertex_input(&pipeline_config.vertex_attributes).map_err(|err| RendererError::Backend(err.to_string()))?;
+    }
+    // Remembered so draws through this pipeline can bind the shader's descriptor sets
+    if let Some((shader_id, _)) = shader {
+      self.pipeline_shaders.insert(pipeline_id.to_string(), shader_id.clone());
+    }
+
+    let compiled = self.compile_shader_stages(&pipeline_config.shader_stages).map_err(|err| RendererError::Backend(err.to_string()))?;
+    watch_dependencies(self.shader_watcher.as_mut(), &compiled)?;
+    let shader_code: Vec<&[u32]> = compiled.iter().map(|shader| shader.spirv.as_slice()).collect();
+    let pipeline = GraphicsPipeline::new(device, render_pass, pipeline_layout, &pipeline_config, &shader_code, self.pipeline_cache)?;
+    self.name_pipeline(pipeline_id, &pipeline, &pipeline_config);
+    if let Some(previous) = self.pipelines.insert(pipeline_id.to_string(), pipeline) {
+      previous.retire(device);
+    }
+    self.track_pipeline_source(pipeline_id, PipelineSource {
+      render_pass,
+      config       : pipeline_config,
+      dependencies : Vec::new(),
+    }, &compiled);
+    Ok(())
+  }
+
+  // Builds `variant_id` from the render pass, layout and config of `base_id`, with `configure` applied to the config.
+  // Wireframe, transparent or shadow versions of a pipeline only differ in their fixed-function state
+  pub fn create_pipeline_variant(
+    &mut self,
+    device     : &Device,
+    base_id    : &str,
+    variant_id : &str,
+    configure  : impl FnOnce(PipelineConfig) -> PipelineConfig
+  ) -> Result<(), RendererError> {
+    let source = self.pipeline_sources.get(base_id).ok_or(RendererError::InvalidHandle("pipeline"))?;
+    let (render_pass, config) = (source.render_pass, configure(source.config.clone()));
+    let pipeline_layout = self.pipelines[base_id].pipeline_layout;
+    if self.pipeline_config(variant_id) == Some(&config) && self.pipeline_layout(variant_id) == Some(pipeline_layout) {
+      return Ok(());
+    }
+    self.create_graphics_pipeline(device, render_pass, variant_id, pipeline_layout, config)
+  }
+
+  pub fn pipeline_config(&self, pipeline_id: &str) -> Option<&PipelineConfig> {
+    self.pipeline_sources.get(pipeline_id).map(|source| &source.config)
+  }
+
+  // Pipelines created from here on compile through `pipeline_cache`, which stays owned by the caller
+  pub fn set_pipeline_cache(&mut self, pipeline_cache: vk::PipelineCache) {
+    self.pipeline_cache = pipeline_cache;
+  }
+
+  fn name_pipeline(&self, pipeline_id: &str, pipeline: &GraphicsPipeline, config: &PipelineConfig) {
+    self.name_object(pipeline.pipeline, pipeline_id);
+    for ((_, module), stage) in pipeline.shader_modules().zip(&config.shader_stages) {
+      self.name_object(module, &format!("{} {}", pipeline_id, stage.shader_path));
+    }
+  }
+
+  pub fn compile_shader_stages(&self, stages: &[ShaderStageConfig]) -> Result<Vec<CompiledShader>, ShaderCompileError> {
+    stages.iter().map(|stage| stage.compile(&self.shader_compiler)).collect()
+  }
+
+  pub fn shader_compiler(&mut self) -> &mut ShaderCompiler {
+    &mut self.shader_compiler
+  }
+
+  fn track_pipeline_source(&mut self, pipeline_id: &str, mut source: PipelineSource, compiled: &[CompiledShader]) {
+    source.dependencies = compiled.iter().flat_map(|shader| shader.dependencies.iter().cloned()).collect();
+    source.dependencies.sort();
+    source.dependencies.dedup();
+    self.pipeline_sources.insert(pipeline_id.to_string(), source);
+  }
+
+  // Starts watching the shader files of every pipeline, existing and future, see `changed_pipelines`
+  pub fn enable_hot_reload(&mut self) -> Result<(), notify::Error> {
+    if self.shader_watcher.is_some() {
+      return Ok(());
+    }
+    let mut watcher = ShaderWatcher::new()?;
+    for dependency in self.pipeline_sources.values().flat_map(|source| source.dependencies.iter()) {
+      watcher.watch(dependency)?;
+    }
+    self.shader_watcher = Some(watcher);
+    Ok(())
+  }
+
+  // Pipelines built from a shader file that changed on disk since the last call
+  pub fn changed_pipelines(&self) -> Vec<String> {
+    let changed: HashSet<PathBuf> = match self.shader_watcher.as_ref() {
+      Some(watcher) => watcher.changed_files(),
+      None => return Vec::new(),
     };
+    if changed.is_empty() {
+      return Vec::new();
+    }
+    let mut pipelines: Vec<String> = self.pipeline_sources.iter()
+      .filter(|(_, source)| source.dependencies.iter().any(|dependency| changed.contains(dependency)))
+      .map(|(pipeline_id, _)| pipeline_id.clone())
+      .collect();
+    pipelines.sort();
+    pipelines
+  }
+
+  // Recompiles the pipeline's shaders and swaps in a pipeline built from them. The descriptor layout and
+  // push constants are baked into the pipeline layout and cannot change, a reload that changes them fails
+  // and leaves the previous pipeline in place, like any other error. The device must be idle
+  pub fn reload_pipeline(&mut self, device: &Device, pipeline_id: &str) -> Result<(), RendererError> {
+    let source = self.pipeline_sources.get(pipeline_id).ok_or(RendererError::InvalidHandle("pipeline"))?;
+    let backend_error = |err: &dyn std::fmt::Display| RendererError::Backend(err.to_string());
+    let compiled = self.compile_shader_stages(&source.config.shader_stages).map_err(|err| backend_error(&err))?;
+    watch_dependencies(self.shader_watcher.as_mut(), &compiled)?;
+
+    let shader_id = self.pipeline_shaders.get(pipeline_id).cloned();
+    let mut vertex_stage = None;
+    if let Some(shader_resources) = shader_id.as_ref().and_then(|shader_id| self.shader_resources.get(shader_id)) {
+      if let Some(reflected_bindings) = &shader_resources.reflected_bindings {
+        let reflections = source.config.shader_stages.iter()
+          .zip(&compiled)
+          .map(|(stage, shader)| stage.reflect(&shader.spirv))
+          .collect::<Result<Vec<_>, _>>()
+          .map_err(|err| backend_error(&err))?;
+        let push_constant_key = |ranges: &[PushConstantRange]| -> Vec<(ShaderStageFlags, u32, u32)> {
+          ranges.iter().map(|range| (range.stage_flags, range.offset, range.size)).collect()
+        };
+        if merge_stage_bindings(&reflections).map_err(|err| backend_error(&err))? != *reflected_bindings
+          || push_constant_key(&merge_push_constants(&reflections)) != push_constant_key(&shader_resources.push_constants) {
+          return Err(RendererError::Backend(format!("The descriptor bindings or push constants of '{}' changed, restart to apply", pipeline_id)));
+        }
+        vertex_stage = reflections.into_iter().find(|stage| stage.stage.contains(ShaderStageFlags::VERTEX));
+        if let Some(vertex_stage) = &vertex_stage {
+          vertex_stage.validate_vertex_input(&source.config.vertex_attributes).map_err(|err| backend_error(&err))?;
+        }
+      }
+    }
+
+    let pipeline_layout = self.pipelines[pipeline_id].pipeline_layout;
+    let shader_code: Vec<&[u32]> = compiled.iter().map(|shader| shader.spirv.as_slice()).collect();
+    let pipeline = GraphicsPipeline::new(device, source.render_pass, pipeline_layout, &source.config, &shader_code, self.pipeline_cache)?;
+    self.name_pipeline(pipeline_id, &pipeline, &source.config);
+    if let Some(previous) = self.pipelines.insert(pipeline_id.to_string(), pipeline) {
+      previous.retire(device);
+    }
+    if let (Some(shader_id), Some(vertex_stage)) = (shader_id, vertex_stage) {
+      self.shader_resources.get_mut(&shader_id).unwrap().vertex_stage = Some(vertex_stage);
+    }
+
+    // An edit may have added or removed includes
+    let source = self.pipeline_sources.remove(pipeline_id).unwrap();
+    self.track_pipeline_source(pipeline_id, source, &compiled);
+    Ok(())
+  }
+
+  // Builds the shader's descriptor set layouts, binding names and push constant ranges from the reflected stages.
+  // Bindings named in `dynamic_uniforms` become UNIFORM_BUFFER_DYNAMIC so uniform rings can be bound to them
+  pub fn define_reflected_shader(
+    &mut self,
+    device           : &Device,
+    shader_id        : &str,
+    stages           : &[ShaderReflection],
+    dynamic_uniforms : &[&str],
+    frame_count      : usize
+  ) -> Result<(), RendererError> {
+    let merged = merge_stage_bindings(stages).map_err(|err| RendererError::Backend(err.to_string()))?;
+
+    self.create_shader_resources(shader_id);
+    let set_count = merged.iter().map(|(binding, _)| binding.set as usize + 1).max().unwrap_or(0);
+    for set in 0..set_count {
+      let bindings = merged.iter()
+        .filter(|(binding, _)| binding.set as usize == set)
+        .map(|(binding, stage_flags)| {
+          let descriptor_type = match binding.descriptor_type {
+            DescriptorType::UNIFORM_BUFFER if dynamic_uniforms.contains(&binding.name.as_str()) => DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+            descriptor_type => descriptor_type,
+          };
+          DescriptorSetLayoutBinding::builder()
+            .binding(binding.binding)
+            .descriptor_type(descriptor_type)
+            .descriptor_count(binding.count)
+            .stage_flags(*stage_flags)
+            .build()
+        })
+        .collect();
+      self.new_descriptor_layout(device, shader_id, bindings)?;
+    }
+    self.allocate_shader_descriptor_sets(device, shader_id, frame_count)?;
+
+    let shader_resources = self.shader_resources.get_mut(shader_id).unwrap();
+    for (binding, _) in merged.iter().filter(|(binding, _)| !binding.name.is_empty()) {
+      shader_resources.binding_names.insert(binding.name.clone(), (binding.set as usize, binding.binding));
+    }
+    shader_resources.push_constants = merge_push_constants(stages);
+    shader_resources.vertex_stage = stages.iter().find(|stage| stage.stage.contains(ShaderStageFlags::VERTEX)).cloned();
+    shader_resources.reflected_bindings = Some(merged);
+    Ok(())
+  }
+
+  // Uploads `data` into a DEVICE_LOCAL buffer through a staging copy, returns its index in the buffer table
+  pub fn upload_buffer(
+    &mut self,
+    data      : &[u8],
+    usage     : BufferUsageFlags,
+    allocator : &mut GpuAllocator,
+    device    : &Device,
+    queues    : &UploadQueues
+  ) -> Result<usize, vk::Result> {
+    let buffer = GpuBuffer::upload_device_local(allocator, device, queues, data, usage)?;
+    self.name_object(buffer.buffer, &format!("buffer {}", self.buffers.len()));
+    self.buffers.push(Some(buffer));
+    Ok(self.buffers.len() - 1)
+  }
+
+  pub fn get_buffer(&self, index: usize) -> Option<Buffer> {
+    self.buffers.get(index)?.as_ref().map(|buffer| buffer.buffer)
+  }
 
-    let vertex_buffer = unsafe { device.create_buffer(&buffer_info, None) }.expect("Failed to create Vertex Buffer");
-    
-    // Find suitable memory type for Vertex Buffer
-    let mem_requirements = unsafe { device.get_buffer_memory_requirements(vertex_buffer) };
-    let mem_properties = unsafe { instance.get_physical_device_memory_properties(physical_device) };
-    let mem_type_index = VulkanResources::query_memory_type(mem_requirements.memory_type_bits, MemoryPropertyFlags::HOST_COHERENT, mem_properties);
-  
-    // Allocate Vertex Buffer
-    let alloc_info = MemoryAllocateInfo {
-      allocation_size   : VulkanResources::align_to(mem_requirements.size, mem_requirements.alignment),
-      memory_type_index : mem_type_index,
-      ..Default::default()
+  // The slot stays empty so other buffer indices remain valid. With `retire_frame` the memory is only freed once
+  // that frame's fence has been waited on in `begin_frame`, without it nothing on the GPU may still read the buffer
+  pub fn destroy_buffer(&mut self, device: &Device, allocator: &mut GpuAllocator, index: usize, retire_frame: Option<usize>) -> bool {
+    let buffer = match self.buffers.get_mut(index).and_then(|slot| slot.take()) {
+      Some(buffer) => buffer,
+      None => return false,
     };
+    match retire_frame {
+      Some(frame) => self.retired_buffers.push((frame, buffer)),
+      None => buffer.destroy(device, allocator),
+    }
+    true
+  }
 
-    let buffer_memory = unsafe { device.allocate_memory(&alloc_info, None) }.expect("Failed to allocate Vertex Buffer memory");
-    unsafe {
-      device.bind_buffer_memory(vertex_buffer, buffer_memory, 0)
-        .expect("Failed to bind Vertex Buffer Memory");
+  pub fn upload_texture(
+    &mut self,
+    pixels       : &RgbaImage,
+    sampler_desc : &SamplerDesc,
+    allocator    : &mut GpuAllocator,
+    device       : &Device,
+    queues       : &UploadQueues,
+    support      : TextureSupport
+  ) -> Result<usize, vk::Result> {
+    let texture = Texture::upload(allocator, device, queues, pixels, sampler_desc, support)?;
+    let name = format!("texture {}", self.textures.len());
+    self.name_object(texture.image, &name);
+    self.name_object(texture.view, &name);
+    self.name_object(texture.sampler, &name);
+    self.textures.push(Some(texture));
+    Ok(self.textures.len() - 1)
+  }
+
+  pub fn get_texture(&self, index: usize) -> Option<&Texture> {
+    self.textures.get(index)?.as_ref()
+  }
+
+  // Like `destroy_buffer`, the texture's draw sets are dropped from the cache right away but only freed with it
+  pub fn destroy_texture(&mut self, device: &Device, allocator: &mut GpuAllocator, index: usize, retire_frame: Option<usize>) -> bool {
+    let texture = match self.textures.get_mut(index).and_then(|slot| slot.take()) {
+      Some(texture) => texture,
+      None => return false,
+    };
+    let draw_sets = self.take_draw_sets(index);
+    match retire_frame {
+      Some(frame) => self.retired_textures.push((frame, texture, draw_sets)),
+      None => {
+        free_descriptor_sets(device, &draw_sets);
+        texture.destroy(device, allocator);
+      },
     }
+    true
+  }
 
-    // Copy vertex data into Vertex Buffer
-    let data_ptr = unsafe { device.map_memory(buffer_memory, 0, buffer_info.size, MemoryMapFlags::empty()) }.expect("Failed to map Vertex Buffer memory");
-    unsafe {
-      let aligned_data_ptr = data_ptr as *mut Vertex;
-      aligned_data_ptr.copy_from_nonoverlapping(vertices.as_ptr(), vertices.len());
-      device.unmap_memory(buffer_memory);
+  // Sets written for a texture that went away or moved, `draw_descriptors` writes new ones when it is drawn again
+  fn take_draw_sets(&mut self, texture_index: usize) -> Vec<DrawSet> {
+    let mut taken = Vec::new();
+    for shader_resources in self.shader_resources.values_mut() {
+      shader_resources.draw_sets.retain(|&(_, index), &mut sets| {
+        if index == texture_index {
+          taken.push(sets);
+        }
+        index != texture_index
+      });
     }
+    taken
+  }
 
-    (vertex_buffer, buffer_memory)
+  fn free_draw_sets(&mut self, device: &Device, texture_index: usize) {
+    let draw_sets = self.take_draw_sets(texture_index);
+    free_descriptor_sets(device, &draw_sets);
   }
 
-  fn align_to(value: DeviceSize, alignment: DeviceSize) -> DeviceSize {
-    (value + alignment - 1) & !(alignment - 1)
+  pub fn create_uniform(&mut self, ring: UniformRing) -> usize {
+    for frame in 0..ring.frame_count() {
+      self.name_object(ring.buffer(frame), &format!("uniform {} frame {}", self.uniforms.len(), frame));
+    }
+    self.uniforms.push(ring);
+    self.uniforms.len() - 1
+  }
+
+  pub fn push_uniform(&mut self, index: usize, frame: usize, data: &[u8]) -> Result<u32, RendererError> {
+    let ring = self.uniforms.get_mut(index).ok_or(RendererError::InvalidHandle("uniform"))?;
+    ring.push(frame, data).ok_or(RendererError::Backend(format!("Uniform ring {} is full for this frame", index)))
+  }
+
+  // The frame's fence has signalled, so every ring can hand out its slots again and everything retired with it is freed
+  pub fn begin_frame(&mut self, device: &Device, allocator: &mut GpuAllocator, frame: usize) {
+    for ring in self.uniforms.iter_mut() {
+      ring.reset(frame);
+    }
+    let (finished, pending) = self.retired_buffers.drain(..).partition(|(retire_frame, _)| *retire_frame == frame);
+    self.retired_buffers = pending;
+    for (_, buffer) in finished {
+      buffer.destroy(device, allocator);
+    }
+    let (finished, pending) = self.retired_textures.drain(..).partition(|(retire_frame, _, _)| *retire_frame == frame);
+    self.retired_textures = pending;
+    for (_, texture, draw_sets) in finished {
+      free_descriptor_sets(device, &draw_sets);
+      texture.destroy(device, allocator);
+    }
+
+    let stale: Vec<String> = self.shader_resources.iter()
+      .filter(|(_, shader_resources)| shader_resources.stale_frames.get(frame) == Some(&true))
+      .map(|(shader_id, _)| shader_id.clone())
+      .collect();
+    for shader_id in stale {
+      self.write_bound_resources(device, &shader_id, frame);
+    }
+  }
+
+  pub fn name_binding(&mut self, shader_id: &str, name: &str, set: usize, binding: u32) -> Result<(), RendererError> {
+    let shader_resources = self.shader_resources.get_mut(shader_id)
+      .ok_or(RendererError::Backend(format!("Shader '{}' not found", shader_id)))?;
+    if shader_resources.binding_type(set, binding).is_none() {
+      return Err(RendererError::Backend(format!("Shader '{}' has no binding {} in set {}", shader_id, binding, set)));
+    }
+    shader_resources.binding_names.insert(name.to_string(), (set, binding));
+    Ok(())
+  }
+
+  // Points a named UNIFORM_BUFFER_DYNAMIC binding at a uniform ring in every frame's copy of the set.
+  // Draws through the shader then bind the ring's latest slot for the current frame.
+  // Only `ready_frame`, whose fence has signalled, is written now, the others once their fence has signalled
+  pub fn bind_uniform(&mut self, device: &Device, shader_id: &str, name: &str, ring_index: usize, ready_frame: Option<usize>) -> Result<(), RendererError> {
+    let (set, binding, descriptor_type) = self.resolve_binding(shader_id, name)?;
+    if descriptor_type != DescriptorType::UNIFORM_BUFFER_DYNAMIC {
+      return Err(RendererError::Backend(format!("Binding '{}' is a {:?}, uniform rings need UNIFORM_BUFFER_DYNAMIC", name, descriptor_type)));
+    }
+    if ring_index >= self.uniforms.len() {
+      return Err(RendererError::InvalidHandle("uniform"));
+    }
+
+    let shader_resources = self.shader_resources.get_mut(shader_id).unwrap();
+    shader_resources.dynamic_uniforms.retain(|&(bound_set, bound_binding, _)| (bound_set, bound_binding) != (set, binding));
+    shader_resources.dynamic_uniforms.push((set, binding, ring_index));
+    shader_resources.dynamic_uniforms.sort();
+    self.mark_stale(device, shader_id, ready_frame);
+    Ok(())
+  }
+
+  // Points a named texture binding at a texture in every frame's copy of the set, written like `bind_uniform`
+  pub fn bind_texture(&mut self, device: &Device, shader_id: &str, name: &str, texture_index: usize, ready_frame: Option<usize>) -> Result<(), RendererError> {
+    let (set, binding) = self.resolve_texture_binding(shader_id, name)?;
+    if self.get_texture(texture_index).is_none() {
+      return Err(RendererError::InvalidHandle("texture"));
+    }
+
+    let shader_resources = self.shader_resources.get_mut(shader_id).unwrap();
+    shader_resources.bound_textures.retain(|&(bound_set, bound_binding, _)| (bound_set, bound_binding) != (set, binding));
+    shader_resources.bound_textures.push((set, binding, texture_index));
+    self.mark_stale(device, shader_id, ready_frame);
+    Ok(())
+  }
+
+  fn mark_stale(&mut self, device: &Device, shader_id: &str, ready_frame: Option<usize>) {
+    let shader_resources = self.shader_resources.get_mut(shader_id).unwrap();
+    shader_resources.stale_frames.iter_mut().for_each(|stale| *stale = true);
+    if let Some(frame) = ready_frame {
+      self.write_bound_resources(device, shader_id, frame);
+    }
   }
 
-  fn query_memory_type(type_filter: u32, properties: MemoryPropertyFlags, mem_properties: PhysicalDeviceMemoryProperties) -> u32 {
-    for i in 0..mem_properties.memory_type_count {
-      if (type_filter & (1 << i)) > 0 && mem_properties.memory_types[i as usize].property_flags.contains(properties) {
-        return i;
+  // Writes every bound ring and texture into `frame`'s copy of the shader's sets. Textures destroyed since are skipped
+  fn write_bound_resources(&mut self, device: &Device, shader_id: &str, frame: usize) {
+    let shader_resources = &self.shader_resources[shader_id];
+    let Some(sets) = shader_resources.descriptor_sets.get(frame) else {
+      return;
+    };
+    for (set, &descriptor_set) in sets.iter().enumerate() {
+      let mut writer = DescriptorWriter::new();
+      for &(_, binding, ring_index) in shader_resources.dynamic_uniforms.iter().filter(|(bound_set, _, _)| *bound_set == set) {
+        let ring = &self.uniforms[ring_index];
+        writer.buffer(binding, DescriptorType::UNIFORM_BUFFER_DYNAMIC, ring.buffer(frame), 0, ring.range());
+      }
+      for &(_, binding, texture_index) in shader_resources.bound_textures.iter().filter(|(bound_set, _, _)| *bound_set == set) {
+        if let Some(texture) = self.get_texture(texture_index) {
+          writer.texture(binding, texture.view, texture.sampler);
+        }
       }
+      writer.write(device, descriptor_set);
+    }
+    self.shader_resources.get_mut(shader_id).unwrap().stale_frames[frame] = false;
+  }
+
+  // Layout, sets and dynamic offsets a draw through `pipeline_id` binds for `frame`. None when the pipeline has no sets
+  pub fn descriptor_bindings(&self, pipeline_id: &str, frame: usize) -> Option<(PipelineLayout, Vec<DescriptorSet>, Vec<u32>)> {
+    let shader_resources = self.shader_resources.get(self.pipeline_shaders.get(pipeline_id)?)?;
+    let sets = shader_resources.descriptor_sets.get(frame)?.clone();
+    if sets.is_empty() {
+      return None;
     }
-    panic!("Failed to query suitable memory type");
+    let offsets = shader_resources.dynamic_uniforms.iter()
+      .map(|&(_, _, ring_index)| self.uniforms[ring_index].last_offset(frame))
+      .collect();
+    Some((shader_resources.pipeline_layout?, sets, offsets))
   }
 
-  pub fn get_graphics_pipeline(&mut self, pipeline_id: &str) -> Pipeline {
-    self.pipelines.get(pipeline_id).unwrap().pipeline
+  fn resolve_binding(&self, shader_id: &str, name: &str) -> Result<(usize, u32, DescriptorType), RendererError> {
+    let shader_resources = self.shader_resources.get(shader_id)
+      .ok_or(RendererError::Backend(format!("Shader '{}' not found", shader_id)))?;
+    let &(set, binding) = shader_resources.binding_names.get(name)
+      .ok_or(RendererError::Backend(format!("Shader '{}' has no binding named '{}'", shader_id, name)))?;
+    let descriptor_type = shader_resources.binding_type(set, binding).unwrap();
+    Ok((set, binding, descriptor_type))
+  }
+
+  // A named texture is a COMBINED_IMAGE_SAMPLER, or a SAMPLED_IMAGE with a SAMPLER named `<name>_sampler`
+  // in the same set. The latter is how shaders compiled by naga declare textures
+  fn resolve_texture_binding(&self, shader_id: &str, name: &str) -> Result<(usize, TextureBinding), RendererError> {
+    let (set, binding, descriptor_type) = self.resolve_binding(shader_id, name)?;
+    match descriptor_type {
+      DescriptorType::COMBINED_IMAGE_SAMPLER => Ok((set, TextureBinding::Combined(binding))),
+      DescriptorType::SAMPLED_IMAGE => {
+        let sampler_name = format!("{}_sampler", name);
+        match self.resolve_binding(shader_id, &sampler_name) {
+          Ok((sampler_set, sampler, DescriptorType::SAMPLER)) if sampler_set == set => Ok((set, TextureBinding::Separate { image: binding, sampler })),
+          _ => Err(RendererError::Backend(format!("Binding '{}' is a SAMPLED_IMAGE without a SAMPLER named '{}' in set {}", name, sampler_name, set))),
+        }
+      },
+      other => Err(RendererError::Backend(format!("Binding '{}' is a {:?}, not a texture", name, other))),
+    }
+  }
+
+  // The set holding a shader's uniform ring and texture bindings, written once per (frame, texture) and reused.
+  // Lets every Renderer::draw pick its own texture while `uniform_data` moves through a dynamic offset.
+  // Sets are only written when first allocated for `frame`, whose fence has signalled, so none is rewritten in flight.
+  // Either binding may be missing from the shader, None when both are
+  pub fn draw_descriptors(
+    &mut self,
+    device        : &Device,
+    shader_id     : &str,
+    frame         : usize,
+    uniform       : (&str, usize, &[u8]),
+    texture       : (&str, usize)
+  ) -> Result<Option<(u32, DescriptorSet, Vec<u32>)>, RendererError> {
+    let (uniform_name, ring_index, uniform_data) = uniform;
+    let (texture_name, texture_index) = texture;
+    let uniform_binding = self.resolve_binding(shader_id, uniform_name).ok();
+    let texture_binding = match self.has_binding(shader_id, texture_name) {
+      true  => Some(self.resolve_texture_binding(shader_id, texture_name)?),
+      false => None,
+    };
+
+    let set = match (uniform_binding, texture_binding) {
+      (None, None) => return Ok(None),
+      (Some((uniform_set, _, _)), Some((texture_set, _))) if uniform_set != texture_set => {
+        return Err(RendererError::Backend(format!("Shader '{}' keeps '{}' and '{}' in different descriptor sets", shader_id, uniform_name, texture_name)));
+      },
+      (Some((set, _, _)), _) | (None, Some((set, _))) => set,
+    };
+    if let Some((_, _, descriptor_type)) = uniform_binding {
+      if descriptor_type != DescriptorType::UNIFORM_BUFFER_DYNAMIC {
+        return Err(RendererError::Backend(format!("Binding '{}' is a {:?}, uniform rings need UNIFORM_BUFFER_DYNAMIC", uniform_name, descriptor_type)));
+      }
+    }
+
+    let dynamic_offsets = match uniform_binding {
+      Some(_) => vec![self.push_uniform(ring_index, frame, uniform_data)?],
+      None => Vec::new(),
+    };
+    let texture_key = texture_binding.map(|_| texture_index);
+    let cache_key = (frame, texture_key.unwrap_or(usize::MAX));
+    if let Some(&(descriptor_set, _)) = self.shader_resources[shader_id].draw_sets.get(&cache_key) {
+      return Ok(Some((set as u32, descriptor_set, dynamic_offsets)));
+    }
+
+    let layouts = [self.shader_resources[shader_id].descriptor_layouts[set]];
+    let (descriptor_sets, descriptor_pool) = self.allocate_descriptor_sets(device, &layouts)?;
+    let descriptor_set = descriptor_sets[0];
+
+    let mut writer = DescriptorWriter::new();
+    if let Some((_, binding, descriptor_type)) = uniform_binding {
+      let ring = self.uniforms.get(ring_index).ok_or(RendererError::InvalidHandle("uniform"))?;
+      writer.buffer(binding, descriptor_type, ring.buffer(frame), 0, ring.range());
+    }
+    if let Some((_, binding)) = texture_binding {
+      let texture = self.get_texture(texture_index).ok_or(RendererError::InvalidHandle("texture"))?;
+      writer.texture(binding, texture.view, texture.sampler);
+    }
+    writer.write(device, descriptor_set);
+
+    self.shader_resources.get_mut(shader_id).unwrap().draw_sets.insert(cache_key, (descriptor_set, descriptor_pool));
+    Ok(Some((set as u32, descriptor_set, dynamic_offsets)))
   }
-}
\ No newline at end of file
+
+  pub fn has_binding(&self, shader_id: &str, name: &str) -> bool {
+    self.resolve_binding(shader_id, name).is_ok()
+  }
+
+  pub fn pipeline_layout(&self, pipeline_id: &str) -> Option<PipelineLayout> {
+    self.pipelines.get(pipeline_id).map(|pipeline| pipeline.pipeline_layout)
+  }
+
+  // Moves buffers and textures out of sparsely used memory blocks so the allocator can release them.
+  // Indices stay the same, only the Vulkan handles change. The device must be idle
+  pub fn defragment(&mut self, allocator: &mut GpuAllocator, device: &Device, command_pool: CommandPool, queue: Queue) -> Result<usize, vk::Result> {
+    let buffer_allocations: Vec<Allocation> = self.buffers.iter().flatten().map(|buffer| buffer.allocation).collect();
+    let texture_allocations: Vec<Allocation> = self.textures.iter().flatten().map(|texture| texture.allocation()).collect();
+    let (moves, texture_moves): (Vec<DefragmentationMove>, Vec<DefragmentationMove>) = allocator
+      .plan_defragmentation(|allocation| buffer_allocations.contains(allocation) || texture_allocations.contains(allocation))
+      .into_iter()
+      .partition(|defragmentation_move| buffer_allocations.contains(&defragmentation_move.source));
+
+    // Textures are copied one at a time, their descriptor sets are written again on their next draw
+    for (position, defragmentation_move) in texture_moves.iter().enumerate() {
+      let index = self.textures.iter().position(|slot| {
+        slot.as_ref().is_some_and(|texture| texture.allocation() == defragmentation_move.source)
+      }).unwrap();
+      let texture = self.textures[index].as_mut().unwrap();
+      if let Err(err) = texture.relocate(device, allocator, command_pool, queue, defragmentation_move.destination) {
+        for pending in texture_moves[position + 1..].iter().chain(&moves) {
+          allocator.free(device, pending.destination);
+        }
+        return Err(err);
+      }
+      let (image, view) = (texture.image, texture.view);
+      self.name_object(image, &format!("texture {}", index));
+      self.name_object(view, &format!("texture {}", index));
+      self.free_draw_sets(device, index);
+      // The device is idle, every frame's copy is written again in its next begin_frame
+      for shader_resources in self.shader_resources.values_mut() {
+        if shader_resources.bound_textures.iter().any(|&(_, _, texture_index)| texture_index == index) {
+          shader_resources.stale_frames.iter_mut().for_each(|stale| *stale = true);
+        }
+      }
+    }
+    if moves.is_empty() {
+      return Ok(texture_moves.len());
+    }
+
+    let mut relocated = Vec::with_capacity(moves.len());
+    for (position, defragmentation_move) in moves.iter().enumerate() {
+      let index = self.buffers.iter().position(|slot| {
+        slot.as_ref().is_some_and(|buffer| buffer.allocation == defragmentation_move.source)
+      }).unwrap();
+      match self.buffers[index].as_ref().unwrap().rebind(device, defragmentation_move.destination) {
+        Ok(buffer) => relocated.push((index, buffer)),
+        Err(err) => {
+          for pending in &moves[position..] {
+            allocator.free(device, pending.destination);
+          }
+          for (_, buffer) in relocated {
+            buffer.destroy(device, allocator);
+          }
+          return Err(err);
+        }
+      }
+    }
+
+    let copies: Vec<(Buffer, Buffer, DeviceSize)> = relocated.iter()
+      .map(|(index, buffer)| (self.buffers[*index].as_ref().unwrap().buffer, buffer.buffer, buffer.size))
+      .collect();
+    if let Err(err) = GpuBuffer::copy(device, command_pool, queue, &copies) {
+      for (_, buffer) in relocated {
+        buffer.destroy(device, allocator);
+      }
+      return Err(err);
+    }
+
+    let moved = texture_moves.len() + relocated.len();
+    for (index, buffer) in relocated {
+      self.name_object(buffer.buffer, &format!("buffer {}", index));
+      if let Some(old) = self.buffers[index].replace(buffer) {
+        old.destroy(device, allocator);
+      }
+    }
+    Ok(moved)
+  }
+
+  // Releases everything this manager created, the device must be idle
+  pub fn destroy(&mut self, device: &Device, allocator: &mut GpuAllocator) {
+    for buffer in self.buffers.drain(..).flatten().chain(self.retired_buffers.drain(..).map(|(_, buffer)| buffer)) {
+      buffer.destroy(device, allocator);
+    }
+    // Retired draw sets go away with their pools
+    for texture in self.textures.drain(..).flatten().chain(self.retired_textures.drain(..).map(|(_, texture, _)| texture)) {
+      texture.destroy(device, allocator);
+    }
+    for ring in self.uniforms.drain(..) {
+      ring.destroy(device, allocator);
+    }
+    // Variants share their base pipeline's layout
+    let mut pipeline_layouts = HashSet::new();
+    for (_, pipeline) in self.pipelines.drain() {
+      pipeline.retire(device);
+      pipeline_layouts.insert(pipeline.pipeline_layout);
+    }
+    for pipeline_layout in pipeline_layouts {
+      unsafe { device.destroy_pipeline_layout(pipeline_layout, None) };
+    }
+    self.pipeline_sources.clear();
+    self.shader_watcher = None;
+    unsafe {
+      for (_, shader_resources) in self.shader_resources.drain() {
+        for layout in shader_resources.descriptor_layouts {
+          device.destroy_descriptor_set_layout(layout, None);
+        }
+      }
+      for descriptor_pool in self.descriptor_pools.drain(..) {
+        device.destroy_descriptor_pool(descriptor_pool, None);
+      }
+    }
+  }
+
+  pub fn get_graphics_pipeline(&self, pipeline_id: &str) -> Option<Pipeline> {
+    self.pipelines.get(pipeline_id).map(|pipeline| pipeline.pipeline)
+  }
+}
+// With hot reload enabled a pipeline is only built once all of its shader files are watched
+fn watch_dependencies(watcher: Option<&mut ShaderWatcher>, compiled: &[CompiledShader]) -> Result<(), RendererError> {
+  let Some(watcher) = watcher else {
+    return Ok(());
+  };
+  for dependency in compiled.iter().flat_map(|shader| shader.dependencies.iter()) {
+    watcher.watch(dependency)
+      .map_err(|err| RendererError::Backend(format!("Cannot watch shader {} for changes: {}", dependency.display(), err)))?;
+  }
+  Ok(())
+}
+
+// Bindings of every stage, each with the stages using it. A set and binding must mean the same thing in every stage
+fn merge_stage_bindings(stages: &[ShaderReflection]) -> Result<Vec<(DescriptorBinding, ShaderStageFlags)>, SpirvError> {
+  let mut merged: Vec<(DescriptorBinding, ShaderStageFlags)> = Vec::new();
+  for stage in stages {
+    for binding in &stage.descriptor_bindings {
+      match merged.iter_mut().find(|(existing, _)| (existing.set, existing.binding) == (binding.set, binding.binding)) {
+        Some((existing, _)) if existing.descriptor_type != binding.descriptor_type || existing.count != binding.count => {
+          return Err(SpirvError::BindingConflict {
+            set     : binding.set,
+            binding : binding.binding,
+            message : format!(
+              "{:?}[{}] '{}' in one stage but {:?}[{}] '{}' in another",
+              existing.descriptor_type, existing.count, existing.name, binding.descriptor_type, binding.count, binding.name
+            ),
+          });
+        },
+        Some((_, stage_flags)) => *stage_flags |= stage.stage,
+        None => merged.push((binding.clone(), stage.stage)),
+      }
+    }
+  }
+  Ok(merged)
+}
+
+// Stages sharing a range share its entry, as the pipeline layout requires
+fn merge_push_constants(stages: &[ShaderReflection]) -> Vec<PushConstantRange> {
+  let mut push_constants: Vec<PushConstantRange> = Vec::new();
+  for range in stages.iter().flat_map(|stage| stage.push_constants.iter()) {
+    match push_constants.iter_mut().find(|existing| (existing.offset, existing.size) == (range.offset, range.size)) {
+      Some(existing) => existing.stage_flags |= range.stage_flags,
+      None => push_constants.push(*range),
+    }
+  }
+  push_constants
+}
+
+fn free_descriptor_sets(device: &Device, sets: &[DrawSet]) {
+  for &(descriptor_set, descriptor_pool) in sets {
+    unsafe { device.free_descriptor_sets(descriptor_pool, &[descriptor_set]).expect("Failed to free Descriptor Sets") };
+  }
+}
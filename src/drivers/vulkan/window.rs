@@ -1,202 +1,110 @@
-use std::{cell::Cell, env};
-use ash::vk::{ DescriptorSetLayoutBinding, DescriptorType, ShaderStageFlags };
+use std::{env, process, time::Instant};
 use winit::{ 
-  window::WindowBuilder,
+  window::{ Window, WindowBuilder },
   event::{ Event, WindowEvent}, 
-  event_loop::{ ControlFlow, EventLoop},
+  event_loop::EventLoop,
 };
 
-use super::vulkan_resources::Vertex;
-use super::vulkan_instance::{ VulkanInstance, MAX_FRAMES_IN_FLIGHT};
-use super::pipeline::{ PipelineConfig, ShaderStageConfig };
+use crate::cli;
+use crate::drivers::renderer::Application;
+use super::debug::DebugMode;
+use super::device_selection::DevicePreference;
+use super::vulkan_instance::{InstanceOptions, VulkanInstance};
 
-  fn run() {
-
-    let application_name = "Vulkan Testing";
-    let event_loop = EventLoop::new().unwrap();
-    let _window = WindowBuilder::new()
-      .with_title(application_name)
-      .build(&event_loop)
-      .unwrap();
-    
+  fn create_vulkan_instance(application_name: &str, window: &Window) -> VulkanInstance {
     let engine_name = "Vulkan Renderer";
-    let mut vulkan_instance = VulkanInstance::new(application_name, engine_name)
+    // `--vulkan-debug` turns validation on like RENDERER_VULKAN_DEBUG=1, `--gpu <index|name>` overrides RENDERER_VULKAN_DEVICE
+    let args: Vec<String> = env::args().collect();
+    let mut options = InstanceOptions::windowed();
+    if cli::has_flag(&args, "--vulkan-debug") && !options.debug.enabled() {
+      options = options.with_debug(DebugMode::Validation);
+    }
+    if let Some(device) = cli::arg_value(&args, "--gpu") {
+      options = options.with_device(DevicePreference::parse(device));
+    }
+    let mut vulkan_instance = VulkanInstance::with_options(application_name, engine_name, options)
       .expect("Vulkan initialization failed");
     unsafe {
+      vulkan_instance.create_surface(window).expect("Vulkan surface creation failed");
+      // No usable device, or a forced one that is missing, is a setup problem to report rather than panic on
+      if let Err(err) = vulkan_instance.configure_hardware() {
+        eprintln!("{}", err);
+        process::exit(1);
+      }
       vulkan_instance
-        .create_surface(&_window).expect("Vulkan surface creation failed")
-        .configure_hardware()
         .create_logical_device().expect("Failed to create Logical Device")
-        .create_swapchain(&_window).unwrap()
+        .create_swapchain(window).unwrap()
         .create_render_pass().expect("Failed to create Render Pass")
+        .create_depth_resources().expect("Failed to create Depth Buffers")
         .create_framebuffers()
-        .allocate_resources(10)
+        .allocate_resources()
         .create_command_pool()
         .allocate_command_buffers()
         .create_synchronization_objects();
+    }
+    // `--shader-include <dir>` is searched for `#include <file>`, `--no-shader-cache` compiles every shader again
+    if let Some(dir) = cli::arg_value(&args, "--shader-include") {
+      vulkan_instance.shader_compiler().add_include_dir(dir);
+    }
+    if cli::has_flag(&args, "--no-shader-cache") {
+      vulkan_instance.shader_compiler().set_cache_dir(None);
+    }
+    if let Err(err) = vulkan_instance.enable_shader_hot_reload() {
+      eprintln!("Shader hot reload disabled: {}", err);
+    }
+    vulkan_instance
+  }
 
-      // Test Shader
-      let cwd = env::current_dir().expect("Failed to get current working directory");
-      let shaders_dir = cwd.join("..").join("..").join("src").join("shaders");
-
-      let vertex_shader_path   = shaders_dir.join("vertex.spv".to_string()).to_str().unwrap().to_string();
-      let fragment_shader_path = shaders_dir.join("fragment.spv".to_string()).to_str().unwrap().to_string();
-      
-      let pipeline_config = PipelineConfig {
-        shader_stages: vec![
-          ShaderStageConfig {
-            stage: ShaderStageFlags::VERTEX,
-            shader_path: vertex_shader_path,
-            entry_point: "main".to_string()
-          },
-          ShaderStageConfig {
-            stage: ShaderStageFlags::FRAGMENT,
-            shader_path: fragment_shader_path,
-            entry_point: "main".to_string()
-          }
-        ]
-      };
-
-      let bindings = vec![
-        DescriptorSetLayoutBinding::builder()
-          .binding(0)
-          .descriptor_type(DescriptorType::UNIFORM_BUFFER)
-          .descriptor_count(1)
-          .stage_flags(ShaderStageFlags::VERTEX)
-          .build(),
-        DescriptorSetLayoutBinding::builder()
-          .binding(1)
-          .descriptor_type(DescriptorType::COMBINED_IMAGE_SAMPLER)
-          .descriptor_count(1)
-          .stage_flags(ShaderStageFlags::FRAGMENT)
-          .build(),
-      ];
-
-      
-      vulkan_instance.define_shader("Demo", bindings); // Defines Descriptor Layouts and allocate Sets
-      let pipeline_layout = vulkan_instance.create_pipeline_layout("Demo");
-      vulkan_instance.configure_graphics_pipeline("PRIMARY", pipeline_layout, pipeline_config);
+  pub fn run_app(mut app: Box<dyn Application>) {
 
-      let vertices: Vec<Vertex> = vec![
-        Vertex { position: [-0.5, -0.5, 0.0], color: [1.0, 0.0, 0.0] },
-        Vertex { position: [0.5, -0.5, 0.0],  color: [0.0, 1.0, 0.0] },
-        Vertex { position: [0.0, 0.5, 0.0],   color: [0.0, 0.0, 1.0] },
-      ];
+    let application_name = "Vulkan Renderer";
+    let event_loop = EventLoop::new().unwrap();
+    let window = WindowBuilder::new()
+      .with_title(application_name)
+      .build(&event_loop)
+      .unwrap();
 
-      vulkan_instance.allocate_vertex_buffer(&vertices);
+    let mut vulkan_instance = create_vulkan_instance(application_name, &window);
+    app.init(&mut vulkan_instance).expect("Application initialization failed");
+    for heap in vulkan_instance.memory_stats().iter().filter(|heap| heap.block_count > 0) {
+      println!("{}", heap);
     }
 
-    /* Render Loop */
-    let current_frame = Cell::new(0);
+    let mut last_frame = Instant::now();
 
-    //event_loop.run(move |event, _, control_flow| {
     event_loop.run(move |event, control_flow| {
-      //*control_flow = ControlFlow::Poll;
-
       match event {
 
         Event::WindowEvent {
           event: WindowEvent::CloseRequested,
           ..
-        } => return,
+        } => {
+          vulkan_instance.wait_idle();
+          control_flow.exit();
+        },
+
+        Event::WindowEvent {
+          event: WindowEvent::Resized(size),
+          ..
+        } => vulkan_instance.resize(size.width, size.height),
 
-        /* Main Draw Loop */
         Event::WindowEvent {
           event: WindowEvent::RedrawRequested,
           ..
         } => {
+          let now = Instant::now();
+          let delta_time = now.duration_since(last_frame).as_secs_f32();
+          last_frame = now;
 
-          let frame_index = current_frame.get();
-          let image_index = match vulkan_instance.acquire_next_image_index(frame_index) {
-            Ok(index) => index,
-            Err(_) => {
-              println!("Failed to acquire next Image Index");
-              return;
-            }
-          };
-          
-          if let Some(fence) = vulkan_instance.get_image_in_flight(image_index as usize) {
-            //vulkan_instance
+          if let Err(err) = app.frame(&mut vulkan_instance, delta_time) {
+            eprintln!("Frame failed: {}", err);
+            control_flow.exit();
           }
-
-          current_frame.set((frame_index + 1) & MAX_FRAMES_IN_FLIGHT)
-        },
-        
-        /*
-        Event::MainEventsCleard => {
-          _window.request_redraw();
-        },
-        */
-        _ => (),
-      }
-    });
-  }
-
-  /*
-
-  event_loop.run(move |event, _, control_flow| {
-    *control_flow = ControlFlow::Poll; // Continuously run the event loop, even if no events are being received.
-
-    match event {
-        Event::WindowEvent {
-            event: WindowEvent::CloseRequested,
-            ..
-        } => *control_flow = ControlFlow::Exit, // Exit the loop when the window is closed.
-
-        Event::RedrawRequested(_) => { // This is triggered for every frame to be drawn.
-            let image_index = match vulkan_instance.acquire_next_image_index(&image_available_semaphores[current_frame]) {
-                Ok(index) => index,
-                Err(_) => {
-                    // Handle error (e.g., recreate swap chain if needed)
-                    return;
-                }
-            };
-
-            if let Some(fence) = images_in_flight[image_index as usize] {
-                vulkan_instance.logical_device.wait_for_fences(&[fence], true, u64::MAX).expect("Failed to wait for fence");
-            }
-            images_in_flight[image_index as usize] = Some(in_flight_fences[current_frame]);
-
-            // Assuming command_buffer recording and other Vulkan operations are correctly handled here
-
-            let wait_semaphores = [image_available_semaphores[current_frame]];
-            let signal_semaphores = [render_finished_semaphores[current_frame]];
-            let wait_stages = [PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
-            let submit_info = vk::SubmitInfo::builder()
-                .wait_semaphores(&wait_semaphores)
-                .wait_dst_stage_mask(&wait_stages)
-                .command_buffers(&[command_buffer]) // Your command buffer for the current frame
-                .signal_semaphores(&signal_semaphores)
-                .build();
-
-            vulkan_instance.logical_device.reset_fences(&[in_flight_fences[current_frame]]).expect("Failed to reset fence");
-            vulkan_instance.graphics_queue.submit(&[submit_info], in_flight_fences[current_frame]).expect("Failed to submit draw command buffer");
-
-            let swapchains = [vulkan_instance.swapchain];
-            let image_indices = [image_index];
-            let present_info = vk::PresentInfoKHR::builder()
-                .wait_semaphores(&signal_semaphores)
-                .swapchains(&swapchains)
-                .image_indices(&image_indices)
-                .build();
-
-            match vulkan_instance.present_queue.present_khr(&present_info) {
-                Ok(_) => (),
-                Err(vk::Result::ERROR_OUT_OF_DATE_KHR) | Err(vk::Result::SUBOPTIMAL_KHR) => {
-                    // Handle swap chain recreation
-                },
-                Err(e) => panic!("Failed to present swapchain image: {:?}", e),
-            }
-
-            current_frame = (current_frame + 1) % MAX_FRAMES_IN_FLIGHT;
         },
 
-        Event::MainEventsCleared => {
-            // Ensure the window is always requested to redraw, enabling continuous animation.
-            window.request_redraw();
-        }
+        Event::AboutToWait => window.request_redraw(),
 
         _ => (),
-    }
-  });
-  */
\ No newline at end of file
+      }
+    }).expect("Event loop terminated abnormally");
+  }
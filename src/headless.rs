@@ -0,0 +1,75 @@
+use std::path::PathBuf;
+use image::RgbaImage;
+use nalgebra::Vector3;
+
+use crate::cli::{arg_value, has_flag, parse_arg, parse_point3, parse_vector3};
+use crate::drivers::{
+  gl::{self, headless::save_image},
+  renderer::{Application, Backend, RendererError},
+  software,
+  vulkan,
+};
+use crate::viewer::{Camera, SceneViewer};
+
+pub struct HeadlessOptions {
+  pub backend : Backend,
+  pub scene   : PathBuf,
+  pub output  : PathBuf,
+  pub width   : u32,
+  pub height  : u32,
+  pub camera  : Camera,
+}
+
+impl HeadlessOptions {
+
+  // Returns None unless `--headless` was passed
+  pub fn from_args(args: &[String]) -> Result<Option<Self>, RendererError> {
+    if !has_flag(args, "--headless") {
+      return Ok(None);
+    }
+
+    let mut options = Self::parse(args).map_err(RendererError::Backend)?;
+    options.backend = Backend::from_args_and_env()?;
+    Ok(Some(options))
+  }
+
+  fn parse(args: &[String]) -> Result<Self, String> {
+    let scene = arg_value(args, "--scene").ok_or("--headless requires --scene <file.obj|file.gltf|file.glb>")?;
+    let output = arg_value(args, "--output").unwrap_or("render.png");
+
+    let defaults = Camera::default();
+    let mut camera = Camera::new(
+      parse_point3(args, "--eye", defaults.viewport.position)?,
+      parse_point3(args, "--target", defaults.viewport.target)?,
+      parse_vector3(args, "--up", Vector3::y())?,
+    );
+    camera.fov_y_degrees = parse_arg(args, "--fov", defaults.fov_y_degrees)?;
+    camera.near = parse_arg(args, "--near", defaults.near)?;
+    camera.far = parse_arg(args, "--far", defaults.far)?;
+
+    Ok(HeadlessOptions {
+      backend : Backend::OpenGl,
+      scene   : PathBuf::from(scene),
+      output  : PathBuf::from(output),
+      width   : parse_arg(args, "--width", 800)?,
+      height  : parse_arg(args, "--height", 600)?,
+      camera,
+    })
+  }
+}
+
+pub fn render(backend: Backend, app: &mut dyn Application, width: u32, height: u32) -> Result<RgbaImage, RendererError> {
+  match backend {
+    Backend::OpenGl => gl::headless::render_offscreen(app, width, height),
+    Backend::Vulkan => vulkan::offscreen::render_offscreen(app, width, height),
+    Backend::Software => software::device::render_offscreen(app, width, height),
+  }
+}
+
+pub fn run(options: HeadlessOptions) -> Result<(), RendererError> {
+  let mut viewer = SceneViewer::new(&options.scene, options.camera);
+  let image = render(options.backend, &mut viewer, options.width, options.height)?;
+  save_image(&image, &options.output)?;
+  println!("Rendered {} to {}", options.scene.display(), options.output.display());
+  Ok(())
+}
@@ -0,0 +1,8 @@
+pub mod cli;
+pub mod demo;
+pub mod drivers;
+pub mod headless;
+pub mod loaders;
+pub mod regression;
+pub mod scene;
+pub mod viewer;
@@ -0,0 +1,561 @@
+use std::{error::Error, fmt, path::Path};
+use ::gltf::{camera::Projection, image::Format, khr_lights_punctual::Kind, mesh::Mode, material::AlphaMode};
+use image::RgbaImage;
+use nalgebra::{Quaternion, UnitQuaternion, Vector3};
+
+use super::obj::OBJ_VERTEX_STRIDE;
+
+#[derive(Debug)]
+pub enum GltfError {
+  Import(::gltf::Error),
+  MissingPositions { mesh: usize, primitive: usize },
+  IndexOutOfRange { mesh: usize, primitive: usize, index: u32, vertex_count: usize },
+  UnsupportedImageFormat { image: usize, format: Format },
+}
+
+impl fmt::Display for GltfError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      GltfError::Import(err)                          => write!(f, "glTF import failed: {}", err),
+      GltfError::MissingPositions { mesh, primitive } => write!(f, "glTF mesh {} primitive {} has no POSITION attribute", mesh, primitive),
+      GltfError::IndexOutOfRange { mesh, primitive, index, vertex_count } => {
+        write!(f, "glTF mesh {} primitive {} references vertex {} but has {} vertices", mesh, primitive, index, vertex_count)
+      },
+      GltfError::UnsupportedImageFormat { image, format } => write!(f, "glTF image {} has unsupported format {:?}", image, format),
+    }
+  }
+}
+
+impl Error for GltfError {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    match self {
+      GltfError::Import(err) => Some(err),
+      _ => None,
+    }
+  }
+}
+
+impl From<::gltf::Error> for GltfError {
+  fn from(err: ::gltf::Error) -> Self {
+    GltfError::Import(err)
+  }
+}
+
+pub struct GltfPrimitive {
+  pub positions  : Vec<[f32; 3]>,
+  pub normals    : Vec<[f32; 3]>,
+  pub tangents   : Vec<[f32; 4]>,
+  pub tex_coords : Vec<[f32; 2]>,
+  pub colors     : Vec<[f32; 4]>,
+  pub indices    : Vec<u32>,
+  pub material   : Option<usize>,
+}
+
+impl GltfPrimitive {
+
+  // Same layout as the OBJ loader (position, tex coord, normal) so both share `VertexLayout::position_tex_normal`
+  pub fn interleaved(&self) -> Vec<f32> {
+    let mut vertices = Vec::with_capacity(self.positions.len() * OBJ_VERTEX_STRIDE);
+    for i in 0..self.positions.len() {
+      vertices.extend_from_slice(&self.positions[i]);
+      vertices.extend_from_slice(&self.tex_coords[i]);
+      vertices.extend_from_slice(&self.normals[i]);
+    }
+    vertices
+  }
+
+  fn generate_normals(&mut self) {
+    let mut normals = vec![Vector3::<f32>::zeros(); self.positions.len()];
+    for triangle in self.indices.chunks_exact(3) {
+      let (a, b, c) = (triangle[0] as usize, triangle[1] as usize, triangle[2] as usize);
+      let p0 = Vector3::from(self.positions[a]);
+      let edge1 = Vector3::from(self.positions[b]) - p0;
+      let edge2 = Vector3::from(self.positions[c]) - p0;
+      // Area weighted, the cross product is left unnormalized on purpose
+      let face_normal = edge1.cross(&edge2);
+      for &index in &[a, b, c] {
+        normals[index] += face_normal;
+      }
+    }
+    self.normals = normals.iter()
+      .map(|normal| normal.try_normalize(f32::EPSILON).unwrap_or_else(Vector3::z).into())
+      .collect();
+  }
+
+  fn generate_tangents(&mut self) {
+    let mut tangents = vec![Vector3::<f32>::zeros(); self.positions.len()];
+    let mut bitangents = vec![Vector3::<f32>::zeros(); self.positions.len()];
+
+    for triangle in self.indices.chunks_exact(3) {
+      let (a, b, c) = (triangle[0] as usize, triangle[1] as usize, triangle[2] as usize);
+      let p0 = Vector3::from(self.positions[a]);
+      let edge1 = Vector3::from(self.positions[b]) - p0;
+      let edge2 = Vector3::from(self.positions[c]) - p0;
+      let (uv0, uv1, uv2) = (self.tex_coords[a], self.tex_coords[b], self.tex_coords[c]);
+      let (du1, dv1) = (uv1[0] - uv0[0], uv1[1] - uv0[1]);
+      let (du2, dv2) = (uv2[0] - uv0[0], uv2[1] - uv0[1]);
+
+      let determinant = du1 * dv2 - du2 * dv1;
+      if determinant.abs() <= f32::EPSILON {
+        continue;
+      }
+      let r = 1.0 / determinant;
+      let tangent = (edge1 * dv2 - edge2 * dv1) * r;
+      let bitangent = (edge2 * du1 - edge1 * du2) * r;
+      for &index in &[a, b, c] {
+        tangents[index] += tangent;
+        bitangents[index] += bitangent;
+      }
+    }
+
+    self.tangents = (0..self.positions.len()).map(|i| {
+      let normal = Vector3::from(self.normals[i]);
+      // Gram-Schmidt orthogonalize, w stores the handedness of the bitangent
+      let tangent = (tangents[i] - normal * normal.dot(&tangents[i]))
+        .try_normalize(f32::EPSILON)
+        .unwrap_or_else(|| normal.cross(&Vector3::y()).try_normalize(f32::EPSILON).unwrap_or_else(Vector3::x));
+      let handedness = if normal.cross(&tangent).dot(&bitangents[i]) < 0.0 { -1.0 } else { 1.0 };
+      [tangent.x, tangent.y, tangent.z, handedness]
+    }).collect();
+  }
+}
+
+pub struct GltfMesh {
+  pub name       : Option<String>,
+  pub primitives : Vec<GltfPrimitive>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GltfAlphaMode {
+  Opaque,
+  Mask(f32),
+  Blend,
+}
+
+// Texture fields index into `GltfScene::images`
+#[derive(Clone, Debug)]
+pub struct PbrMaterial {
+  pub name                       : Option<String>,
+  pub base_color_factor          : [f32; 4],
+  pub base_color_texture         : Option<usize>,
+  pub metallic_factor            : f32,
+  pub roughness_factor           : f32,
+  pub metallic_roughness_texture : Option<usize>,
+  pub normal_texture             : Option<usize>,
+  pub occlusion_texture          : Option<usize>,
+  pub emissive_factor            : [f32; 3],
+  pub emissive_texture           : Option<usize>,
+  pub alpha_mode                 : GltfAlphaMode,
+  pub double_sided               : bool,
+}
+
+impl Default for PbrMaterial {
+  fn default() -> Self {
+    PbrMaterial {
+      name                       : None,
+      base_color_factor          : [1.0, 1.0, 1.0, 1.0],
+      base_color_texture         : None,
+      metallic_factor            : 1.0,
+      roughness_factor           : 1.0,
+      metallic_roughness_texture : None,
+      normal_texture             : None,
+      occlusion_texture          : None,
+      emissive_factor            : [0.0, 0.0, 0.0],
+      emissive_texture           : None,
+      alpha_mode                 : GltfAlphaMode::Opaque,
+      double_sided               : false,
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum GltfCamera {
+  Perspective { aspect_ratio: Option<f32>, yfov: f32, znear: f32, zfar: Option<f32> },
+  Orthographic { xmag: f32, ymag: f32, znear: f32, zfar: f32 },
+}
+
+// KHR_lights_punctual, lights shine down the node's local -Z axis
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GltfLight {
+  Directional { color: [f32; 3], intensity: f32 },
+  Point { color: [f32; 3], intensity: f32, range: Option<f32> },
+}
+
+pub struct GltfNode {
+  pub name        : Option<String>,
+  pub translation : Vector3<f32>,
+  pub rotation    : UnitQuaternion<f32>,
+  pub scale       : Vector3<f32>,
+  pub children    : Vec<usize>,
+  pub mesh        : Option<usize>,
+  pub camera      : Option<usize>,
+  pub light       : Option<usize>,
+}
+
+// A primitive left out of `GltfMesh::primitives` because it is not drawn as triangles
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SkippedPrimitive {
+  pub mesh      : usize,
+  pub primitive : usize,
+  pub mode      : Mode,
+}
+
+pub struct GltfScene {
+  pub meshes             : Vec<GltfMesh>,
+  pub materials          : Vec<PbrMaterial>,
+  pub images             : Vec<RgbaImage>,
+  pub cameras            : Vec<GltfCamera>,
+  pub lights             : Vec<GltfLight>,
+  pub nodes              : Vec<GltfNode>,
+  pub root_nodes         : Vec<usize>,
+  pub skipped_primitives : Vec<SkippedPrimitive>,
+}
+
+impl GltfScene {
+
+  // Handles .gltf with external or base64 embedded buffers as well as binary .glb
+  pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, GltfError> {
+    let (document, buffers, images) = ::gltf::import(path)?;
+
+    let mut meshes = Vec::new();
+    let mut skipped_primitives = Vec::new();
+    for mesh in document.meshes() {
+      let mut primitives = Vec::new();
+      for primitive in mesh.primitives() {
+        match Self::load_primitive(&mesh, &primitive, &buffers)? {
+          Some(loaded) => primitives.push(loaded),
+          None => skipped_primitives.push(SkippedPrimitive { mesh: mesh.index(), primitive: primitive.index(), mode: primitive.mode() }),
+        }
+      }
+      meshes.push(GltfMesh { name: mesh.name().map(str::to_string), primitives });
+    }
+
+    let materials = document.materials().map(|material| {
+      let pbr = material.pbr_metallic_roughness();
+      PbrMaterial {
+        name                       : material.name().map(str::to_string),
+        base_color_factor          : pbr.base_color_factor(),
+        base_color_texture         : pbr.base_color_texture().map(|info| info.texture().source().index()),
+        metallic_factor            : pbr.metallic_factor(),
+        roughness_factor           : pbr.roughness_factor(),
+        metallic_roughness_texture : pbr.metallic_roughness_texture().map(|info| info.texture().source().index()),
+        normal_texture             : material.normal_texture().map(|info| info.texture().source().index()),
+        occlusion_texture          : material.occlusion_texture().map(|info| info.texture().source().index()),
+        emissive_factor            : material.emissive_factor(),
+        emissive_texture           : material.emissive_texture().map(|info| info.texture().source().index()),
+        alpha_mode                 : match material.alpha_mode() {
+          AlphaMode::Opaque => GltfAlphaMode::Opaque,
+          AlphaMode::Mask   => GltfAlphaMode::Mask(material.alpha_cutoff().unwrap_or(0.5)),
+          AlphaMode::Blend  => GltfAlphaMode::Blend,
+        },
+        double_sided               : material.double_sided(),
+      }
+    }).collect();
+
+    let images = images.into_iter()
+      .enumerate()
+      .map(|(index, image)| Self::convert_image(index, image))
+      .collect::<Result<Vec<_>, _>>()?;
+
+    let cameras = document.cameras().map(|camera| match camera.projection() {
+      Projection::Perspective(perspective) => GltfCamera::Perspective {
+        aspect_ratio : perspective.aspect_ratio(),
+        yfov         : perspective.yfov(),
+        znear        : perspective.znear(),
+        zfar         : perspective.zfar(),
+      },
+      Projection::Orthographic(orthographic) => GltfCamera::Orthographic {
+        xmag  : orthographic.xmag(),
+        ymag  : orthographic.ymag(),
+        znear : orthographic.znear(),
+        zfar  : orthographic.zfar(),
+      },
+    }).collect();
+
+    // Spot lights load as point lights, their cones are dropped
+    let lights = document.lights().into_iter().flatten().map(|light| {
+      let (color, intensity, range) = (light.color(), light.intensity(), light.range());
+      match light.kind() {
+        Kind::Directional => GltfLight::Directional { color, intensity },
+        Kind::Point | Kind::Spot { .. } => GltfLight::Point { color, intensity, range },
+      }
+    }).collect();
+
+    let nodes = document.nodes().map(|node| {
+      let (translation, rotation, scale) = node.transform().decomposed();
+      GltfNode {
+        name        : node.name().map(str::to_string),
+        translation : Vector3::from(translation),
+        rotation    : UnitQuaternion::from_quaternion(Quaternion::new(rotation[3], rotation[0], rotation[1], rotation[2])),
+        scale       : Vector3::from(scale),
+        children    : node.children().map(|child| child.index()).collect(),
+        mesh        : node.mesh().map(|mesh| mesh.index()),
+        camera      : node.camera().map(|camera| camera.index()),
+        light       : node.light().map(|light| light.index()),
+      }
+    }).collect();
+
+    let root_nodes = match document.default_scene().or_else(|| document.scenes().next()) {
+      Some(scene) => scene.nodes().map(|node| node.index()).collect(),
+      None => Vec::new(),
+    };
+
+    Ok(GltfScene { meshes, materials, images, cameras, lights, nodes, root_nodes, skipped_primitives })
+  }
+
+  pub fn material(&self, primitive: &GltfPrimitive) -> PbrMaterial {
+    primitive.material
+      .and_then(|index| self.materials.get(index).cloned())
+      .unwrap_or_default()
+  }
+
+  fn load_primitive(
+    mesh      : &::gltf::Mesh,
+    primitive : &::gltf::Primitive,
+    buffers   : &[::gltf::buffer::Data]
+  ) -> Result<Option<GltfPrimitive>, GltfError> {
+
+    let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+    let positions: Vec<[f32; 3]> = match reader.read_positions() {
+      Some(positions) => positions.collect(),
+      None => return Err(GltfError::MissingPositions { mesh: mesh.index(), primitive: primitive.index() }),
+    };
+    let vertex_count = positions.len();
+
+    let indices: Vec<u32> = match reader.read_indices() {
+      Some(indices) => indices.into_u32().collect(),
+      None => (0..vertex_count as u32).collect(),
+    };
+    // Normal and tangent generation index the attributes directly, so malformed files must stop here
+    if let Some(&index) = indices.iter().find(|&&index| index as usize >= vertex_count) {
+      return Err(GltfError::IndexOutOfRange { mesh: mesh.index(), primitive: primitive.index(), index, vertex_count });
+    }
+
+    let indices = match primitive.mode() {
+      Mode::Triangles => indices,
+      Mode::TriangleStrip => (0..indices.len().saturating_sub(2)).flat_map(|i| {
+        // Alternate the winding so every triangle keeps the strip's orientation
+        if i % 2 == 0 { [indices[i], indices[i + 1], indices[i + 2]] } else { [indices[i + 1], indices[i], indices[i + 2]] }
+      }).collect(),
+      Mode::TriangleFan => (1..indices.len().saturating_sub(1)).flat_map(|i| {
+        [indices[0], indices[i], indices[i + 1]]
+      }).collect(),
+      // Points and lines, reported through `GltfScene::skipped_primitives`
+      _ => return Ok(None),
+    };
+
+    let mut primitive = GltfPrimitive {
+      normals    : reader.read_normals().map(|normals| normals.collect()).unwrap_or_default(),
+      tangents   : reader.read_tangents().map(|tangents| tangents.collect()).unwrap_or_default(),
+      tex_coords : reader.read_tex_coords(0).map(|coords| coords.into_f32().collect()).unwrap_or_default(),
+      colors     : reader.read_colors(0).map(|colors| colors.into_rgba_f32().collect()).unwrap_or_default(),
+      material   : primitive.material().index(),
+      positions,
+      indices,
+    };
+
+    if primitive.tex_coords.len() != vertex_count {
+      primitive.tex_coords = vec![[0.0, 0.0]; vertex_count];
+    }
+    if primitive.colors.len() != vertex_count {
+      primitive.colors = vec![[1.0, 1.0, 1.0, 1.0]; vertex_count];
+    }
+    if primitive.normals.len() != vertex_count {
+      primitive.generate_normals();
+    }
+    if primitive.tangents.len() != vertex_count {
+      primitive.generate_tangents();
+    }
+
+    Ok(Some(primitive))
+  }
+
+  // glTF places the UV origin at the top-left, so unlike `load_image` the rows are not flipped
+  fn convert_image(index: usize, image: ::gltf::image::Data) -> Result<RgbaImage, GltfError> {
+    let pixel_count = (image.width * image.height) as usize;
+    let mut rgba = Vec::with_capacity(pixel_count * 4);
+
+    match image.format {
+      Format::R8 => {
+        for &r in &image.pixels { rgba.extend_from_slice(&[r, r, r, 255]); }
+      },
+      Format::R8G8 => {
+        for pixel in image.pixels.chunks_exact(2) { rgba.extend_from_slice(&[pixel[0], pixel[1], 0, 255]); }
+      },
+      Format::R8G8B8 => {
+        for pixel in image.pixels.chunks_exact(3) { rgba.extend_from_slice(&[pixel[0], pixel[1], pixel[2], 255]); }
+      },
+      Format::R8G8B8A8 => rgba = image.pixels,
+      Format::R16G16B16 => {
+        // Keep the high byte of each little endian 16-bit channel
+        for pixel in image.pixels.chunks_exact(6) { rgba.extend_from_slice(&[pixel[1], pixel[3], pixel[5], 255]); }
+      },
+      Format::R16G16B16A16 => {
+        for pixel in image.pixels.chunks_exact(8) { rgba.extend_from_slice(&[pixel[1], pixel[3], pixel[5], pixel[7]]); }
+      },
+      format => return Err(GltfError::UnsupportedImageFormat { image: index, format }),
+    }
+
+    RgbaImage::from_raw(image.width, image.height, rgba)
+      .ok_or(GltfError::UnsupportedImageFormat { image: index, format: image.format })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{fs, path::PathBuf};
+  use super::*;
+
+  // A single triangle without normals, indexed by three u16 indices
+  fn write_triangle(name: &str, indices: [u16; 3]) -> PathBuf {
+    write_triangle_with(name, indices, r#"{ "attributes": { "POSITION": 0 }, "indices": 1 }"#, "")
+  }
+
+  // `primitives` is the JSON array body of the only mesh, `extra` adds top-level properties
+  fn write_triangle_with(name: &str, indices: [u16; 3], primitives: &str, extra: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("gltf_{}_{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    let mut buffer = Vec::new();
+    for value in [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0] {
+      buffer.extend_from_slice(&value.to_le_bytes());
+    }
+    for index in indices {
+      buffer.extend_from_slice(&index.to_le_bytes());
+    }
+    buffer.extend_from_slice(&[0, 0]);
+    fs::write(dir.join("triangle.bin"), &buffer).unwrap();
+
+    let document = format!(r#"{{
+      "asset": {{ "version": "2.0" }},
+      "buffers": [{{ "uri": "triangle.bin", "byteLength": {} }}],
+      "bufferViews": [
+        {{ "buffer": 0, "byteOffset": 0, "byteLength": 36 }},
+        {{ "buffer": 0, "byteOffset": 36, "byteLength": 6 }}
+      ],
+      "accessors": [
+        {{ "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3", "min": [0, 0, 0], "max": [1, 1, 0] }},
+        {{ "bufferView": 1, "componentType": 5123, "count": 3, "type": "SCALAR" }}
+      ],
+      "meshes": [{{ "primitives": [{}] }}],
+      "nodes": [{{ "mesh": 0, "translation": [1, 2, 3] }}],
+      "scenes": [{{ "nodes": [0] }}],
+      {}
+      "scene": 0
+    }}"#, buffer.len(), primitives, extra);
+    let path = dir.join("triangle.gltf");
+    fs::write(&path, document).unwrap();
+    path
+  }
+
+  #[test]
+  fn loads_a_triangle_and_generates_missing_attributes() {
+    let path = write_triangle("valid", [0, 1, 2]);
+    let scene = GltfScene::load(&path).unwrap();
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+    let primitive = &scene.meshes[0].primitives[0];
+    assert_eq!(primitive.indices, vec![0, 1, 2]);
+    assert_eq!(primitive.normals, vec![[0.0, 0.0, 1.0]; 3]);
+    assert_eq!(primitive.tex_coords, vec![[0.0, 0.0]; 3]);
+    assert_eq!(primitive.tangents.len(), 3);
+    assert_eq!(primitive.interleaved().len(), 3 * OBJ_VERTEX_STRIDE);
+    assert_eq!(scene.root_nodes, vec![0]);
+    assert_eq!(scene.nodes[0].translation, Vector3::new(1.0, 2.0, 3.0));
+  }
+
+  #[test]
+  fn rejects_indices_past_the_last_vertex() {
+    let path = write_triangle("out_of_range", [0, 1, 7]);
+    let result = GltfScene::load(&path);
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+    assert!(matches!(result, Err(GltfError::IndexOutOfRange { mesh: 0, primitive: 0, index: 7, vertex_count: 3 })));
+  }
+
+  #[test]
+  fn loads_metallic_roughness_materials() {
+    let path = write_triangle_with(
+      "materials", [0, 1, 2],
+      r#"{ "attributes": { "POSITION": 0 }, "indices": 1, "material": 0 }"#,
+      r#""materials": [{
+        "name": "red",
+        "pbrMetallicRoughness": { "baseColorFactor": [1, 0, 0, 1], "metallicFactor": 0.25, "roughnessFactor": 0.75 },
+        "emissiveFactor": [0.5, 0, 0],
+        "alphaMode": "MASK",
+        "alphaCutoff": 0.3,
+        "doubleSided": true
+      }],"#
+    );
+    let scene = GltfScene::load(&path).unwrap();
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+    let material = scene.material(&scene.meshes[0].primitives[0]);
+    assert_eq!(material.name.as_deref(), Some("red"));
+    assert_eq!(material.base_color_factor, [1.0, 0.0, 0.0, 1.0]);
+    assert_eq!(material.metallic_factor, 0.25);
+    assert_eq!(material.roughness_factor, 0.75);
+    assert_eq!(material.emissive_factor, [0.5, 0.0, 0.0]);
+    assert_eq!(material.alpha_mode, GltfAlphaMode::Mask(0.3));
+    assert!(material.double_sided);
+    assert_eq!((material.base_color_texture, material.normal_texture, material.occlusion_texture), (None, None, None));
+  }
+
+  #[test]
+  fn falls_back_to_the_default_material() {
+    let path = write_triangle("default_material", [0, 1, 2]);
+    let scene = GltfScene::load(&path).unwrap();
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+    let material = scene.material(&scene.meshes[0].primitives[0]);
+    assert_eq!(material.base_color_factor, [1.0; 4]);
+    assert_eq!((material.metallic_factor, material.roughness_factor), (1.0, 1.0));
+    assert_eq!(material.alpha_mode, GltfAlphaMode::Opaque);
+    assert!(!material.double_sided);
+  }
+
+  #[test]
+  fn reports_primitives_that_are_not_triangles() {
+    let path = write_triangle_with(
+      "points", [0, 1, 2],
+      r#"{ "attributes": { "POSITION": 0 }, "indices": 1 }, { "attributes": { "POSITION": 0 }, "mode": 0 }"#,
+      ""
+    );
+    let scene = GltfScene::load(&path).unwrap();
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+    assert_eq!(scene.meshes[0].primitives.len(), 1);
+    assert_eq!(scene.skipped_primitives, vec![SkippedPrimitive { mesh: 0, primitive: 1, mode: Mode::Points }]);
+  }
+
+  #[test]
+  fn loads_cameras_and_punctual_lights() {
+    let dir = std::env::temp_dir().join(format!("gltf_lights_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("lights.gltf");
+    fs::write(&path, r#"{
+      "asset": { "version": "2.0" },
+      "extensionsUsed": ["KHR_lights_punctual"],
+      "extensions": { "KHR_lights_punctual": { "lights": [
+        { "type": "directional", "color": [1, 0.5, 0], "intensity": 3 },
+        { "type": "spot", "intensity": 2, "range": 5, "spot": { "outerConeAngle": 0.5 } }
+      ] } },
+      "cameras": [{ "type": "orthographic", "orthographic": { "xmag": 2, "ymag": 1, "znear": 0.1, "zfar": 50 } }],
+      "nodes": [
+        { "camera": 0 },
+        { "extensions": { "KHR_lights_punctual": { "light": 1 } } }
+      ],
+      "scenes": [{ "nodes": [0, 1] }]
+    }"#).unwrap();
+    let scene = GltfScene::load(&path).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(matches!(scene.cameras[0], GltfCamera::Orthographic { xmag, ymag, .. } if xmag == 2.0 && ymag == 1.0));
+    assert_eq!(scene.lights, vec![
+      GltfLight::Directional { color: [1.0, 0.5, 0.0], intensity: 3.0 },
+      GltfLight::Point { color: [1.0, 1.0, 1.0], intensity: 2.0, range: Some(5.0) },
+    ]);
+    assert_eq!(scene.nodes[0].camera, Some(0));
+    assert_eq!(scene.nodes[1].light, Some(1));
+  }
+}
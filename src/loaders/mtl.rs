@@ -0,0 +1,172 @@
+use std::{collections::HashMap, fs::File, io::{BufRead, BufReader}, path::{Path, PathBuf}};
+
+use super::obj::ObjError;
+
+#[derive(Clone, Debug)]
+pub struct Material {
+  pub name         : String,
+  pub ambient      : [f32; 3],
+  pub diffuse      : [f32; 3],
+  pub specular     : [f32; 3],
+  pub shininess    : f32,
+  pub dissolve     : f32,
+  pub illum        : u32,
+  pub diffuse_map  : Option<PathBuf>,
+  pub bump_map     : Option<PathBuf>,
+  pub specular_map : Option<PathBuf>,
+}
+
+impl Material {
+  fn new(name: &str) -> Self {
+    Material {
+      name         : name.to_string(),
+      ambient      : [0.0, 0.0, 0.0],
+      diffuse      : [0.8, 0.8, 0.8],
+      specular     : [0.0, 0.0, 0.0],
+      shininess    : 0.0,
+      dissolve     : 1.0,
+      illum        : 2,
+      diffuse_map  : None,
+      bump_map     : None,
+      specular_map : None,
+    }
+  }
+}
+
+// Texture paths are resolved against `base_dir`, the directory of the OBJ file that references the library
+pub fn load_mtl<P: AsRef<Path>>(path: P, base_dir: Option<&Path>) -> Result<HashMap<String, Material>, ObjError> {
+  let file = File::open(path)?;
+  parse_mtl(BufReader::new(file), base_dir)
+}
+
+pub fn parse_mtl<R: BufRead>(reader: R, base_dir: Option<&Path>) -> Result<HashMap<String, Material>, ObjError> {
+  let mut materials = HashMap::new();
+  let mut current: Option<Material> = None;
+
+  for (line_index, line) in reader.lines().enumerate() {
+    let line = line?;
+    let line_number = line_index + 1;
+
+    let line = match line.find('#') {
+      Some(comment) => &line[..comment],
+      None => &line[..],
+    };
+
+    let mut parts = line.split_whitespace();
+    let keyword = match parts.next() {
+      Some(keyword) => keyword,
+      None => continue,
+    };
+    let args: Vec<&str> = parts.collect();
+
+    if keyword == "newmtl" {
+      if let Some(material) = current.take() {
+        materials.insert(material.name.clone(), material);
+      }
+      current = Some(Material::new(&args.join(" ")));
+      continue;
+    }
+
+    let material = match current.as_mut() {
+      Some(material) => material,
+      None => continue,
+    };
+
+    match keyword {
+      "Ka" => material.ambient   = parse_color(&args, line_number)?,
+      "Kd" => material.diffuse   = parse_color(&args, line_number)?,
+      "Ks" => material.specular  = parse_color(&args, line_number)?,
+      "Ns" => material.shininess = parse_float(&args, line_number)?,
+      "d"  => material.dissolve  = parse_float(&args, line_number)?,
+      "Tr" => material.dissolve  = 1.0 - parse_float(&args, line_number)?,
+      "illum" => material.illum  = parse_float(&args, line_number)? as u32,
+      "map_Kd" => material.diffuse_map = parse_texture_path(&args, base_dir),
+      "map_Ks" => material.specular_map = parse_texture_path(&args, base_dir),
+      "map_Bump" | "map_bump" | "bump" => material.bump_map = parse_texture_path(&args, base_dir),
+      _ => {}
+    }
+  }
+
+  if let Some(material) = current.take() {
+    materials.insert(material.name.clone(), material);
+  }
+
+  Ok(materials)
+}
+
+fn parse_float(args: &[&str], line: usize) -> Result<f32, ObjError> {
+  let arg = args.first().ok_or_else(|| ObjError::Parse { line, message: "missing value".to_string() })?;
+  arg.parse().map_err(|_| ObjError::Parse { line, message: format!("invalid number '{}'", arg) })
+}
+
+fn parse_color(args: &[&str], line: usize) -> Result<[f32; 3], ObjError> {
+  if args.first() == Some(&"spectral") || args.first() == Some(&"xyz") {
+    return Err(ObjError::Parse { line, message: format!("unsupported color format '{}'", args[0]) });
+  }
+  let r = parse_float(args, line)?;
+  // A single component means grey
+  let g = if args.len() > 1 { parse_float(&args[1..], line)? } else { r };
+  let b = if args.len() > 2 { parse_float(&args[2..], line)? } else { r };
+  Ok([r, g, b])
+}
+
+// Skips texture options such as `-bm 0.5` or `-s 1 1 1`; what remains is the file name
+fn parse_texture_path(args: &[&str], base_dir: Option<&Path>) -> Option<PathBuf> {
+  let mut index = 0;
+  while index < args.len() && args[index].starts_with('-') {
+    let option = args[index];
+    index += 1;
+    if option == "-imfchan" || option == "-type" {
+      index += 1;
+      continue;
+    }
+    while index < args.len() && (args[index].parse::<f32>().is_ok() || args[index] == "on" || args[index] == "off") {
+      index += 1;
+    }
+  }
+
+  if index >= args.len() {
+    return None;
+  }
+
+  let file_name = args[index..].join(" ").replace('\\', "/");
+  match base_dir {
+    Some(base_dir) => Some(base_dir.join(file_name)),
+    None => Some(PathBuf::from(file_name)),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Cursor;
+  use super::*;
+
+  #[test]
+  fn parses_colors_and_scalars() {
+    let source = "newmtl shiny\nKa 0.1 0.2 0.3\nKd 0.5\nKs 1 1 1\nNs 250\nd 0.25\nillum 3\nnewmtl glass\nTr 0.75\n";
+    let materials = parse_mtl(Cursor::new(source), None).unwrap();
+    let shiny = &materials["shiny"];
+    assert_eq!(shiny.ambient, [0.1, 0.2, 0.3]);
+    assert_eq!(shiny.diffuse, [0.5, 0.5, 0.5]);
+    assert_eq!(shiny.specular, [1.0, 1.0, 1.0]);
+    assert_eq!((shiny.shininess, shiny.dissolve, shiny.illum), (250.0, 0.25, 3));
+    assert_eq!(materials["glass"].dissolve, 0.25);
+  }
+
+  #[test]
+  fn resolves_texture_paths_against_the_base_dir() {
+    let source = "newmtl textured\nmap_Kd -s 1 1 1 -clamp on textures\\wood.png\nmap_Bump -bm 0.5 normal.png\nmap_Ks spec map.png\n";
+    let materials = parse_mtl(Cursor::new(source), Some(Path::new("models"))).unwrap();
+    let textured = &materials["textured"];
+    assert_eq!(textured.diffuse_map, Some(PathBuf::from("models/textures/wood.png")));
+    assert_eq!(textured.bump_map, Some(PathBuf::from("models/normal.png")));
+    assert_eq!(textured.specular_map, Some(PathBuf::from("models/spec map.png")));
+  }
+
+  #[test]
+  fn rejects_malformed_values() {
+    assert!(matches!(parse_mtl(Cursor::new("newmtl a\nKd red\n"), None), Err(ObjError::Parse { line: 2, .. })));
+    assert!(matches!(parse_mtl(Cursor::new("newmtl a\nNs\n"), None), Err(ObjError::Parse { line: 2, .. })));
+    assert!(matches!(parse_mtl(Cursor::new("newmtl a\nKd spectral file.rfl\n"), None), Err(ObjError::Parse { line: 2, .. })));
+  }
+}
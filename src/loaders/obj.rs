@@ -0,0 +1,512 @@
+use std::{collections::HashMap, error::Error, fmt, fs::File, io::{self, BufRead, BufReader}, path::{Path, PathBuf}};
+
+use super::mtl::{load_mtl, Material};
+
+// Interleaved layout of every vertex emitted by the loader: position (3), tex coord (2), normal (3)
+pub const OBJ_VERTEX_STRIDE: usize = 8;
+
+#[derive(Debug)]
+pub enum ObjError {
+  Io(io::Error),
+  Parse { line: usize, message: String },
+  IndexOutOfRange { line: usize, index: i64 },
+}
+
+impl fmt::Display for ObjError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ObjError::Io(err)                       => write!(f, "I/O error reading OBJ: {}", err),
+      ObjError::Parse { line, message }       => write!(f, "OBJ parse error on line {}: {}", line, message),
+      ObjError::IndexOutOfRange { line, index } => write!(f, "OBJ index {} out of range on line {}", index, line),
+    }
+  }
+}
+
+impl Error for ObjError {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    match self {
+      ObjError::Io(err) => Some(err),
+      _ => None,
+    }
+  }
+}
+
+impl From<io::Error> for ObjError {
+  fn from(err: io::Error) -> Self {
+    ObjError::Io(err)
+  }
+}
+
+pub struct ObjMesh {
+  pub name     : String,
+  pub material : Option<String>,
+  pub vertices : Vec<f32>,
+  pub indices  : Vec<u32>,
+}
+
+impl ObjMesh {
+  fn new(name: &str, material: Option<String>) -> Self {
+    ObjMesh { name: name.to_string(), material, vertices: Vec::new(), indices: Vec::new() }
+  }
+
+  pub fn vertex_count(&self) -> usize {
+    self.vertices.len() / OBJ_VERTEX_STRIDE
+  }
+}
+
+pub struct ObjModel {
+  pub meshes    : Vec<ObjMesh>,
+  pub materials : HashMap<String, Material>,
+  pub warnings  : Vec<String>, // Problems the load recovered from, such as an unreadable material library
+}
+
+impl ObjModel {
+
+  pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ObjError> {
+    let path = path.as_ref();
+    let file = File::open(path)?;
+    Self::parse(BufReader::new(file), path.parent())
+  }
+
+  // `base_dir` is where `mtllib` files and the textures they reference are looked up
+  pub fn parse<R: BufRead>(reader: R, base_dir: Option<&Path>) -> Result<Self, ObjError> {
+    let mut builder = ObjBuilder::new();
+
+    for (line_index, line) in reader.lines().enumerate() {
+      let line = line?;
+      builder.line = line_index + 1;
+
+      let line = match line.find('#') {
+        Some(comment) => &line[..comment],
+        None => &line[..],
+      };
+
+      let mut parts = line.split_whitespace();
+      let keyword = match parts.next() {
+        Some(keyword) => keyword,
+        None => continue,
+      };
+      let args: Vec<&str> = parts.collect();
+
+      match keyword {
+        "v"  => { let v = builder.parse_floats(&args, 3)?; builder.positions.push([v[0], v[1], v[2]]); },
+        "vt" => { let v = builder.parse_floats(&args, 1)?; builder.tex_coords.push([v[0], *v.get(1).unwrap_or(&0.0)]); },
+        "vn" => { let v = builder.parse_floats(&args, 3)?; builder.normals.push([v[0], v[1], v[2]]); },
+        "f"  => builder.add_face(&args)?,
+        "o" | "g" => builder.begin_mesh(&args.join(" ")),
+        "usemtl" => builder.use_material(&args.join(" ")),
+        "mtllib" => {
+          for library in &args {
+            let library_path = match base_dir {
+              Some(base_dir) => base_dir.join(library),
+              None => PathBuf::from(library),
+            };
+            match load_mtl(&library_path, base_dir) {
+              Ok(materials) => builder.materials.extend(materials),
+              Err(ObjError::Io(err)) => builder.warnings.push(format!("Skipping material library {}: {}", library_path.display(), err)),
+              Err(err) => return Err(err),
+            }
+          }
+        },
+        _ => {}
+      }
+    }
+
+    Ok(builder.finish())
+  }
+
+  pub fn material(&self, mesh: &ObjMesh) -> Option<&Material> {
+    mesh.material.as_ref().and_then(|name| self.materials.get(name))
+  }
+
+  // Flattens all submeshes into a single vertex/index buffer pair
+  pub fn merged(&self) -> (Vec<f32>, Vec<u32>) {
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    for mesh in &self.meshes {
+      let base = (vertices.len() / OBJ_VERTEX_STRIDE) as u32;
+      vertices.extend_from_slice(&mesh.vertices);
+      indices.extend(mesh.indices.iter().map(|index| index + base));
+    }
+    (vertices, indices)
+  }
+
+  // Like `merged`, but one vertex/index buffer pair per material, in order of first use
+  pub fn merged_by_material(&self) -> Vec<(Option<&Material>, Vec<f32>, Vec<u32>)> {
+    let mut groups: Vec<(Option<&String>, Vec<f32>, Vec<u32>)> = Vec::new();
+    for mesh in &self.meshes {
+      let group = match groups.iter().position(|group| group.0 == mesh.material.as_ref()) {
+        Some(group) => group,
+        None => {
+          groups.push((mesh.material.as_ref(), Vec::new(), Vec::new()));
+          groups.len() - 1
+        }
+      };
+      let (_, vertices, indices) = &mut groups[group];
+      let base = (vertices.len() / OBJ_VERTEX_STRIDE) as u32;
+      vertices.extend_from_slice(&mesh.vertices);
+      indices.extend(mesh.indices.iter().map(|index| index + base));
+    }
+    groups.into_iter()
+      .map(|(material, vertices, indices)| (material.and_then(|name| self.materials.get(name)), vertices, indices))
+      .collect()
+  }
+}
+
+// A normal read from a `vn` line, or one generated for a face without normals. Generated normals live in their
+// own list so they do not shift what relative `vn` indices resolve to
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum NormalIndex {
+  File(usize),
+  Face(usize),
+}
+
+// (position, tex coord, normal) indices of a face corner, already resolved to 0-based
+type CornerKey = (usize, Option<usize>, NormalIndex);
+
+struct ObjBuilder {
+  line         : usize,
+  positions    : Vec<[f32; 3]>,
+  tex_coords   : Vec<[f32; 2]>,
+  normals      : Vec<[f32; 3]>,
+  face_normals : Vec<[f32; 3]>,
+  meshes       : Vec<ObjMesh>,
+  materials    : HashMap<String, Material>,
+  warnings     : Vec<String>,
+  current      : ObjMesh,
+  vertex_cache : HashMap<CornerKey, u32>,
+}
+
+impl ObjBuilder {
+
+  fn new() -> Self {
+    ObjBuilder {
+      line         : 0,
+      positions    : Vec::new(),
+      tex_coords   : Vec::new(),
+      normals      : Vec::new(),
+      face_normals : Vec::new(),
+      meshes       : Vec::new(),
+      materials    : HashMap::new(),
+      warnings     : Vec::new(),
+      current      : ObjMesh::new("default", None),
+      vertex_cache : HashMap::new(),
+    }
+  }
+
+  fn parse_floats(&self, args: &[&str], min_count: usize) -> Result<Vec<f32>, ObjError> {
+    if args.len() < min_count {
+      return Err(self.parse_error(format!("expected at least {} components, found {}", min_count, args.len())));
+    }
+    args.iter()
+      .map(|arg| arg.parse::<f32>().map_err(|_| self.parse_error(format!("invalid number '{}'", arg))))
+      .collect()
+  }
+
+  fn parse_error(&self, message: String) -> ObjError {
+    ObjError::Parse { line: self.line, message }
+  }
+
+  fn begin_mesh(&mut self, name: &str) {
+    let name = if name.is_empty() { "default" } else { name };
+    let material = self.current.material.clone();
+    self.push_current(ObjMesh::new(name, material));
+  }
+
+  // A material switch splits the current group into a new submesh carrying the same name
+  fn use_material(&mut self, material: &str) {
+    let name = self.current.name.clone();
+    self.push_current(ObjMesh::new(&name, Some(material.to_string())));
+  }
+
+  fn push_current(&mut self, next: ObjMesh) {
+    let finished = std::mem::replace(&mut self.current, next);
+    if !finished.indices.is_empty() {
+      self.meshes.push(finished);
+    }
+    self.vertex_cache.clear();
+  }
+
+  // Resolves a 1-based (or negative, relative) OBJ index against the number of elements read so far
+  fn resolve_index(&self, token: &str, count: usize) -> Result<usize, ObjError> {
+    let index: i64 = token.parse().map_err(|_| self.parse_error(format!("invalid index '{}'", token)))?;
+    let resolved = if index > 0 {
+      index - 1
+    } else if index < 0 {
+      count as i64 + index
+    } else {
+      return Err(ObjError::IndexOutOfRange { line: self.line, index });
+    };
+
+    if resolved < 0 || resolved >= count as i64 {
+      return Err(ObjError::IndexOutOfRange { line: self.line, index });
+    }
+    Ok(resolved as usize)
+  }
+
+  fn add_face(&mut self, args: &[&str]) -> Result<(), ObjError> {
+    if args.len() < 3 {
+      return Err(self.parse_error(format!("face requires at least 3 vertices, found {}", args.len())));
+    }
+
+    let mut corners = Vec::with_capacity(args.len());
+    for arg in args {
+      let mut fields = arg.split('/');
+      let position = self.resolve_index(fields.next().unwrap_or(""), self.positions.len())?;
+      let tex_coord = match fields.next() {
+        Some(token) if !token.is_empty() => Some(self.resolve_index(token, self.tex_coords.len())?),
+        _ => None,
+      };
+      let normal = match fields.next() {
+        Some(token) if !token.is_empty() => Some(self.resolve_index(token, self.normals.len())?),
+        _ => None,
+      };
+      corners.push((position, tex_coord, normal));
+    }
+
+    let normal = self.face_normal(&corners);
+
+    // Corners without a normal share the flat face normal
+    let face_normal = if corners.iter().any(|corner| corner.2.is_none()) {
+      self.face_normals.push(normal);
+      Some(NormalIndex::Face(self.face_normals.len() - 1))
+    } else {
+      None
+    };
+
+    let indices: Vec<u32> = corners.iter()
+      .map(|&(position, tex_coord, normal)| self.emit_vertex((position, tex_coord, normal.map(NormalIndex::File).or(face_normal).unwrap())))
+      .collect();
+
+    let polygon: Vec<[f32; 3]> = corners.iter().map(|corner| self.positions[corner.0]).collect();
+    for [a, b, c] in triangulate(&polygon, normal) {
+      self.current.indices.extend_from_slice(&[indices[a], indices[b], indices[c]]);
+    }
+    Ok(())
+  }
+
+  // Newell's method, robust for non-planar polygons
+  fn face_normal(&self, corners: &[(usize, Option<usize>, Option<usize>)]) -> [f32; 3] {
+    let mut normal = [0.0f32; 3];
+    for i in 0..corners.len() {
+      let current = self.positions[corners[i].0];
+      let next = self.positions[corners[(i + 1) % corners.len()].0];
+      normal[0] += (current[1] - next[1]) * (current[2] + next[2]);
+      normal[1] += (current[2] - next[2]) * (current[0] + next[0]);
+      normal[2] += (current[0] - next[0]) * (current[1] + next[1]);
+    }
+    let length = (normal[0] * normal[0] + normal[1] * normal[1] + normal[2] * normal[2]).sqrt();
+    if length > 0.0 {
+      [normal[0] / length, normal[1] / length, normal[2] / length]
+    } else {
+      [0.0, 0.0, 1.0]
+    }
+  }
+
+  fn emit_vertex(&mut self, key: CornerKey) -> u32 {
+    if let Some(&index) = self.vertex_cache.get(&key) {
+      return index;
+    }
+
+    let (position, tex_coord, normal) = key;
+    let position = self.positions[position];
+    let tex_coord = tex_coord.map(|index| self.tex_coords[index]).unwrap_or([0.0, 0.0]);
+    let normal = match normal {
+      NormalIndex::File(index) => self.normals[index],
+      NormalIndex::Face(index) => self.face_normals[index],
+    };
+
+    let index = self.current.vertex_count() as u32;
+    self.current.vertices.extend_from_slice(&position);
+    self.current.vertices.extend_from_slice(&tex_coord);
+    self.current.vertices.extend_from_slice(&normal);
+    self.vertex_cache.insert(key, index);
+    index
+  }
+
+  fn finish(mut self) -> ObjModel {
+    self.begin_mesh("");
+    ObjModel { meshes: self.meshes, materials: self.materials, warnings: self.warnings }
+  }
+}
+
+// Ear clipping in the polygon's plane, so concave faces are split without covering their notches. Each step
+// clips the first ear after the start, which gives the same fan as `(0, i, i + 1)` for convex polygons
+fn triangulate(polygon: &[[f32; 3]], normal: [f32; 3]) -> Vec<[usize; 3]> {
+  // Drop the axis the face is most aligned with
+  let axis = (0..3).max_by(|&a, &b| normal[a].abs().total_cmp(&normal[b].abs())).unwrap();
+  let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+  let points: Vec<[f32; 2]> = polygon.iter().map(|position| [position[u], position[v]]).collect();
+
+  let cross = |a: [f32; 2], b: [f32; 2], c: [f32; 2]| (b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0]);
+  let orientation = (0..points.len())
+    .map(|i| cross([0.0, 0.0], points[i], points[(i + 1) % points.len()]))
+    .sum::<f32>()
+    .signum();
+
+  let mut remaining: Vec<usize> = (0..polygon.len()).collect();
+  let mut triangles = Vec::with_capacity(polygon.len() - 2);
+  while remaining.len() > 3 {
+    let ear = (1..=remaining.len()).map(|i| i % remaining.len()).find(|&i| {
+      let prev = remaining[(i + remaining.len() - 1) % remaining.len()];
+      let next = remaining[(i + 1) % remaining.len()];
+      let (a, b, c) = (points[prev], points[remaining[i]], points[next]);
+      if cross(a, b, c) * orientation <= 0.0 {
+        return false;
+      }
+      remaining.iter()
+        .filter(|&&other| other != prev && other != remaining[i] && other != next)
+        .all(|&other| {
+          let p = points[other];
+          let inside = cross(a, b, p) * orientation >= 0.0 && cross(b, c, p) * orientation >= 0.0 && cross(c, a, p) * orientation >= 0.0;
+          !inside || p == a || p == b || p == c
+        })
+    });
+
+    // Degenerate or self-intersecting faces have no ear left, fan what remains
+    let Some(i) = ear else { break };
+    let prev = remaining[(i + remaining.len() - 1) % remaining.len()];
+    let next = remaining[(i + 1) % remaining.len()];
+    triangles.push([prev, remaining[i], next]);
+    remaining.remove(i);
+  }
+  for i in 1..remaining.len() - 1 {
+    triangles.push([remaining[0], remaining[i], remaining[i + 1]]);
+  }
+  triangles
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Cursor;
+  use super::*;
+
+  fn parse(source: &str) -> Result<ObjModel, ObjError> {
+    ObjModel::parse(Cursor::new(source), None)
+  }
+
+  fn vertex(mesh: &ObjMesh, index: u32) -> &[f32] {
+    let start = index as usize * OBJ_VERTEX_STRIDE;
+    &mesh.vertices[start..start + OBJ_VERTEX_STRIDE]
+  }
+
+  fn triangle_area(mesh: &ObjMesh, triangle: &[u32]) -> f32 {
+    let [a, b, c] = [0, 1, 2].map(|corner| vertex(mesh, triangle[corner]));
+    ((b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0])) / 2.0
+  }
+
+  #[test]
+  fn resolves_negative_indices() {
+    let model = parse("v 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0 0\nvt 1 0\nvt 0 1\nvn 0 0 1\nf -3/-3/-1 -2/-2/-1 -1/-1/-1\n").unwrap();
+    let mesh = &model.meshes[0];
+    assert_eq!(mesh.indices, vec![0, 1, 2]);
+    assert_eq!(vertex(mesh, 1), &[1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0]);
+  }
+
+  #[test]
+  fn generated_normals_do_not_shift_relative_normal_indices() {
+    let source = "\
+      v 0 0 0\nv 1 0 0\nv 0 1 0\n\
+      vn 1 0 0\n\
+      f 1 2 3\n\
+      f 1//-1 2//-1 3//-1\n";
+    let mesh = &parse(source).unwrap().meshes[0];
+    assert_eq!(&vertex(mesh, 0)[5..], &[0.0, 0.0, 1.0]);
+    assert_eq!(&vertex(mesh, 3)[5..], &[1.0, 0.0, 0.0]);
+  }
+
+  #[test]
+  fn missing_normals_use_the_face_normal() {
+    let mesh = &parse("v 0 0 0\nv 0 0 1\nv 0 1 0\nvt 0 0\nf 1/1 2/1 3/1\n").unwrap().meshes[0];
+    for index in 0..3 {
+      assert_eq!(&vertex(mesh, index)[5..], &[-1.0, 0.0, 0.0]);
+    }
+  }
+
+  #[test]
+  fn shares_identical_corners() {
+    let mesh = &parse("v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvn 0 0 1\nf 1//1 2//1 3//1\nf 1//1 3//1 4//1\n").unwrap().meshes[0];
+    assert_eq!(mesh.vertex_count(), 4);
+    assert_eq!(mesh.indices, vec![0, 1, 2, 0, 2, 3]);
+  }
+
+  #[test]
+  fn triangulates_quads_and_ngons_as_fans() {
+    let model = parse("v 0 0 0\nv 1 0 0\nv 2 1 0\nv 1 2 0\nv 0 1 0\nf 1 2 3 4\nf 1 2 3 4 5\n").unwrap();
+    // Each face gets its own generated normal, so the pentagon's corners start at 4
+    assert_eq!(model.meshes[0].indices, vec![0, 1, 2, 0, 2, 3, 4, 5, 6, 4, 6, 7, 4, 7, 8]);
+  }
+
+  #[test]
+  fn triangulates_concave_polygons_inside_their_outline() {
+    // An arrow head whose notch at (1, 1) would be covered by a fan from the first corner
+    let mesh = &parse("v 0 0 0\nv 1 3 0\nv 2 0 0\nv 1 1 0\nf 1 2 3 4\n").unwrap().meshes[0];
+    assert_eq!(mesh.indices.len(), 6);
+    let total: f32 = mesh.indices.chunks(3).map(|triangle| triangle_area(mesh, triangle)).sum();
+    assert!(mesh.indices.chunks(3).all(|triangle| triangle_area(mesh, triangle) < 0.0));
+    assert!((total + 2.0).abs() < 1e-5, "area {}", total);
+  }
+
+  #[test]
+  fn splits_groups_and_materials_into_submeshes() {
+    let model = parse("v 0 0 0\nv 1 0 0\nv 0 1 0\no first\nusemtl red\nf 1 2 3\nusemtl blue\nf 1 2 3\ng second\nf 1 2 3\n").unwrap();
+    let summary: Vec<(&str, Option<&str>)> = model.meshes.iter().map(|mesh| (mesh.name.as_str(), mesh.material.as_deref())).collect();
+    assert_eq!(summary, vec![("first", Some("red")), ("first", Some("blue")), ("second", Some("blue"))]);
+  }
+
+  #[test]
+  fn merges_submeshes_with_offset_indices() {
+    let model = parse("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\no other\nf 3 2 1\n").unwrap();
+    let (vertices, indices) = model.merged();
+    assert_eq!(vertices.len(), 6 * OBJ_VERTEX_STRIDE);
+    assert_eq!(indices, vec![0, 1, 2, 3, 4, 5]);
+  }
+
+  #[test]
+  fn merges_submeshes_per_material() {
+    let model = parse("v 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl a\nf 1 2 3\nusemtl b\nf 1 2 3\no other\nusemtl a\nf 3 2 1\n").unwrap();
+    let groups = model.merged_by_material();
+    assert_eq!(groups.len(), 2);
+    assert_eq!(groups[0].2, vec![0, 1, 2, 3, 4, 5]);
+    assert_eq!(groups[1].2, vec![0, 1, 2]);
+  }
+
+  #[test]
+  fn resolves_material_textures_relative_to_the_obj() {
+    let dir = std::env::temp_dir().join(format!("obj_mtl_paths_{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("materials")).unwrap();
+    std::fs::write(dir.join("materials/lib.mtl"), "newmtl painted\nmap_Kd textures/paint.png\n").unwrap();
+    std::fs::write(dir.join("model.obj"), "mtllib materials/lib.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl painted\nf 1 2 3\n").unwrap();
+
+    let model = ObjModel::load(dir.join("model.obj")).unwrap();
+    let material = model.material(&model.meshes[0]).unwrap();
+    assert_eq!(material.diffuse_map, Some(dir.join("textures/paint.png")));
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn warns_about_unreadable_material_libraries() {
+    let model = parse("mtllib missing.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n").unwrap();
+    assert_eq!(model.meshes.len(), 1);
+    assert_eq!(model.warnings.len(), 1);
+    assert!(model.warnings[0].contains("missing.mtl"));
+  }
+
+  #[test]
+  fn rejects_malformed_lines() {
+    assert!(matches!(parse("v 0 0\n"), Err(ObjError::Parse { line: 1, .. })));
+    assert!(matches!(parse("v 0 0 0\nv 0 x 0\n"), Err(ObjError::Parse { line: 2, .. })));
+    assert!(matches!(parse("v 0 0 0\nv 1 0 0\nf 1 2\n"), Err(ObjError::Parse { line: 3, .. })));
+    assert!(matches!(parse("v 0 0 0\nv 1 0 0\nf 1 2 a\n"), Err(ObjError::Parse { line: 3, .. })));
+    assert!(matches!(parse("v 0 0 0\nf 1 2 3\n"), Err(ObjError::IndexOutOfRange { line: 2, index: 2 })));
+    assert!(matches!(parse("v 0 0 0\nf 0 1 1\n"), Err(ObjError::IndexOutOfRange { line: 2, index: 0 })));
+    assert!(matches!(parse("v 0 0 0\nf -2 1 1\n"), Err(ObjError::IndexOutOfRange { line: 2, index: -2 })));
+  }
+
+  #[test]
+  fn ignores_comments_and_unknown_keywords() {
+    let model = parse("# header\nv 0 0 0 # origin\nv 1 0 0\nv 0 1 0\ns off\nf 1 2 3\n").unwrap();
+    assert_eq!(model.meshes.len(), 1);
+    assert_eq!(model.meshes[0].indices.len(), 3);
+  }
+}
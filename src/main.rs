@@ -1,6 +1,54 @@
-use drivers::gl::window;
-mod drivers;
+use rust_renderer::{demo, headless, regression};
+use rust_renderer::drivers::{gl, renderer::Backend, vulkan};
 
 pub fn main() {
-  window::run();
-}
\ No newline at end of file
+  let args: Vec<String> = std::env::args().collect();
+
+  match headless::HeadlessOptions::from_args(&args) {
+    Ok(Some(options)) => {
+      if let Err(err) = headless::run(options) {
+        eprintln!("Headless render failed: {}", err);
+        std::process::exit(1);
+      }
+      return;
+    },
+    Ok(None) => {},
+    Err(err) => {
+      eprintln!("{}", err);
+      std::process::exit(2);
+    }
+  }
+
+  match regression::RegressionOptions::from_args(&args) {
+    Ok(Some(options)) => {
+      match regression::run(&options) {
+        Ok(true) => return,
+        Ok(false) => std::process::exit(1),
+        Err(err) => {
+          eprintln!("Regression run failed: {}", err);
+          std::process::exit(1);
+        }
+      }
+    },
+    Ok(None) => {},
+    Err(err) => {
+      eprintln!("{}", err);
+      std::process::exit(2);
+    }
+  }
+
+  let backend = Backend::from_args_and_env().unwrap_or_else(|err| {
+    eprintln!("{}", err);
+    std::process::exit(2);
+  });
+
+  let app = Box::new(demo::CubeDemo::new());
+  match backend {
+    Backend::OpenGl => gl::window::run_app(app),
+    Backend::Vulkan => vulkan::window::run_app(app),
+    Backend::Software => {
+      eprintln!("The software backend has no window, use it with --headless or --regression");
+      std::process::exit(2);
+    }
+  }
+}
@@ -0,0 +1,221 @@
+use image::{Rgba, RgbaImage};
+
+const SSIM_WINDOW : u32 = 8;
+const SSIM_STRIDE : u32 = 4;
+const SSIM_C1     : f64 = (0.01 * 255.0) * (0.01 * 255.0);
+const SSIM_C2     : f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+#[derive(Clone, Copy, Debug)]
+pub struct Tolerance {
+  pub channel            : u8,  // Largest per-channel difference a pixel may have and still match
+  pub max_mismatch_ratio : f64,
+  pub max_rmse           : f64, // In 0..255 channel units
+  pub min_ssim           : f64,
+}
+
+impl Default for Tolerance {
+  fn default() -> Self {
+    // Loose enough to absorb rasterization differences between drivers, tight enough to catch a broken texture or transform
+    Tolerance {
+      channel            : 8,
+      max_mismatch_ratio : 0.005,
+      max_rmse           : 4.0,
+      min_ssim           : 0.98,
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Comparison {
+  pub mismatched_pixels : u64,
+  pub total_pixels      : u64,
+  pub rmse              : f64,
+  pub ssim              : f64,
+}
+
+impl Comparison {
+
+  pub fn mismatch_ratio(&self) -> f64 {
+    self.mismatched_pixels as f64 / self.total_pixels.max(1) as f64
+  }
+
+  pub fn passes(&self, tolerance: &Tolerance) -> bool {
+    self.mismatch_ratio() <= tolerance.max_mismatch_ratio
+      && self.rmse <= tolerance.max_rmse
+      && self.ssim >= tolerance.min_ssim
+  }
+}
+
+// Images must have the same dimensions, callers report a size mismatch separately
+pub fn compare(actual: &RgbaImage, expected: &RgbaImage, tolerance: &Tolerance) -> Comparison {
+  assert_eq!(actual.dimensions(), expected.dimensions(), "Compared images differ in size");
+
+  let mut mismatched_pixels = 0;
+  let mut squared_error = 0.0;
+  for (a, e) in actual.pixels().zip(expected.pixels()) {
+    if channel_difference(a, e) > tolerance.channel {
+      mismatched_pixels += 1;
+    }
+    for channel in 0..4 {
+      let difference = a[channel] as f64 - e[channel] as f64;
+      squared_error += difference * difference;
+    }
+  }
+
+  let total_pixels = actual.width() as u64 * actual.height() as u64;
+  Comparison {
+    mismatched_pixels,
+    total_pixels,
+    rmse : (squared_error / (total_pixels.max(1) * 4) as f64).sqrt(),
+    ssim : ssim(actual, expected),
+  }
+}
+
+// Matching pixels are a dimmed grayscale of the expected image, mismatches are red scaled by their error
+pub fn diff_image(actual: &RgbaImage, expected: &RgbaImage, tolerance: &Tolerance) -> RgbaImage {
+  RgbaImage::from_fn(actual.width(), actual.height(), |x, y| {
+    let a = actual.get_pixel(x, y);
+    let e = expected.get_pixel(x, y);
+    let difference = channel_difference(a, e);
+    if difference > tolerance.channel {
+      Rgba([128 + difference / 2, 0, 0, 255])
+    } else {
+      let gray = (luma(e) / 4.0) as u8;
+      Rgba([gray, gray, gray, 255])
+    }
+  })
+}
+
+fn channel_difference(a: &Rgba<u8>, b: &Rgba<u8>) -> u8 {
+  (0..4).map(|channel| a[channel].abs_diff(b[channel])).max().unwrap_or(0)
+}
+
+fn luma(pixel: &Rgba<u8>) -> f64 {
+  0.299 * pixel[0] as f64 + 0.587 * pixel[1] as f64 + 0.114 * pixel[2] as f64
+}
+
+// Mean SSIM over overlapping windows of the luma channel
+fn ssim(actual: &RgbaImage, expected: &RgbaImage) -> f64 {
+  let (width, height) = actual.dimensions();
+  let window_width = SSIM_WINDOW.min(width);
+  let window_height = SSIM_WINDOW.min(height);
+  if window_width == 0 || window_height == 0 {
+    return 1.0;
+  }
+
+  let mut total = 0.0;
+  let mut windows = 0;
+  let mut y = 0;
+  while y + window_height <= height {
+    let mut x = 0;
+    while x + window_width <= width {
+      total += window_ssim(actual, expected, x, y, window_width, window_height);
+      windows += 1;
+      x += SSIM_STRIDE;
+    }
+    y += SSIM_STRIDE;
+  }
+  total / windows as f64
+}
+
+fn window_ssim(actual: &RgbaImage, expected: &RgbaImage, x0: u32, y0: u32, width: u32, height: u32) -> f64 {
+  let count = (width * height) as f64;
+  let (mut sum_a, mut sum_e, mut sum_aa, mut sum_ee, mut sum_ae) = (0.0, 0.0, 0.0, 0.0, 0.0);
+
+  for y in y0..y0 + height {
+    for x in x0..x0 + width {
+      let a = luma(actual.get_pixel(x, y));
+      let e = luma(expected.get_pixel(x, y));
+      sum_a += a;
+      sum_e += e;
+      sum_aa += a * a;
+      sum_ee += e * e;
+      sum_ae += a * e;
+    }
+  }
+
+  let mean_a = sum_a / count;
+  let mean_e = sum_e / count;
+  let variance_a = sum_aa / count - mean_a * mean_a;
+  let variance_e = sum_ee / count - mean_e * mean_e;
+  let covariance = sum_ae / count - mean_a * mean_e;
+
+  ((2.0 * mean_a * mean_e + SSIM_C1) * (2.0 * covariance + SSIM_C2))
+    / ((mean_a * mean_a + mean_e * mean_e + SSIM_C1) * (variance_a + variance_e + SSIM_C2))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn gradient(width: u32, height: u32) -> RgbaImage {
+    RgbaImage::from_fn(width, height, |x, y| Rgba([(x * 8) as u8, (y * 8) as u8, 128, 255]))
+  }
+
+  #[test]
+  fn identical_images_match_exactly() {
+    let image = gradient(16, 16);
+    let comparison = compare(&image, &image, &Tolerance::default());
+    assert_eq!(comparison.mismatched_pixels, 0);
+    assert_eq!(comparison.total_pixels, 256);
+    assert_eq!(comparison.rmse, 0.0);
+    assert!((comparison.ssim - 1.0).abs() < 1e-9);
+    assert!(comparison.passes(&Tolerance::default()));
+  }
+
+  #[test]
+  fn channel_tolerance_is_inclusive() {
+    let expected = gradient(16, 16);
+    let mut actual = expected.clone();
+    let tolerance = Tolerance::default();
+    actual.get_pixel_mut(3, 3)[2] += tolerance.channel;
+    assert_eq!(compare(&actual, &expected, &tolerance).mismatched_pixels, 0);
+
+    actual.get_pixel_mut(3, 3)[2] += 1;
+    assert_eq!(compare(&actual, &expected, &tolerance).mismatched_pixels, 1);
+  }
+
+  #[test]
+  fn rmse_averages_over_every_channel() {
+    let expected = RgbaImage::from_pixel(4, 4, Rgba([0, 0, 0, 255]));
+    let actual = RgbaImage::from_pixel(4, 4, Rgba([8, 0, 0, 255]));
+    let comparison = compare(&actual, &expected, &Tolerance::default());
+    // One channel of four differs by 8 everywhere: sqrt(64 / 4)
+    assert!((comparison.rmse - 4.0).abs() < 1e-9);
+  }
+
+  #[test]
+  fn mismatch_ratio_above_tolerance_fails() {
+    let expected = gradient(16, 16);
+    let mut actual = expected.clone();
+    let tolerance = Tolerance { max_rmse: f64::MAX, min_ssim: 0.0, ..Tolerance::default() };
+    // One pixel of 256 is 0.39%, below the default 0.5%
+    actual.put_pixel(0, 0, Rgba([255, 255, 255, 255]));
+    assert!(compare(&actual, &expected, &tolerance).passes(&tolerance));
+
+    actual.put_pixel(1, 0, Rgba([255, 255, 255, 255]));
+    let comparison = compare(&actual, &expected, &tolerance);
+    assert_eq!(comparison.mismatched_pixels, 2);
+    assert!(!comparison.passes(&tolerance));
+  }
+
+  #[test]
+  fn structural_changes_lower_ssim() {
+    let expected = gradient(16, 16);
+    let actual = RgbaImage::from_fn(16, 16, |x, y| Rgba([(y * 8) as u8, (x * 8) as u8, 128, 255]));
+    let tolerance = Tolerance { channel: 255, max_mismatch_ratio: 1.0, max_rmse: f64::MAX, ..Tolerance::default() };
+    let comparison = compare(&actual, &expected, &tolerance);
+    assert!(comparison.ssim < tolerance.min_ssim);
+    assert!(!comparison.passes(&tolerance));
+  }
+
+  #[test]
+  fn diff_image_marks_mismatches_red() {
+    let expected = RgbaImage::from_pixel(2, 1, Rgba([100, 100, 100, 255]));
+    let mut actual = expected.clone();
+    actual.put_pixel(1, 0, Rgba([200, 100, 100, 255]));
+    let diff = diff_image(&actual, &expected, &Tolerance::default());
+    assert_eq!(*diff.get_pixel(0, 0), Rgba([25, 25, 25, 255]));
+    assert_eq!(*diff.get_pixel(1, 0), Rgba([178, 0, 0, 255]));
+  }
+}
@@ -0,0 +1,184 @@
+pub mod compare;
+
+use std::{fs, path::{Path, PathBuf}};
+use image::RgbaImage;
+use nalgebra::{Point3, Vector3};
+
+use crate::cli::{arg_value, has_flag};
+use crate::demo::root_dir;
+use crate::drivers::renderer::{Backend, RendererError};
+use crate::headless::render;
+use crate::viewer::{Camera, SceneViewer};
+use compare::{compare, diff_image, Comparison, Tolerance};
+
+pub struct GoldenScene {
+  pub name   : &'static str,
+  pub scene  : &'static str, // Relative to the repository root
+  pub width  : u32,
+  pub height : u32,
+  pub camera : Camera,
+}
+
+// Every scene the harness knows about, new regression cases are added here
+pub fn golden_scenes() -> Vec<GoldenScene> {
+  vec![
+    GoldenScene {
+      name   : "cube",
+      scene  : "assets/cube.obj",
+      width  : 256,
+      height : 256,
+      camera : Camera::new(Point3::new(2.0, 2.0, 3.0), Point3::origin(), Vector3::y()),
+    },
+  ]
+}
+
+pub struct RegressionOptions {
+  pub backend    : Backend,
+  pub bless      : bool,
+  pub filter     : Option<String>,
+  pub golden_dir : PathBuf,
+  pub output_dir : PathBuf,
+  pub tolerance  : Tolerance,
+}
+
+impl RegressionOptions {
+
+  // Returns None unless `--regression` was passed
+  pub fn from_args(args: &[String]) -> Result<Option<Self>, RendererError> {
+    if !has_flag(args, "--regression") {
+      return Ok(None);
+    }
+
+    let root = root_dir();
+    Ok(Some(RegressionOptions {
+      backend    : Backend::from_args_and_env()?,
+      bless      : has_flag(args, "--bless"),
+      filter     : arg_value(args, "--filter").map(str::to_string),
+      golden_dir : arg_value(args, "--golden-dir").map_or(root.join("assets").join("golden"), PathBuf::from),
+      output_dir : arg_value(args, "--output-dir").map_or(root.join("target").join("regression"), PathBuf::from),
+      tolerance  : Tolerance::default(),
+    }))
+  }
+}
+
+enum Outcome {
+  Blessed,
+  Passed(Comparison),
+  Failed(String),
+}
+
+// Renders every selected scene and checks it against its golden image, returns whether all of them passed
+pub fn run(options: &RegressionOptions) -> Result<bool, RendererError> {
+  let golden_dir = options.golden_dir.join(options.backend.name());
+  let root = root_dir();
+  let mut failures = 0;
+  let mut ran = 0;
+
+  for golden in golden_scenes() {
+    if options.filter.as_deref().is_some_and(|filter| !golden.name.contains(filter)) {
+      continue;
+    }
+    ran += 1;
+
+    let mut viewer = SceneViewer::new(root.join(golden.scene), golden.camera.clone());
+    let outcome = match render(options.backend, &mut viewer, golden.width, golden.height) {
+      Ok(actual) => check(options, &golden, &golden_dir, &actual)?,
+      Err(err) => Outcome::Failed(format!("render failed: {}", err)),
+    };
+
+    match outcome {
+      Outcome::Blessed => println!("[bless] {} ({})", golden.name, options.backend.name()),
+      Outcome::Passed(comparison) => println!("[ok]    {} ({}) {}", golden.name, options.backend.name(), describe(&comparison)),
+      Outcome::Failed(reason) => {
+        failures += 1;
+        println!("[FAIL]  {} ({}) {}", golden.name, options.backend.name(), reason);
+      }
+    }
+  }
+
+  println!("{} scene(s), {} failed", ran, failures);
+  Ok(failures == 0)
+}
+
+fn check(options: &RegressionOptions, golden: &GoldenScene, golden_dir: &PathBuf, actual: &RgbaImage) -> Result<Outcome, RendererError> {
+  let golden_path = golden_dir.join(format!("{}.png", golden.name));
+
+  if options.bless {
+    fs::create_dir_all(golden_dir).map_err(|err| io_error(golden_dir, err))?;
+    actual.save(&golden_path).map_err(|err| RendererError::Backend(format!("Failed to write {}: {}", golden_path.display(), err)))?;
+    return Ok(Outcome::Blessed);
+  }
+
+  let expected = match image::open(&golden_path) {
+    Ok(expected) => expected.to_rgba8(),
+    Err(err) => return Ok(Outcome::Failed(format!("missing golden {} ({}), rerun with --bless", golden_path.display(), err))),
+  };
+
+  if expected.dimensions() != actual.dimensions() {
+    write_artifacts(options, golden.name, actual, None)?;
+    return Ok(Outcome::Failed(format!(
+      "size mismatch: rendered {:?}, golden {:?}", actual.dimensions(), expected.dimensions()
+    )));
+  }
+
+  let comparison = compare(actual, &expected, &options.tolerance);
+  if comparison.passes(&options.tolerance) {
+    return Ok(Outcome::Passed(comparison));
+  }
+
+  let diff = diff_image(actual, &expected, &options.tolerance);
+  let written = write_artifacts(options, golden.name, actual, Some(&diff))?;
+  Ok(Outcome::Failed(format!("{}, see {}", describe(&comparison), written.display())))
+}
+
+// Writes <name>.actual.png and <name>.diff.png next to each other, returns the diff path if there is one
+fn write_artifacts(options: &RegressionOptions, name: &str, actual: &RgbaImage, diff: Option<&RgbaImage>) -> Result<PathBuf, RendererError> {
+  let output_dir = options.output_dir.join(options.backend.name());
+  fs::create_dir_all(&output_dir).map_err(|err| io_error(&output_dir, err))?;
+
+  let save = |image: &RgbaImage, path: PathBuf| -> Result<PathBuf, RendererError> {
+    image.save(&path).map_err(|err| RendererError::Backend(format!("Failed to write {}: {}", path.display(), err)))?;
+    Ok(path)
+  };
+
+  let actual_path = save(actual, output_dir.join(format!("{}.actual.png", name)))?;
+  match diff {
+    Some(diff) => save(diff, output_dir.join(format!("{}.diff.png", name))),
+    None => Ok(actual_path),
+  }
+}
+
+fn describe(comparison: &Comparison) -> String {
+  format!(
+    "mismatched {}/{} ({:.3}%), rmse {:.3}, ssim {:.4}",
+    comparison.mismatched_pixels,
+    comparison.total_pixels,
+    comparison.mismatch_ratio() * 100.0,
+    comparison.rmse,
+    comparison.ssim
+  )
+}
+
+fn io_error(path: &Path, err: std::io::Error) -> RendererError {
+  RendererError::Backend(format!("Failed to create {}: {}", path.display(), err))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // GL and Vulkan need a display or a GPU, the software rasterizer runs anywhere
+  #[test]
+  fn software_goldens_match() {
+    let root = root_dir();
+    let options = RegressionOptions {
+      backend    : Backend::Software,
+      bless      : false,
+      filter     : None,
+      golden_dir : root.join("assets").join("golden"),
+      output_dir : root.join("target").join("regression"),
+      tolerance  : Tolerance::default(),
+    };
+    assert!(run(&options).expect("Regression run failed"), "Software render differs from its golden, see target/regression/software");
+  }
+}
@@ -0,0 +1,348 @@
+use nalgebra::{Isometry3, Matrix4, Orthographic3, Perspective3, Point3, Translation3, UnitQuaternion, Vector3};
+
+use crate::drivers::renderer::{BufferId, DrawCall, Lighting, PipelineId, Renderer, RendererError, TextureId, MAX_LIGHTS};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct NodeId(pub usize);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform {
+  pub isometry : Isometry3<f32>,
+  pub scale    : Vector3<f32>,
+}
+
+impl Transform {
+
+  pub fn identity() -> Self {
+    Transform {
+      isometry : Isometry3::identity(),
+      scale    : Vector3::new(1.0, 1.0, 1.0),
+    }
+  }
+
+  pub fn from_trs(translation: Vector3<f32>, rotation: UnitQuaternion<f32>, scale: Vector3<f32>) -> Self {
+    Transform {
+      isometry : Isometry3::from_parts(Translation3::from(translation), rotation),
+      scale,
+    }
+  }
+
+  // Scale is applied first, then rotation, then translation
+  pub fn matrix(&self) -> Matrix4<f32> {
+    self.isometry.to_homogeneous() * Matrix4::new_nonuniform_scaling(&self.scale)
+  }
+}
+
+impl Default for Transform {
+  fn default() -> Self {
+    Transform::identity()
+  }
+}
+
+// Geometry already uploaded through the renderer's Device
+#[derive(Clone, Copy, Debug)]
+pub struct MeshRef {
+  pub vertex_buffer : BufferId,
+  pub index_buffer  : Option<BufferId>,
+  pub element_count : u32,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct MaterialRef {
+  pub pipeline : PipelineId,
+  pub texture  : Option<TextureId>,
+}
+
+// Lights point down the node's local -Z axis
+#[derive(Clone, Copy, Debug)]
+pub enum Light {
+  Directional { color: [f32; 3], intensity: f32 },
+  Point { color: [f32; 3], intensity: f32, range: Option<f32> },
+}
+
+// The view matrix comes from the inverse of the node's world transform, looking down -Z
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SceneCamera {
+  // `aspect_ratio` overrides the viewport's when set
+  Perspective { fov_y_degrees: f32, aspect_ratio: Option<f32>, near: f32, far: f32 },
+  // Half the width and height of the view volume
+  Orthographic { x_magnification: f32, y_magnification: f32, near: f32, far: f32 },
+}
+
+impl SceneCamera {
+  pub fn projection_matrix(&self, aspect_ratio: f32) -> Matrix4<f32> {
+    match *self {
+      SceneCamera::Perspective { fov_y_degrees, aspect_ratio: fixed_aspect, near, far } => {
+        Perspective3::new(fixed_aspect.unwrap_or(aspect_ratio), fov_y_degrees.to_radians(), near, far).to_homogeneous()
+      },
+      SceneCamera::Orthographic { x_magnification, y_magnification, near, far } => {
+        Orthographic3::new(-x_magnification, x_magnification, -y_magnification, y_magnification, near, far).to_homogeneous()
+      },
+    }
+  }
+}
+
+impl Default for SceneCamera {
+  fn default() -> Self {
+    SceneCamera::Perspective { fov_y_degrees: 45.0, aspect_ratio: None, near: 0.1, far: 100.0 }
+  }
+}
+
+pub struct Node {
+  pub name     : String,
+  pub mesh     : Option<MeshRef>,
+  pub material : Option<MaterialRef>,
+  pub light    : Option<Light>,
+  pub camera   : Option<SceneCamera>,
+  transform    : Transform,
+  children     : Vec<NodeId>,
+  world        : Matrix4<f32>,
+  dirty        : bool,
+}
+
+impl Node {
+
+  // Cached by Scene::update, stale while the node is dirty
+  pub fn world_matrix(&self) -> &Matrix4<f32> {
+    debug_assert!(!self.dirty, "World matrix of '{}' read before Scene::update", self.name);
+    &self.world
+  }
+}
+
+// Node hierarchy with cached world matrices. Changing a transform only marks the
+// affected subtree dirty, `update` recomputes what changed before the next traversal
+pub struct Scene {
+  nodes             : Vec<Node>,
+  roots             : Vec<NodeId>,
+  pub active_camera : Option<NodeId>,
+}
+
+impl Scene {
+
+  pub fn new() -> Self {
+    Scene {
+      nodes         : Vec::new(),
+      roots         : Vec::new(),
+      active_camera : None,
+    }
+  }
+
+  pub fn add_node(&mut self, name: &str, transform: Transform, parent: Option<NodeId>) -> NodeId {
+    let id = NodeId(self.nodes.len());
+    self.nodes.push(Node {
+      name      : name.to_string(),
+      mesh      : None,
+      material  : None,
+      light     : None,
+      camera    : None,
+      transform,
+      children  : Vec::new(),
+      world     : Matrix4::identity(),
+      dirty     : true,
+    });
+
+    match parent {
+      Some(parent) => self.nodes[parent.0].children.push(id),
+      None => self.roots.push(id),
+    }
+    id
+  }
+
+  // Attachments only, transforms go through the Scene so dirty flags stay correct
+  pub fn node_mut(&mut self, id: NodeId) -> &mut Node {
+    &mut self.nodes[id.0]
+  }
+
+  pub fn modify_transform<F: FnOnce(&mut Transform)>(&mut self, id: NodeId, modify: F) {
+    modify(&mut self.nodes[id.0].transform);
+    self.mark_dirty(id);
+  }
+
+  // A dirty node's descendants are always dirty too, so the walk stops at the first dirty one
+  fn mark_dirty(&mut self, id: NodeId) {
+    let mut stack = vec![id];
+    while let Some(id) = stack.pop() {
+      let node = &mut self.nodes[id.0];
+      if node.dirty {
+        continue;
+      }
+      node.dirty = true;
+      stack.extend(node.children.iter().copied());
+    }
+  }
+
+  pub fn update(&mut self) {
+    let mut stack: Vec<(NodeId, Matrix4<f32>, bool)> = self.roots.iter()
+      .map(|&root| (root, Matrix4::identity(), false))
+      .collect();
+
+    while let Some((id, parent_world, parent_changed)) = stack.pop() {
+      let node = &mut self.nodes[id.0];
+      let changed = node.dirty || parent_changed;
+      if changed {
+        node.world = parent_world * node.transform.matrix();
+        node.dirty = false;
+      }
+      let world = node.world;
+      stack.extend(node.children.iter().map(|&child| (child, world, changed)));
+    }
+  }
+
+  // Depth first, parents before children
+  pub fn traverse<F: FnMut(NodeId, &Node)>(&self, mut visit: F) {
+    let mut stack: Vec<NodeId> = self.roots.iter().rev().copied().collect();
+    while let Some(id) = stack.pop() {
+      let node = &self.nodes[id.0];
+      visit(id, node);
+      stack.extend(node.children.iter().rev().copied());
+    }
+  }
+
+  // View and projection of the active camera node
+  pub fn camera_matrices(&self, aspect_ratio: f32) -> Option<(Matrix4<f32>, Matrix4<f32>)> {
+    let node = &self.nodes[self.active_camera?.0];
+    let camera = node.camera?;
+    let view = node.world_matrix().try_inverse()?;
+    Some((view, camera.projection_matrix(aspect_ratio)))
+  }
+
+  // The first MAX_LIGHTS lights in traversal order, placed by their nodes' world transforms
+  pub fn lighting(&self) -> Lighting {
+    let mut lighting = Lighting::default();
+    self.traverse(|_, node| {
+      let light = match node.light {
+        Some(light) if (lighting.count as usize) < MAX_LIGHTS => light,
+        _ => return,
+      };
+      let world = node.world_matrix();
+      let (position_or_direction, color, intensity, range) = match light {
+        Light::Directional { color, intensity } => {
+          let direction = world.transform_vector(&-Vector3::z()).normalize();
+          ([direction.x, direction.y, direction.z, 0.0], color, intensity, 0.0)
+        },
+        Light::Point { color, intensity, range } => {
+          let position = world.transform_point(&Point3::origin());
+          ([position.x, position.y, position.z, 1.0], color, intensity, range.unwrap_or(0.0))
+        },
+      };
+      let index = lighting.count as usize;
+      lighting.positions[index] = position_or_direction;
+      lighting.colors[index] = [color[0] * intensity, color[1] * intensity, color[2] * intensity, range];
+      lighting.count += 1;
+    });
+    lighting
+  }
+
+  // Issues one draw per node carrying both a mesh and a material, lit by the scene's lights. Call `update` first
+  pub fn draw(&self, renderer: &mut dyn Renderer, view: &Matrix4<f32>, projection: &Matrix4<f32>) -> Result<(), RendererError> {
+    let lighting = self.lighting();
+    let mut result = Ok(());
+    self.traverse(|_, node| {
+      if result.is_err() {
+        return;
+      }
+      if let (Some(mesh), Some(material)) = (node.mesh, node.material) {
+        result = renderer.draw(&DrawCall {
+          pipeline      : material.pipeline,
+          vertex_buffer : mesh.vertex_buffer,
+          index_buffer  : mesh.index_buffer,
+          element_count : mesh.element_count,
+          texture       : material.texture,
+          model         : node.world_matrix(),
+          view,
+          projection,
+          lighting      : &lighting,
+        });
+      }
+    });
+    result
+  }
+}
+
+impl Default for Scene {
+  fn default() -> Self {
+    Scene::new()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn translation(x: f32, y: f32, z: f32) -> Transform {
+    Transform::from_trs(Vector3::new(x, y, z), UnitQuaternion::identity(), Vector3::new(1.0, 1.0, 1.0))
+  }
+
+  fn world_position(scene: &mut Scene, id: NodeId) -> Point3<f32> {
+    scene.node_mut(id).world_matrix().transform_point(&Point3::origin())
+  }
+
+  #[test]
+  fn children_follow_their_parents() {
+    let mut scene = Scene::new();
+    let parent = scene.add_node("parent", translation(1.0, 0.0, 0.0), None);
+    let child = scene.add_node("child", translation(0.0, 2.0, 0.0), Some(parent));
+    scene.update();
+    assert_eq!(world_position(&mut scene, child), Point3::new(1.0, 2.0, 0.0));
+
+    scene.modify_transform(parent, |transform| transform.scale = Vector3::new(2.0, 2.0, 2.0));
+    scene.update();
+    assert_eq!(world_position(&mut scene, child), Point3::new(1.0, 4.0, 0.0));
+  }
+
+  #[test]
+  fn traversal_visits_parents_before_children() {
+    let mut scene = Scene::new();
+    let a = scene.add_node("a", Transform::identity(), None);
+    let b = scene.add_node("b", Transform::identity(), Some(a));
+    let c = scene.add_node("c", Transform::identity(), None);
+    let d = scene.add_node("d", Transform::identity(), Some(b));
+
+    let mut order = Vec::new();
+    scene.traverse(|id, _| order.push(id));
+    assert_eq!(order, vec![a, b, d, c]);
+  }
+
+  #[test]
+  fn active_camera_drives_the_view() {
+    let mut scene = Scene::new();
+    assert!(scene.camera_matrices(1.0).is_none());
+
+    let camera = scene.add_node("camera", translation(0.0, 0.0, 5.0), None);
+    scene.node_mut(camera).camera = Some(SceneCamera::Orthographic { x_magnification: 2.0, y_magnification: 1.0, near: 0.1, far: 10.0 });
+    scene.active_camera = Some(camera);
+    scene.update();
+
+    let (view, projection) = scene.camera_matrices(1.0).unwrap();
+    assert_eq!(view.transform_point(&Point3::origin()), Point3::new(0.0, 0.0, -5.0));
+    assert_eq!(projection.transform_point(&Point3::new(2.0, 1.0, -0.1)), Point3::new(1.0, 1.0, -1.0));
+  }
+
+  #[test]
+  fn lighting_collects_lights_in_world_space() {
+    let mut scene = Scene::new();
+    let pivot = scene.add_node("pivot", translation(0.0, 3.0, 0.0), None);
+    let lamp = scene.add_node("lamp", translation(1.0, 0.0, 0.0), Some(pivot));
+    scene.node_mut(lamp).light = Some(Light::Point { color: [1.0, 0.5, 0.0], intensity: 2.0, range: Some(10.0) });
+    let sun = scene.add_node("sun", Transform::identity(), None);
+    scene.node_mut(sun).light = Some(Light::Directional { color: [1.0, 1.0, 1.0], intensity: 1.0 });
+    scene.update();
+
+    let lighting = scene.lighting();
+    assert_eq!(lighting.count, 2);
+    assert_eq!(lighting.positions[0], [1.0, 3.0, 0.0, 1.0]);
+    assert_eq!(lighting.colors[0], [2.0, 1.0, 0.0, 10.0]);
+    assert_eq!(lighting.positions[1], [0.0, 0.0, -1.0, 0.0]);
+  }
+
+  #[test]
+  fn lights_past_the_limit_are_dropped() {
+    let mut scene = Scene::new();
+    for index in 0..MAX_LIGHTS + 2 {
+      let node = scene.add_node(&format!("light {}", index), Transform::identity(), None);
+      scene.node_mut(node).light = Some(Light::Directional { color: [1.0, 1.0, 1.0], intensity: 1.0 });
+    }
+    scene.update();
+    assert_eq!(scene.lighting().count as usize, MAX_LIGHTS);
+  }
+}
@@ -0,0 +1,23 @@
+// Per-vertex diffuse lighting from the lights in `matrices`, same as `Lighting::shade`. Include matrices.glsl first
+vec3 shade(vec3 position, vec3 normal) {
+  if (matrices.light_count == 0u || length(normal) == 0.0) {
+    return vec3(1.0);
+  }
+  normal = normalize(normal);
+  vec3 result = vec3(0.1);
+  for (uint i = 0u; i < min(matrices.light_count, 4u); i++) {
+    vec4 light = matrices.light_positions[i];
+    vec4 color = matrices.light_colors[i];
+    vec3 to_light = -light.xyz;
+    float attenuation = 1.0;
+    if (light.w != 0.0) {
+      to_light = light.xyz - position;
+      float light_distance = length(to_light);
+      float window = color.w > 0.0 ? clamp(1.0 - light_distance / color.w, 0.0, 1.0) : 1.0;
+      attenuation = window / (1.0 + light_distance * light_distance);
+    }
+    float diffuse = length(to_light) > 0.0 ? max(dot(normal, normalize(to_light)), 0.0) : 0.0;
+    result += color.rgb * diffuse * attenuation;
+  }
+  return result;
+}
@@ -0,0 +1,17 @@
+// The model, view and projection uniforms of the GL shaders, bound as one block by the Vulkan device.
+// The lights follow `Lighting` in drivers/renderer.rs
+layout(set = 0, binding = 0) uniform Matrices {
+  mat4 model;
+  mat4 view;
+  mat4 projection;
+  vec4 light_positions[4];
+  vec4 light_colors[4];
+  uint light_count;
+} matrices;
+
+// Projections are built for GL's [-1, 1] depth range, Vulkan clips depth to [0, 1]
+vec4 to_clip_space(vec3 position) {
+  vec4 clip = matrices.projection * matrices.view * matrices.model * vec4(position, 1.0);
+  clip.z = (clip.z + clip.w) * 0.5;
+  return clip;
+}
@@ -0,0 +1,7 @@
+// naga has no combined image samplers, the sampler is bound next to the texture as `texture1_sampler`
+layout(set = 0, binding = 1) uniform texture2D texture1;
+layout(set = 0, binding = 2) uniform sampler texture1_sampler;
+
+vec4 sample_texture1(vec2 tex_coord) {
+  return texture(sampler2D(texture1, texture1_sampler), tex_coord);
+}
@@ -0,0 +1,12 @@
+#version 450
+#include "include/texture.glsl"
+
+layout(location = 0) in vec2 TexCoord;
+layout(location = 1) in vec3 Shade;
+
+layout(location = 0) out vec4 FragColor;
+
+void main() {
+  vec4 color = sample_texture1(TexCoord);
+  FragColor = vec4(color.rgb * Shade, color.a);
+}
@@ -0,0 +1,17 @@
+#version 450
+#include "include/matrices.glsl"
+#include "include/lighting.glsl"
+
+layout(location = 0) in vec3 aPos;
+layout(location = 1) in vec2 aTexCoord;
+layout(location = 2) in vec3 aNormal;
+
+layout(location = 0) out vec2 TexCoord;
+layout(location = 1) out vec3 Shade;
+
+void main() {
+  gl_Position = to_clip_space(aPos);
+  TexCoord = aTexCoord;
+  vec4 world_position = matrices.model * vec4(aPos, 1.0);
+  Shade = shade(world_position.xyz, transpose(inverse(mat3(matrices.model))) * aNormal);
+}
@@ -0,0 +1,11 @@
+#version 450
+#include "include/texture.glsl"
+
+layout(location = 0) in vec3 Color;
+layout(location = 1) in vec2 TexCoord;
+
+layout(location = 0) out vec4 FragColor;
+
+void main() {
+  FragColor = vec4(Color, 1.0) * sample_texture1(TexCoord);
+}
@@ -0,0 +1,14 @@
+#version 450
+#include "include/matrices.glsl"
+
+layout(location = 0) in vec3 aPos;
+layout(location = 1) in vec3 aColor;
+
+layout(location = 0) out vec3 Color;
+layout(location = 1) out vec2 TexCoord;
+
+void main() {
+  gl_Position = to_clip_space(aPos);
+  Color = aColor;
+  TexCoord = aPos.xy + 0.5;
+}
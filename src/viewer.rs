@@ -0,0 +1,248 @@
+use std::{collections::{hash_map::Entry, HashMap}, path::{Path, PathBuf}};
+use image::{Rgba, RgbaImage};
+use nalgebra::{Matrix4, Perspective3, Point3, Vector3};
+
+use crate::demo::{root_dir, FRAGMENT_SOURCE, VERTEX_SOURCE};
+use crate::drivers::{
+  gl::{utils::load_material_texture, viewport::Viewport},
+  renderer::{
+    as_bytes, Application, BufferUsage, PipelineDesc, PipelineId, Renderer, RendererError, ShaderSource, TextureId, VertexLayout
+  },
+};
+use crate::loaders::{gltf::{GltfCamera, GltfLight, GltfScene}, obj::ObjModel};
+use crate::scene::{Light, MaterialRef, MeshRef, NodeId, Scene, SceneCamera, Transform};
+
+#[derive(Clone, Debug)]
+pub struct Camera {
+  pub viewport      : Viewport,
+  pub fov_y_degrees : f32,
+  pub near          : f32,
+  pub far           : f32,
+}
+
+impl Camera {
+  pub fn new(eye: Point3<f32>, target: Point3<f32>, up: Vector3<f32>) -> Self {
+    Camera {
+      viewport      : Viewport::new(eye, target, up),
+      fov_y_degrees : 45.0,
+      near          : 0.1,
+      far           : 100.0,
+    }
+  }
+
+  pub fn projection_matrix(&self, aspect_ratio: f32) -> Matrix4<f32> {
+    Perspective3::new(aspect_ratio, self.fov_y_degrees.to_radians(), self.near, self.far).to_homogeneous()
+  }
+}
+
+impl Default for Camera {
+  fn default() -> Self {
+    Camera::new(Point3::new(0.0, 0.0, 3.0), Point3::origin(), Vector3::y())
+  }
+}
+
+// Draws every mesh of an OBJ or glTF scene file, from the file's first camera or else from `camera`
+pub struct SceneViewer {
+  scene_path  : PathBuf,
+  pub camera  : Camera,
+  clear_color : [f32; 4],
+  pipeline    : Option<PipelineId>,
+  scene       : Scene,
+}
+
+impl SceneViewer {
+
+  pub fn new<P: AsRef<Path>>(scene_path: P, camera: Camera) -> Self {
+    SceneViewer {
+      scene_path  : scene_path.as_ref().to_path_buf(),
+      camera,
+      clear_color : [0.2, 0.2, 0.2, 1.0],
+      pipeline    : None,
+      scene       : Scene::new(),
+    }
+  }
+
+  // Untextured materials get a 1x1 texture holding their color, so one shader covers both cases
+  fn solid_texture(renderer: &mut dyn Renderer, color: [f32; 4]) -> Result<TextureId, RendererError> {
+    let pixel = Rgba(color.map(|channel| (channel.clamp(0.0, 1.0) * 255.0).round() as u8));
+    renderer.create_texture(&RgbaImage::from_pixel(1, 1, pixel))
+  }
+
+  // Every OBJ object becomes a root node with an identity transform
+  fn load_obj(&mut self, renderer: &mut dyn Renderer, pipeline: PipelineId) -> Result<(), RendererError> {
+    let model = ObjModel::load(&self.scene_path)
+      .map_err(|err| RendererError::Backend(format!("Failed to load {}: {}", self.scene_path.display(), err)))?;
+    for warning in &model.warnings {
+      eprintln!("{}: {}", self.scene_path.display(), warning);
+    }
+
+    let mut textures: HashMap<Option<String>, Option<TextureId>> = HashMap::new();
+    for mesh in &model.meshes {
+      let texture = match textures.get(&mesh.material) {
+        Some(texture) => *texture,
+        None => {
+          let texture = match model.material(mesh) {
+            Some(material) => match load_material_texture(material) {
+              Some(image) => {
+                let image = image.map_err(|err| RendererError::Backend(format!("Failed to load texture: {}", err)))?;
+                Some(renderer.create_texture(&image)?)
+              },
+              None => {
+                let [r, g, b] = material.diffuse;
+                Some(Self::solid_texture(renderer, [r, g, b, material.dissolve])?)
+              },
+            },
+            None => Some(Self::solid_texture(renderer, [0.8, 0.8, 0.8, 1.0])?),
+          };
+          textures.insert(mesh.material.clone(), texture);
+          texture
+        }
+      };
+
+      let node = self.scene.add_node(&mesh.name, Transform::identity(), None);
+      let node = self.scene.node_mut(node);
+      node.mesh = Some(MeshRef {
+        vertex_buffer : renderer.create_buffer(BufferUsage::Vertex, as_bytes(&mesh.vertices))?,
+        index_buffer  : Some(renderer.create_buffer(BufferUsage::Index, as_bytes(&mesh.indices))?),
+        element_count : mesh.indices.len() as u32,
+      });
+      node.material = Some(MaterialRef { pipeline, texture });
+    }
+    Ok(())
+  }
+
+  // Mirrors the glTF node hierarchy, primitives of a mesh become children of the node instancing it
+  fn load_gltf(&mut self, renderer: &mut dyn Renderer, pipeline: PipelineId) -> Result<(), RendererError> {
+    let gltf = GltfScene::load(&self.scene_path)
+      .map_err(|err| RendererError::Backend(format!("Failed to load {}: {}", self.scene_path.display(), err)))?;
+    for skipped in &gltf.skipped_primitives {
+      eprintln!("Skipping glTF mesh {} primitive {}: unsupported mode {:?}", skipped.mesh, skipped.primitive, skipped.mode);
+    }
+
+    let mut images: HashMap<usize, TextureId> = HashMap::new();
+    // Uploaded once per (mesh, primitive), shared by every node instancing the mesh
+    let mut primitives: HashMap<(usize, usize), (MeshRef, MaterialRef)> = HashMap::new();
+
+    let mut stack: Vec<(usize, Option<NodeId>)> = gltf.root_nodes.iter().map(|&root| (root, None)).collect();
+    while let Some((node_index, parent)) = stack.pop() {
+      let gltf_node = &gltf.nodes[node_index];
+      let transform = Transform::from_trs(gltf_node.translation, gltf_node.rotation, gltf_node.scale);
+      let node = self.scene.add_node(gltf_node.name.as_deref().unwrap_or("node"), transform, parent);
+      stack.extend(gltf_node.children.iter().map(|&child| (child, Some(node))));
+
+      // The first camera becomes the scene's active camera
+      if let Some(camera) = gltf_node.camera.map(|camera| gltf.cameras[camera]) {
+        self.scene.node_mut(node).camera = Some(match camera {
+          GltfCamera::Perspective { aspect_ratio, yfov, znear, zfar } => SceneCamera::Perspective {
+            fov_y_degrees : yfov.to_degrees(),
+            aspect_ratio,
+            near          : znear,
+            far           : zfar.unwrap_or(1000.0),
+          },
+          GltfCamera::Orthographic { xmag, ymag, znear, zfar } => SceneCamera::Orthographic {
+            x_magnification : xmag,
+            y_magnification : ymag,
+            near            : znear,
+            far             : zfar,
+          },
+        });
+        self.scene.active_camera.get_or_insert(node);
+      }
+
+      self.scene.node_mut(node).light = gltf_node.light.map(|light| match gltf.lights[light] {
+        GltfLight::Directional { color, intensity } => Light::Directional { color, intensity },
+        GltfLight::Point { color, intensity, range } => Light::Point { color, intensity, range },
+      });
+
+      let mesh_index = match gltf_node.mesh {
+        Some(mesh_index) => mesh_index,
+        None => continue,
+      };
+
+      for (primitive_index, primitive) in gltf.meshes[mesh_index].primitives.iter().enumerate() {
+        let key = (mesh_index, primitive_index);
+        if let Entry::Vacant(entry) = primitives.entry(key) {
+          let material = gltf.material(primitive);
+          let texture = match material.base_color_texture {
+            Some(image_index) => match images.get(&image_index) {
+              Some(texture) => *texture,
+              None => {
+                let texture = renderer.create_texture(&gltf.images[image_index])?;
+                images.insert(image_index, texture);
+                texture
+              }
+            },
+            None => Self::solid_texture(renderer, material.base_color_factor)?,
+          };
+
+          entry.insert((
+            MeshRef {
+              vertex_buffer : renderer.create_buffer(BufferUsage::Vertex, as_bytes(&primitive.interleaved()))?,
+              index_buffer  : Some(renderer.create_buffer(BufferUsage::Index, as_bytes(&primitive.indices))?),
+              element_count : primitive.indices.len() as u32,
+            },
+            MaterialRef { pipeline, texture: Some(texture) },
+          ));
+        }
+
+        let (mesh, material) = primitives[&key];
+        let name = format!("{}/{}", gltf.meshes[mesh_index].name.as_deref().unwrap_or("mesh"), primitive_index);
+        let primitive_node = self.scene.add_node(&name, Transform::identity(), Some(node));
+        let primitive_node = self.scene.node_mut(primitive_node);
+        primitive_node.mesh = Some(mesh);
+        primitive_node.material = Some(material);
+      }
+    }
+    Ok(())
+  }
+}
+
+impl Application for SceneViewer {
+
+  fn init(&mut self, renderer: &mut dyn Renderer) -> Result<(), RendererError> {
+    let shaders_dir = root_dir().join("src").join("shaders");
+    let shader = renderer.create_shader(&[
+      ShaderSource::Glsl {
+        vertex   : VERTEX_SOURCE.to_string(),
+        fragment : FRAGMENT_SOURCE.to_string(),
+      },
+      ShaderSource::Files {
+        vertex_path   : shaders_dir.join("mesh.vert").to_string_lossy().into_owned(),
+        fragment_path : shaders_dir.join("mesh.frag").to_string_lossy().into_owned(),
+        defines       : Vec::new(),
+      },
+    ])?;
+
+    let pipeline = renderer.create_pipeline(&PipelineDesc {
+      shader,
+      vertex_layout : VertexLayout::position_tex_normal(),
+      depth_test    : true,
+      cull_back     : false,
+    })?;
+    self.pipeline = Some(pipeline);
+
+    let extension = self.scene_path.extension()
+      .and_then(|extension| extension.to_str())
+      .map(str::to_ascii_lowercase);
+
+    match extension.as_deref() {
+      Some("obj") => self.load_obj(renderer, pipeline),
+      Some("gltf") | Some("glb") => self.load_gltf(renderer, pipeline),
+      _ => Err(RendererError::Backend(format!("Unsupported scene file: {}", self.scene_path.display()))),
+    }
+  }
+
+  fn frame(&mut self, renderer: &mut dyn Renderer, _delta_time: f32) -> Result<(), RendererError> {
+    if self.pipeline.is_none() {
+      return Err(RendererError::Backend("SceneViewer used before init".to_string()));
+    }
+    self.scene.update();
+    let aspect_ratio = renderer.aspect_ratio();
+    let (view, projection) = self.scene.camera_matrices(aspect_ratio)
+      .unwrap_or_else(|| (self.camera.viewport.get_view_matrix(), self.camera.projection_matrix(aspect_ratio)));
+
+    renderer.begin_frame(self.clear_color)?;
+    self.scene.draw(renderer, &view, &projection)?;
+    renderer.end_frame()
+  }
+}